@@ -0,0 +1,149 @@
+//! `--export-accent`: make the accent color of the desktop the @accent color of the
+//! palette, so window decorations, buttons and selections follow the scheme along with
+//! the wallpaper.
+//!
+//! - GNOME only has a fixed set of accent colors, the closest one is set with gsettings
+//! - Plasma gets the exact color in kdeglobals with kwriteconfig
+//! - Windows gets the exact color in the registry with reg, for the taskbar, the start
+//!   menu and window borders. It shows after signing in again
+
+use crate::color::delta_e;
+use crate::error::Error;
+use crate::exec;
+use crate::palette::{parse_hex, resolve_color};
+use image::Rgb;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Desktop {
+    /// The running desktop, from XDG_CURRENT_DESKTOP or the operating system
+    Auto,
+    Gnome,
+    Kde,
+    Windows,
+}
+
+/// The accent colors GNOME offers, as libadwaita draws them
+const GNOME_ACCENTS: [(&str, &str); 9] = [
+    ("blue", "#3584e4"),
+    ("teal", "#2190a4"),
+    ("green", "#3a944a"),
+    ("yellow", "#c88800"),
+    ("orange", "#ed5b00"),
+    ("red", "#e62d42"),
+    ("pink", "#d56199"),
+    ("purple", "#9141ac"),
+    ("slate", "#6f8396"),
+];
+
+fn detect() -> Result<Desktop, Error> {
+    if cfg!(windows) {
+        return Ok(Desktop::Windows);
+    }
+    let current = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+    for desktop in current.split(':') {
+        match desktop.to_ascii_lowercase().as_str() {
+            "gnome" | "ubuntu" => return Ok(Desktop::Gnome),
+            "kde" => return Ok(Desktop::Kde),
+            _ => {}
+        }
+    }
+    Err(Error::ExternalTool(format!(
+        "can't export the accent color to the desktop '{}', choose one of gnome, kde or windows",
+        current
+    )))
+}
+
+/// Run a command, failing with the error output of the program
+fn run(program: &str, args: &[&str]) -> Result<(), Error> {
+    let output = exec::command(program, "--export-accent")?
+        .args(args)
+        .output()
+        .map_err(|err| Error::ExternalTool(format!("failed to execute {}: {}", program, err)))?;
+    if !output.status.success() {
+        return Err(Error::ExternalTool(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+fn gnome_accent(color: Rgb<u8>) -> &'static str {
+    GNOME_ACCENTS
+        .iter()
+        .min_by(|(_, a), (_, b)| {
+            let distance = |hex: &str| delta_e(color, parse_hex(hex).unwrap());
+            distance(a).total_cmp(&distance(b))
+        })
+        .map(|(name, _)| *name)
+        .unwrap()
+}
+
+fn set_kde(color: Rgb<u8>) -> Result<(), Error> {
+    let Rgb([red, green, blue]) = color;
+    let value = format!("{},{},{}", red, green, blue);
+    let settings = [
+        ("AccentColor", value.as_str()),
+        ("accentColorFromWallpaper", "false"),
+    ];
+    // Plasma 6 ships kwriteconfig6, Plasma 5 kwriteconfig5
+    let program = match run("kwriteconfig6", &["--help"]) {
+        Ok(()) => "kwriteconfig6",
+        Err(_) => "kwriteconfig5",
+    };
+    for (key, value) in settings {
+        run(
+            program,
+            &[
+                "--file",
+                "kdeglobals",
+                "--group",
+                "General",
+                "--key",
+                key,
+                value,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn set_windows(color: Rgb<u8>) -> Result<(), Error> {
+    let Rgb([red, green, blue]) = color;
+    // the accent colors are kept as 0xAABBGGRR, the colorization color as 0xAARRGGBB
+    let abgr = format!("0xff{:02x}{:02x}{:02x}", blue, green, red);
+    let argb = format!("0xff{:02x}{:02x}{:02x}", red, green, blue);
+    let dwm = r"HKCU\Software\Microsoft\Windows\DWM";
+    let accent = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Explorer\Accent";
+    for (key, value, data) in [
+        (dwm, "AccentColor", &abgr),
+        (dwm, "ColorizationColor", &argb),
+        (accent, "AccentColorMenu", &abgr),
+    ] {
+        run(
+            "reg",
+            &["add", key, "/v", value, "/t", "REG_DWORD", "/d", data, "/f"],
+        )?;
+    }
+    Ok(())
+}
+
+/// Set the accent color of `desktop` to the accent of the palette
+pub fn export(desktop: Desktop, palette: &[Rgb<u8>]) -> Result<(), Error> {
+    let color = resolve_color("@accent", palette).map_err(Error::Palette)?;
+    match desktop {
+        Desktop::Auto => export(detect()?, palette),
+        Desktop::Gnome => run(
+            "gsettings",
+            &[
+                "set",
+                "org.gnome.desktop.interface",
+                "accent-color",
+                gnome_accent(color),
+            ],
+        ),
+        Desktop::Kde => set_kde(color),
+        Desktop::Windows => set_windows(color),
+    }
+}
@@ -0,0 +1,268 @@
+use crate::color::luminance;
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+
+/// Settings for `--split-tone`, the colors are kept as role strings until the
+/// palette has been loaded
+#[derive(Clone, Debug)]
+pub struct SplitTone {
+    pub shadows: String,
+    pub highlights: String,
+    /// -1.0 favours the shadow tint, 1.0 favours the highlight tint
+    pub balance: f32,
+    pub amount: f32,
+}
+
+pub fn parse_split_tone(input: &str) -> Result<SplitTone, String> {
+    let mut split_tone = SplitTone {
+        shadows: String::new(),
+        highlights: String::new(),
+        balance: 0.0,
+        amount: 0.5,
+    };
+    for option in input.split(',') {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got '{}'", option))?;
+        match key.trim() {
+            "shadows" => split_tone.shadows = value.trim().to_string(),
+            "highlights" => split_tone.highlights = value.trim().to_string(),
+            "balance" => {
+                split_tone.balance = value
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|_| format!("balance '{}' is not a number", value))?
+                    .clamp(-1.0, 1.0)
+            }
+            "amount" => {
+                split_tone.amount = value
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|_| format!("amount '{}' is not a number", value))?
+                    .clamp(0.0, 1.0)
+            }
+            other => return Err(format!("unknown split tone option '{}'", other)),
+        }
+    }
+    if split_tone.shadows.is_empty() || split_tone.highlights.is_empty() {
+        return Err("both shadows= and highlights= have to be given".to_string());
+    }
+    Ok(split_tone)
+}
+
+fn smoothstep(x: f32) -> f32 {
+    let x = x.clamp(0.0, 1.0);
+    x * x * (3.0 - 2.0 * x)
+}
+
+/// Classic split toning: tint the shadows and highlights with the hue of two colors
+/// while leaving the luminance of every pixel (mostly) untouched
+pub fn split_tone(
+    img: &mut RgbImage,
+    shadows: Rgb<u8>,
+    highlights: Rgb<u8>,
+    balance: f32,
+    amount: f32,
+) {
+    // only the hue/saturation of the tint colors is applied, so strip their luminance
+    let tint_offset = |color: Rgb<u8>| {
+        let lum = luminance(color);
+        color.0.map(|channel| channel as f32 / 255.0 - lum)
+    };
+    let shadow_offset = tint_offset(shadows);
+    let highlight_offset = tint_offset(highlights);
+    // a positive balance moves the pivot down so more of the image counts as highlights
+    let pivot = (0.5 - balance * 0.5).clamp(0.05, 0.95);
+
+    img.par_pixels_mut().for_each(|pixel| {
+        let lum = luminance(*pixel);
+        let shadow_weight = smoothstep((pivot - lum) / pivot);
+        let highlight_weight = smoothstep((lum - pivot) / (1.0 - pivot));
+        for x in 0..=2 {
+            let offset = shadow_offset[x] * shadow_weight + highlight_offset[x] * highlight_weight;
+            let channel = pixel.0[x] as f32 / 255.0 + offset * amount;
+            pixel.0[x] = (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    });
+}
+
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum WhiteBalance {
+    /// Assume the scene averages out to gray
+    GrayWorld,
+    /// Assume the brightest part of the scene is white
+    WhitePatch,
+}
+
+/// Remove a color cast by scaling each channel independently
+pub fn auto_white_balance(img: &mut RgbImage, method: WhiteBalance) {
+    if img.width() == 0 || img.height() == 0 {
+        return;
+    }
+    let gains: [f32; 3] = match method {
+        WhiteBalance::GrayWorld => {
+            let sums = img.pixels().fold([0u64; 3], |mut acc, pixel| {
+                for (sum, channel) in acc.iter_mut().zip(pixel.0) {
+                    *sum += channel as u64;
+                }
+                acc
+            });
+            let means = sums.map(|sum| sum as f32 / (img.width() * img.height()) as f32);
+            let gray = means.iter().sum::<f32>() / 3.0;
+            means.map(|mean| if mean > 0.0 { gray / mean } else { 1.0 })
+        }
+        WhiteBalance::WhitePatch => {
+            // use the 99th percentile instead of the maximum so a few blown out
+            // pixels don't stop the correction
+            let mut histograms = [[0u32; 256]; 3];
+            for pixel in img.pixels() {
+                for x in 0..=2 {
+                    histograms[x][pixel.0[x] as usize] += 1;
+                }
+            }
+            let cutoff = (img.width() * img.height()) / 100;
+            histograms.map(|histogram| {
+                let mut count = 0;
+                let mut white = 255;
+                for value in (0..256).rev() {
+                    count += histogram[value];
+                    if count > cutoff {
+                        white = value;
+                        break;
+                    }
+                }
+                if white > 0 {
+                    255.0 / white as f32
+                } else {
+                    1.0
+                }
+            })
+        }
+    };
+
+    img.par_pixels_mut().for_each(|pixel| {
+        for (channel, gain) in pixel.0.iter_mut().zip(gains) {
+            *channel = (*channel as f32 * gain).round().clamp(0.0, 255.0) as u8;
+        }
+    });
+}
+
+/// Contrast limited adaptive histogram equalization on the luminance of the image.
+/// `clip` limits how far each tile histogram is allowed to stretch, higher values
+/// give stronger local contrast
+pub fn clahe(img: &mut RgbImage, clip: f32) {
+    const TILES: u32 = 8;
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    let tiles_x = TILES.min(width);
+    let tiles_y = TILES.min(height);
+    let tile_width = width.div_ceil(tiles_x);
+    let tile_height = height.div_ceil(tiles_y);
+    let luma = |pixel: &Rgb<u8>| (luminance(*pixel) * 255.0).round() as usize;
+
+    // build the clipped equalization lookup table for every tile
+    let luts: Vec<[f32; 256]> = (0..tiles_x * tiles_y)
+        .into_par_iter()
+        .map(|tile| {
+            let x0 = (tile % tiles_x) * tile_width;
+            let y0 = (tile / tiles_x) * tile_height;
+            let mut histogram = [0f32; 256];
+            let mut count = 0.0;
+            for y in y0..(y0 + tile_height).min(height) {
+                for x in x0..(x0 + tile_width).min(width) {
+                    histogram[luma(img.get_pixel(x, y))] += 1.0;
+                    count += 1.0;
+                }
+            }
+            // clip the histogram and hand the excess back out evenly
+            let limit = (clip * count / 256.0).max(1.0);
+            let mut excess = 0.0;
+            for bin in histogram.iter_mut() {
+                if *bin > limit {
+                    excess += *bin - limit;
+                    *bin = limit;
+                }
+            }
+            let mut lut = [0f32; 256];
+            let mut cdf = 0.0;
+            for (value, bin) in histogram.iter().enumerate() {
+                cdf += bin + excess / 256.0;
+                lut[value] = cdf / count.max(1.0) * 255.0;
+            }
+            lut
+        })
+        .collect();
+
+    img.par_enumerate_pixels_mut().for_each(|(x, y, pixel)| {
+        // interpolate between the lookup tables of the four closest tile centers
+        let tile_x = ((x as f32 + 0.5) / tile_width as f32 - 0.5).clamp(0.0, (tiles_x - 1) as f32);
+        let tile_y = ((y as f32 + 0.5) / tile_height as f32 - 0.5).clamp(0.0, (tiles_y - 1) as f32);
+        let (left, top) = (tile_x.floor() as u32, tile_y.floor() as u32);
+        let (right, bottom) = ((left + 1).min(tiles_x - 1), (top + 1).min(tiles_y - 1));
+        let (fx, fy) = (tile_x - left as f32, tile_y - top as f32);
+        let value = luma(pixel);
+        let lookup = |tx: u32, ty: u32| luts[(ty * tiles_x + tx) as usize][value];
+        let equalized = (lookup(left, top) * (1.0 - fx) + lookup(right, top) * fx) * (1.0 - fy)
+            + (lookup(left, bottom) * (1.0 - fx) + lookup(right, bottom) * fx) * fy;
+        // shift all channels by the same amount so the hue stays put
+        let shift = equalized - value as f32;
+        for channel in pixel.0.iter_mut() {
+            *channel = (*channel as f32 + shift).round().clamp(0.0, 255.0) as u8;
+        }
+    });
+}
+
+/// Lift or darken the shadows and highlights with a smooth tone curve, `shadows` and
+/// `highlights` range from -100 to 100
+pub fn tone_curve(img: &mut RgbImage, shadows: f32, highlights: f32) {
+    let shadows = shadows.clamp(-100.0, 100.0) / 100.0;
+    let highlights = highlights.clamp(-100.0, 100.0) / 100.0;
+    // two bumps peaking at 1/3 and 2/3 luminance, both zero at black and white so the
+    // end points never move. Scaled so the curve stays monotonic at the extremes
+    let curve: [f32; 256] = std::array::from_fn(|value| {
+        let lum = value as f32 / 255.0;
+        let shadow_bump = 6.75 * lum * (1.0 - lum) * (1.0 - lum);
+        let highlight_bump = 6.75 * lum * lum * (1.0 - lum);
+        (lum + 0.25 * (shadows * shadow_bump + highlights * highlight_bump)) * 255.0
+    });
+
+    img.par_pixels_mut().for_each(|pixel| {
+        let value = luminance(*pixel) * 255.0;
+        let shift = curve[value.round() as usize] - value;
+        for channel in pixel.0.iter_mut() {
+            *channel = (*channel as f32 + shift).round().clamp(0.0, 255.0) as u8;
+        }
+    });
+}
+
+/// Mean luminance from 0.0 (black) to 1.0 (white)
+pub fn mean_luminance(img: &RgbImage) -> f32 {
+    let sum: f64 = img.par_pixels().map(|pixel| luminance(*pixel) as f64).sum();
+    (sum / (img.width() * img.height()).max(1) as f64) as f32
+}
+
+/// Raise the luminance to the power `gamma`, brightening below 1.0 and darkening above it
+/// without moving black and white
+pub fn gamma(img: &mut RgbImage, gamma: f32) {
+    let curve: [f32; 256] = std::array::from_fn(|value| (value as f32 / 255.0).powf(gamma) * 255.0);
+    img.par_pixels_mut().for_each(|pixel| {
+        let value = luminance(*pixel) * 255.0;
+        let shift = curve[value.round() as usize] - value;
+        for channel in pixel.0.iter_mut() {
+            *channel = (*channel as f32 + shift).round().clamp(0.0, 255.0) as u8;
+        }
+    });
+}
+
+/// Reduce every channel to `levels` evenly spaced values
+pub fn posterize(img: &mut RgbImage, levels: u8) {
+    let steps = (levels.max(2) - 1) as f32;
+    img.par_pixels_mut().for_each(|pixel| {
+        for channel in pixel.0.iter_mut() {
+            *channel = ((*channel as f32 / 255.0 * steps).round() / steps * 255.0).round() as u8;
+        }
+    });
+}
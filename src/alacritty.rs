@@ -0,0 +1,189 @@
+//! `--alacritty`: the terminal colors of an Alacritty config, `colors.normal` followed by
+//! `colors.bright`, so @color0 is normal black and @color15 bright white.
+//!
+//! Both the TOML config and the older YAML one are read. Colors can come from files the
+//! TOML config imports (`general.import`, or `import` at the top level in older
+//! versions), as color schemes usually do, with the config itself taking precedence.
+//! Without a path, the config is looked for where Alacritty looks for it.
+
+use crate::error::Error;
+use crate::palette::parse_hex;
+use image::Rgb;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SECTIONS: [&str; 2] = ["normal", "bright"];
+const NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+/// Deepest chain of imports followed, against import loops
+const MAX_IMPORT_DEPTH: usize = 5;
+
+type Colors = [Option<Rgb<u8>>; 16];
+
+/// A color as Alacritty writes it, `#rrggbb` or `0xrrggbb`
+fn parse_color(value: &str) -> Result<Rgb<u8>, String> {
+    let hex = value.trim().trim_matches(['"', '\'']);
+    let hex = hex
+        .strip_prefix("0x")
+        .unwrap_or(hex.trim_start_matches('#'));
+    parse_hex(&format!("#{}", hex))
+}
+
+fn set(colors: &mut Colors, section: &str, name: &str, value: &str) -> Result<(), String> {
+    let (Some(section), Some(index)) = (
+        SECTIONS.iter().position(|known| *known == section),
+        NAMES.iter().position(|known| *known == name),
+    ) else {
+        return Ok(());
+    };
+    let color = parse_color(value)
+        .map_err(|err| format!("colors.{}.{}: {}", SECTIONS[section], name, err))?;
+    colors[section * 8 + index] = Some(color);
+    Ok(())
+}
+
+/// Expand `~` and resolve paths relative to the directory of the importing config
+fn import_path(import: &str, config: &Path) -> PathBuf {
+    match import.strip_prefix("~/") {
+        Some(rest) => homedir::my_home()
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .join(rest),
+        None => config.parent().unwrap_or(Path::new("")).join(import),
+    }
+}
+
+fn decode_toml(
+    contents: &str,
+    path: &Path,
+    colors: &mut Colors,
+    depth: usize,
+) -> Result<(), String> {
+    let config: toml::Table = toml::from_str(contents).map_err(|err| err.to_string())?;
+    let imports = config
+        .get("general")
+        .and_then(|general| general.get("import"))
+        .or_else(|| config.get("import"))
+        .and_then(|imports| imports.as_array());
+    for import in imports
+        .into_iter()
+        .flatten()
+        .filter_map(|import| import.as_str())
+    {
+        if depth < MAX_IMPORT_DEPTH {
+            read(&import_path(import, path), colors, depth + 1)?;
+        }
+    }
+    for section in SECTIONS {
+        let Some(table) = config
+            .get("colors")
+            .and_then(|colors| colors.get(section))
+            .and_then(|table| table.as_table())
+        else {
+            continue;
+        };
+        for (name, value) in table {
+            if let Some(value) = value.as_str() {
+                set(colors, section, name, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The colors of a YAML config, found by the indentation of its `colors:` section
+fn decode_yaml(contents: &str, colors: &mut Colors) -> Result<(), String> {
+    // the keys of the sections the current line is in, with their indentation
+    let mut sections: Vec<(usize, String)> = Vec::new();
+    for line in contents.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        while sections.last().is_some_and(|(level, _)| *level >= indent) {
+            sections.pop();
+        }
+        let Some((key, value)) = trimmed.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().trim_matches(['"', '\'']);
+        let value = value.trim();
+        if value.is_empty() || value.starts_with('#') {
+            sections.push((indent, key.to_string()));
+            continue;
+        }
+        if let [(_, colors_key), (_, section)] = &sections[..] {
+            if colors_key == "colors" {
+                set(
+                    colors,
+                    section,
+                    key,
+                    value.split(" #").next().unwrap_or(value),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read(path: &Path, colors: &mut Colors, depth: usize) -> Result<(), String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let yaml = path
+        .extension()
+        .is_some_and(|extension| extension == "yml" || extension == "yaml");
+    if yaml {
+        decode_yaml(&contents, colors)
+    } else {
+        decode_toml(&contents, path, colors, depth)
+    }
+    .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Where Alacritty looks for its config, in its order
+pub fn default_path() -> Option<PathBuf> {
+    let home = homedir::my_home().ok().flatten();
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+    let mut candidates = Vec::new();
+    for name in ["alacritty.toml", "alacritty.yml"] {
+        if let Some(dir) = &config_home {
+            candidates.push(dir.join("alacritty").join(name));
+            candidates.push(dir.join(name));
+        }
+    }
+    if let Some(home) = &home {
+        candidates.push(home.join(".config/alacritty/alacritty.toml"));
+        candidates.push(home.join(".alacritty.toml"));
+        candidates.push(home.join(".alacritty.yml"));
+    }
+    candidates.into_iter().find(|path| path.is_file())
+}
+
+/// The palette of the config at `path`, or of the default config
+pub fn load(path: Option<&str>) -> Result<Vec<Rgb<u8>>, Error> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => default_path()
+            .ok_or_else(|| Error::Palette("could not find an Alacritty config".to_string()))?,
+    };
+    let mut colors: Colors = [None; 16];
+    read(&path, &mut colors, 0).map_err(Error::Palette)?;
+    let missing: Vec<String> = (0..colors.len())
+        .filter(|index| colors[*index].is_none())
+        .map(|index| format!("colors.{}.{}", SECTIONS[index / 8], NAMES[index % 8]))
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::Palette(format!(
+            "{}: missing {}",
+            path.display(),
+            missing.join(", ")
+        )));
+    }
+    Ok(colors.into_iter().flatten().collect())
+}
@@ -0,0 +1,109 @@
+//! The dark or light preference of the desktop, for the default palette when none is
+//! chosen: [`DARK_THEME`] for dark mode, [`LIGHT_THEME`] for light mode, and the classic
+//! [`FALLBACK_THEME`] when there is no preference or it can't be found out.
+//!
+//! It is read from the freedesktop settings portal (`color-scheme`), or GNOME's setting
+//! without a portal, on Linux and the BSDs, `AppleInterfaceStyle` on macOS and
+//! `AppsUseLightTheme` on Windows, each through the command line tool of the system.
+//! With `--no-exec` the preference is unknown.
+
+use crate::exec;
+
+pub const DARK_THEME: &str = "tokyo-night";
+pub const LIGHT_THEME: &str = "catppuccin-latte";
+pub const FALLBACK_THEME: &str = "pico8";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Appearance {
+    Dark,
+    Light,
+}
+
+/// The output of a program, or None when it can't run or fails
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = exec::command(program, "the default palette")
+        .ok()?
+        .args(args)
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(target_os = "macos")]
+pub fn detect() -> Option<Appearance> {
+    // the key only exists in dark mode
+    match output("defaults", &["read", "-g", "AppleInterfaceStyle"]) {
+        Some(style) if style.trim() == "Dark" => Some(Appearance::Dark),
+        _ => Some(Appearance::Light),
+    }
+}
+
+#[cfg(windows)]
+pub fn detect() -> Option<Appearance> {
+    let query = output(
+        "reg",
+        &[
+            "query",
+            r"HKCU\Software\Microsoft\Windows\CurrentVersion\Themes\Personalize",
+            "/v",
+            "AppsUseLightTheme",
+        ],
+    )?;
+    let value = query.split_whitespace().last()?;
+    match value {
+        "0x0" => Some(Appearance::Dark),
+        "0x1" => Some(Appearance::Light),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+pub fn detect() -> Option<Appearance> {
+    // prints (<<uint32 1>>,) with 1 for dark, 2 for light and 0 for no preference
+    let portal = output(
+        "gdbus",
+        &[
+            "call",
+            "--session",
+            "--dest",
+            "org.freedesktop.portal.Desktop",
+            "--object-path",
+            "/org/freedesktop/portal/desktop",
+            "--method",
+            "org.freedesktop.portal.Settings.Read",
+            "org.freedesktop.appearance",
+            "color-scheme",
+        ],
+    );
+    if let Some(portal) = portal {
+        let value = portal
+            .split(|c: char| !c.is_ascii_digit())
+            .rfind(|part| !part.is_empty());
+        match value {
+            Some("1") => return Some(Appearance::Dark),
+            Some("2") => return Some(Appearance::Light),
+            _ => {}
+        }
+    }
+    let scheme = output(
+        "gsettings",
+        &["get", "org.gnome.desktop.interface", "color-scheme"],
+    )?;
+    match scheme.trim().trim_matches('\'') {
+        "prefer-dark" => Some(Appearance::Dark),
+        "prefer-light" => Some(Appearance::Light),
+        _ => None,
+    }
+}
+
+/// The built-in theme for the preference of the desktop, and why it was picked
+pub fn default_theme() -> (&'static str, &'static str) {
+    match detect() {
+        Some(Appearance::Dark) => (DARK_THEME, "the system prefers a dark appearance"),
+        Some(Appearance::Light) => (LIGHT_THEME, "the system prefers a light appearance"),
+        None => (FALLBACK_THEME, "the system has no dark or light preference"),
+    }
+}
@@ -0,0 +1,84 @@
+//! `palette audit`: how far apart the palette colors are, to catch schemes where several
+//! colors will look like one on the wallpaper.
+
+use crate::color::{contrast_ratio, delta_e};
+use crate::palette::to_hex;
+use image::Rgb;
+
+/// Default ΔE below which two colors count as indistinguishable after mapping. 2 is
+/// just noticeable side by side, but mapped and dithered areas need a lot more
+pub const DEFAULT_MIN_DELTA_E: f32 = 10.0;
+
+fn print_matrix(title: &str, palette: &[Rgb<u8>], value: impl Fn(Rgb<u8>, Rgb<u8>) -> f32) {
+    println!("{}:", title);
+    print!("{:>9}", "");
+    for i in 0..palette.len() {
+        print!("{:>6}", i);
+    }
+    println!();
+    for (i, color) in palette.iter().enumerate() {
+        print!("{:>2} {}", i, to_hex(*color));
+        for (j, other) in palette.iter().enumerate() {
+            if i == j {
+                print!("{:>6}", "-");
+            } else {
+                print!("{:>6.1}", value(*color, *other));
+            }
+        }
+        println!();
+    }
+    println!();
+}
+
+/// Groups of palette indices whose colors are closer than `min_delta_e` to another
+/// color of the group
+fn similar_groups(palette: &[Rgb<u8>], min_delta_e: f32) -> Vec<Vec<usize>> {
+    let mut group_of: Vec<usize> = (0..palette.len()).collect();
+    for i in 0..palette.len() {
+        for j in i + 1..palette.len() {
+            if delta_e(palette[i], palette[j]) < min_delta_e {
+                let (from, to) = (group_of[j], group_of[i]);
+                group_of
+                    .iter_mut()
+                    .filter(|group| **group == from)
+                    .for_each(|group| *group = to);
+            }
+        }
+    }
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, group) in group_of.iter().enumerate() {
+        match groups
+            .iter_mut()
+            .find(|members| group_of[members[0]] == *group)
+        {
+            Some(members) => members.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+    groups.retain(|members| members.len() > 1);
+    groups
+}
+
+/// Print the ΔE and contrast matrices and the colors that will be hard to tell apart,
+/// returning whether there are any
+pub fn print(palette: &[Rgb<u8>], min_delta_e: f32) -> bool {
+    print_matrix("delta E (Oklab)", palette, delta_e);
+    print_matrix("contrast ratio (WCAG)", palette, contrast_ratio);
+    let groups = similar_groups(palette, min_delta_e);
+    if groups.is_empty() {
+        println!("all colors are at least delta E {} apart", min_delta_e);
+        return false;
+    }
+    println!(
+        "indistinguishable after mapping (delta E below {}):",
+        min_delta_e
+    );
+    for members in &groups {
+        let colors: Vec<String> = members
+            .iter()
+            .map(|i| format!("@color{} {}", i, to_hex(palette[*i])))
+            .collect();
+        println!("  {}", colors.join(", "));
+    }
+    true
+}
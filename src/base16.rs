@@ -0,0 +1,171 @@
+//! `--base16`: palettes from base16 and base24 scheme files, as published by the
+//! tinted-theming scheme repositories. Both the classic layout with the colors at the
+//! top level and the newer one with them under `palette:` are read:
+//!
+//! ```yaml
+//! scheme: "Default Dark"
+//! author: "Chris Kempson"
+//! base00: "181818"
+//! base01: "282828"
+//! ```
+//!
+//! The palette is base00 to base0F in order, followed by base10 to base17 for base24
+//! schemes, so @background is base00 and @colorN is baseN in hex.
+
+use crate::error::Error;
+use crate::palette::parse_hex;
+use image::Rgb;
+use std::fs;
+
+/// Colors in a base16 and in a base24 scheme
+const BASE16_KEYS: usize = 16;
+const BASE24_KEYS: usize = 24;
+
+fn key(index: usize) -> String {
+    format!("base{:02X}", index)
+}
+
+/// The value of a `key: value` line, without quotes and trailing comments
+fn value(rest: &str) -> &str {
+    let rest = rest.trim();
+    for quote in ['"', '\''] {
+        if let Some(quoted) = rest.strip_prefix(quote) {
+            return quoted.split(quote).next().unwrap_or_default();
+        }
+    }
+    rest.split_whitespace().next().unwrap_or_default()
+}
+
+/// Parse the colors of a scheme file
+pub fn decode(contents: &str) -> Result<Vec<Rgb<u8>>, String> {
+    let mut colors: Vec<Option<Rgb<u8>>> = vec![None; BASE24_KEYS];
+    for line in contents.lines() {
+        let Some((name, rest)) = line.trim().split_once(':') else {
+            continue;
+        };
+        let Some(index) = (0..BASE24_KEYS).find(|index| name.eq_ignore_ascii_case(&key(*index)))
+        else {
+            continue;
+        };
+        let hex = value(rest);
+        let hex = if hex.starts_with('#') {
+            hex.to_string()
+        } else {
+            format!("#{}", hex)
+        };
+        colors[index] = Some(parse_hex(&hex).map_err(|err| format!("{}: {}", name, err))?);
+    }
+    let count = if colors[BASE16_KEYS..].iter().any(Option::is_some) {
+        BASE24_KEYS
+    } else {
+        BASE16_KEYS
+    };
+    colors[..count]
+        .iter()
+        .enumerate()
+        .map(|(index, color)| color.ok_or_else(|| format!("{} is missing", key(index))))
+        .collect()
+}
+
+pub fn load(path: &str) -> Result<Vec<Rgb<u8>>, Error> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| Error::Palette(format!("could not read {}: {}", path, err)))?;
+    decode(&contents).map_err(|err| Error::Palette(format!("{}: {}", path, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// base16-default-dark.yaml of the classic scheme repository, quoted values without #
+    const DEFAULT_DARK: &str = r#"scheme: "Default Dark"
+author: "Chris Kempson (http://chriskempson.com)"
+base00: "181818"
+base01: "282828"
+base02: "383838"
+base03: "585858"
+base04: "b8b8b8"
+base05: "d8d8d8"
+base06: "e8e8e8"
+base07: "f8f8f8"
+base08: "ab4642"
+base09: "dc9656"
+base0A: "f7ca88"
+base0B: "a1b56c"
+base0C: "86c1b9"
+base0D: "7cafc2"
+base0E: "ba8baf"
+base0F: "a16946"
+"#;
+
+    /// dracula.yaml of tinted-theming's base24 schemes, under `palette:` with #
+    const DRACULA: &str = r##"system: "base24"
+name: "Dracula"
+author: "FredHappyface (https://github.com/fredHappyface)"
+variant: "dark"
+palette:
+  base00: "#282a36"
+  base01: "#363447"
+  base02: "#44475a"
+  base03: "#6272a4"
+  base04: "#9ea8c7"
+  base05: "#f8f8f2"
+  base06: "#f0f1f4"
+  base07: "#ffffff"
+  base08: "#ff5555"
+  base09: "#ffb86c"
+  base0A: "#f1fa8c"
+  base0B: "#50fa7b"
+  base0C: "#8be9fd"
+  base0D: "#80bfff"
+  base0E: "#ff79c6"
+  base0F: "#bd93f9"
+  base10: "#1e2029"
+  base11: "#16171f"
+  base12: "#ff6e6e"
+  base13: "#ffffa5"
+  base14: "#69ff94"
+  base15: "#a4ffff"
+  base16: "#d6acff"
+  base17: "#ff92df"
+"##;
+
+    fn hex(colors: &[Rgb<u8>]) -> Vec<String> {
+        colors
+            .iter()
+            .map(|color| crate::palette::to_hex(*color))
+            .collect()
+    }
+
+    #[test]
+    fn base16_quoted_without_hash() {
+        let colors = decode(DEFAULT_DARK).unwrap();
+        assert_eq!(colors.len(), 16);
+        assert_eq!(hex(&colors[..2]), ["#181818", "#282828"]);
+        assert_eq!(hex(&colors[15..]), ["#a16946"]);
+    }
+
+    #[test]
+    fn base24_under_palette_with_hash() {
+        let colors = decode(DRACULA).unwrap();
+        assert_eq!(colors.len(), 24);
+        assert_eq!(hex(&colors[..1]), ["#282a36"]);
+        assert_eq!(hex(&colors[15..17]), ["#bd93f9", "#1e2029"]);
+        assert_eq!(hex(&colors[23..]), ["#ff92df"]);
+    }
+
+    #[test]
+    fn unquoted_values_with_comments() {
+        let unquoted = DEFAULT_DARK
+            .replace('"', "")
+            .replace("base08: ab4642", "base08: ab4642 # red")
+            .replace("base0D: 7cafc2", "base0D: '#7cafc2'");
+        assert_eq!(decode(&unquoted).unwrap(), decode(DEFAULT_DARK).unwrap());
+    }
+
+    #[test]
+    fn missing_key() {
+        let incomplete = DRACULA.replace("  base13: \"#ffffa5\"\n", "");
+        assert_eq!(decode(&incomplete).unwrap_err(), "base13 is missing");
+    }
+}
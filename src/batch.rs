@@ -0,0 +1,384 @@
+use crate::error::{warn, Error};
+use crate::palette::{to_hex, PaletteEntry};
+use crate::{jpeg, provenance, rerun, watch, Args};
+use image::ImageFormat;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Progress of a batch is kept in this file inside the output directory, one completed
+/// output per line: `name<TAB>input hash<TAB>settings hash<TAB>output hash`
+const MANIFEST: &str = ".imagecolorizer-progress";
+
+/// What to do with inputs that are identical to one that was already processed
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Dedupe {
+    /// copy the first output
+    Copy,
+    /// hard link to the first output
+    Hardlink,
+    /// symlink to the first output
+    Symlink,
+}
+
+fn link_duplicate(policy: Dedupe, original: &Path, duplicate: &Path) -> std::io::Result<()> {
+    if duplicate.symlink_metadata().is_ok() {
+        fs::remove_file(duplicate)?;
+    }
+    match policy {
+        Dedupe::Copy => fs::copy(original, duplicate).map(|_| ()),
+        Dedupe::Hardlink => fs::hard_link(original, duplicate),
+        // both live in the output directory, so a relative link survives moving it
+        Dedupe::Symlink => {
+            #[cfg(unix)]
+            return std::os::unix::fs::symlink(original.file_name().unwrap(), duplicate);
+            #[cfg(windows)]
+            return std::os::windows::fs::symlink_file(original.file_name().unwrap(), duplicate);
+        }
+    }
+}
+
+struct Entry {
+    input_hash: String,
+    settings_hash: String,
+    output_hash: String,
+}
+
+/// The options that change how an image is rendered. The palette options count by the
+/// palette they made, and --threads, --no-resume and the like not at all
+fn render_options(args: &Args) -> Vec<String> {
+    rerun::group(&provenance::options(args))
+        .into_iter()
+        .filter(|(id, _)| {
+            id.as_ref().is_some_and(|id| {
+                !rerun::PALETTE_OPTIONS.contains(&id.as_str())
+                    && !jpeg::RUN_OPTIONS.contains(&id.as_str())
+            })
+        })
+        .flat_map(|(_, tokens)| tokens)
+        .collect()
+}
+
+fn file_hash(path: &Path) -> Result<String, std::io::Error> {
+    Ok(blake3::hash(&fs::read(path)?).to_hex().to_string())
+}
+
+fn read_manifest(path: &Path) -> HashMap<String, Entry> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.next()?.to_string();
+            let entry = Entry {
+                input_hash: fields.next()?.to_string(),
+                settings_hash: fields.next()?.to_string(),
+                output_hash: fields.next()?.to_string(),
+            };
+            Some((name, entry))
+        })
+        .collect()
+}
+
+/// How a file of the batch went
+enum Status {
+    Done,
+    /// completed by an earlier run
+    Resumed,
+    /// made from the output of an identical input
+    Duplicate(String),
+    Failed(String),
+}
+
+/// Progress table of a batch, printed a row at a time as the files finish
+struct Report {
+    total: usize,
+    rows: Vec<(String, f64, Duration, Status)>,
+    started: Instant,
+}
+
+impl Report {
+    fn new(total: usize) -> Report {
+        println!(
+            "{:>9}  {:<32} {:>8} {:>8}  status",
+            "", "file", "MP", "time"
+        );
+        Report {
+            total,
+            rows: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    fn add(&mut self, name: String, megapixels: f64, duration: Duration, status: Status) {
+        let text = match &status {
+            Status::Done => "done".to_string(),
+            Status::Resumed => "already done".to_string(),
+            Status::Duplicate(original) => format!("duplicate of {}", original),
+            Status::Failed(err) => format!("failed: {}", err),
+        };
+        let line = format!(
+            "{:>4}/{:<4}  {:<32} {:>8.2} {:>7.2}s  {}",
+            self.rows.len() + 1,
+            self.total,
+            name,
+            megapixels,
+            duration.as_secs_f64(),
+            text
+        );
+        if matches!(status, Status::Failed(_)) {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+        self.rows.push((name, megapixels, duration, status));
+    }
+
+    fn failures(&self) -> Vec<String> {
+        self.rows
+            .iter()
+            .filter(|(_, _, _, status)| matches!(status, Status::Failed(_)))
+            .map(|(name, _, _, _)| name.clone())
+            .collect()
+    }
+
+    fn summary(&self) {
+        let count = |wanted: fn(&Status) -> bool| {
+            self.rows
+                .iter()
+                .filter(|(_, _, _, status)| wanted(status))
+                .count()
+        };
+        println!(
+            "{} rendered, {} already done, {} duplicates, {} failed, {} not reached in {:.1}s",
+            count(|status| matches!(status, Status::Done)),
+            count(|status| matches!(status, Status::Resumed)),
+            count(|status| matches!(status, Status::Duplicate(_))),
+            count(|status| matches!(status, Status::Failed(_))),
+            self.total - self.rows.len(),
+            self.started.elapsed().as_secs_f64()
+        );
+        if let Some((name, _, duration, _)) = self
+            .rows
+            .iter()
+            .filter(|(_, _, _, status)| matches!(status, Status::Done))
+            .max_by_key(|(_, _, duration, _)| *duration)
+        {
+            println!("slowest: {} ({:.2}s)", name, duration.as_secs_f64());
+        }
+    }
+}
+
+/// Size of an image in megapixels, from its header, as an estimate of its render time
+fn megapixels(path: &Path) -> f64 {
+    image::image_dimensions(path)
+        .map(|(width, height)| width as f64 * height as f64 / 1_000_000.0)
+        .unwrap_or(0.0)
+}
+
+/// The image files of a directory, sorted by name so runs are repeatable
+pub fn list_images(dir: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>), Error> {
+    let entries = fs::read_dir(dir)
+        .map_err(|err| Error::Input(format!("could not read {}: {}", dir.display(), err)))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            !path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        })
+        .collect();
+    files.sort();
+    Ok(files
+        .into_iter()
+        .partition(|path| ImageFormat::from_path(path).is_ok()))
+}
+
+/// Process every image of `input_dir` into `output_dir`, keeping the file names.
+/// Completed files are recorded in a manifest as they finish, so a run that was
+/// interrupted picks up where it stopped. The smallest images go first so results
+/// arrive early and the cost of a run shows in the table as it goes, except for
+/// `--sequence` frames
+pub fn run(
+    input_dir: &str,
+    output_dir: &str,
+    palette: &[PaletteEntry],
+    args: &Args,
+) -> Result<Vec<PathBuf>, Error> {
+    let output_dir = Path::new(output_dir);
+    fs::create_dir_all(output_dir).map_err(|err| {
+        Error::Encode(format!(
+            "could not create {}: {}",
+            output_dir.display(),
+            err
+        ))
+    })?;
+    let (images, skipped) = list_images(Path::new(input_dir))?;
+    for path in &skipped {
+        warn(
+            args.strict,
+            Error::PartialBatch(format!("skipping {}, not an image", path.display())),
+        )?;
+    }
+    let mut images: Vec<(PathBuf, f64)> = images
+        .into_iter()
+        .map(|path| {
+            let megapixels = megapixels(&path);
+            (path, megapixels)
+        })
+        .collect();
+    // stable, so images of the same size stay in name order. Frames stay in name order
+    // so each can reuse the quantization of the one before
+    if !args.sequence {
+        images.sort_by(|a, b| a.1.total_cmp(&b.1));
+    }
+
+    // anything that changes the result of a render invalidates the previous progress
+    let palette_hex: Vec<String> = palette.iter().map(|entry| to_hex(entry.color)).collect();
+    let script = args
+        .script
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok());
+    let settings = format!(
+        "{:?} {:?} {:?} {:?}",
+        render_options(args),
+        palette_hex,
+        palette,
+        script
+    );
+    let settings_hash = blake3::hash(settings.as_bytes()).to_hex().to_string();
+    let manifest_path = output_dir.join(MANIFEST);
+    let completed = if args.no_resume {
+        HashMap::new()
+    } else {
+        read_manifest(&manifest_path)
+    };
+    let mut manifest = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&manifest_path)
+        .map_err(|err| {
+            Error::Encode(format!(
+                "could not open {}: {}",
+                manifest_path.display(),
+                err
+            ))
+        })?;
+
+    let mut report = Report::new(images.len());
+    let mut outputs = Vec::new();
+    // first output rendered for every distinct input, for --dedupe
+    let mut rendered: HashMap<String, PathBuf> = HashMap::new();
+    for (path, megapixels) in &images {
+        // the palette changed under --watch, the rest is rendered with the new one
+        if watch::superseded() {
+            break;
+        }
+        let started = Instant::now();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let output = output_dir.join(&name);
+        let input_hash = match file_hash(path) {
+            Ok(hash) => hash,
+            Err(err) => {
+                let err = format!("could not read {}: {}", path.display(), err);
+                report.add(name, *megapixels, started.elapsed(), Status::Failed(err));
+                if args.fail_fast {
+                    break;
+                }
+                continue;
+            }
+        };
+        if let Some(entry) = completed.get(&name) {
+            let intact = file_hash(&output).is_ok_and(|hash| hash == entry.output_hash);
+            if intact && entry.input_hash == input_hash && entry.settings_hash == settings_hash {
+                report.add(name, *megapixels, started.elapsed(), Status::Resumed);
+                rendered.entry(input_hash).or_insert(output.clone());
+                outputs.push(output);
+                continue;
+            }
+        }
+        let original = rendered.get(&input_hash).cloned();
+
+        // render next to the final file and rename it into place, so an interrupted
+        // render never leaves something that looks finished
+        let partial = output_dir.join(format!(".partial-{}", name));
+        let result = match (args.dedupe, &original) {
+            (Some(policy), Some(original)) => {
+                link_duplicate(policy, original, &output).map_err(|err| {
+                    Error::Encode(format!("could not write {}: {}", output.display(), err))
+                })
+            }
+            _ => crate::process(
+                &path.to_string_lossy(),
+                &partial.to_string_lossy(),
+                palette,
+                args,
+            )
+            .and_then(|_| {
+                fs::rename(&partial, &output).map_err(|err| {
+                    Error::Encode(format!("could not write {}: {}", output.display(), err))
+                })
+            }),
+        };
+        match result {
+            Ok(()) => {
+                let status = match (args.dedupe, &original) {
+                    (Some(_), Some(original)) => Status::Duplicate(
+                        original.file_name().unwrap().to_string_lossy().to_string(),
+                    ),
+                    _ => Status::Done,
+                };
+                rendered.entry(input_hash.clone()).or_insert(output.clone());
+                let output_hash = file_hash(&output).unwrap_or_default();
+                writeln!(
+                    manifest,
+                    "{}\t{}\t{}\t{}",
+                    name, input_hash, settings_hash, output_hash
+                )
+                .and_then(|_| manifest.flush())
+                .map_err(|err| {
+                    Error::Encode(format!(
+                        "could not update {}: {}",
+                        manifest_path.display(),
+                        err
+                    ))
+                })?;
+                report.add(name, *megapixels, started.elapsed(), status);
+                outputs.push(output);
+            }
+            Err(Error::Superseded) => {
+                let _ = fs::remove_file(&partial);
+                break;
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&partial);
+                report.add(
+                    name,
+                    *megapixels,
+                    started.elapsed(),
+                    Status::Failed(err.to_string()),
+                );
+                if args.fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+
+    report.summary();
+    watch::check()?;
+    let failures = report.failures();
+    if failures.is_empty() {
+        Ok(outputs)
+    } else {
+        Err(Error::PartialBatch(format!(
+            "{} of {} files failed: {}",
+            failures.len(),
+            images.len(),
+            failures.join(", ")
+        )))
+    }
+}
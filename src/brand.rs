@@ -0,0 +1,174 @@
+//! The `brand` subcommand: small assets for chat and streaming profiles in the colors of
+//! the palette, such as a Discord profile banner, a Slack workspace icon, a stream
+//! overlay background and an OBS stinger frame. Each asset is cropped from the colorized
+//! wallpaper given with `--wallpaper`, or is a gradient of palette colors.
+//!
+//! The assets come from a template, the built-in [`DEFAULT_TEMPLATE`] unless
+//! `--template` gives another one:
+//!
+//! ```toml
+//! [[assets]]
+//! name = "twitch-offline"
+//! size = "1920x1080"
+//! source = "wallpaper"
+//!
+//! [[assets]]
+//! name = "badge"
+//! size = "112x112"
+//! source = "gradient"
+//! colors = ["@accent", "@background"]
+//! direction = "diagonal"
+//! ```
+//!
+//! `source` is `wallpaper` or `gradient`, by default the wallpaper when there is one.
+//! Gradients run through `colors`, hex codes or palette roles, `vertical` (the default),
+//! `horizontal` or `diagonal`. Every asset is saved as `<name>.png`.
+
+use crate::color::blend;
+use crate::error::Error;
+use crate::layout::{self, Fit};
+use crate::palette::resolve_color;
+use image::{Rgb, RgbImage};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+pub const DEFAULT_TEMPLATE: &str = r#"
+[[assets]]
+name = "discord-banner"
+size = "680x240"
+
+[[assets]]
+name = "slack-workspace-icon"
+size = "512x512"
+source = "gradient"
+colors = ["@accent", "@background"]
+direction = "diagonal"
+
+[[assets]]
+name = "stream-overlay"
+size = "1920x1080"
+
+[[assets]]
+name = "obs-stinger"
+size = "1920x1080"
+source = "gradient"
+colors = ["@background", "@accent", "@background"]
+direction = "horizontal"
+"#;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Template {
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+enum Source {
+    Wallpaper,
+    Gradient,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+enum Direction {
+    #[default]
+    Vertical,
+    Horizontal,
+    Diagonal,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Asset {
+    name: String,
+    size: String,
+    source: Option<Source>,
+    #[serde(default = "default_colors")]
+    colors: Vec<String>,
+    #[serde(default)]
+    direction: Direction,
+}
+
+fn default_colors() -> Vec<String> {
+    vec!["@background".to_string(), "@accent".to_string()]
+}
+
+/// A gradient running evenly through `stops`
+fn gradient(width: u32, height: u32, stops: &[Rgb<u8>], direction: Direction) -> RgbImage {
+    let segments = (stops.len() - 1).max(1) as f32;
+    RgbImage::from_fn(width, height, |x, y| {
+        let x = x as f32 / (width - 1).max(1) as f32;
+        let y = y as f32 / (height - 1).max(1) as f32;
+        let position = match direction {
+            Direction::Vertical => y,
+            Direction::Horizontal => x,
+            Direction::Diagonal => (x + y) / 2.0,
+        } * segments;
+        let index = (position as usize).min(stops.len() - 1);
+        let next = (index + 1).min(stops.len() - 1);
+        blend(stops[index], stops[next], position - index as f32)
+    })
+}
+
+/// Render every asset of the template into `output_dir`
+pub fn run(
+    output_dir: &str,
+    wallpaper: Option<&str>,
+    template: Option<&str>,
+    palette: &[Rgb<u8>],
+) -> Result<(), Error> {
+    let (contents, name) = match template {
+        Some(path) => (
+            fs::read_to_string(path)
+                .map_err(|err| Error::Input(format!("could not read {}: {}", path, err)))?,
+            path,
+        ),
+        None => (DEFAULT_TEMPLATE.to_string(), "the built-in template"),
+    };
+    let template: Template =
+        toml::from_str(&contents).map_err(|err| Error::Input(format!("{}: {}", name, err)))?;
+    let wallpaper = wallpaper.map(crate::open_image).transpose()?;
+    let output_dir = Path::new(output_dir);
+    fs::create_dir_all(output_dir).map_err(|err| {
+        Error::Encode(format!(
+            "could not create {}: {}",
+            output_dir.display(),
+            err
+        ))
+    })?;
+    for asset in &template.assets {
+        let invalid = |err: String| Error::Input(format!("{}: {}: {}", name, asset.name, err));
+        let (width, height) = layout::parse_size(&asset.size).map_err(invalid)?;
+        let source = asset.source.unwrap_or(match wallpaper {
+            Some(_) => Source::Wallpaper,
+            None => Source::Gradient,
+        });
+        let img = match (source, &wallpaper) {
+            (Source::Wallpaper, Some(wallpaper)) => {
+                layout::fit(wallpaper, width, height, Fit::Cover, None)
+            }
+            (Source::Wallpaper, None) => {
+                return Err(invalid("needs a --wallpaper".to_string()));
+            }
+            (Source::Gradient, _) => {
+                if asset.colors.is_empty() {
+                    return Err(invalid("a gradient needs colors".to_string()));
+                }
+                let stops: Vec<Rgb<u8>> = asset
+                    .colors
+                    .iter()
+                    .map(|spec| resolve_color(spec, palette))
+                    .collect::<Result<_, _>>()
+                    .map_err(invalid)?;
+                gradient(width, height, &stops, asset.direction)
+            }
+        };
+        let path = output_dir.join(format!("{}.png", asset.name));
+        img.save(&path)
+            .map_err(|err| Error::Encode(format!("could not write {}: {}", path.display(), err)))?;
+        println!("{}: {}x{}", path.display(), width, height);
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+//! On disk cache of intermediate results, in `~/.cache/imagecolorizer/`.
+//!
+//! Quantization is by far the slowest stage and only depends on the (adjusted) input
+//! image, the number of colors and dithering, not on the palette colors themselves. With
+//! `--cache` its result is kept, so switching between palettes of the same size or
+//! changing the mapping options only re-runs the mapping.
+//!
+//! Entries are trimmed with `cache gc`, and automatically to [`DEFAULT_MAX_SIZE`] and
+//! [`DEFAULT_MAX_AGE`] whenever `--cache` is used. Using an entry counts as a
+//! modification, so the least recently used entries go first.
+
+use crate::error::Error;
+use crate::hash::pixel_hash;
+use crate::history;
+use crate::render::{self, Weighting};
+use homedir::my_home;
+use image::RgbImage;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_MAX_SIZE: &str = "500M";
+pub const DEFAULT_MAX_AGE: &str = "30d";
+
+pub fn cache_dir() -> Option<PathBuf> {
+    let mut dir = my_home().ok().flatten()?;
+    dir.push(".cache/imagecolorizer");
+    Some(dir)
+}
+
+/// [`render::quantize`], reusing the result of an earlier run with the same image and
+/// settings when there is one
+pub fn quantize(
+    input_img: &RgbImage,
+    colors: usize,
+    dither: bool,
+    weighting: Weighting,
+    deterministic: bool,
+) -> RgbImage {
+    let Some(dir) = cache_dir().map(|dir| dir.join("quantized")) else {
+        return render::quantize(input_img, colors, dither, weighting, deterministic);
+    };
+    // bump the version whenever the quantization itself changes
+    let mut key = format!("v1 {} {} {}", pixel_hash(input_img), colors, dither);
+    if weighting != Weighting::Uniform {
+        key.push_str(&format!(" {:?}", weighting));
+    }
+    if deterministic {
+        key.push_str(" deterministic");
+    }
+    let path = dir.join(format!("{}.png", blake3::hash(key.as_bytes()).to_hex()));
+    if let Ok(cached) = image::open(&path) {
+        let _ = fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        return cached.into_rgb8();
+    }
+
+    let quantized = render::quantize(input_img, colors, dither, weighting, deterministic);
+    // a cache that can't be written is not worth failing the render over
+    let partial = path.with_extension("partial.png");
+    if fs::create_dir_all(&dir).is_ok() && quantized.save(&partial).is_ok() {
+        let _ = fs::rename(&partial, &path);
+    }
+    quantized
+}
+
+/// Parse a size such as `500M`, with an optional K, M or G suffix (powers of 1024)
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let (number, unit) = input.split_at(input.trim_end_matches(char::is_alphabetic).len());
+    let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("'{}' is not a size like 500M", input)),
+    };
+    let number = number
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("'{}' is not a size like 500M", input))?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("'{}' is too large a size", input))
+}
+
+/// Parse an age such as `30d`, in seconds (s), minutes (m), hours (h), days (d) or
+/// weeks (w)
+pub fn parse_age(input: &str) -> Result<Duration, String> {
+    let (number, unit) = input.split_at(input.trim_end_matches(char::is_alphabetic).len());
+    let seconds: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("'{}' is not an age like 30d", input)),
+    };
+    let number = number
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("'{}' is not an age like 30d", input))?;
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("'{}' is too long an age", input))
+}
+
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// Every file of the cache, in the kind of cache (subdirectory) it belongs to
+fn entries(dir: &Path) -> Vec<(String, Entry)> {
+    let Ok(kinds) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut entries = Vec::new();
+    for kind in kinds.filter_map(|kind| kind.ok()) {
+        let Ok(files) = fs::read_dir(kind.path()) else {
+            continue;
+        };
+        let name = kind.file_name().to_string_lossy().to_string();
+        for file in files.filter_map(|file| file.ok()) {
+            let Ok(metadata) = file.metadata() else {
+                continue;
+            };
+            if metadata.is_file() {
+                entries.push((
+                    name.clone(),
+                    Entry {
+                        path: file.path(),
+                        size: metadata.len(),
+                        modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    },
+                ));
+            }
+        }
+    }
+    entries
+}
+
+/// Remove entries older than `max_age`, then the least recently used ones until the
+/// cache fits in `max_size` bytes. Returns the number of files and bytes removed
+pub fn gc(max_size: u64, max_age: Duration) -> Result<(usize, u64), Error> {
+    let Some(dir) = cache_dir() else {
+        return Ok((0, 0));
+    };
+    // the history keeps its own number of renders
+    let mut entries: Vec<Entry> = entries(&dir)
+        .into_iter()
+        .filter(|(kind, _)| kind != history::DIR)
+        .map(|(_, entry)| entry)
+        .collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.modified));
+    let now = SystemTime::now();
+    let (mut kept, mut full) = (0, false);
+    let (mut removed, mut freed) = (0, 0);
+    for entry in entries {
+        let age = now.duration_since(entry.modified).unwrap_or_default();
+        // after the first entry that doesn't fit, everything less recently used goes too
+        full = full || kept + entry.size > max_size;
+        if age <= max_age && !full {
+            kept += entry.size;
+            continue;
+        }
+        fs::remove_file(&entry.path).map_err(|err| {
+            Error::Encode(format!(
+                "could not remove {}: {}",
+                entry.path.display(),
+                err
+            ))
+        })?;
+        removed += 1;
+        freed += entry.size;
+    }
+    Ok((removed, freed))
+}
+
+/// Human readable size, e.g. `12.3M`
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{}B", bytes),
+        1024..=1_048_575 => format!("{:.1}K", bytes as f64 / 1024.0),
+        1_048_576..=1_073_741_823 => format!("{:.1}M", bytes as f64 / 1_048_576.0),
+        _ => format!("{:.1}G", bytes as f64 / 1_073_741_824.0),
+    }
+}
+
+pub fn print_stats() {
+    let Some(dir) = cache_dir() else {
+        println!("no cache directory");
+        return;
+    };
+    println!("cache directory: {}", dir.display());
+    let mut kinds: Vec<(String, usize, u64, SystemTime)> = Vec::new();
+    for (kind, entry) in entries(&dir) {
+        match kinds.iter_mut().find(|(name, ..)| *name == kind) {
+            Some((_, files, size, oldest)) => {
+                *files += 1;
+                *size += entry.size;
+                *oldest = (*oldest).min(entry.modified);
+            }
+            None => kinds.push((kind, 1, entry.size, entry.modified)),
+        }
+    }
+    kinds.sort_by(|a, b| a.0.cmp(&b.0));
+    let now = SystemTime::now();
+    let days = |time: SystemTime| now.duration_since(time).unwrap_or_default().as_secs() / 86400;
+    for (kind, files, size, oldest) in &kinds {
+        println!(
+            "{:<12} {:>6} files {:>8}  oldest used {} days ago",
+            kind,
+            files,
+            format_size(*size),
+            days(*oldest)
+        );
+    }
+    let total: u64 = kinds.iter().map(|(_, _, size, _)| size).sum();
+    println!(
+        "{:<12} {:>6} files {:>8}",
+        "total",
+        kinds.iter().map(|(_, files, ..)| files).sum::<usize>(),
+        format_size(total)
+    );
+}
@@ -0,0 +1,119 @@
+use image::Rgb;
+
+/// Relative luminance of a color (Rec. 709 weights on the gamma encoded values), from 0.0 to 1.0
+pub fn luminance(color: Rgb<u8>) -> f32 {
+    let [r, g, b] = color.0.map(|channel| channel as f32 / 255.0);
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Rough chroma of a color: the spread between its strongest and weakest channel
+pub fn chroma(color: Rgb<u8>) -> u8 {
+    color.0.iter().max().unwrap() - color.0.iter().min().unwrap()
+}
+
+pub fn color_difference(color1: Rgb<u8>, color2: Rgb<u8>) -> u32 {
+    color1
+        .0 // these .0 just extract the [u8] from the Rgb datastructure
+        .iter()
+        .zip(color2.0.iter())
+        // find the difference in all 3 colors and sum them
+        .fold(0, |acc, colors: (&u8, &u8)| {
+            acc + (colors.0.max(colors.1) - colors.0.min(colors.1)) as u32
+        })
+}
+
+/// Mix two colors, `amount` 0.0 is `color1` and 1.0 is `color2`
+pub fn blend(color1: Rgb<u8>, color2: Rgb<u8>, amount: f32) -> Rgb<u8> {
+    Rgb(std::array::from_fn(|x| {
+        (color1.0[x] as f32 * (1.0 - amount) + color2.0[x] as f32 * amount).round() as u8
+    }))
+}
+
+pub fn average_color(pixels: Vec<Rgb<u8>>) -> Rgb<u8> {
+    let avg = pixels
+        .iter()
+        .map(|pixel| pixel.0) // at this point we have an array of rgb values
+        .fold([0, 0, 0], |mut acc, pixels| {
+            for x in 0..=2 {
+                acc[x] += pixels[x] as usize; // now we calculate the sum for r,g,b
+            }
+            acc
+        });
+
+    let red = (avg[0] / pixels.len()).clamp(0, 255) as u8; // now we calculate the average
+    let green = (avg[1] / pixels.len()).clamp(0, 255) as u8;
+    let blue = (avg[2] / pixels.len()).clamp(0, 255) as u8;
+    Rgb([red, green, blue])
+}
+
+/// WCAG relative luminance, computed on linear light
+pub fn relative_luminance(color: Rgb<u8>) -> f32 {
+    let [r, g, b] = color.0.map(to_linear);
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// WCAG contrast ratio between two colors, from 1.0 to 21.0
+pub fn contrast_ratio(color1: Rgb<u8>, color2: Rgb<u8>) -> f32 {
+    let (lum1, lum2) = (relative_luminance(color1), relative_luminance(color2));
+    (lum1.max(lum2) + 0.05) / (lum1.min(lum2) + 0.05)
+}
+
+pub fn to_linear(channel: u8) -> f32 {
+    let channel = channel as f32 / 255.0;
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn from_linear(channel: f32) -> u8 {
+    let channel = channel.clamp(0.0, 1.0);
+    let encoded = if channel <= 0.003_130_8 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+/// Convert a color to Oklab `[L, a, b]`
+pub fn to_oklab(color: Rgb<u8>) -> [f32; 3] {
+    let [r, g, b] = color.0.map(to_linear);
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+/// Convert Oklab `[L, a, b]` to a color, clipping what is out of the sRGB gamut
+pub fn from_oklab([lightness, a, b]: [f32; 3]) -> Rgb<u8> {
+    let l = (lightness + 0.396_337_78 * a + 0.215_803_76 * b).powi(3);
+    let m = (lightness - 0.105_561_346 * a - 0.063_854_17 * b).powi(3);
+    let s = (lightness - 0.089_484_18 * a - 1.291_485_5 * b).powi(3);
+    Rgb([
+        from_linear(4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s),
+        from_linear(-1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s),
+        from_linear(-0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s),
+    ])
+}
+
+/// Convert a color to OkLCh `[L, C, h]` with the hue in degrees
+pub fn to_oklch(color: Rgb<u8>) -> [f32; 3] {
+    let [l, a, b] = to_oklab(color);
+    [l, a.hypot(b), b.atan2(a).to_degrees().rem_euclid(360.0)]
+}
+
+/// Perceptual distance between two colors: the euclidean distance in Oklab, scaled so
+/// that a difference of around 2 is just noticeable
+pub fn delta_e(color1: Rgb<u8>, color2: Rgb<u8>) -> f32 {
+    let (lab1, lab2) = (to_oklab(color1), to_oklab(color2));
+    let distance =
+        ((lab1[0] - lab2[0]).powi(2) + (lab1[1] - lab2[1]).powi(2) + (lab1[2] - lab2[2]).powi(2))
+            .sqrt();
+    distance * 100.0
+}
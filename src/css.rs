@@ -0,0 +1,253 @@
+//! `--css`: a palette from the colors of a style sheet, so assets can match the theme of
+//! a site. The colors are taken in the order they appear, each once:
+//!
+//! - custom properties that are colors, as themes define them:
+//!
+//!   ```css
+//!   :root {
+//!     --color-bg: #1a1b26;
+//!     --color-accent: rgb(122 162 247);
+//!     --color-text: white;
+//!   }
+//!   ```
+//!
+//! - hex colors and `rgb()`/`hsl()` colors in any other declaration, like
+//!   `border: 1px solid #414868`. The 16 basic named colors are only taken from custom
+//!   properties, elsewhere words like `black` are too often just defaults
+//!
+//! The style sheet is tokenized rather than searched, so ids in selectors (`#main`),
+//! comments and strings don't end up in the palette. Broken rules are skipped like a
+//! browser skips them. Alpha channels are ignored.
+
+use crate::error::Error;
+use crate::palette::{self, parse_hex};
+use crate::svg::named_color;
+use image::Rgb;
+use std::fs;
+
+/// A part of a declaration value
+enum Token<'a> {
+    Hash(&'a str),
+    Ident(&'a str),
+    Function(&'a str, &'a str),
+    Other,
+}
+
+/// The style sheet without comments, strings kept as they are
+fn strip_comments(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("/*") {
+            rest = rest[2..].split_once("*/").map_or("", |(_, rest)| rest);
+            stripped.push(' ');
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            let end = string_end(rest);
+            stripped.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        stripped.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    stripped
+}
+
+/// Length of the string at the start of `source`, quotes and escapes included
+fn string_end(source: &str) -> usize {
+    let quote = source.chars().next().unwrap();
+    let mut escaped = false;
+    for (index, c) in source.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\n' => return index,
+            _ if c == quote => return index + 1,
+            _ => {}
+        }
+    }
+    source.len()
+}
+
+/// The text up to the first of `stops` outside of strings, parentheses and brackets
+fn until<'a>(source: &'a str, stops: &[char]) -> (&'a str, &'a str) {
+    let mut depth = 0;
+    let mut index = 0;
+    while let Some(c) = source[index..].chars().next() {
+        match c {
+            '"' | '\'' => {
+                index += string_end(&source[index..]);
+                continue;
+            }
+            '(' | '[' => depth += 1,
+            ')' | ']' if depth > 0 => depth -= 1,
+            _ if depth == 0 && stops.contains(&c) => break,
+            _ => {}
+        }
+        index += c.len_utf8();
+    }
+    source.split_at(index)
+}
+
+/// Every declaration of the style sheet, in nested rules and at-rules as well, as name
+/// and value
+fn declarations(source: &str) -> Vec<(&str, &str)> {
+    let mut found = Vec::new();
+    let mut rest = source;
+    while !rest.is_empty() {
+        let (item, after) = until(rest, &[';', '{', '}']);
+        rest = after;
+        match after.chars().next() {
+            // a selector or at-rule prelude, its block is read on like the rest
+            Some('{') => {}
+            // a declaration, or junk that is dropped
+            _ => {
+                let declaration = item.split_once(':').filter(|(name, _)| {
+                    let name = name.trim();
+                    !name.is_empty()
+                        && !name.starts_with('@')
+                        && !name.contains(char::is_whitespace)
+                });
+                if let Some((name, value)) = declaration {
+                    let value = value.trim();
+                    let value = value.strip_suffix("!important").unwrap_or(value);
+                    found.push((name.trim(), value.trim()));
+                }
+            }
+        }
+        if let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    found
+}
+
+fn tokens(value: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = value;
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    while let Some(c) = rest.chars().next() {
+        if c == '#' {
+            let length = rest[1..].find(|c| !is_name(c)).unwrap_or(rest.len() - 1);
+            tokens.push(Token::Hash(&rest[1..1 + length]));
+            rest = &rest[1 + length..];
+        } else if c.is_ascii_alphabetic() || c == '-' {
+            let length = rest.find(|c| !is_name(c)).unwrap_or(rest.len());
+            let (name, after) = rest.split_at(length);
+            if let Some(arguments) = after.strip_prefix('(') {
+                let (arguments, after) = until(arguments, &[')']);
+                tokens.push(Token::Function(name, arguments));
+                rest = after.get(1..).unwrap_or_default();
+            } else {
+                tokens.push(Token::Ident(name));
+                rest = after;
+            }
+        } else if c == '"' || c == '\'' {
+            tokens.push(Token::Other);
+            rest = &rest[string_end(rest)..];
+        } else if c == '(' {
+            let (_, after) = until(&rest[1..], &[')']);
+            tokens.push(Token::Other);
+            rest = after.get(1..).unwrap_or_default();
+        } else {
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    tokens
+}
+
+/// `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`
+fn hex_color(hex: &str) -> Option<Rgb<u8>> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    match hex.len() {
+        3 | 4 => parse_hex(&hex[..3].chars().flat_map(|c| [c, c]).collect::<String>()).ok(),
+        6 | 8 => parse_hex(&hex[..6]).ok(),
+        _ => None,
+    }
+}
+
+/// A number, or a percentage of `full`
+fn number(argument: &str, full: f32) -> Option<f32> {
+    match argument.strip_suffix('%') {
+        Some(percent) => Some(percent.parse::<f32>().ok()? / 100.0 * full),
+        None => argument.parse().ok(),
+    }
+}
+
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> Rgb<u8> {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let channel = |n: f32| {
+        let k = (n + hue / 30.0) % 12.0;
+        let value = lightness - chroma / 2.0 * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+        (value * 255.0).round().clamp(0.0, 255.0) as u8
+    };
+    Rgb([channel(0.0), channel(8.0), channel(4.0)])
+}
+
+/// `rgb()`, `rgba()`, `hsl()` and `hsla()`, with commas or spaces
+fn function_color(name: &str, arguments: &str) -> Option<Rgb<u8>> {
+    let arguments: Vec<&str> = arguments
+        .split(['/', ','])
+        .flat_map(str::split_whitespace)
+        .collect();
+    let [first, second, third, ..] = arguments[..] else {
+        return None;
+    };
+    match name.to_ascii_lowercase().as_str() {
+        "rgb" | "rgba" => {
+            let channel = |argument| {
+                number(argument, 255.0).map(|value: f32| value.round().clamp(0.0, 255.0) as u8)
+            };
+            Some(Rgb([channel(first)?, channel(second)?, channel(third)?]))
+        }
+        "hsl" | "hsla" => {
+            let hue = first
+                .strip_suffix("deg")
+                .unwrap_or(first)
+                .parse::<f32>()
+                .ok()?;
+            Some(hsl_to_rgb(
+                hue.rem_euclid(360.0),
+                (number(second, 1.0)? / if second.ends_with('%') { 1.0 } else { 100.0 })
+                    .clamp(0.0, 1.0),
+                (number(third, 1.0)? / if third.ends_with('%') { 1.0 } else { 100.0 })
+                    .clamp(0.0, 1.0),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// The colors of a style sheet
+fn decode(source: &str) -> Vec<Rgb<u8>> {
+    let source = strip_comments(source);
+    let mut colors = Vec::new();
+    for (name, value) in declarations(&source) {
+        let custom = name.starts_with("--");
+        for token in tokens(value) {
+            let color = match token {
+                Token::Hash(hex) => hex_color(hex),
+                Token::Function(name, arguments) => function_color(name, arguments),
+                Token::Ident(word) if custom => named_color(&word.to_ascii_lowercase()),
+                _ => None,
+            };
+            colors.extend(color);
+        }
+    }
+    palette::dedup(colors)
+}
+
+/// The palette of the style sheet at `path`
+pub fn load(path: &str) -> Result<Vec<Rgb<u8>>, Error> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| Error::Palette(format!("could not read {}: {}", path, err)))?;
+    let colors = decode(&source);
+    if colors.is_empty() {
+        return Err(Error::Palette(format!("{} has no colors", path)));
+    }
+    Ok(colors)
+}
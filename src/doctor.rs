@@ -0,0 +1,236 @@
+//! `doctor`: check the parts of the system imagecolorizer talks to, the display
+//! session, xrdb, pywal's cache, the wallpaper setter and the terminal, and print what
+//! works and how to fix what doesn't. Its output is also what to paste into a bug report.
+//!
+//! Warnings are things that are missing, which only matters for the options that need
+//! them. Failures are things that are there but broken. Only failures make `doctor`
+//! exit with 1.
+
+use crate::exec;
+use std::env;
+use std::path::PathBuf;
+
+/// Programs that set the wallpaper, looked for when no `--set` is given
+const SETTERS: [&str; 8] = [
+    "feh",
+    "xwallpaper",
+    "nitrogen",
+    "swaybg",
+    "swww",
+    "hyprctl",
+    "plasma-apply-wallpaperimage",
+    "gsettings",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    status: Status,
+    name: &'static str,
+    detail: String,
+    /// What to do about a warning or failure
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: String) -> Check {
+        Check {
+            status: Status::Pass,
+            name,
+            detail,
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: String, hint: &str) -> Check {
+        Check {
+            status: Status::Warn,
+            hint: Some(hint.to_string()),
+            ..Check::pass(name, detail)
+        }
+    }
+
+    fn fail(name: &'static str, detail: String, hint: &str) -> Check {
+        Check {
+            status: Status::Fail,
+            ..Check::warn(name, detail, hint)
+        }
+    }
+}
+
+/// Where `program` would be run from, without running it
+fn find_program(program: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+fn session() -> Check {
+    let wayland = env::var("WAYLAND_DISPLAY").ok();
+    let x11 = env::var("DISPLAY").ok();
+    match (wayland, x11) {
+        (Some(wayland), Some(x11)) => Check::pass(
+            "session",
+            format!("Wayland ({}) with Xwayland ({})", wayland, x11),
+        ),
+        (Some(wayland), None) => Check::pass("session", format!("Wayland ({})", wayland)),
+        (None, Some(x11)) => Check::pass("session", format!("X11 ({})", x11)),
+        (None, None) => Check::warn(
+            "session",
+            "neither WAYLAND_DISPLAY nor DISPLAY is set".to_string(),
+            "--xresources, --match-window and --scale auto need a desktop session, run from a \
+             terminal inside it",
+        ),
+    }
+}
+
+fn xrdb() -> Check {
+    let Some(path) = find_program("xrdb") else {
+        return Check::warn(
+            "xrdb",
+            "not installed".to_string(),
+            "--xresources needs xrdb (x11-xserver-utils or xorg-xrdb), without X use \
+             --xresources-file ~/.Xresources",
+        );
+    };
+    if exec::forbidden() {
+        return Check::pass(
+            "xrdb",
+            format!("{}, not queried with --no-exec", path.display()),
+        );
+    }
+    match crate::xresources_load() {
+        Ok(colors) if colors.is_empty() => Check::warn(
+            "xrdb",
+            "the resource database has no colors".to_string(),
+            "load your colors with xrdb -merge ~/.Xresources",
+        ),
+        Ok(colors) => Check::pass("xrdb", format!("{} colors", colors.len())),
+        Err(err) => Check::fail(
+            "xrdb",
+            err.to_string(),
+            "xrdb needs a running X server or Xwayland, check that DISPLAY is right",
+        ),
+    }
+}
+
+fn wal() -> Check {
+    let cache = homedir::my_home()
+        .ok()
+        .flatten()
+        .map(|home| home.join(".cache/wal"));
+    let Some(cache) = cache.filter(|cache| cache.is_dir()) else {
+        return Check::warn(
+            "pywal",
+            "there is no ~/.cache/wal".to_string(),
+            "--wal needs pywal to have run once: wal -i WALLPAPER",
+        );
+    };
+    match crate::pywal_load() {
+        Ok(colors) => Check::pass(
+            "pywal",
+            format!("{} colors in {}", colors.len(), cache.display()),
+        ),
+        Err(err) => Check::fail(
+            "pywal",
+            err.to_string(),
+            "run wal again to regenerate the cache, or point --wal-file at a good copy",
+        ),
+    }
+}
+
+fn setter(set: Option<&str>) -> Check {
+    match set {
+        Some("portal") => match find_program("gdbus") {
+            Some(_) if env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() => Check::fail(
+                "wallpaper setter",
+                "portal: DBUS_SESSION_BUS_ADDRESS is not set".to_string(),
+                "the portal is reached over the session bus, run inside the desktop session",
+            ),
+            Some(path) => Check::pass("wallpaper setter", format!("portal, {}", path.display())),
+            None => Check::fail(
+                "wallpaper setter",
+                "portal: gdbus is not installed".to_string(),
+                "--set portal calls the portal with gdbus, which comes with GLib (libglib2.0-bin)",
+            ),
+        },
+        Some(command) => {
+            let program = command.split_whitespace().next().unwrap_or_default();
+            match find_program(program) {
+                Some(path) => Check::pass("wallpaper setter", path.display().to_string()),
+                None => Check::fail(
+                    "wallpaper setter",
+                    format!("{} is not installed or not in PATH", program),
+                    "install it, or use --set portal on GNOME, KDE and in Flatpak",
+                ),
+            }
+        }
+        None => {
+            let found: Vec<&str> = SETTERS
+                .into_iter()
+                .filter(|program| find_program(program).is_some())
+                .collect();
+            if found.is_empty() {
+                Check::warn(
+                    "wallpaper setter",
+                    "none of the usual ones is installed".to_string(),
+                    "fetch --set needs one, such as feh on X11 or swaybg on Wayland, or use \
+                     --set portal",
+                )
+            } else {
+                Check::pass("wallpaper setter", found.join(", "))
+            }
+        }
+    }
+}
+
+/// The image protocol of the terminal, by the variables terminals set
+fn terminal_graphics() -> Check {
+    let variable = |name: &str| env::var(name).unwrap_or_default();
+    let term = variable("TERM");
+    let program = variable("TERM_PROGRAM");
+    let kitty = env::var_os("KITTY_WINDOW_ID").is_some()
+        || ["xterm-kitty", "xterm-ghostty"].contains(&term.as_str())
+        || program == "WezTerm";
+    let protocol = if kitty {
+        Some("kitty graphics")
+    } else if program == "iTerm.app" {
+        Some("iTerm2 inline images")
+    } else if term.starts_with("foot") || term.starts_with("mlterm") || term.contains("sixel") {
+        Some("sixel")
+    } else {
+        None
+    };
+    match protocol {
+        Some(protocol) => Check::pass("terminal graphics", format!("{} ({})", protocol, term)),
+        None => Check::warn(
+            "terminal graphics",
+            format!("no image protocol known for TERM={}", term),
+            "images can't be shown in this terminal, use kitty, WezTerm, foot or another \
+             terminal with sixel support to preview them there",
+        ),
+    }
+}
+
+/// Run the checks, with `set` as the wallpaper setter to check. Returns whether any
+/// failed
+pub fn print(set: Option<&str>) -> bool {
+    let checks = [session(), xrdb(), wal(), setter(set), terminal_graphics()];
+    for check in &checks {
+        let status = match check.status {
+            Status::Pass => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        println!("{:<5} {:<18} {}", status, check.name, check.detail);
+        if let Some(hint) = &check.hint {
+            println!("{:<24} {}", "", hint);
+        }
+    }
+    checks.iter().any(|check| check.status == Status::Fail)
+}
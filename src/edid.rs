@@ -0,0 +1,67 @@
+//! Physical monitor sizes from the EDID data the kernel exposes in `/sys/class/drm`, so
+//! bezels given in millimeters can be converted to pixels.
+
+use std::fs;
+
+pub struct Monitor {
+    /// DRM connector name, e.g. `card0-DP-1`
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub width_mm: u32,
+    pub height_mm: u32,
+}
+
+impl Monitor {
+    pub fn pixels_per_mm(&self) -> f32 {
+        self.width as f32 / self.width_mm as f32
+    }
+}
+
+/// Read the preferred resolution and the physical size from an EDID block, both come
+/// from the first detailed timing descriptor
+pub fn parse(name: &str, edid: &[u8]) -> Option<Monitor> {
+    const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+    if edid.len() < 128 || edid[..8] != HEADER {
+        return None;
+    }
+    let timing = &edid[54..72];
+    // a zero pixel clock means this is not a timing descriptor
+    if timing[0] == 0 && timing[1] == 0 {
+        return None;
+    }
+    let width = timing[2] as u32 | ((timing[4] as u32 >> 4) << 8);
+    let height = timing[5] as u32 | ((timing[7] as u32 >> 4) << 8);
+    let width_mm = timing[12] as u32 | ((timing[14] as u32 >> 4) << 8);
+    let height_mm = timing[13] as u32 | ((timing[14] as u32 & 0x0f) << 8);
+    if width == 0 || height == 0 || width_mm == 0 || height_mm == 0 {
+        return None;
+    }
+    Some(Monitor {
+        name: name.to_string(),
+        width,
+        height,
+        width_mm,
+        height_mm,
+    })
+}
+
+/// The connected monitors that report their size, sorted by connector name
+pub fn connected_monitors() -> Vec<Monitor> {
+    let Ok(connectors) = fs::read_dir("/sys/class/drm") else {
+        return Vec::new();
+    };
+    let mut monitors: Vec<Monitor> = connectors
+        .filter_map(|connector| connector.ok())
+        .filter(|connector| {
+            fs::read_to_string(connector.path().join("status"))
+                .is_ok_and(|status| status.trim() == "connected")
+        })
+        .filter_map(|connector| {
+            let edid = fs::read(connector.path().join("edid")).ok()?;
+            parse(&connector.file_name().to_string_lossy(), &edid)
+        })
+        .collect();
+    monitors.sort_by(|a, b| a.name.cmp(&b.name));
+    monitors
+}
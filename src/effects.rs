@@ -0,0 +1,253 @@
+use crate::color::{average_color, blend, contrast_ratio};
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+
+/// Settings for `--frame`, the color is kept as a role string until the palette has been
+/// loaded
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub width: u32,
+    pub color: String,
+}
+
+/// Parse `PX[:COLOR]`, the color defaults to @accent
+pub fn parse_frame(input: &str) -> Result<Frame, String> {
+    let (width, color) = input.split_once(':').unwrap_or((input, "@accent"));
+    let width = width
+        .trim()
+        .parse::<u32>()
+        .map_err(|_| format!("frame width '{}' is not a number of pixels", width))?;
+    Ok(Frame {
+        width,
+        color: color.trim().to_string(),
+    })
+}
+
+/// Average color of each `size` pixel square region of the image
+fn region_averages(img: &RgbImage, size: u32) -> Vec<Rgb<u8>> {
+    let mut regions = Vec::new();
+    for y0 in (0..img.height()).step_by(size as usize) {
+        for x0 in (0..img.width()).step_by(size as usize) {
+            let mut pixels = Vec::new();
+            for y in y0..(y0 + size).min(img.height()) {
+                for x in x0..(x0 + size).min(img.width()) {
+                    pixels.push(*img.get_pixel(x, y));
+                }
+            }
+            regions.push(average_color(pixels));
+        }
+    }
+    regions
+}
+
+/// The lowest level of dimming towards `background` at which every one of `text_colors`
+/// reaches `min_contrast` against every region. Region averages blend the same way the
+/// pixels do, so the check doesn't need to touch the full image
+fn dim_level(
+    regions: &[Rgb<u8>],
+    background: Rgb<u8>,
+    text_colors: &[Rgb<u8>],
+    min_contrast: f32,
+) -> f32 {
+    let mut dim = 0.0;
+    while dim < 1.0 {
+        let passes = regions.iter().all(|region| {
+            let dimmed = blend(*region, background, dim);
+            text_colors
+                .iter()
+                .all(|color| contrast_ratio(*color, dimmed) >= min_contrast)
+        });
+        if passes {
+            break;
+        }
+        dim = (dim + 0.05_f32).min(1.0);
+    }
+    dim
+}
+
+/// Blur the image and dim it towards `background` until every one of `text_colors`
+/// reaches `min_contrast` against every region of the image. Returns the image and the
+/// dim level that was needed
+pub fn terminal_background(
+    img: &RgbImage,
+    background: Rgb<u8>,
+    text_colors: &[Rgb<u8>],
+    min_contrast: f32,
+) -> (RgbImage, f32) {
+    const REGION_SIZE: u32 = 32;
+    let blurred = image::imageops::blur(img, 2.0);
+    let regions = region_averages(&blurred, REGION_SIZE);
+
+    let dim = dim_level(&regions, background, text_colors, min_contrast);
+    let mut output_img = blurred;
+    output_img
+        .par_pixels_mut()
+        .for_each(|pixel| *pixel = blend(*pixel, background, dim));
+    (output_img, dim)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+/// Settings for `--fade`, the color is kept as a role string until the palette has been
+/// loaded
+#[derive(Clone, Debug)]
+pub struct Fade {
+    pub edge: Edge,
+    pub size: u32,
+    pub color: String,
+}
+
+fn parse_edge(edge: &str) -> Result<Edge, String> {
+    match edge.trim() {
+        "top" => Ok(Edge::Top),
+        "bottom" => Ok(Edge::Bottom),
+        "left" => Ok(Edge::Left),
+        "right" => Ok(Edge::Right),
+        other => Err(format!(
+            "unknown edge '{}', expected top, bottom, left or right",
+            other
+        )),
+    }
+}
+
+/// Parse `EDGE:PX[,COLOR]`, the color defaults to @background
+pub fn parse_fade(input: &str) -> Result<Fade, String> {
+    let (edge, rest) = input
+        .split_once(':')
+        .ok_or_else(|| format!("expected EDGE:PX[,COLOR], got '{}'", input))?;
+    let edge = parse_edge(edge)?;
+    let (size, color) = rest.split_once(',').unwrap_or((rest, "@background"));
+    let size = size
+        .trim()
+        .parse::<u32>()
+        .map_err(|_| format!("fade size '{}' is not a number of pixels", size))?;
+    Ok(Fade {
+        edge,
+        size,
+        color: color.trim().to_string(),
+    })
+}
+
+/// Fade one edge of the image into `color` over `size` pixels
+pub fn fade(img: &mut RgbImage, edge: Edge, size: u32, color: Rgb<u8>) {
+    let (width, height) = img.dimensions();
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let distance = match edge {
+            Edge::Top => y,
+            Edge::Bottom => height - 1 - y,
+            Edge::Left => x,
+            Edge::Right => width - 1 - x,
+        };
+        if distance < size {
+            // eased so the image doesn't end in a visible line
+            let amount = 1.0 - distance as f32 / size as f32;
+            *pixel = blend(*pixel, color, amount * amount * (3.0 - 2.0 * amount));
+        }
+    }
+}
+
+/// Settings for `--safe-area`, the color is kept as a role string until the palette has
+/// been loaded
+#[derive(Clone, Debug)]
+pub struct SafeArea {
+    pub edge: Edge,
+    pub size: u32,
+    /// blur away the detail of the strip before dimming it
+    pub flatten: bool,
+    pub color: String,
+}
+
+/// Parse `EDGE:PX[,dim|flat][,COLOR]`, dimming towards @background by default
+pub fn parse_safe_area(input: &str) -> Result<SafeArea, String> {
+    let (edge, rest) = input
+        .split_once(':')
+        .ok_or_else(|| format!("expected EDGE:PX[,dim|flat][,COLOR], got '{}'", input))?;
+    let edge = parse_edge(edge)?;
+    let mut parts = rest.split(',').map(str::trim);
+    let size = parts.next().unwrap_or_default();
+    let size = size
+        .strip_suffix("px")
+        .unwrap_or(size)
+        .parse::<u32>()
+        .map_err(|_| format!("safe area size '{}' is not a number of pixels", size))?;
+    let mut safe_area = SafeArea {
+        edge,
+        size,
+        flatten: false,
+        color: "@background".to_string(),
+    };
+    for part in parts {
+        match part {
+            "dim" => safe_area.flatten = false,
+            "flat" => safe_area.flatten = true,
+            color => safe_area.color = color.to_string(),
+        }
+    }
+    Ok(safe_area)
+}
+
+/// Dim the `size` pixel strip along one edge towards `color`, just enough for `text` to
+/// reach `min_contrast` everywhere on it, and blur it first with `flatten`
+pub fn safe_area(
+    img: &mut RgbImage,
+    area: &SafeArea,
+    size: u32,
+    color: Rgb<u8>,
+    text: Rgb<u8>,
+    min_contrast: f32,
+) {
+    const REGION_SIZE: u32 = 16;
+    let (width, height) = img.dimensions();
+    let (x, y, strip_width, strip_height) = match area.edge {
+        Edge::Top => (0, 0, width, size.min(height)),
+        Edge::Bottom => (0, height - size.min(height), width, size.min(height)),
+        Edge::Left => (0, 0, size.min(width), height),
+        Edge::Right => (width - size.min(width), 0, size.min(width), height),
+    };
+    if strip_width == 0 || strip_height == 0 {
+        return;
+    }
+    let mut strip = image::imageops::crop_imm(img, x, y, strip_width, strip_height).to_image();
+    if area.flatten {
+        strip = image::imageops::blur(&strip, 8.0);
+    }
+    let dim = dim_level(
+        &region_averages(&strip, REGION_SIZE),
+        color,
+        &[text],
+        min_contrast,
+    );
+    strip
+        .par_pixels_mut()
+        .for_each(|pixel| *pixel = blend(*pixel, color, dim));
+    image::imageops::replace(img, &strip, x as i64, y as i64);
+}
+
+/// A vertical gradient from `top` to `bottom`
+pub fn gradient(width: u32, height: u32, top: Rgb<u8>, bottom: Rgb<u8>) -> RgbImage {
+    RgbImage::from_fn(width, height, |_, y| {
+        blend(top, bottom, y as f32 / (height - 1).max(1) as f32)
+    })
+}
+
+/// Draw a `width` pixel border in `color` over the edges of the image, with an inner
+/// shadow fading out over `shadow` pixels
+pub fn frame(img: &mut RgbImage, width: u32, color: Rgb<u8>, shadow: u32) {
+    let (image_width, image_height) = img.dimensions();
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        // distance to the closest edge
+        let edge = x.min(y).min(image_width - 1 - x).min(image_height - 1 - y);
+        if edge < width {
+            *pixel = color;
+        } else if edge - width < shadow {
+            let depth = 1.0 - (edge - width) as f32 / shadow as f32;
+            *pixel = blend(*pixel, Rgb([0, 0, 0]), 0.6 * depth * depth);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+/// Everything that can stop a run. Each kind has its own exit code so scripts can tell
+/// them apart, see `EXIT_CODES`
+#[derive(Debug)]
+pub enum Error {
+    /// the input image could not be read or decoded
+    Input(String),
+    /// the palette could not be loaded, parsed or resolved
+    Palette(String),
+    /// an external program (such as xrdb) is missing or failed
+    ExternalTool(String),
+    /// the output could not be encoded or written
+    Encode(String),
+    /// some of the files of a batch failed or were skipped
+    PartialBatch(String),
+    /// the files `--watch` follows changed during the render, which starts over
+    Superseded,
+}
+
+/// Shown at the end of `--help`
+pub const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  verify found a difference
+  2  invalid command line
+  3  bad input image
+  4  bad palette
+  5  missing or failing external tool
+  6  could not encode or write the output
+  7  some files of a batch failed or were skipped";
+
+impl Error {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Input(_) => 3,
+            Error::Palette(_) => 4,
+            Error::ExternalTool(_) => 5,
+            Error::Encode(_) => 6,
+            Error::PartialBatch(_) => 7,
+            // --watch starts the render over rather than exiting
+            Error::Superseded => 0,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Input(message)
+            | Error::Palette(message)
+            | Error::ExternalTool(message)
+            | Error::Encode(message)
+            | Error::PartialBatch(message) => write!(f, "{}", message),
+            Error::Superseded => write!(f, "the watched files changed during the render"),
+        }
+    }
+}
+
+/// Report a warning, or fail with it when running with `--strict`
+pub fn warn(strict: bool, warning: Error) -> Result<(), Error> {
+    if strict {
+        return Err(warning);
+    }
+    eprintln!("warning: {}", warning);
+    Ok(())
+}
@@ -0,0 +1,28 @@
+//! `--no-exec`: never start external programs, for Flatpak and Firejail sandboxes and
+//! build systems. Every feature that would run one fails with an error naming the
+//! program instead.
+
+use crate::error::Error;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static FORBIDDEN: AtomicBool = AtomicBool::new(false);
+
+pub fn forbid() {
+    FORBIDDEN.store(true, Ordering::Relaxed);
+}
+
+pub fn forbidden() -> bool {
+    FORBIDDEN.load(Ordering::Relaxed)
+}
+
+/// A command running `program` for `feature`, unless programs are forbidden
+pub fn command(program: &str, feature: &str) -> Result<Command, Error> {
+    if forbidden() {
+        return Err(Error::ExternalTool(format!(
+            "{} needs to run {}, which --no-exec forbids",
+            feature, program
+        )));
+    }
+    Ok(Command::new(program))
+}
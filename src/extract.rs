@@ -0,0 +1,110 @@
+//! `--palette-from`: a palette extracted from a reference image, its most common colors
+//! by k-means clustering, most common first.
+//!
+//! Photos and screenshots are noisy, so the image is cleaned up before clustering:
+//!
+//! - it is scaled down to at most [`MAX_SIZE`] pixels across, which averages grain and
+//!   JPEG block noise away
+//! - isolated pixels, that are far from all but one of their neighbours, are left out, so
+//!   specks, dead pixels and ringing around sharp edges don't make clusters of their own
+//! - the image is clustered into twice as many colors as asked for, and clusters that
+//!   cover less than `--min-share` of the image are dropped before the most common ones
+//!   are kept
+
+use crate::color::to_oklab;
+use image::{Rgb, RgbImage};
+use quantette::ImagePipeline;
+
+/// Largest side of the image that is clustered
+const MAX_SIZE: u32 = 256;
+/// Oklab distance (as delta E, times 100) up to which a neighbour counts as the same color
+const NEIGHBOUR_DELTA_E: f32 = 8.0;
+/// Similar neighbours a pixel needs not to count as an outlier
+const MIN_NEIGHBOURS: usize = 2;
+
+/// A share of the image, as a percentage (`1%`) or a fraction (`0.01`)
+pub fn parse_share(input: &str) -> Result<f32, String> {
+    let share = match input.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f32>().map(|percent| percent / 100.0),
+        None => input.parse::<f32>(),
+    }
+    .map_err(|_| format!("'{}' is not a share like 1% or 0.01", input))?;
+    if !(0.0..=1.0).contains(&share) {
+        return Err(format!("'{}' is not between 0% and 100%", input));
+    }
+    Ok(share)
+}
+
+/// The pixels of `img` that have enough similar neighbours
+fn inliers(img: &RgbImage) -> Vec<Rgb<u8>> {
+    let (width, height) = img.dimensions();
+    let lab: Vec<[f32; 3]> = img.pixels().map(|pixel| to_oklab(*pixel)).collect();
+    let distance = |a: [f32; 3], b: [f32; 3]| {
+        ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt() * 100.0
+    };
+    let mut kept = Vec::with_capacity(lab.len());
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let center = lab[(y * width + x) as usize];
+        let mut similar = 0;
+        let mut neighbours = 0;
+        for (dx, dy) in (-1i32..=1).flat_map(|dy| (-1i32..=1).map(move |dx| (dx, dy))) {
+            let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+            if (dx, dy) == (0, 0) || nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                continue;
+            }
+            neighbours += 1;
+            if distance(center, lab[(ny as u32 * width + nx as u32) as usize]) <= NEIGHBOUR_DELTA_E
+            {
+                similar += 1;
+            }
+        }
+        if similar >= MIN_NEIGHBOURS.min(neighbours) {
+            kept.push(*pixel);
+        }
+    }
+    kept
+}
+
+/// Up to `colors` colors of the image that each cover at least `min_share` of it
+pub fn palette(img: &RgbImage, colors: u8, min_share: f32) -> Result<Vec<Rgb<u8>>, String> {
+    let img = if img.width().max(img.height()) > MAX_SIZE {
+        image::imageops::thumbnail(img, MAX_SIZE, MAX_SIZE)
+    } else {
+        img.clone()
+    };
+    let mut pixels = inliers(&img);
+    if pixels.is_empty() {
+        // nothing but noise, cluster all of it rather than nothing
+        pixels = img.pixels().copied().collect();
+    }
+    let total = pixels.len();
+    let kept = RgbImage::from_fn(total as u32, 1, |x, _| pixels[x as usize]);
+    let (clusters, indices) = ImagePipeline::try_from(&kept)
+        .map_err(|err| err.to_string())?
+        .palette_size(colors.saturating_mul(2))
+        .indexed_palette_par();
+    let mut counts = vec![0usize; clusters.len()];
+    for index in &indices {
+        counts[*index as usize] += 1;
+    }
+    let mut shares: Vec<(Rgb<u8>, f32)> = clusters
+        .into_iter()
+        .zip(counts)
+        .map(|(color, count)| {
+            (
+                Rgb([color.red, color.green, color.blue]),
+                count as f32 / total as f32,
+            )
+        })
+        .filter(|(_, share)| *share >= min_share)
+        .collect();
+    shares.sort_by(|a, b| b.1.total_cmp(&a.1));
+    shares.truncate(colors as usize);
+    if shares.is_empty() {
+        return Err(format!(
+            "no color covers {:.1}% of the image, lower --min-share",
+            min_share * 100.0
+        ));
+    }
+    Ok(shares.into_iter().map(|(color, _)| color).collect())
+}
@@ -0,0 +1,147 @@
+//! Download wallpapers from online image providers, for the `fetch` subcommand.
+//!
+//! API keys come from the environment: `WALLHAVEN_API_KEY` (optional, needed for NSFW
+//! results and personal filters) and `UNSPLASH_ACCESS_KEY` (required by Unsplash).
+//! Downloads are kept in `~/.cache/imagecolorizer/fetched/`, where `cache gc` cleans
+//! them up like any other cache entry.
+
+use crate::cache::cache_dir;
+use crate::error::Error;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+/// Largest image that will be downloaded
+const MAX_DOWNLOAD: u64 = 100 << 20;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Provider {
+    /// wallhaven.cc, a random match with at least the requested resolution
+    Wallhaven,
+    /// unsplash.com, a random match cropped to the requested resolution
+    Unsplash,
+}
+
+#[derive(Deserialize)]
+struct WallhavenSearch {
+    data: Vec<WallhavenImage>,
+}
+
+#[derive(Deserialize)]
+struct WallhavenImage {
+    id: String,
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct UnsplashPhoto {
+    id: String,
+    urls: UnsplashUrls,
+    links: UnsplashLinks,
+}
+
+#[derive(Deserialize)]
+struct UnsplashUrls {
+    raw: String,
+}
+
+#[derive(Deserialize)]
+struct UnsplashLinks {
+    download_location: String,
+}
+
+fn request_error(err: ureq::Error) -> Error {
+    Error::ExternalTool(format!("request failed: {}", err))
+}
+
+pub fn get_text(
+    request: ureq::RequestBuilder<ureq::typestate::WithoutBody>,
+) -> Result<String, Error> {
+    request
+        .call()
+        .map_err(request_error)?
+        .body_mut()
+        .read_to_string()
+        .map_err(request_error)
+}
+
+pub fn get_json<T: serde::de::DeserializeOwned>(
+    request: ureq::RequestBuilder<ureq::typestate::WithoutBody>,
+) -> Result<T, Error> {
+    let body = get_text(request)?;
+    serde_json::from_str(&body)
+        .map_err(|err| Error::ExternalTool(format!("unexpected response: {}", err)))
+}
+
+/// Where a download goes, and the URL to get it from
+fn find_image(
+    provider: Provider,
+    query: &str,
+    resolution: Option<(u32, u32)>,
+) -> Result<(String, String), Error> {
+    match provider {
+        Provider::Wallhaven => {
+            let mut request = ureq::get("https://wallhaven.cc/api/v1/search")
+                .query("q", query)
+                .query("sorting", "random");
+            if let Some((width, height)) = resolution {
+                request = request.query("atleast", format!("{}x{}", width, height));
+            }
+            if let Ok(key) = std::env::var("WALLHAVEN_API_KEY") {
+                request = request.header("X-API-Key", key);
+            }
+            let search: WallhavenSearch = get_json(request)?;
+            let image = search.data.into_iter().next().ok_or_else(|| {
+                Error::ExternalTool(format!("wallhaven has no wallpapers matching '{}'", query))
+            })?;
+            let extension = image.path.rsplit('.').next().unwrap_or("jpg").to_string();
+            Ok((format!("wallhaven-{}.{}", image.id, extension), image.path))
+        }
+        Provider::Unsplash => {
+            let key = std::env::var("UNSPLASH_ACCESS_KEY").map_err(|_| {
+                Error::ExternalTool("set UNSPLASH_ACCESS_KEY to use unsplash".to_string())
+            })?;
+            let authorization = format!("Client-ID {}", key);
+            let photo: UnsplashPhoto = get_json(
+                ureq::get("https://api.unsplash.com/photos/random")
+                    .query("query", query)
+                    .header("Authorization", &authorization),
+            )?;
+            // unsplash's guidelines ask for downloads to be reported
+            let _ = ureq::get(&photo.links.download_location)
+                .header("Authorization", &authorization)
+                .call();
+            let mut url = format!("{}&fm=jpg&q=90", photo.urls.raw);
+            if let Some((width, height)) = resolution {
+                url.push_str(&format!("&w={}&h={}&fit=crop", width, height));
+            }
+            Ok((format!("unsplash-{}.jpg", photo.id), url))
+        }
+    }
+}
+
+/// Download an image matching `query`, returning where it was saved
+pub fn fetch(
+    provider: Provider,
+    query: &str,
+    resolution: Option<(u32, u32)>,
+) -> Result<PathBuf, Error> {
+    let (name, url) = find_image(provider, query, resolution)?;
+    let dir = cache_dir()
+        .map(|dir| dir.join("fetched"))
+        .unwrap_or_else(std::env::temp_dir);
+    let path = dir.join(name);
+    println!("downloading {}", url);
+    let image = ureq::get(&url)
+        .call()
+        .map_err(request_error)?
+        .body_mut()
+        .with_config()
+        .limit(MAX_DOWNLOAD)
+        .read_to_vec()
+        .map_err(request_error)?;
+    fs::create_dir_all(&dir)
+        .and_then(|_| fs::write(&path, image))
+        .map_err(|err| Error::Encode(format!("could not write {}: {}", path.display(), err)))?;
+    Ok(path)
+}
@@ -0,0 +1,110 @@
+//! `--dark-theme` and `--install-gnome`: a light and a dark variant of a wallpaper that
+//! GNOME switches between with the appearance setting.
+//!
+//! Next to the variants, `<name>.xml` describes the pair in the
+//! `gnome-background-properties` format. `--install-gnome` copies the variants to
+//! `~/.local/share/backgrounds/imagecolorizer/` and the description to
+//! `~/.local/share/gnome-background-properties/`, where the wallpaper picker of GNOME
+//! Settings finds them.
+
+use crate::error::Error;
+use crate::palette::to_hex;
+use image::Rgb;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn failed(path: &Path, err: impl std::fmt::Display) -> Error {
+    Error::Encode(format!("could not write {}: {}", path.display(), err))
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Where the dark variant of `output` goes: `<stem>-dark.<extension>`
+pub fn dark_path(output: &str) -> String {
+    let path = Path::new(output);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-dark.{}", stem, extension.to_string_lossy()),
+        None => format!("{}-dark", stem),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+/// `$XDG_DATA_HOME`, by default `~/.local/share`
+pub fn data_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => Some(homedir::my_home().ok().flatten()?.join(".local/share")),
+    }
+}
+
+/// The description of the wallpaper pair, with `background` as the color around the
+/// image
+fn properties(name: &str, light: &Path, dark: &Path, background: Rgb<u8>) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE wallpapers SYSTEM "gnome-wp-list.dtd">
+<wallpapers>
+  <wallpaper deleted="false">
+    <name>{}</name>
+    <filename>{}</filename>
+    <filename-dark>{}</filename-dark>
+    <options>zoom</options>
+    <shade_type>solid</shade_type>
+    <pcolor>{}</pcolor>
+    <scolor>{}</scolor>
+  </wallpaper>
+</wallpapers>
+"#,
+        escape(name),
+        escape(&light.to_string_lossy()),
+        escape(&dark.to_string_lossy()),
+        to_hex(background),
+        to_hex(background),
+    )
+}
+
+/// Describe the rendered `light` and `dark` variants for GNOME, and install them if
+/// `install` is set
+pub fn write(light: &str, dark: &str, background: Rgb<u8>, install: bool) -> Result<(), Error> {
+    let light = fs::canonicalize(light).map_err(|err| failed(Path::new(light), err))?;
+    let dark = fs::canonicalize(dark).map_err(|err| failed(Path::new(dark), err))?;
+    let name = light
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let xml = light.with_extension("xml");
+    fs::write(&xml, properties(&name, &light, &dark, background))
+        .map_err(|err| failed(&xml, err))?;
+    if !install {
+        return Ok(());
+    }
+    let data = data_dir().ok_or_else(|| {
+        Error::Encode("could not find the home directory to install the wallpaper".to_string())
+    })?;
+    let backgrounds = data.join("backgrounds/imagecolorizer");
+    let descriptions = data.join("gnome-background-properties");
+    for dir in [&backgrounds, &descriptions] {
+        fs::create_dir_all(dir).map_err(|err| failed(dir, err))?;
+    }
+    let mut installed = Vec::new();
+    for variant in [&light, &dark] {
+        let copy = backgrounds.join(variant.file_name().unwrap());
+        fs::copy(variant, &copy).map_err(|err| failed(&copy, err))?;
+        installed.push(copy);
+    }
+    let description = descriptions.join(format!("imagecolorizer-{}.xml", name));
+    fs::write(
+        &description,
+        properties(&name, &installed[0], &installed[1], background),
+    )
+    .map_err(|err| failed(&description, err))?;
+    println!("installed {} for GNOME", name);
+    Ok(())
+}
@@ -0,0 +1,135 @@
+//! `--gogh`: the terminal themes of Gogh (<https://gogh-co.github.io/Gogh/>), by their
+//! name, e.g. `"Tokyo Night"`, or from a theme file of the Gogh repository:
+//!
+//! ```yaml
+//! name: 'Tokyo Night'
+//! color_01: '#414868'    # Black (Host)
+//! ...
+//! color_16: '#C0CAF5'    # Bright White
+//! background: '#1A1B26'
+//! foreground: '#A9B1D6'
+//! ```
+//!
+//! The palette is color_01 to color_16, followed by the background and foreground.
+//! Themes given by name are downloaded from the Gogh repository once and kept in
+//! `~/.cache/imagecolorizer/gogh/`, so later runs work offline. Without the `net` feature
+//! only themes already in the cache can be used by name.
+
+use crate::cache::cache_dir;
+use crate::error::Error;
+use crate::palette::parse_hex;
+use image::Rgb;
+use std::fs;
+use std::path::Path;
+
+const KEYS: [&str; 18] = [
+    "color_01",
+    "color_02",
+    "color_03",
+    "color_04",
+    "color_05",
+    "color_06",
+    "color_07",
+    "color_08",
+    "color_09",
+    "color_10",
+    "color_11",
+    "color_12",
+    "color_13",
+    "color_14",
+    "color_15",
+    "color_16",
+    "background",
+    "foreground",
+];
+
+/// The colors of a theme file
+fn decode(contents: &str) -> Result<Vec<Rgb<u8>>, String> {
+    let mut colors = [None; KEYS.len()];
+    for line in contents.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let Some(slot) = KEYS.iter().position(|name| *name == key.trim()) else {
+            continue;
+        };
+        let value = value.split('#').nth(1).map(|hex| {
+            let hex: String = hex.chars().take_while(char::is_ascii_hexdigit).collect();
+            format!("#{}", hex)
+        });
+        colors[slot] = Some(
+            value
+                .ok_or_else(|| format!("{} is not a color", key.trim()))
+                .and_then(|hex| {
+                    parse_hex(&hex).map_err(|err| format!("{}: {}", key.trim(), err))
+                })?,
+        );
+    }
+    colors
+        .iter()
+        .zip(KEYS)
+        .map(|(color, key)| color.ok_or_else(|| format!("missing {}", key)))
+        .collect()
+}
+
+/// Theme names are file names in the Gogh repository
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(Error::Palette(format!(
+            "'{}' is neither a Gogh theme file nor a theme name, like \"Tokyo Night\"",
+            name
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "net")]
+fn download(name: &str) -> Result<String, Error> {
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+    let url = format!(
+        "https://raw.githubusercontent.com/Gogh-Co/Gogh/master/themes/{}.yml",
+        utf8_percent_encode(name, NON_ALPHANUMERIC)
+    );
+    crate::fetch::get_text(ureq::get(&url)).map_err(|err| {
+        Error::Palette(format!(
+            "could not download the Gogh theme {}: {}. Theme names are case sensitive",
+            name, err
+        ))
+    })
+}
+
+#[cfg(not(feature = "net"))]
+fn download(name: &str) -> Result<String, Error> {
+    Err(Error::Palette(format!(
+        "the Gogh theme {} is not cached, and this build can't download it",
+        name
+    )))
+}
+
+/// The palette of a theme file or of a theme by name, from the cache or downloaded
+pub fn load(theme: &str) -> Result<Vec<Rgb<u8>>, Error> {
+    let path = Path::new(theme);
+    if path.is_file() {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| Error::Palette(format!("could not read {}: {}", theme, err)))?;
+        return decode(&contents).map_err(|err| Error::Palette(format!("{}: {}", theme, err)));
+    }
+    check_name(theme)?;
+    let cached_path = cache_dir().map(|dir| dir.join("gogh").join(format!("{}.yml", theme)));
+    let cached = cached_path
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| decode(&contents).ok());
+    if let Some(palette) = cached {
+        return Ok(palette);
+    }
+    let contents = download(theme)?;
+    let palette = decode(&contents)
+        .map_err(|err| Error::Palette(format!("Gogh theme {}: {}", theme, err)))?;
+    // a theme that can't be cached still works, just not offline
+    if let Some(path) = &cached_path {
+        let _ = fs::create_dir_all(path.parent().unwrap()).and_then(|_| fs::write(path, &contents));
+    }
+    Ok(palette)
+}
@@ -0,0 +1,11 @@
+use image::RgbImage;
+
+/// BLAKE3 hash of the image dimensions and RGB pixels, so two files hash the same
+/// whenever they decode to the same image regardless of format or metadata
+pub fn pixel_hash(img: &RgbImage) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&img.width().to_le_bytes());
+    hasher.update(&img.height().to_le_bytes());
+    hasher.update(img.as_raw());
+    hasher.finalize()
+}
@@ -0,0 +1,141 @@
+//! The palette history of `--watch`: after every render, its palette and copies of the
+//! files it wrote are kept in `~/.cache/imagecolorizer/history/`, the last
+//! `HISTORY_SIZE` of them. `history` lists them, newest first, and `rollback` copies the
+//! files of an earlier one back over the outputs, for when a new scheme turns out ugly.
+//! The history trims itself, so [`crate::cache::gc`] leaves it alone and the index never
+//! points at copies that are gone.
+
+use crate::cache::cache_dir;
+use crate::error::Error;
+use crate::palette::to_hex;
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HISTORY_SIZE: usize = 10;
+/// The directory of the copies, inside the cache
+pub const DIR: &str = "history";
+
+#[derive(Serialize, Deserialize)]
+struct Render {
+    /// seconds since the Unix epoch
+    time: u64,
+    palette: Vec<String>,
+    /// the outputs and the names of their copies in the history directory
+    files: Vec<(PathBuf, String)>,
+}
+
+/// The index of the history, next to the directory of copies
+fn index_path() -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join("history.json"))
+}
+
+fn read(path: &Path) -> Vec<Render> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn failed(path: &Path, err: impl std::fmt::Display) -> Error {
+    Error::Encode(format!("could not write {}: {}", path.display(), err))
+}
+
+/// Keep the render of `files` with `palette` in the history
+pub fn record(files: &[PathBuf], palette: &[Rgb<u8>]) -> Result<(), Error> {
+    let (Some(index), Some(dir)) = (index_path(), cache_dir().map(|dir| dir.join(DIR))) else {
+        return Ok(());
+    };
+    fs::create_dir_all(&dir).map_err(|err| failed(&dir, err))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut copies = Vec::new();
+    for (number, file) in files.iter().enumerate() {
+        let extension = file.extension().unwrap_or_default().to_string_lossy();
+        let name = format!("{}-{}.{}", now.as_millis(), number, extension);
+        fs::copy(file, dir.join(&name)).map_err(|err| failed(&dir.join(&name), err))?;
+        let file = fs::canonicalize(file).unwrap_or_else(|_| file.clone());
+        copies.push((file, name));
+    }
+    let mut renders = read(&index);
+    renders.push(Render {
+        time: now.as_secs(),
+        palette: palette.iter().map(|color| to_hex(*color)).collect(),
+        files: copies,
+    });
+    let dropped = renders.len().saturating_sub(HISTORY_SIZE);
+    for render in renders.drain(..dropped) {
+        for (_, name) in render.files {
+            let _ = fs::remove_file(dir.join(name));
+        }
+    }
+    let json = serde_json::to_string_pretty(&renders).map_err(|err| failed(&index, err))?;
+    fs::write(&index, json).map_err(|err| failed(&index, err))
+}
+
+/// How long ago `time` was, like `5m`
+fn ago(time: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs().saturating_sub(time);
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+/// Print the renders, newest first, numbered by how many steps back they are
+pub fn print() {
+    let renders = index_path().map(|index| read(&index)).unwrap_or_default();
+    if renders.is_empty() {
+        println!("no renders of --watch yet");
+    }
+    for (steps, render) in renders.iter().rev().enumerate() {
+        let files: Vec<String> = render
+            .files
+            .iter()
+            .map(|(file, _)| file.display().to_string())
+            .collect();
+        println!(
+            "{:>2} {:>4} ago  {}  {}",
+            steps,
+            ago(render.time),
+            files.join(", "),
+            render.palette.join(" ")
+        );
+    }
+}
+
+/// Copy the files of the render `steps` before the last back over its outputs
+pub fn rollback(steps: usize) -> Result<(), Error> {
+    let (Some(index), Some(dir)) = (index_path(), cache_dir().map(|dir| dir.join(DIR))) else {
+        return Err(Error::Input("there is no cache directory".to_string()));
+    };
+    let renders = read(&index);
+    let Some(render) = renders.iter().rev().nth(steps) else {
+        return Err(Error::Input(format!(
+            "the history has {} renders, there is none {} back",
+            renders.len(),
+            steps
+        )));
+    };
+    for (file, name) in &render.files {
+        if !dir.join(name).is_file() {
+            return Err(Error::Input(format!(
+                "the copy of {} was removed from the cache",
+                file.display()
+            )));
+        }
+    }
+    for (file, name) in &render.files {
+        fs::copy(dir.join(name), file).map_err(|err| failed(file, err))?;
+        println!("restored {} from {} ago", file.display(), ago(render.time));
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Integral images (summed-area tables): the sums of the channels and of their squares
+//! over any box of an image in constant time, for averages and variances of boxes of
+//! every size without visiting their pixels.
+
+use image::{Rgb, RgbImage};
+
+/// Per pixel sums of red, green, blue and red² + green² + blue² over the rectangle from
+/// the top left corner, one row and column larger than the image. The sums wrap: a
+/// difference of wrapped sums is still exact as long as the box sum itself fits in a
+/// u32, which it does for any box smaller than 128x128.
+pub struct Integral {
+    width: u32,
+    height: u32,
+    sums: Vec<[u32; 4]>,
+}
+
+/// Average color and summed channel variance of a box
+pub struct BoxStats {
+    pub average: Rgb<u8>,
+    pub variance: f32,
+}
+
+impl Integral {
+    pub fn new(img: &RgbImage) -> Integral {
+        let (width, height) = img.dimensions();
+        let stride = width as usize + 1;
+        let mut sums = vec![[0u32; 4]; stride * (height as usize + 1)];
+        for y in 0..height as usize {
+            let mut row = [0u32; 4];
+            for x in 0..width as usize {
+                let Rgb([red, green, blue]) = *img.get_pixel(x as u32, y as u32);
+                let (red, green, blue) = (red as u32, green as u32, blue as u32);
+                row[0] = row[0].wrapping_add(red);
+                row[1] = row[1].wrapping_add(green);
+                row[2] = row[2].wrapping_add(blue);
+                row[3] = row[3].wrapping_add(red * red + green * green + blue * blue);
+                let above = sums[y * stride + x + 1];
+                sums[(y + 1) * stride + x + 1] =
+                    std::array::from_fn(|i| above[i].wrapping_add(row[i]));
+            }
+        }
+        Integral {
+            width,
+            height,
+            sums,
+        }
+    }
+
+    /// Stats of the box within `radius` of the pixel at `x`, `y`, cut to the image
+    pub fn stats(&self, x: u32, y: u32, radius: u32) -> BoxStats {
+        let stride = self.width as usize + 1;
+        let (left, top) = (
+            x.saturating_sub(radius) as usize,
+            y.saturating_sub(radius) as usize,
+        );
+        let right = (x + radius + 1).min(self.width) as usize;
+        let bottom = (y + radius + 1).min(self.height) as usize;
+        let at = |x: usize, y: usize| self.sums[y * stride + x];
+        let (a, b, c, d) = (
+            at(left, top),
+            at(right, top),
+            at(left, bottom),
+            at(right, bottom),
+        );
+        let sum: [u32; 4] = std::array::from_fn(|i| {
+            d[i].wrapping_sub(b[i])
+                .wrapping_sub(c[i])
+                .wrapping_add(a[i])
+        });
+        let count = ((right - left) * (bottom - top)) as u32;
+        let mean: [f32; 3] = std::array::from_fn(|i| sum[i] as f32 / count as f32);
+        let variance =
+            sum[3] as f32 / count as f32 - mean.iter().map(|mean| mean * mean).sum::<f32>();
+        BoxStats {
+            average: Rgb(std::array::from_fn(|i| (sum[i] / count) as u8)),
+            variance: variance.max(0.0),
+        }
+    }
+}
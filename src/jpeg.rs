@@ -0,0 +1,279 @@
+//! JPEG outputs without generational loss. A JPEG made by imagecolorizer with
+//! `--embed-settings` already has its colors mapped, so when it is the input of a run
+//! that maps it to the same palette and only changes its size, decoding and encoding it
+//! again would only add artifacts. Instead the file is copied, or cropped losslessly
+//! with `jpegtran` when `--resize` only needs a crop: the image already has the target
+//! width or height, with `--fit cover`. Those are the only two operations passed
+//! through, there are no options for the rotations and flips `jpegtran` could also do.
+//! The settings embedded in the copy are replaced by the ones of the run, or removed
+//! without `--embed-settings`.
+//!
+//! `jpegtran` can only cut along the blocks of the JPEG (8 or 16 pixels), so the crop
+//! is shifted up and left by up to a block from the center. Without `jpegtran`, or with
+//! any other option that changes pixels, the image is rendered as usual.
+
+use crate::error::{warn, Error};
+use crate::exec;
+use crate::layout::Fit;
+use crate::palette::to_hex;
+use crate::provenance;
+use crate::Args;
+use image::Rgb;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+const SOI: u8 = 0xd8;
+const SOS: u8 = 0xda;
+const EOI: u8 = 0xd9;
+const COM: u8 = 0xfe;
+
+/// Options that only change how a run goes, not the images it writes
+pub const RUN_OPTIONS: [&str; 9] = [
+    "strict",
+    "threads",
+    "no_exec",
+    "cache",
+    "no_resume",
+    "fail_fast",
+    "dedupe",
+    "watch",
+    "watch_debounce",
+];
+
+/// Options besides `RUN_OPTIONS` that don't change the pixels, or that choose a palette,
+/// which only has to come out the same as the one the input was made with
+const PASSTHROUGH_OPTIONS: [&str; 5] =
+    ["resize", "fit", "scale", "embed_settings", "deterministic"];
+
+pub fn is_jpeg(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".jpg") || path.ends_with(".jpeg")
+}
+
+/// A marker segment of the JPEG header
+struct Segment {
+    marker: u8,
+    payload: Range<usize>,
+}
+
+/// The segments before the image data, empty if `data` is not a JPEG
+fn segments(data: &[u8]) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    if data.get(..2) != Some(&[0xff, SOI]) {
+        return segments;
+    }
+    let mut position = 2;
+    while position + 4 <= data.len() && data[position] == 0xff {
+        let marker = data[position + 1];
+        // fill bytes before a marker
+        if marker == 0xff {
+            position += 1;
+            continue;
+        }
+        if marker == SOS || marker == EOI {
+            break;
+        }
+        let length = u16::from_be_bytes([data[position + 2], data[position + 3]]) as usize;
+        if length < 2 || position + 2 + length > data.len() {
+            break;
+        }
+        segments.push(Segment {
+            marker,
+            payload: position + 4..position + 2 + length,
+        });
+        position += 2 + length;
+    }
+    segments
+}
+
+/// The text of the comment segment that starts with `keyword`
+pub fn comment(data: &[u8], keyword: &str) -> Option<String> {
+    segments(data)
+        .into_iter()
+        .filter(|segment| segment.marker == COM)
+        .find_map(|segment| {
+            let text = data[segment.payload].strip_prefix(keyword.as_bytes())?;
+            let text = text.strip_prefix(b"\0")?;
+            String::from_utf8(text.to_vec()).ok()
+        })
+}
+
+/// Whether `segment` is a comment that starts with `keyword`
+fn is_comment(data: &[u8], segment: &Segment, keyword: &str) -> bool {
+    segment.marker == COM
+        && data[segment.payload.clone()]
+            .strip_prefix(keyword.as_bytes())
+            .is_some_and(|text| text.starts_with(b"\0"))
+}
+
+/// `data` without the comment segments that start with `keyword`
+pub fn without_comment(data: &[u8], keyword: &str) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut position = 0;
+    for segment in segments(data) {
+        if is_comment(data, &segment, keyword) {
+            // the marker and the length come before the payload
+            output.extend_from_slice(&data[position..segment.payload.start - 4]);
+            position = segment.payload.end;
+        }
+    }
+    output.extend_from_slice(&data[position..]);
+    output
+}
+
+/// `data` with a comment segment of `keyword` and `text` after the application
+/// segments, where JFIF and Exif readers expect those, in place of any comment of
+/// `keyword` it had
+pub fn with_comment(data: &[u8], keyword: &str, text: &str) -> Result<Vec<u8>, String> {
+    let data = &without_comment(data, keyword);
+    let segments = segments(data);
+    if segments.is_empty() {
+        return Err("not a JPEG".to_string());
+    }
+    let payload = [keyword.as_bytes(), b"\0", text.as_bytes()].concat();
+    let length = u16::try_from(payload.len() + 2)
+        .map_err(|_| format!("{} bytes is too long for a JPEG comment", payload.len()))?;
+    let position = segments
+        .iter()
+        .take_while(|segment| (0xe0..=0xef).contains(&segment.marker))
+        .last()
+        .map_or(2, |segment| segment.payload.end);
+    let mut output = Vec::with_capacity(data.len() + payload.len() + 4);
+    output.extend_from_slice(&data[..position]);
+    output.extend_from_slice(&[0xff, COM]);
+    output.extend_from_slice(&length.to_be_bytes());
+    output.extend_from_slice(&payload);
+    output.extend_from_slice(&data[position..]);
+    Ok(output)
+}
+
+/// The size of the image and of the blocks it can be cut along
+struct Frame {
+    width: u32,
+    height: u32,
+    block_width: u32,
+    block_height: u32,
+}
+
+fn frame(data: &[u8]) -> Option<Frame> {
+    // start of frame markers, without DHT (c4), JPG (c8) and DAC (cc)
+    let segment = segments(data).into_iter().find(|segment| {
+        matches!(segment.marker, 0xc0..=0xcf) && ![0xc4, 0xc8, 0xcc].contains(&segment.marker)
+    })?;
+    let payload = &data[segment.payload];
+    let height = u16::from_be_bytes([*payload.get(1)?, *payload.get(2)?]) as u32;
+    let width = u16::from_be_bytes([*payload.get(3)?, *payload.get(4)?]) as u32;
+    let components = *payload.get(5)? as usize;
+    let sampling = payload.get(6..6 + components * 3)?;
+    let (horizontal, vertical) =
+        sampling
+            .chunks(3)
+            .fold((1, 1), |(horizontal, vertical), component| {
+                (
+                    horizontal.max(component[1] >> 4),
+                    vertical.max(component[1] & 0x0f),
+                )
+            });
+    Some(Frame {
+        width,
+        height,
+        block_width: 8 * horizontal as u32,
+        block_height: 8 * vertical as u32,
+    })
+}
+
+/// Write `output` straight from `input` when the run can't change its pixels. Returns
+/// whether it did, otherwise the image is to be rendered as usual
+pub fn passthrough(
+    input: &str,
+    output: &str,
+    palette: &[Rgb<u8>],
+    args: &Args,
+) -> Result<bool, Error> {
+    if !is_jpeg(input) || !is_jpeg(output) || !Path::new(input).is_file() {
+        return Ok(false);
+    }
+    let changes_pixels = args
+        .argv
+        .iter()
+        .skip(1)
+        .filter_map(|token| crate::rerun::option_id(token))
+        .any(|id| {
+            !PASSTHROUGH_OPTIONS.contains(&id.as_str())
+                && !RUN_OPTIONS.contains(&id.as_str())
+                && !crate::rerun::PALETTE_OPTIONS.contains(&id.as_str())
+        });
+    if changes_pixels || args.fit != Fit::Cover {
+        return Ok(false);
+    }
+    let data = fs::read(input)
+        .map_err(|err| Error::Input(format!("could not read {}: {}", input, err)))?;
+    let Some(frame) = frame(&data) else {
+        return Ok(false);
+    };
+    let hex: Vec<String> = palette.iter().map(|color| to_hex(*color)).collect();
+    let same_palette =
+        provenance::read_jpeg(&data).is_some_and(|provenance| provenance.palette == hex);
+    if !same_palette {
+        return Ok(false);
+    }
+    let factor = args.scale.factor()?;
+    let scaled = |pixels: u32| (pixels as f32 * factor).round() as u32;
+    let (width, height) = args
+        .resize
+        .map_or((frame.width, frame.height), |(width, height)| {
+            (scaled(width), scaled(height))
+        });
+    let provenance = args
+        .embed_settings
+        .then(|| provenance::Provenance::new(input, palette, args))
+        .transpose()?;
+    if (width, height) == (frame.width, frame.height) {
+        fs::copy(input, output)
+            .map_err(|err| Error::Encode(format!("could not write {}: {}", output, err)))?;
+        provenance::replace_jpeg(output, provenance.as_ref())?;
+        return Ok(true);
+    }
+    // the crop --resize makes when it doesn't need to scale
+    let crop_only = (width == frame.width && height <= frame.height)
+        || (height == frame.height && width <= frame.width);
+    if !crop_only || exec::forbidden() {
+        return Ok(false);
+    }
+    let x = (frame.width - width) / 2 / frame.block_width * frame.block_width;
+    let y = (frame.height - height) / 2 / frame.block_height * frame.block_height;
+    let result = exec::command("jpegtran", "lossless JPEG cropping")?
+        .args(["-copy", "all", "-perfect", "-crop"])
+        .arg(format!("{}x{}+{}+{}", width, height, x, y))
+        .arg("-outfile")
+        .arg(output)
+        .arg(input)
+        .output();
+    match result {
+        Ok(result) if result.status.success() => {
+            provenance::replace_jpeg(output, provenance.as_ref())?;
+            Ok(true)
+        }
+        // not installed, render it instead
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Ok(result) => {
+            warn(
+                args.strict,
+                Error::ExternalTool(format!(
+                    "jpegtran could not crop {}, rendering it instead: {}",
+                    input,
+                    String::from_utf8_lossy(&result.stderr).trim()
+                )),
+            )?;
+            Ok(false)
+        }
+        Err(err) => {
+            warn(
+                args.strict,
+                Error::ExternalTool(format!("failed to execute jpegtran: {}", err)),
+            )?;
+            Ok(false)
+        }
+    }
+}
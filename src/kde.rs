@@ -0,0 +1,91 @@
+//! `--install-kde`: install rendered wallpapers as a Plasma wallpaper package in
+//! `~/.local/share/wallpapers/imagecolorizer-<name>/`, where the wallpaper chooser of
+//! Plasma finds them:
+//!
+//! ```text
+//! metadata.json
+//! contents/images/1920x1080.png
+//! contents/images/3840x2160.png
+//! contents/images_dark/1920x1080.png
+//! ```
+//!
+//! Every variant is named by its size, so Plasma picks the one closest to the screen,
+//! and the dark variants are used with a dark color scheme.
+
+use crate::error::Error;
+use crate::gnome::data_dir;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Metadata {
+    #[serde(rename = "KPlugin")]
+    plugin: Plugin,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Plugin {
+    id: String,
+    name: String,
+    description: String,
+}
+
+fn failed(path: &Path, err: impl std::fmt::Display) -> Error {
+    Error::Encode(format!("could not write {}: {}", path.display(), err))
+}
+
+/// Copy the variants into `dir`, named by their size
+fn copy_variants(variants: &[PathBuf], dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dir).map_err(|err| failed(dir, err))?;
+    for variant in variants {
+        let (width, height) = image::image_dimensions(variant).map_err(|err| {
+            Error::Input(format!(
+                "{} can't be a Plasma wallpaper: {}",
+                variant.display(),
+                err
+            ))
+        })?;
+        let extension = variant.extension().unwrap_or_default().to_string_lossy();
+        let copy = dir.join(format!("{}x{}.{}", width, height, extension));
+        fs::copy(variant, &copy).map_err(|err| failed(&copy, err))?;
+    }
+    Ok(())
+}
+
+/// Install the package `name` with the `light` variants of the wallpaper, and the `dark`
+/// ones if there are any. An earlier package of the same name is replaced
+pub fn install(name: &str, light: &[PathBuf], dark: &[PathBuf]) -> Result<(), Error> {
+    let id: String = format!("imagecolorizer-{}", name)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let root = data_dir().ok_or_else(|| {
+        Error::Encode("could not find the home directory to install the wallpaper".to_string())
+    })?;
+    let package = root.join("wallpapers").join(&id);
+    if package.exists() {
+        fs::remove_dir_all(&package).map_err(|err| failed(&package, err))?;
+    }
+    copy_variants(light, &package.join("contents/images"))?;
+    if !dark.is_empty() {
+        copy_variants(dark, &package.join("contents/images_dark"))?;
+    }
+    let metadata = Metadata {
+        plugin: Plugin {
+            id,
+            name: name.to_string(),
+            description: "Made with imagecolorizer".to_string(),
+        },
+    };
+    let path = package.join("metadata.json");
+    fs::write(
+        &path,
+        serde_json::to_string_pretty(&metadata).unwrap() + "\n",
+    )
+    .map_err(|err| failed(&path, err))?;
+    println!("installed {} for Plasma", name);
+    Ok(())
+}
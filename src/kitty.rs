@@ -0,0 +1,99 @@
+//! `--kitty`: the colors of a kitty config, color0 to color15 followed by the background
+//! and foreground, as for pywal. Files pulled in with `include` are read in place, so
+//! themes installed with `kitty +kitten themes` are found as well. Colors the config
+//! doesn't set have kitty's defaults. Without a path, the config is looked for where
+//! kitty looks for it.
+
+use crate::error::Error;
+use crate::palette::parse_hex;
+use image::Rgb;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The colors kitty uses when the config doesn't set them, in palette order
+const DEFAULTS: [&str; 18] = [
+    "#000000", "#cc0403", "#19cb00", "#cecb00", "#0d73cc", "#cb1ed1", "#0dcdcd", "#dddddd",
+    "#767676", "#f2201f", "#23fd00", "#fffd00", "#1a8fff", "#fd28ff", "#14ffff", "#ffffff",
+    "#000000", "#dddddd",
+];
+/// Deepest chain of includes followed, against include loops
+const MAX_INCLUDE_DEPTH: usize = 5;
+
+/// The palette slot of a config key
+fn slot(key: &str) -> Option<usize> {
+    match key {
+        "background" => Some(16),
+        "foreground" => Some(17),
+        _ => key
+            .strip_prefix("color")
+            .and_then(|index| index.parse::<usize>().ok())
+            .filter(|index| *index < 16),
+    }
+}
+
+/// Read the config at `path` into `colors`, with includes resolved against `dir`
+fn read(path: &Path, dir: &Path, colors: &mut [Rgb<u8>], depth: usize) -> Result<(), String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let value = value.trim();
+        if key == "include" {
+            let include = match value.strip_prefix("~/") {
+                Some(rest) => homedir::my_home()
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default()
+                    .join(rest),
+                None => dir.join(value),
+            };
+            if depth < MAX_INCLUDE_DEPTH {
+                read(&include, dir, colors, depth + 1)?;
+            }
+        } else if let Some(slot) = slot(key) {
+            colors[slot] =
+                parse_hex(value).map_err(|err| format!("{}: {}: {}", path.display(), key, err))?;
+        }
+    }
+    Ok(())
+}
+
+/// Where kitty looks for its config
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("KITTY_CONFIG_DIRECTORY")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| PathBuf::from(dir).join("kitty"))
+        });
+    let home = homedir::my_home()
+        .ok()
+        .flatten()
+        .map(|home| home.join(".config/kitty"));
+    [config_dir, home]
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.join("kitty.conf"))
+        .find(|path| path.is_file())
+}
+
+/// The palette of the config at `path`, or of the default config
+pub fn load(path: Option<&str>) -> Result<Vec<Rgb<u8>>, Error> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => default_path()
+            .ok_or_else(|| Error::Palette("could not find a kitty config".to_string()))?,
+    };
+    let mut colors: Vec<Rgb<u8>> = DEFAULTS.iter().map(|hex| parse_hex(hex).unwrap()).collect();
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    read(&path, &dir, &mut colors, 0).map_err(Error::Palette)?;
+    Ok(colors)
+}
@@ -0,0 +1,387 @@
+//! Layer by layer recoloring of OpenRaster (`.ora`) and Photoshop (`.psd`) files, for
+//! artists theming their own source files.
+//!
+//! Every raster layer is mapped to the palette on its own, keeping its transparency, and
+//! the layers are then composited again with their opacity and blend mode. Normal,
+//! multiply, screen, overlay, darken and lighten are supported, other modes are
+//! composited as normal with a warning. An `.ora` output keeps the layers, any other
+//! output gets the flattened result. PSD files can only be read.
+
+use crate::error::Error;
+use crate::palette::PaletteEntry;
+use crate::render::{self, RenderOptions};
+use image::imageops;
+use image::{ImageFormat, Rgb, RgbImage, Rgba, RgbaImage};
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Largest side of the thumbnail an OpenRaster file carries
+const THUMBNAIL_SIZE: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Blend {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+}
+
+impl Blend {
+    fn from_ora(op: &str) -> Option<Blend> {
+        match op {
+            "svg:src-over" => Some(Blend::Normal),
+            "svg:multiply" => Some(Blend::Multiply),
+            "svg:screen" => Some(Blend::Screen),
+            "svg:overlay" => Some(Blend::Overlay),
+            "svg:darken" => Some(Blend::Darken),
+            "svg:lighten" => Some(Blend::Lighten),
+            _ => None,
+        }
+    }
+
+    fn to_ora(self) -> &'static str {
+        match self {
+            Blend::Normal => "svg:src-over",
+            Blend::Multiply => "svg:multiply",
+            Blend::Screen => "svg:screen",
+            Blend::Overlay => "svg:overlay",
+            Blend::Darken => "svg:darken",
+            Blend::Lighten => "svg:lighten",
+        }
+    }
+
+    /// Blend one channel of the layer (`source`) onto what is below it (`backdrop`)
+    fn channel(self, backdrop: f32, source: f32) -> f32 {
+        let screen = |a: f32, b: f32| a + b - a * b;
+        match self {
+            Blend::Normal => source,
+            Blend::Multiply => backdrop * source,
+            Blend::Screen => screen(backdrop, source),
+            Blend::Overlay if backdrop <= 0.5 => 2.0 * backdrop * source,
+            Blend::Overlay => screen(2.0 * backdrop - 1.0, source),
+            Blend::Darken => backdrop.min(source),
+            Blend::Lighten => backdrop.max(source),
+        }
+    }
+}
+
+pub struct Layer {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub image: RgbaImage,
+    pub opacity: f32,
+    pub visible: bool,
+    pub blend: Blend,
+    /// the blend mode as the file names it, written back unchanged to OpenRaster files
+    op: String,
+}
+
+/// A layered image, with its layers from the topmost down
+pub struct Document {
+    pub width: u32,
+    pub height: u32,
+    pub layers: Vec<Layer>,
+}
+
+pub fn is_layered(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".ora") || path.ends_with(".psd")
+}
+
+fn is_ora(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".ora")
+}
+
+/// The value of `name="..."` in an XML tag
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    loop {
+        let start = rest.find(name)?;
+        let before = rest[..start].chars().last();
+        rest = &rest[start + name.len()..];
+        if before.is_some_and(char::is_whitespace) {
+            if let Some(value) = rest.strip_prefix("=\"") {
+                return value.split('"').next();
+            }
+        }
+    }
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn read_ora(input: &str) -> Result<Document, Error> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        Error::Input(format!("{} is not a valid OpenRaster file: {}", input, err))
+    };
+    let file = fs::File::open(input)
+        .map_err(|err| Error::Input(format!("could not read {}: {}", input, err)))?;
+    let mut archive = ZipArchive::new(file).map_err(|err| invalid(&err))?;
+    let mut stack = String::new();
+    archive
+        .by_name("stack.xml")
+        .map_err(|err| invalid(&err))?
+        .read_to_string(&mut stack)
+        .map_err(|err| invalid(&err))?;
+
+    let image_tag = stack
+        .split('<')
+        .find(|tag| tag.starts_with("image"))
+        .ok_or_else(|| invalid(&"stack.xml has no image"))?;
+    let size = |name| {
+        attribute(image_tag, name)
+            .and_then(|value| value.parse::<u32>().ok())
+            .ok_or_else(|| invalid(&format!("the image has no {}", name)))
+    };
+    let (width, height) = (size("w")?, size("h")?);
+
+    // layers of nested stacks are taken as they are, without the stack's own settings
+    let mut layers = Vec::new();
+    for tag in stack.split('<').filter(|tag| tag.starts_with("layer")) {
+        let src = attribute(tag, "src").ok_or_else(|| invalid(&"a layer has no src"))?;
+        let position = |name| {
+            attribute(tag, name)
+                .and_then(|value| value.parse::<i32>().ok())
+                .unwrap_or(0)
+        };
+        let mut png = Vec::new();
+        archive
+            .by_name(src)
+            .map_err(|err| invalid(&format!("{}: {}", src, err)))?
+            .read_to_end(&mut png)
+            .map_err(|err| invalid(&err))?;
+        let image = image::load_from_memory(&png)
+            .map_err(|err| invalid(&format!("{}: {}", src, err)))?
+            .into_rgba8();
+        let op = attribute(tag, "composite-op")
+            .unwrap_or("svg:src-over")
+            .to_string();
+        layers.push(Layer {
+            name: unescape(attribute(tag, "name").unwrap_or(src)),
+            x: position("x"),
+            y: position("y"),
+            image,
+            opacity: attribute(tag, "opacity")
+                .and_then(|value| value.parse::<f32>().ok())
+                .unwrap_or(1.0),
+            visible: attribute(tag, "visibility") != Some("hidden"),
+            blend: Blend::from_ora(&op).unwrap_or(Blend::Normal),
+            op,
+        });
+    }
+    Ok(Document {
+        width,
+        height,
+        layers,
+    })
+}
+
+fn read_psd(input: &str) -> Result<Document, Error> {
+    let bytes = fs::read(input)
+        .map_err(|err| Error::Input(format!("could not read {}: {}", input, err)))?;
+    let psd = psd::Psd::from_bytes(&bytes)
+        .map_err(|err| Error::Input(format!("{} is not a supported PSD file: {}", input, err)))?;
+    let (width, height) = (psd.width(), psd.height());
+    let layers = psd
+        .layers()
+        .iter()
+        .map(|layer| {
+            // the psd crate doesn't export its blend mode type, only its names
+            let op = format!("{:?}", layer.blend_mode());
+            let blend = match op.as_str() {
+                "Normal" | "PassThrough" => Some(Blend::Normal),
+                "Multiply" => Some(Blend::Multiply),
+                "Screen" => Some(Blend::Screen),
+                "Overlay" => Some(Blend::Overlay),
+                "Darken" => Some(Blend::Darken),
+                "Lighten" => Some(Blend::Lighten),
+                _ => None,
+            };
+            Layer {
+                name: layer.name().to_string(),
+                x: 0,
+                y: 0,
+                // layers come at the size of the document
+                image: RgbaImage::from_raw(width, height, layer.rgba())
+                    .unwrap_or_else(|| RgbaImage::new(width, height)),
+                opacity: layer.opacity() as f32 / 255.0,
+                visible: layer.visible(),
+                blend: blend.unwrap_or(Blend::Normal),
+                op: if blend.is_some() { String::new() } else { op },
+            }
+        })
+        .collect();
+    Ok(Document {
+        width,
+        height,
+        layers,
+    })
+}
+
+pub fn read(input: &str) -> Result<Document, Error> {
+    if is_ora(input) {
+        read_ora(input)
+    } else {
+        read_psd(input)
+    }
+}
+
+/// Blend modes of the document that are composited as normal
+pub fn unsupported_blends(document: &Document) -> Vec<String> {
+    document
+        .layers
+        .iter()
+        .filter(|layer| layer.blend == Blend::Normal && !layer.op.is_empty())
+        .filter(|layer| layer.op != "svg:src-over")
+        .map(|layer| format!("{} ({})", layer.op, layer.name))
+        .collect()
+}
+
+/// Map the colors of every layer to the palette, keeping their transparency
+pub fn recolor(document: &mut Document, palette: &[PaletteEntry], options: &RenderOptions) {
+    for layer in &mut document.layers {
+        let (width, height) = layer.image.dimensions();
+        let rgb = RgbImage::from_fn(width, height, |x, y| {
+            let [r, g, b, _] = layer.image.get_pixel(x, y).0;
+            Rgb([r, g, b])
+        });
+        let mapped = render::render(&rgb, palette, options);
+        for (pixel, color) in layer.image.pixels_mut().zip(mapped.pixels()) {
+            let alpha = pixel[3];
+            *pixel = Rgba([color[0], color[1], color[2], alpha]);
+        }
+    }
+}
+
+/// Composite the visible layers, bottom up, onto a transparent canvas
+pub fn flatten(document: &Document) -> RgbaImage {
+    let mut canvas = RgbaImage::new(document.width, document.height);
+    for layer in document.layers.iter().rev().filter(|layer| layer.visible) {
+        for (x, y, source) in layer.image.enumerate_pixels() {
+            let (cx, cy) = (x as i32 + layer.x, y as i32 + layer.y);
+            if cx < 0 || cy < 0 || cx >= document.width as i32 || cy >= document.height as i32 {
+                continue;
+            }
+            let backdrop = canvas.get_pixel_mut(cx as u32, cy as u32);
+            let source_alpha = source[3] as f32 / 255.0 * layer.opacity;
+            let backdrop_alpha = backdrop[3] as f32 / 255.0;
+            let alpha = source_alpha + backdrop_alpha * (1.0 - source_alpha);
+            if alpha <= 0.0 {
+                continue;
+            }
+            let mut result = [0; 4];
+            for channel in 0..3 {
+                let (b, s) = (
+                    backdrop[channel] as f32 / 255.0,
+                    source[channel] as f32 / 255.0,
+                );
+                // where there is nothing below, the layer shows as it is
+                let blended =
+                    (1.0 - backdrop_alpha) * s + backdrop_alpha * layer.blend.channel(b, s);
+                let color = source_alpha * blended + (1.0 - source_alpha) * backdrop_alpha * b;
+                result[channel] = (color / alpha * 255.0).round().clamp(0.0, 255.0) as u8;
+            }
+            result[3] = (alpha * 255.0).round() as u8;
+            *backdrop = Rgba(result);
+        }
+    }
+    canvas
+}
+
+fn png(image: &RgbaImage) -> Result<Vec<u8>, Error> {
+    let mut bytes = Cursor::new(Vec::new());
+    image
+        .write_to(&mut bytes, ImageFormat::Png)
+        .map_err(|err| Error::Encode(format!("could not encode a layer: {}", err)))?;
+    Ok(bytes.into_inner())
+}
+
+/// Write the document as an OpenRaster file, with its merged image and thumbnail
+pub fn write_ora(document: &Document, output: &str) -> Result<(), Error> {
+    let failed =
+        |err: &dyn std::fmt::Display| Error::Encode(format!("could not write {}: {}", output, err));
+    let mut stack = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <image version=\"0.0.3\" w=\"{}\" h=\"{}\">\n<stack>\n",
+        document.width, document.height
+    );
+    let mut files = Vec::new();
+    for (i, layer) in document.layers.iter().enumerate() {
+        let src = format!("data/layer{}.png", i);
+        let op = match layer.op.as_str() {
+            op if op.starts_with("svg:") => op,
+            _ => layer.blend.to_ora(),
+        };
+        stack.push_str(&format!(
+            "<layer name=\"{}\" src=\"{}\" x=\"{}\" y=\"{}\" opacity=\"{:.3}\" \
+             visibility=\"{}\" composite-op=\"{}\"/>\n",
+            escape(&layer.name),
+            src,
+            layer.x,
+            layer.y,
+            layer.opacity,
+            if layer.visible { "visible" } else { "hidden" },
+            op
+        ));
+        files.push((src, png(&layer.image)?));
+    }
+    stack.push_str("</stack>\n</image>\n");
+    let merged = flatten(document);
+    let thumbnail = merged.width().max(merged.height()) as f32 / THUMBNAIL_SIZE as f32;
+    let thumbnail = imageops::thumbnail(
+        &merged,
+        ((merged.width() as f32 / thumbnail.max(1.0)) as u32).max(1),
+        ((merged.height() as f32 / thumbnail.max(1.0)) as u32).max(1),
+    );
+    files.push(("mergedimage.png".to_string(), png(&merged)?));
+    files.push(("Thumbnails/thumbnail.png".to_string(), png(&thumbnail)?));
+
+    let partial = format!("{}.partial", output);
+    let file = fs::File::create(&partial).map_err(|err| failed(&err))?;
+    let mut archive = ZipWriter::new(file);
+    // the mimetype has to come first and uncompressed, so the file can be identified
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    archive
+        .start_file("mimetype", stored)
+        .and_then(|_| Ok(archive.write_all(b"image/openraster")?))
+        .map_err(|err| failed(&err))?;
+    let deflated = SimpleFileOptions::default();
+    for (name, bytes) in std::iter::once(("stack.xml".to_string(), stack.into_bytes())).chain(files)
+    {
+        archive
+            .start_file(name, deflated)
+            .and_then(|_| Ok(archive.write_all(&bytes)?))
+            .map_err(|err| failed(&err))?;
+    }
+    archive.finish().map_err(|err| failed(&err))?;
+    fs::rename(&partial, output).map_err(|err| failed(&err))
+}
+
+/// Whether the output keeps the layers, checking that it can
+pub fn keeps_layers(output: &str) -> Result<bool, Error> {
+    if output.to_ascii_lowercase().ends_with(".psd") {
+        return Err(Error::Input(
+            "PSD files can't be written, use an .ora output to keep the layers".to_string(),
+        ));
+    }
+    Ok(is_ora(output))
+}
@@ -0,0 +1,427 @@
+use crate::color::{average_color, delta_e, from_linear, to_linear};
+use image::imageops::FilterType;
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use std::path::Path;
+
+/// Resampling filters for `--resize-filter`, which work in linear light
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResizeFilter {
+    /// Sharpest, keeps the most detail but can ring around hard edges
+    Lanczos3,
+    /// Sharp with less ringing
+    Catmullrom,
+    /// Softer, with almost no ringing
+    Mitchell,
+    /// Plain averaging of the covered pixels
+    Box,
+}
+
+impl ResizeFilter {
+    /// How far the kernel reaches, in source pixels at 1:1 scale
+    fn support(self) -> f32 {
+        match self {
+            ResizeFilter::Lanczos3 => 3.0,
+            ResizeFilter::Catmullrom | ResizeFilter::Mitchell => 2.0,
+            ResizeFilter::Box => 0.5,
+        }
+    }
+
+    fn kernel(self, x: f32) -> f32 {
+        let sinc = |x: f32| {
+            if x == 0.0 {
+                1.0
+            } else {
+                let x = x * std::f32::consts::PI;
+                x.sin() / x
+            }
+        };
+        // the Mitchell-Netravali family of cubics
+        let cubic = |b: f32, c: f32| {
+            let x = x.abs();
+            if x < 1.0 {
+                ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3)
+                    + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2)
+                    + (6.0 - 2.0 * b))
+                    / 6.0
+            } else if x < 2.0 {
+                ((-b - 6.0 * c) * x.powi(3)
+                    + (6.0 * b + 30.0 * c) * x.powi(2)
+                    + (-12.0 * b - 48.0 * c) * x
+                    + (8.0 * b + 24.0 * c))
+                    / 6.0
+            } else {
+                0.0
+            }
+        };
+        match self {
+            ResizeFilter::Lanczos3 if x.abs() < 3.0 => sinc(x) * sinc(x / 3.0),
+            ResizeFilter::Lanczos3 => 0.0,
+            ResizeFilter::Catmullrom => cubic(0.0, 0.5),
+            ResizeFilter::Mitchell => cubic(1.0 / 3.0, 1.0 / 3.0),
+            ResizeFilter::Box if x.abs() <= 0.5 => 1.0,
+            ResizeFilter::Box => 0.0,
+        }
+    }
+
+    /// The first source pixel and the normalized weights of every target pixel along
+    /// one axis
+    fn weights(self, source: u32, target: u32) -> Vec<(usize, Vec<f32>)> {
+        let ratio = source as f32 / target as f32;
+        // when shrinking, the kernel is stretched to cover every source pixel
+        let stretch = ratio.max(1.0);
+        let support = self.support() * stretch;
+        (0..target)
+            .map(|i| {
+                let center = (i as f32 + 0.5) * ratio;
+                let start = (center - support).floor().max(0.0) as usize;
+                let end = ((center + support).ceil() as usize).min(source as usize);
+                let mut weights: Vec<f32> = (start..end)
+                    .map(|j| self.kernel((j as f32 + 0.5 - center) / stretch))
+                    .collect();
+                let sum: f32 = weights.iter().sum();
+                if sum != 0.0 {
+                    weights.iter_mut().for_each(|weight| *weight /= sum);
+                }
+                (start, weights)
+            })
+            .collect()
+    }
+}
+
+/// Resample the image to exactly `width`x`height`, filtering in linear light so that
+/// fine bright and dark detail averages to the right brightness
+pub fn resize_linear(img: &RgbImage, width: u32, height: u32, filter: ResizeFilter) -> RgbImage {
+    let (source_width, source_height) = (img.width() as usize, img.height() as usize);
+    let linear: Vec<[f32; 3]> = img.pixels().map(|pixel| pixel.0.map(to_linear)).collect();
+
+    let columns = filter.weights(img.width(), width);
+    let mut horizontal = vec![[0.0f32; 3]; width as usize * source_height];
+    horizontal
+        .par_chunks_mut(width as usize)
+        .zip(linear.par_chunks(source_width))
+        .for_each(|(row, source)| {
+            for (pixel, (start, weights)) in row.iter_mut().zip(&columns) {
+                for (offset, weight) in weights.iter().enumerate() {
+                    for channel in 0..3 {
+                        pixel[channel] += weight * source[start + offset][channel];
+                    }
+                }
+            }
+        });
+
+    let rows = filter.weights(img.height(), height);
+    let mut output = RgbImage::new(width, height);
+    output
+        .par_chunks_mut(width as usize * 3)
+        .zip(rows.par_iter())
+        .for_each(|(row, (start, weights))| {
+            for (x, pixel) in row.chunks_mut(3).enumerate() {
+                let mut sum = [0.0f32; 3];
+                for (offset, weight) in weights.iter().enumerate() {
+                    let source = horizontal[(start + offset) * width as usize + x];
+                    for channel in 0..3 {
+                        sum[channel] += weight * source[channel];
+                    }
+                }
+                pixel.copy_from_slice(&sum.map(from_linear));
+            }
+        });
+    output
+}
+
+/// Parse a `WIDTHxHEIGHT` size such as `1920x1080`
+pub fn parse_size(input: &str) -> Result<(u32, u32), String> {
+    let (width, height) = input
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("'{}' is not a WIDTHxHEIGHT size", input))?;
+    let width = width.trim().parse::<u32>();
+    let height = height.trim().parse::<u32>();
+    match (width, height) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("'{}' is not a WIDTHxHEIGHT size", input)),
+    }
+}
+
+/// Scale the image to cover `width`x`height` and crop off whatever overhangs. Without a
+/// filter it is resampled with Lanczos3 on the gamma encoded values
+pub fn fill(img: &RgbImage, width: u32, height: u32, filter: Option<ResizeFilter>) -> RgbImage {
+    let scale = (width as f32 / img.width() as f32).max(height as f32 / img.height() as f32);
+    let scaled_width = ((img.width() as f32 * scale).round() as u32).max(width);
+    let scaled_height = ((img.height() as f32 * scale).round() as u32).max(height);
+    let scaled = match filter {
+        Some(filter) => resize_linear(img, scaled_width, scaled_height, filter),
+        None => image::imageops::resize(img, scaled_width, scaled_height, FilterType::Lanczos3),
+    };
+    image::imageops::crop_imm(
+        &scaled,
+        (scaled_width - width) / 2,
+        (scaled_height - height) / 2,
+        width,
+        height,
+    )
+    .to_image()
+}
+
+/// How `--resize` deals with a different aspect ratio
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fit {
+    /// Scale to cover the size and crop off the overhang
+    #[default]
+    Cover,
+    /// Scale to fit inside the size and extend the edges with their average color
+    Extend,
+    /// Extend when the edges that would be extended are near uniform (logos, minimal
+    /// art), cover otherwise
+    Auto,
+}
+
+/// ΔE from the edge color within which edge pixels count as the same color
+const EDGE_TOLERANCE: f32 = 5.0;
+
+/// Share of the edge pixels that have to be within [`EDGE_TOLERANCE`] of their average
+const EDGE_UNIFORMITY: f32 = 0.98;
+
+/// The average color of the edges the image would be extended at to get the aspect
+/// ratio of `width`x`height`, and whether they are near uniform
+fn edge_color(img: &RgbImage, width: u32, height: u32) -> (Rgb<u8>, bool) {
+    let wider = width as f32 / height as f32 > img.width() as f32 / img.height() as f32;
+    // a band of 2% of the image on both extended sides
+    let mut pixels = Vec::new();
+    if wider {
+        let band = (img.width() / 50).max(1);
+        for y in 0..img.height() {
+            for x in (0..band).chain(img.width().saturating_sub(band)..img.width()) {
+                pixels.push(*img.get_pixel(x, y));
+            }
+        }
+    } else {
+        let band = (img.height() / 50).max(1);
+        for y in (0..band).chain(img.height().saturating_sub(band)..img.height()) {
+            for x in 0..img.width() {
+                pixels.push(*img.get_pixel(x, y));
+            }
+        }
+    }
+    let total = pixels.len();
+    let average = average_color(pixels.clone());
+    let close = pixels
+        .into_iter()
+        .filter(|pixel| delta_e(*pixel, average) <= EDGE_TOLERANCE)
+        .count();
+    (average, close as f32 >= total as f32 * EDGE_UNIFORMITY)
+}
+
+/// Scale the image to fit inside `width`x`height` and fill the rest with `color`
+pub fn extend(
+    img: &RgbImage,
+    width: u32,
+    height: u32,
+    color: Rgb<u8>,
+    filter: Option<ResizeFilter>,
+) -> RgbImage {
+    let scale = (width as f32 / img.width() as f32).min(height as f32 / img.height() as f32);
+    let scaled_width = ((img.width() as f32 * scale).round() as u32).clamp(1, width);
+    let scaled_height = ((img.height() as f32 * scale).round() as u32).clamp(1, height);
+    let scaled = match filter {
+        Some(filter) => resize_linear(img, scaled_width, scaled_height, filter),
+        None => image::imageops::resize(img, scaled_width, scaled_height, FilterType::Lanczos3),
+    };
+    let mut canvas = RgbImage::from_pixel(width, height, color);
+    image::imageops::replace(
+        &mut canvas,
+        &scaled,
+        ((width - scaled_width) / 2) as i64,
+        ((height - scaled_height) / 2) as i64,
+    );
+    canvas
+}
+
+/// Bring the image to `width`x`height` as `fit` says
+pub fn fit(
+    img: &RgbImage,
+    width: u32,
+    height: u32,
+    fit: Fit,
+    filter: Option<ResizeFilter>,
+) -> RgbImage {
+    if fit == Fit::Cover {
+        return fill(img, width, height, filter);
+    }
+    match edge_color(img, width, height) {
+        (color, uniform) if uniform || fit == Fit::Extend => {
+            extend(img, width, height, color, filter)
+        }
+        _ => fill(img, width, height, filter),
+    }
+}
+
+/// Rotate the image a quarter turn if its orientation (portrait or landscape) does not
+/// match `width`x`height`
+pub fn match_orientation(img: RgbImage, width: u32, height: u32) -> RgbImage {
+    if (img.width() > img.height()) != (width > height) && img.width() != img.height() {
+        image::imageops::rotate90(&img)
+    } else {
+        img
+    }
+}
+
+/// Cut the image into `columns`x`rows` equally sized tiles, left to right and top to
+/// bottom, leaving out a `gap` pixel strip between neighbouring tiles
+pub fn split(img: &RgbImage, columns: u32, rows: u32, gap: u32) -> Vec<RgbImage> {
+    let tile_width = img.width().saturating_sub(gap * (columns - 1)) / columns;
+    let tile_height = img.height().saturating_sub(gap * (rows - 1)) / rows;
+    let mut tiles = Vec::new();
+    for row in 0..rows {
+        for column in 0..columns {
+            tiles.push(
+                image::imageops::crop_imm(
+                    img,
+                    column * (tile_width + gap),
+                    row * (tile_height + gap),
+                    tile_width,
+                    tile_height,
+                )
+                .to_image(),
+            );
+        }
+    }
+    tiles
+}
+
+/// `path` with `-number` added before the extension, e.g. `out-1.png`
+pub fn numbered_path(path: &str, number: usize) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, number),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+/// Pixel art upscaling algorithms for `--upscale`, neither adds colors to the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpscaleMethod {
+    /// Every pixel becomes a square block
+    Nearest,
+    /// Scale2x and Scale3x (AdvMAME), which round off the staircases of diagonal edges
+    Scale2x,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Upscale {
+    pub method: UpscaleMethod,
+    pub factor: u32,
+}
+
+/// Parse `METHOD:FACTOR` for `--upscale`, e.g. `scale2x:4`. Scale2x needs a factor made
+/// of twos and threes, as it is applied repeatedly
+pub fn parse_upscale(input: &str) -> Result<Upscale, String> {
+    let (method, factor) = input
+        .split_once(':')
+        .ok_or_else(|| format!("'{}' is not METHOD:FACTOR, e.g. scale2x:4", input))?;
+    let method = match method.trim() {
+        "nearest" => UpscaleMethod::Nearest,
+        "scale2x" => UpscaleMethod::Scale2x,
+        method => return Err(format!("unknown upscale method '{}'", method)),
+    };
+    let factor = match factor.trim().parse::<u32>() {
+        Ok(factor @ 2..=16) => factor,
+        _ => return Err(format!("upscale factor '{}' is not from 2 to 16", factor)),
+    };
+    if method == UpscaleMethod::Scale2x && scale2x_steps(factor).is_none() {
+        return Err(format!(
+            "scale2x can't scale by {}, use a factor such as 2, 3, 4, 6 or 8",
+            factor
+        ));
+    }
+    Ok(Upscale { method, factor })
+}
+
+/// The 2x and 3x steps that make up `factor`
+fn scale2x_steps(mut factor: u32) -> Option<Vec<u32>> {
+    let mut steps = Vec::new();
+    for step in [3, 2] {
+        while factor.is_multiple_of(step) {
+            steps.push(step);
+            factor /= step;
+        }
+    }
+    (factor == 1).then_some(steps)
+}
+
+/// Scale2x (`step` 2) or Scale3x (`step` 3): every pixel becomes a block that takes the
+/// color of a neighbour where two of its neighbours form an edge through it
+fn scale_step(img: &RgbImage, step: u32) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let pixel = |x: i64, y: i64| {
+        *img.get_pixel(
+            x.clamp(0, width as i64 - 1) as u32,
+            y.clamp(0, height as i64 - 1) as u32,
+        )
+    };
+    let mut output = RgbImage::new(width * step, height * step);
+    for (x, y, center) in img.enumerate_pixels() {
+        let (x, y) = (x as i64, y as i64);
+        let (a, b, c) = (pixel(x - 1, y - 1), pixel(x, y - 1), pixel(x + 1, y - 1));
+        let (d, e, f) = (pixel(x - 1, y), *center, pixel(x + 1, y));
+        let (g, h, i) = (pixel(x - 1, y + 1), pixel(x, y + 1), pixel(x + 1, y + 1));
+        let block = if b == h || d == f {
+            vec![e; (step * step) as usize]
+        } else if step == 2 {
+            vec![
+                if d == b { d } else { e },
+                if b == f { f } else { e },
+                if d == h { d } else { e },
+                if h == f { f } else { e },
+            ]
+        } else {
+            vec![
+                if d == b { d } else { e },
+                if (d == b && e != c) || (b == f && e != a) {
+                    b
+                } else {
+                    e
+                },
+                if b == f { f } else { e },
+                if (d == b && e != g) || (d == h && e != a) {
+                    d
+                } else {
+                    e
+                },
+                e,
+                if (b == f && e != i) || (h == f && e != c) {
+                    f
+                } else {
+                    e
+                },
+                if d == h { d } else { e },
+                if (d == h && e != i) || (h == f && e != g) {
+                    h
+                } else {
+                    e
+                },
+                if h == f { f } else { e },
+            ]
+        };
+        for (n, color) in block.into_iter().enumerate() {
+            let (dx, dy) = (n as u32 % step, n as u32 / step);
+            output.put_pixel(x as u32 * step + dx, y as u32 * step + dy, color);
+        }
+    }
+    output
+}
+
+pub fn upscale(img: &RgbImage, upscale: Upscale) -> RgbImage {
+    let (width, height) = (img.width() * upscale.factor, img.height() * upscale.factor);
+    match upscale.method {
+        UpscaleMethod::Nearest => image::imageops::resize(img, width, height, FilterType::Nearest),
+        UpscaleMethod::Scale2x => scale2x_steps(upscale.factor)
+            .unwrap_or_default()
+            .into_iter()
+            .fold(img.clone(), |img, step| scale_step(&img, step)),
+    }
+}
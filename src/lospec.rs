@@ -0,0 +1,67 @@
+//! `--lospec`: palettes from the Lospec palette list, by the slug in their URL, e.g.
+//! `sweetie-16` for <https://lospec.com/palette-list/sweetie-16>.
+//!
+//! Palettes are kept in `~/.cache/imagecolorizer/lospec/` after the first download, so
+//! later runs work offline. `cache gc` cleans them up like any other cache entry.
+
+use crate::cache::cache_dir;
+use crate::error::Error;
+use crate::fetch::get_json;
+use crate::palette::parse_hex;
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+#[derive(Deserialize, Serialize)]
+struct Palette {
+    colors: Vec<String>,
+}
+
+fn check_slug(slug: &str) -> Result<(), Error> {
+    let valid = !slug.is_empty()
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Palette(format!(
+            "'{}' is not a Lospec palette slug, like sweetie-16",
+            slug
+        )))
+    }
+}
+
+/// The colors of a Lospec palette, from the cache or downloaded
+pub fn load(slug: &str) -> Result<Vec<Rgb<u8>>, Error> {
+    check_slug(slug)?;
+    let path = cache_dir().map(|dir| dir.join("lospec").join(format!("{}.json", slug)));
+    let cached = path
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str::<Palette>(&contents).ok());
+    let palette = match cached {
+        Some(palette) => palette,
+        None => {
+            let url = format!("https://lospec.com/palette-list/{}.json", slug);
+            let palette: Palette = get_json(ureq::get(&url)).map_err(|err| {
+                Error::Palette(format!(
+                    "could not download the Lospec palette {}: {}",
+                    slug, err
+                ))
+            })?;
+            // a palette that can't be cached still works, just not offline
+            if let Some(path) = &path {
+                let _ = fs::create_dir_all(path.parent().unwrap())
+                    .and_then(|_| fs::write(path, serde_json::to_string(&palette).unwrap()));
+            }
+            palette
+        }
+    };
+    palette
+        .colors
+        .iter()
+        .map(|color| parse_hex(&format!("#{}", color.trim_start_matches('#'))))
+        .collect::<Result<_, _>>()
+        .map_err(|err| Error::Palette(format!("Lospec palette {}: {}", slug, err)))
+}
@@ -1,35 +1,163 @@
+mod accent;
+mod adjust;
+mod alacritty;
+mod appearance;
+mod audit;
+mod base16;
+mod batch;
+mod brand;
+mod cache;
+mod color;
+mod css;
+mod doctor;
+mod edid;
+mod effects;
+mod error;
+mod exec;
+mod extract;
+#[cfg(feature = "net")]
+mod fetch;
+mod gnome;
+mod gogh;
+mod hash;
+mod history;
+mod integral;
+mod jpeg;
+mod kde;
+mod kitty;
+#[cfg(feature = "layered")]
+mod layered;
+mod layout;
+#[cfg(feature = "net")]
+mod lospec;
+mod manifest;
+mod matugen;
+mod named_colors;
+mod pack;
+mod palette;
+mod palette_export;
+mod palette_file;
+mod pipeline;
+mod plugins;
+#[cfg(feature = "net")]
+mod portal;
+mod provenance;
+mod regions;
+mod render;
+mod rerun;
+mod saliency;
+mod scale;
+mod script;
+mod sequence;
+mod shm;
+mod stats;
+mod svg;
+mod swatch;
+mod target_size;
+mod term_colors;
+mod themes;
+mod thumbnail;
+mod tint;
+mod tmux;
+mod wallust;
+mod watch;
+mod wezterm;
+mod widgets;
+mod window;
+mod windows_theme;
+
 use clap::Parser;
+use error::{warn, Error};
 use homedir::my_home;
 use image::{ImageReader, Rgb, RgbImage};
-use quantette::{ColorSpace, ImagePipeline, QuantizeMethod};
-use rayon::prelude::*;
-use std::fs::File;
-use std::io::prelude::*;
 use std::collections::HashSet;
-use std::process::Command;
-
-// TODO: proper error handling without .unwrap() and .panic() (use result in the main function)
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 #[derive(Parser, Debug)]
 #[command(version, about = "Make any wallpaper fit any colorscheme", long_about = None, max_term_width=120)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(after_help = error::EXIT_CODES)]
 struct Args {
-    /// File to generate image from
-    input: String,
+    #[command(subcommand)]
+    command: Option<Commands>,
 
-    /// File to generate image to
-    output: String,
+    /// File to generate image from, or a directory of images to process in batch. The
+    /// colors of an SVG are mapped to the palette directly, keeping it a vector image. With
+    /// the layered feature, the layers of an .ora or .psd file are recolored one by one
+    #[arg(required_unless_present_any = ["list_themes", "swatch"])]
+    input: Option<String>,
 
-    /// Image Palette
-    #[arg(long, short, num_args = 0..)]
-    palette: Option<Vec<String>>,
+    /// File to generate image to, or the output directory in batch mode. With --pipeline,
+    /// the directory relative output paths of the pipeline are written to. shm:NAME
+    /// writes a raw frame to shared memory for wallpaper setters instead
+    ///
+    /// The frame is written to /dev/shm/imagecolorizer-NAME, or to the temporary directory
+    /// where there is no /dev/shm. It is a 32 byte header: the magic ICFRAME1, the width, height, stride (width * 4) and pixel format (1, the
+    /// wl_shm code for XRGB8888) as little endian 32 bit numbers, and 8 bytes of a hash
+    /// that changes with every frame. The XRGB8888 pixels follow row by row, in the byte
+    /// order B, G, R, X
+    #[arg(required_unless_present_any = ["pipeline", "list_themes", "swatch"])]
+    output: Option<String>,
 
-    /// Use palette from pywal
-    #[arg(long, short)]
-    wal: bool,
+    /// The command line these arguments were parsed from
+    #[arg(skip)]
+    argv: Vec<String>,
 
-    /// Use palette from Xresources
-    #[arg(long, short)]
-    xresources: bool,
+    /// Never run external programs such as xrdb or wallpaper setters, and fail where an
+    /// option needs one, for running in sandboxes and build systems
+    #[arg(long, global = true)]
+    no_exec: bool,
+
+    /// Number of threads to render with, all cores by default
+    #[arg(long, value_name = "N", global = true)]
+    threads: Option<usize>,
+
+    /// Run quantization and dithering in a fixed order so the output is identical
+    /// whatever --threads is, at the cost of speed. For comparing outputs in CI
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Print the built-in themes for --theme and their colors, and exit
+    #[arg(long)]
+    list_themes: bool,
+
+    /// Save an image of the palette as labeled swatches, to check the colors the palette
+    /// options come down to. Without an input and output, nothing else is rendered
+    #[arg(long, value_name = "FILE", conflicts_with = "themes")]
+    swatch: Option<String>,
+
+    /// Run the stages and outputs described in a pipeline file instead of the processing
+    /// options given on the command line
+    #[arg(long, value_name = "FILE", long_help = pipeline::HELP)]
+    pipeline: Option<String>,
+
+    /// Scale and crop the image to cover this size before processing
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = layout::parse_size,
+          conflicts_with = "eink")]
+    resize: Option<(u32, u32)>,
+
+    /// How --resize and --eink deal with a different aspect ratio: crop (cover), extend
+    /// the edges with their color (extend), or extend only when the edges are near
+    /// uniform as for logos and minimal art (auto)
+    #[arg(long, value_enum, default_value = "cover")]
+    fit: layout::Fit,
+
+    /// Resample for --resize and --eink with this filter in linear light, instead of
+    /// Lanczos3 on the gamma encoded values. Affects how much fine detail survives
+    /// shrinking large photos
+    #[arg(long, value_enum)]
+    resize_filter: Option<layout::ResizeFilter>,
+
+    /// Treat the pixel sizes of --resize, --fade, --safe-area, --frame, --frame-shadow and
+    /// --split-gap as logical pixels and multiply them by this display scale factor, or by the one of
+    /// the desktop (Wayland output scale, Xft.dpi) with auto
+    #[arg(long, value_name = "FACTOR|auto", default_value = "1", value_parser = scale::parse_scale)]
+    scale: scale::Scale,
+
+    #[command(flatten)]
+    source: PaletteArgs,
 
     /// Blur the image
     #[arg(long, short)]
@@ -39,210 +167,2351 @@ struct Args {
     #[arg(long)]
     no_quantize: bool,
 
+    /// Weight the pixels when quantizing: evenly, or by saliency so the reduced colors
+    /// and the mapping favor the subject over large plain areas like the sky
+    #[arg(
+        long,
+        value_enum,
+        default_value = "uniform",
+        conflicts_with = "no_quantize"
+    )]
+    quantize_weighting: render::Weighting,
+
     /// Do not dither the image while quantizing
     #[arg(long)]
     no_dither: bool,
 
+    /// Palette colors (hex codes or roles like @background) that are never dithered, so
+    /// large regions of them stay flat
+    #[arg(long, num_args = 1..)]
+    no_dither_colors: Vec<String>,
+
+    /// How pixels pick their palette color: the nearest one, or by hue bucket and then
+    /// lightness, which keeps the hues of colorful images apart
+    #[arg(long, value_enum, default_value = "nearest")]
+    assign: render::Assign,
+
+    /// Map pixels that no palette color matches well to this color (a hex code or a role
+    /// like @background) instead of the closest one, so a muted scheme doesn't get lone
+    /// neon pixels
+    #[arg(long)]
+    fallback: Option<String>,
+
+    /// Delta E (Oklab) from the closest palette color above which --fallback is used
+    #[arg(long, default_value_t = render::DEFAULT_FALLBACK_DELTA_E, requires = "fallback")]
+    fallback_threshold: f32,
+
     /// Use average algorithm (calculate the average color of each pixel with the pixels around)
     /// to generate the wallpaper, and set the size of the box to calculate the color from.
-    /// A value of 0 disables this
-    #[arg(long, default_value_t = 0)]
-    average: i32,
+    /// A value of 0 disables this. With auto the box is up to 10 pixels around in flat
+    /// regions and shrinks near edges and fine detail, down to the pixel itself
+    #[arg(long, value_name = "SIZE|auto", default_value = "0", value_parser = render::parse_average)]
+    average: render::Average,
+
+    /// Rhai script that picks the color of every pixel after the palette mapping
+    ///
+    /// The script sees pixel, average and mapped (the input color, the average of the box
+    /// around it and the color the mapping picked), x and y, palette, and the roles
+    /// background, foreground and accent. Colors have r, g, b, luminance, chroma, hue and
+    /// hex, and rgb(r, g, b), nearest(color), contrast(a, b) and distance(a, b) are
+    /// available. The script evaluates to a color, a palette index, a hex code or role
+    /// such as "@background", or () to keep mapped. For example:
+    /// if pixel.luminance < 0.1 { background } else if pixel.chroma > 128 { accent }
+    #[arg(long, value_name = "FILE",
+          conflicts_with_all = ["split_tone", "posterize", "ladder", "threshold", "eink"])]
+    script: Option<String>,
+
+    /// Split tone the image with two palette colors instead of mapping it to the palette,
+    /// e.g. shadows=@color4,highlights=@color3,balance=0.1,amount=0.5. Colors can be hex
+    /// codes or the roles @colorN, @background, @foreground and @accent
+    #[arg(long, value_parser = adjust::parse_split_tone, conflicts_with = "posterize")]
+    split_tone: Option<adjust::SplitTone>,
+
+    /// Correct the white balance of the image before processing
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "gray-world")]
+    auto_wb: Option<adjust::WhiteBalance>,
+
+    /// Boost local contrast with adaptive histogram equalization before processing,
+    /// optionally setting the clip limit (higher is stronger)
+    #[arg(long, value_name = "CLIP", num_args = 0..=1, default_missing_value = "2.0")]
+    clahe: Option<f32>,
+
+    /// Lift (positive) or deepen (negative) the shadows before processing, from -100 to 100
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    shadows: f32,
+
+    /// Brighten (positive) or recover (negative) the highlights before processing,
+    /// from -100 to 100
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    highlights: f32,
+
+    /// When the mapped image comes out much darker or brighter than the input, as with
+    /// palettes without mid-tones, brighten or darken the input with a tone curve and map
+    /// it again. Without this only a warning is printed
+    #[arg(long)]
+    auto_exposure_fix: bool,
+
+    /// Posterize the image to N levels per channel instead of mapping it to the palette
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(2..))]
+    posterize: Option<u8>,
+
+    /// Render a contact sheet of the image mapped with the first 2, 4, 8 and 16 palette
+    /// colors, to help choose how many colors of the scheme to use
+    #[arg(long, conflicts_with_all = ["split_tone", "posterize"])]
+    ladder: bool,
+
+    /// Map the subject of the image to these colors (hex codes or roles like @accent)
+    /// instead of the whole palette. The subject is --mask, or found automatically
+    #[arg(long, num_args = 1..,
+          conflicts_with_all = ["split_tone", "posterize", "ladder", "threshold", "eink", "script"])]
+    fg_palette: Vec<String>,
+
+    /// Map the backdrop of the image, everything but the subject, to these colors
+    #[arg(long, num_args = 1..,
+          conflicts_with_all = ["split_tone", "posterize", "ladder", "threshold", "eink", "script"])]
+    bg_palette: Vec<String>,
+
+    /// Image that is white (or opaque) where the subject is, for --fg-palette and
+    /// --bg-palette
+    #[arg(long, value_name = "FILE")]
+    mask: Option<String>,
+
+    /// Map the area behind a window to the palette colors closest to its color, for
+    /// windows that stay in place. CLASS is the window class (app id on Wayland), or
+    /// focused for the focused window. Needs swaymsg, hyprctl or xdotool and a screenshot
+    /// tool, grim or ImageMagick's import
+    #[arg(long, value_name = "CLASS|focused",
+          conflicts_with_all = ["fg_palette", "bg_palette", "mask", "split_tone", "posterize",
+                                "ladder", "threshold", "eink", "script"])]
+    match_window: Option<String>,
+
+    /// Produce a strict two color image using the @background and @foreground palette
+    /// colors, split at a luminance level from 0 to 255 or at one picked with otsu's method.
+    /// Dithered unless --no-dither is given
+    #[arg(long, value_name = "LEVEL|otsu", num_args = 0..=1, default_missing_value = "otsu",
+          value_parser = render::parse_threshold,
+          conflicts_with_all = ["split_tone", "posterize", "ladder"])]
+    threshold: Option<render::Threshold>,
+
+    /// Produce an e-ink screensaver: a grayscale ramp with the given number of levels (or
+    /// the colors given with --palette for color e-paper) with strong ordered dithering,
+    /// rotated and cropped to --eink-size
+    #[arg(long, value_name = "LEVELS", num_args = 0..=1, default_missing_value = "16",
+          value_parser = clap::value_parser!(u8).range(2..),
+          conflicts_with_all = ["split_tone", "posterize", "ladder", "threshold"])]
+    eink: Option<u8>,
+
+    /// Screen size of the e-reader in portrait orientation
+    #[arg(long, value_name = "WIDTHxHEIGHT", default_value = "1072x1448",
+          value_parser = layout::parse_size)]
+    eink_size: (u32, u32),
+
+    /// Enlarge the result of small pixel art inputs such as game art and icons by an
+    /// integer factor, after mapping so it stays crisp and adds no colors: nearest or
+    /// scale2x (which smooths diagonal edges) and the factor, e.g. scale2x:4
+    #[arg(long, value_name = "METHOD:FACTOR", value_parser = layout::parse_upscale,
+          conflicts_with = "eink")]
+    upscale: Option<layout::Upscale>,
+
+    /// Blur and dim the result towards the palette background until the palette's text
+    /// colors stay readable on top of it, for use as a terminal background image
+    #[arg(long)]
+    terminal_bg: bool,
+
+    /// Minimum contrast ratio (WCAG, 1 to 21) text colors need for --terminal-bg and
+    /// --safe-area
+    #[arg(long, default_value_t = 4.5)]
+    min_contrast: f32,
+
+    /// Draw a border this many pixels wide around the result, in a palette role or hex
+    /// color (@accent by default), e.g. 24:@color4
+    #[arg(long, value_name = "PX[:COLOR]", value_parser = effects::parse_frame)]
+    frame: Option<effects::Frame>,
+
+    /// Fade an edge of the result into a palette role or hex color (@background by
+    /// default) over PX pixels, e.g. top:200 for a bar. Can be repeated for more edges
+    #[arg(long, value_name = "EDGE:PX[,COLOR]", value_parser = effects::parse_fade)]
+    fade: Vec<effects::Fade>,
+
+    /// Dim the strip along an edge where a bar lives, e.g. top:40px, towards a palette
+    /// role or hex color (@background by default), just enough for @foreground text to
+    /// reach --min-contrast on it. flat also blurs away its detail. Can be repeated
+    #[arg(long, value_name = "EDGE:PX[,dim|flat][,COLOR]", value_parser = effects::parse_safe_area)]
+    safe_area: Vec<effects::SafeArea>,
+
+    /// Add an inner shadow this many pixels deep to --frame
+    #[arg(long, value_name = "PX", default_value_t = 0, requires = "frame")]
+    frame_shadow: u32,
+
+    /// Save the image after every stage into this directory, to find the one that causes
+    /// a problem: 1-adjusted (after resizing and adjustments), 2-quantized, 3-averaged
+    /// (for --average) and 4-mapped (before effects such as --blur and --frame)
+    #[arg(long, value_name = "DIR")]
+    keep_intermediates: Option<PathBuf>,
+
+    /// Keep quantization results in ~/.cache/imagecolorizer, so rendering the same image
+    /// again with another palette of the same size or other mapping options is faster
+    #[arg(long)]
+    cache: bool,
+
+    /// Slice the result into COLUMNSxROWS tiles for a spanned multi-monitor setup, saved
+    /// as OUTPUT-1, OUTPUT-2, ... from left to right and top to bottom
+    #[arg(long, value_name = "COLUMNSxROWS", value_parser = layout::parse_size)]
+    split: Option<(u32, u32)>,
+
+    /// Pixels between the tiles of --split that are hidden behind the monitor bezels
+    #[arg(long, value_name = "PX", default_value_t = 0, requires = "split")]
+    split_gap: u32,
+
+    /// Millimeters of bezel between two neighbouring screens (both bezels together), an
+    /// alternative to --split-gap that uses the pixel density of the monitors
+    #[arg(
+        long,
+        value_name = "MM",
+        requires = "split",
+        conflicts_with = "split_gap"
+    )]
+    bezel: Option<f32>,
+
+    /// Pixel density of the monitors for --bezel, read from their EDID when not given
+    #[arg(long, requires = "bezel")]
+    dpi: Option<f32>,
+
+    /// Print a BLAKE3 hash of the output pixels, independent of the file format and
+    /// metadata, to check that a render is reproducible
+    #[arg(long)]
+    emit_hash: bool,
+
+    /// Write a JSON manifest of the output files with their dimensions, content hashes,
+    /// cache busting names and the settings used
+    ///
+    /// The manifest has the keys arguments (the command line), palette (the colors as
+    /// hex) and outputs, a list of the files with path, hashed_name, width, height, bytes
+    /// and hash. hash is the BLAKE3 hash of the file and hashed_name the file name with
+    /// the start of the hash added, for cache busting. Paths are relative to the manifest
+    /// when they are inside its directory, and the dimensions are null for vector and
+    /// layered outputs
+    #[arg(long, value_name = "FILE")]
+    emit_manifest: Option<String>,
+
+    /// Embed the palette, the options and the source image in PNG and JPEG outputs, to
+    /// read back with the inspect subcommand. A JPEG with settings that is rendered again
+    /// with the same palette and only --resize is cropped without re-encoding it
+    ///
+    /// The JPEG is copied when it already has the size, and cropped losslessly with
+    /// jpegtran when --fit cover only needs a crop. jpegtran cuts along the 8 or 16 pixel
+    /// blocks of the JPEG, so the crop can be up to a block up and left of the center. The
+    /// settings in the copy are replaced by the ones of the run, or removed without
+    /// --embed-settings. Any other option that changes pixels, or a missing jpegtran,
+    /// renders the image as usual
+    #[arg(long)]
+    embed_settings: bool,
+
+    /// Keep the output file under SIZE, like 500KB: it is encoded as small as its format
+    /// allows, then rendered without dithering and with fewer --extend-shades or
+    /// --expand-palette shades until it fits
+    ///
+    /// The tries go in this order, each keeping what the one before gave up: first the
+    /// render as asked, as a PNG with 1 to 8 bit palette indices or a JPEG at the best
+    /// quality from 95 down to 50 that fits, then the same without dithering, then one
+    /// shade fewer per color at a time, with the palette cut down to its most distinct
+    /// colors. WebP is only written lossless, so for it only the render changes. When
+    /// nothing fits, the smallest try is written with a warning
+    #[arg(long, value_name = "SIZE", value_parser = cache::parse_size,
+          conflicts_with_all = ["embed_settings", "split"])]
+    target_size: Option<u64>,
+
+    /// Also save a preview no larger than SIZE pixels next to the output, as
+    /// <name>.thumb.png
+    #[arg(long, value_name = "SIZE", value_parser = clap::value_parser!(u32).range(1..))]
+    thumbnail: Option<u32>,
+
+    /// Add the output to the freedesktop thumbnail cache, so file managers and pickers
+    /// show the preview right away
+    #[arg(long)]
+    thumbnail_cache: bool,
+
+    /// Keep running and render again whenever the files the palette comes from change,
+    /// such as pywal's cache with --wal
+    #[arg(long, conflicts_with_all = ["palette_stdin", "list_themes"])]
+    watch: bool,
+
+    /// Milliseconds the palette files have to stay unchanged before --watch renders
+    /// again, so a tool writing them several times starts a single render
+    #[arg(long, value_name = "MS", default_value_t = watch::DEFAULT_DEBOUNCE)]
+    watch_debounce: u64,
+
+    /// Also render a dark variant of the image with this built-in theme, as
+    /// <name>-dark.<ext>, and describe the pair in <name>.xml for GNOME, which switches
+    /// between them with the appearance setting. The palette options give the light one
+    #[arg(long, value_name = "THEME", conflicts_with_all = ["pipeline", "split"])]
+    dark_theme: Option<String>,
+
+    /// Install the light and dark variants in ~/.local/share, so they show up in the
+    /// wallpaper picker of GNOME Settings
+    #[arg(long, requires = "dark_theme")]
+    install_gnome: bool,
+
+    /// Also make the @accent color of the palette the accent color of the desktop, with
+    /// gsettings, kwriteconfig or reg. GNOME gets its closest named accent color
+    #[arg(long, value_enum, value_name = "DESKTOP")]
+    export_accent: Option<accent::Desktop>,
+
+    /// Also write the palette roles to a variables file for conky (.lua) or eww (.yuck or
+    /// .scss) widgets
+    ///
+    /// The format follows the extension: .lua is a table of hex colors without the #, for
+    /// conky's local colors = dofile("colors.lua"), .yuck one (defvar ...) per color for
+    /// eww's (include "colors.yuck") and .scss one $variable per color. The colors are
+    /// background, foreground, accent and color0 up to the size of the palette, and
+    /// NAME_average and NAME_text for every --widget-region
+    #[arg(long, value_name = "FILE", value_parser = widgets::parse_target)]
+    widget_colors: Option<widgets::Target>,
+
+    /// Add the average color of this part of the output and a readable text color on it
+    /// to --widget-colors, e.g. clock=300x120+40+40 for the area a widget covers
+    #[arg(long, value_name = "NAME=WIDTHxHEIGHT+X+Y", value_parser = widgets::parse_region,
+          requires = "widget_colors")]
+    widget_region: Vec<widgets::Region>,
+
+    /// Install the output, and its dark variant with --dark-theme, as a Plasma wallpaper
+    /// package in ~/.local/share/wallpapers, so it shows up in the wallpaper chooser
+    #[arg(long, conflicts_with_all = ["pipeline", "split"])]
+    install_kde: bool,
+
+    /// Treat warnings (unused or truncated palette colors, unreadable text colors) as errors
+    #[arg(long)]
+    strict: bool,
+
+    /// In batch mode, render every file again instead of skipping the ones a previous
+    /// (interrupted) run already completed
+    #[arg(long)]
+    no_resume: bool,
+
+    /// In batch mode, render identical input files only once and create the other
+    /// outputs from the first one
+    #[arg(long, value_enum)]
+    dedupe: Option<batch::Dedupe>,
+
+    /// In batch mode, stop at the first file that fails instead of rendering the rest
+    /// and reporting all failures at the end
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// The input directory holds the frames of a video or timelapse: render them in name
+    /// order and reuse the quantization of earlier frames until the colors change, which
+    /// is much faster and doesn't flicker
+    #[arg(long)]
+    sequence: bool,
+
+    /// How far a frame's color histogram may drift from the last quantized frame before
+    /// it is quantized again, from 0 (any change) to 1 (never). The drift is the share of
+    /// pixels that would have to change color bins to turn one histogram into the other
+    #[arg(long, value_name = "AMOUNT", default_value_t = sequence::DEFAULT_THRESHOLD, requires = "sequence")]
+    sequence_threshold: f32,
+
+    /// In batch mode, render every image for each of these built-in themes into
+    /// THEME/NAME in the output directory, quantizing each image only once through the
+    /// quantization cache. The palette modifiers like --exclude apply to every theme
+    #[arg(long, value_name = "THEME,...", value_delimiter = ',',
+          conflicts_with_all = ["theme", "palette", "wal", "wal_file", "wallust", "xresources",
+                                "xresources_file", "base16", "palette_file", "palette_stdin",
+                                "palette_from", "css", "matugen", "gogh", "alacritty", "kitty",
+                                "tmux", "wezterm", "term_colors", "plugin_palette", "pipeline",
+                                "split", "dark_theme", "install_kde", "export_accent",
+                                "widget_colors", "emit_manifest"])]
+    themes: Vec<String>,
+
+    /// Run the output through an installed plugin's processing stage, can be repeated to
+    /// chain stages in order
+    #[arg(long, value_name = "NAME")]
+    plugin: Vec<String>,
 }
 
-fn color_difference(color1: Rgb<u8>, color2: Rgb<u8>) -> u32 {
-    color1
-        .0 // these .0 just extract the [u8] from the Rgb datastructure
-        .iter()
-        .zip(color2.0.iter())
-        // find the difference in all 3 colors and sum them
-        .fold(0, |acc, colors: (&u8, &u8)| {
-            acc + (colors.0.max(colors.1) - colors.0.min(colors.1)) as u32
-        })
+/// Where the palette comes from, shared by the subcommands that need one
+#[derive(clap::Args, Debug)]
+struct PaletteArgs {
+    /// Built-in color scheme to use as the palette, see --list-themes. Without any
+    /// palette option, a dark or light theme is picked to match the system appearance
+    #[arg(long, value_name = "NAME")]
+    theme: Option<String>,
+
+    /// Image palette, as colors like #1a1b26, #abc, 0x1a1b26, 1a1b26, rgb(26, 27, 38) or
+    /// CSS names like rebeccapurple, separated by spaces or commas. A weight after a colon,
+    /// like #1a1b26:3, makes the closest color search prefer (above 1) or avoid (below 1)
+    /// the color and the shades derived from it
+    #[arg(long, short, num_args = 0..)]
+    palette: Option<Vec<String>>,
+
+    /// Add N darker and N lighter shades of every palette color, to get more out of small
+    /// palettes
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "2",
+          value_parser = clap::value_parser!(u8).range(..=palette::MAX_SHADES))]
+    extend_shades: Option<u8>,
+
+    /// Add N shades and N tints of every palette color, spaced evenly in Oklab, for
+    /// smoother gradients from small palettes. Unlike --extend-shades, which mixes in
+    /// sRGB, the steps look even and keep the hue
+    #[arg(long, value_name = "N", conflicts_with = "extend_shades",
+          value_parser = clap::value_parser!(u8).range(..=palette::MAX_SHADES))]
+    expand_palette: Option<u8>,
+
+    /// Cut palettes with more colors down to the N most distinct ones, found by k-means
+    /// clustering, e.g. for 256 color Xresources. --extend-shades and --expand-palette
+    /// add their shades afterwards
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(2..))]
+    max_colors: Option<u8>,
+
+    /// Drop these colors, and colors close to them, from the palette, whatever its source,
+    /// e.g. --exclude black to keep shadows from crushing
+    #[arg(long, num_args = 1.., value_name = "COLOR", value_parser = palette::parse_color)]
+    exclude: Vec<Rgb<u8>>,
+
+    /// Delta E (Oklab) within which --exclude drops a color
+    #[arg(long, default_value_t = palette::DEFAULT_EXCLUDE_DELTA_E, requires = "exclude")]
+    exclude_distance: f32,
+
+    /// Drop palette colors within this delta E (Oklab) of an earlier one, keeping the
+    /// first, for themes and extracted palettes with near duplicates
+    #[arg(long, value_name = "DELTA_E")]
+    merge_distance: Option<f32>,
+
+    /// Sort the palette into a fixed order, so the output doesn't change with the order
+    /// the source lists its colors in. @colorN roles follow the sorted order
+    #[arg(long, value_name = "ORDER")]
+    sort_palette: Option<palette::Order>,
+
+    /// Use palette from pywal
+    #[arg(long, short)]
+    wal: bool,
+
+    /// Use the palette of a pywal colors.json or colors.Xresources file, instead of the
+    /// one in ~/.cache/wal
+    #[arg(long, value_name = "FILE")]
+    wal_file: Option<String>,
+
+    /// Use the palette of wallust: the one its last run left in ~/.cache/wallust, or the
+    /// colors JSON of a wallust cache file or template output. Put -- before the input
+    /// when no path is given
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    wallust: Option<Option<String>>,
+
+    /// Use palette from Xresources
+    #[arg(long, short)]
+    xresources: bool,
+
+    /// Use the palette of an Xresources file such as ~/.Xresources, read directly rather
+    /// than through xrdb, for systems without X
+    #[arg(long, value_name = "FILE")]
+    xresources_file: Option<String>,
+
+    /// Use the palette of a base16 or base24 scheme file (YAML)
+    #[arg(long, value_name = "FILE")]
+    base16: Option<String>,
+
+    /// Use the colors of a palette file: a GIMP palette (.gpl), as downloaded from Lospec,
+    /// or an Adobe Swatch Exchange file (.ase)
+    #[arg(long, value_name = "FILE")]
+    palette_file: Option<String>,
+
+    /// Read the palette from stdin: one color per line, in any of the forms --palette
+    /// takes, or a palette file or pywal colors.json, told apart by their contents
+    #[arg(long)]
+    palette_stdin: bool,
+
+    /// Use the most common colors of a reference image, most common first
+    #[arg(long, value_name = "IMAGE")]
+    palette_from: Option<String>,
+
+    /// Number of colors to extract with --palette-from
+    #[arg(long, value_name = "N", default_value_t = 8, requires = "palette_from",
+          value_parser = clap::value_parser!(u8).range(1..))]
+    palette_colors: u8,
+
+    /// Leave out colors covering less of the reference image than this, so noise and
+    /// specks don't end up in the --palette-from palette
+    #[arg(long, value_name = "SHARE", default_value = "1%", value_parser = extract::parse_share,
+          requires = "palette_from")]
+    min_share: f32,
+
+    /// Use the colors of a CSS style sheet: its color custom properties, like
+    /// --color-bg: #1a1b26, and the hex and rgb()/hsl() colors of its other declarations
+    #[arg(long, value_name = "FILE")]
+    css: Option<String>,
+
+    /// Use a palette from lospec.com by the slug in its URL, e.g. sweetie-16. It is
+    /// downloaded once and cached
+    #[cfg(feature = "net")]
+    #[arg(long, value_name = "SLUG")]
+    lospec: Option<String>,
+
+    /// Use a Material You palette from matugen: the JSON of `matugen image --json hex`,
+    /// or an image to run matugen on
+    #[arg(long, value_name = "JSON|IMAGE")]
+    matugen: Option<String>,
+
+    /// Tones of the dark or light Material You scheme for --matugen
+    #[arg(long, value_enum, default_value_t, requires = "matugen")]
+    matugen_mode: matugen::Mode,
+
+    /// Use a Gogh terminal theme by name, e.g. "Tokyo Night", or a Gogh theme file (YAML).
+    /// Themes are downloaded once and cached
+    #[arg(long, value_name = "THEME")]
+    gogh: Option<String>,
+
+    /// Use the terminal colors of an Alacritty config (TOML or YAML), by default the one
+    /// Alacritty loads. Put -- before the input when no path is given
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    alacritty: Option<Option<String>>,
+
+    /// Use the colors of a kitty config and the files it includes, by default the one
+    /// kitty loads. Put -- before the input when no path is given
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    kitty: Option<Option<String>>,
+
+    /// Use the hex colors of the *-style options of a tmux config or theme, by default the
+    /// config tmux loads. Put -- before the input when no path is given
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    tmux: Option<Option<String>>,
+
+    /// Use a WezTerm color scheme: a scheme file (TOML), the colors of a Lua config, or
+    /// the name of a scheme in ~/.config/wezterm/colors
+    #[arg(long, value_name = "SCHEME")]
+    wezterm: Option<String>,
+
+    /// Use the colors of the terminal the program runs in, asked for with escape
+    /// sequences
+    #[arg(long)]
+    term_colors: bool,
+
+    /// Use the palette provided by an installed plugin
+    #[arg(long, value_name = "NAME")]
+    plugin_palette: Option<String>,
 }
 
-fn average_color(pixels: Vec<Rgb<u8>>) -> Rgb<u8> {
-    let avg = pixels
-        .iter()
-        .map(|pixel| pixel.0) // at this point we have an array of rgb values
-        .fold([0, 0, 0], |mut acc, pixels| {
-            for x in 0..=2 {
-                acc[x] += pixels[x] as usize; // now we calculate the sum for r,g,b
+#[derive(clap::Subcommand, Debug)]
+enum Commands {
+    /// Print histograms, dominant colors, luminance and colorfulness of an image
+    Stats {
+        /// Image to analyze
+        image: String,
+
+        /// Number of dominant colors to report
+        #[arg(long, default_value_t = 8)]
+        colors: u8,
+
+        /// Print the statistics as JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print the average color, luminance and busyness of parts of an image as JSON, for
+    /// bars and widgets to pick their light or dark variant by what is behind them
+    RegionStats {
+        /// Image to analyze, usually the wallpaper
+        image: String,
+
+        /// Part of the image, in pixels
+        #[arg(long, value_name = "X,Y,WIDTH,HEIGHT", value_parser = stats::parse_rect)]
+        rect: Vec<stats::Rect>,
+
+        /// File with one part per line, a name and X,Y,WIDTH,HEIGHT: clock 1600,60,280,120
+        #[arg(long, value_name = "FILE", required_unless_present = "rect")]
+        rects_from: Option<String>,
+    },
+
+    /// Rank the built-in themes by how little an image would have to change to fit them
+    SuggestTheme {
+        /// Image to find a theme for
+        image: String,
+
+        /// Number of colors to extract from the image for the comparison
+        #[arg(long, default_value_t = 16)]
+        colors: u8,
+    },
+
+    /// List the plugins installed in ~/.config/imagecolorizer/plugins
+    Plugins,
+
+    /// Download a wallpaper matching a search and colorize it. Options after -- are used
+    /// for colorizing, as in a normal run: fetch --query mountains out.png -- --wal
+    #[cfg(feature = "net")]
+    Fetch {
+        /// Site to search
+        #[arg(long, value_enum, default_value = "wallhaven")]
+        provider: fetch::Provider,
+
+        /// What to search for
+        #[arg(long)]
+        query: String,
+
+        /// Size of the wallpaper, the download is scaled and cropped to it
+        #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = layout::parse_size)]
+        resolution: Option<(u32, u32)>,
+
+        /// Command that sets the wallpaper, run with the output file as its last
+        /// argument, e.g. "feh --bg-fill", or portal to set it through the desktop portal,
+        /// which works on GNOME, KDE and from inside Flatpak
+        #[arg(long, value_name = "COMMAND|portal")]
+        set: Option<String>,
+
+        /// File to save the colorized wallpaper to
+        output: String,
+
+        /// Options for colorizing
+        #[arg(last = true)]
+        options: Vec<String>,
+    },
+
+    /// Render a directory of wallpapers for several built-in themes and resolutions into
+    /// THEME/RESOLUTION/NAME.png with an index.json. Options after -- are used for
+    /// colorizing: pack wallpapers/ pack/ --theme nord --resolution 1920x1080 -- --average 2
+    Pack {
+        /// Directory of images to render
+        input: String,
+
+        /// Directory to build the pack in
+        output: String,
+
+        /// Built-in theme to render the images in, can be repeated
+        #[arg(long, required = true)]
+        theme: Vec<String>,
+
+        /// Size to scale and crop the images to, can be repeated
+        #[arg(long, value_name = "WIDTHxHEIGHT", required = true,
+              value_parser = layout::parse_size)]
+        resolution: Vec<(u32, u32)>,
+
+        /// Also write a Windows slideshow theme of every theme and resolution to
+        /// windows/THEME-RESOLUTION/, to copy to %LOCALAPPDATA%\Microsoft\Windows\Themes
+        #[arg(long)]
+        windows_theme: bool,
+
+        /// Also pack every Windows theme into a .deskthemepack, which installs it when
+        /// opened
+        #[arg(long, requires = "windows_theme")]
+        deskthemepack: bool,
+
+        /// Also install every image in every theme as a Plasma wallpaper package with a
+        /// variant per resolution, in ~/.local/share/wallpapers
+        #[arg(long)]
+        install_kde: bool,
+
+        /// Options for colorizing
+        #[arg(last = true)]
+        options: Vec<String>,
+    },
+
+    /// Recolor a directory of monochrome SVG and PNG icons, such as an icon theme or the
+    /// PNG frames of an X cursor theme, to palette colors. Other files are copied, so the
+    /// output is a complete theme
+    Tint {
+        /// Directory of icons, searched recursively
+        input: String,
+
+        /// Directory to write the tinted icons to, with the same structure
+        output: String,
+
+        /// Color of the icons, a hex code or a palette role like @accent
+        #[arg(long, default_value = "@foreground")]
+        color: String,
+
+        /// Color of the dark parts of two-tone icons, like the outline of cursors
+        #[arg(long)]
+        shadow: Option<String>,
+
+        #[command(flatten)]
+        source: PaletteArgs,
+
+        /// Treat warnings as errors
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Render assets for chat and streaming profiles, such as banners and overlay
+    /// backgrounds, from the colorized wallpaper or gradients of palette colors. The
+    /// assets and their sizes come from a template
+    ///
+    /// The template is a TOML file of [[assets]], each with a name (it is saved as
+    /// NAME.png), a size like size = "680x240" and a source, "wallpaper" or "gradient",
+    /// by default the wallpaper when --wallpaper is given. Gradients run through colors,
+    /// a list of hex codes or palette roles like colors = ["@accent", "@background"], along
+    /// direction, "vertical" (the default), "horizontal" or "diagonal"
+    Brand {
+        /// Directory to write the assets to
+        output: String,
+
+        /// Colorized wallpaper to crop the assets from
+        #[arg(long, value_name = "IMAGE")]
+        wallpaper: Option<String>,
+
+        /// Template listing the assets, instead of the built-in one
+        #[arg(long, value_name = "FILE")]
+        template: Option<String>,
+
+        #[command(flatten)]
+        source: PaletteArgs,
+
+        /// Treat warnings as errors
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Check the palette
+    Palette {
+        #[command(subcommand)]
+        command: PaletteCommands,
+    },
+
+    /// Generate wallpapers from the palette
+    Generate {
+        #[command(subcommand)]
+        command: GenerateCommands,
+    },
+
+    /// List the connected monitors with their resolution, physical size and density
+    Monitors,
+
+    /// Check xrdb, pywal's cache, the wallpaper setter, the display session and the
+    /// terminal, and print how to fix what doesn't work. Exits with 1 if something is
+    /// broken
+    Doctor {
+        /// Wallpaper setter to check, as given to fetch --set
+        #[arg(long, value_name = "COMMAND|portal")]
+        set: Option<String>,
+    },
+
+    /// List the renders of --watch that can be rolled back to, newest first, numbered by
+    /// how many renders back they are
+    History,
+
+    /// Copy the files of an earlier render of --watch back over its outputs
+    Rollback {
+        /// How many renders to go back, 1 is the one before the last
+        #[arg(default_value_t = 1)]
+        steps: usize,
+    },
+
+    /// Manage the cache in ~/.cache/imagecolorizer
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+
+    /// Print the settings embedded in an image with --embed-settings
+    Inspect {
+        image: String,
+
+        /// Print the settings as JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Render an image made with --embed-settings again, from the same source and with
+    /// the same options except for those given after --, e.g. `rerun old.png -- --wal`
+    /// to update it to the current pywal colors
+    Rerun {
+        /// Image with embedded settings
+        previous: String,
+
+        /// File to write to instead of replacing the previous image
+        #[arg(long, short)]
+        output: Option<String>,
+
+        /// Directory to look for the source in if it moved, can be repeated
+        #[arg(long, value_name = "DIR")]
+        search: Vec<String>,
+
+        /// Use the palette the image was made with, instead of loading it again from
+        /// where it came from
+        #[arg(long)]
+        same_palette: bool,
+
+        /// Options to change
+        #[arg(last = true)]
+        overrides: Vec<String>,
+    },
+
+    /// Check that two images have identical pixels, exits with 1 if they differ
+    Verify {
+        /// Image to check
+        image: String,
+
+        /// Image to compare against, or a hash printed by --emit-hash
+        expected: String,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum GenerateCommands {
+    /// A solid color (or a subtle gradient) wallpaper in the palette color closest to the
+    /// dominant color of an image
+    AverageOf {
+        /// Image to take the color from
+        image: String,
+
+        /// File to generate image to
+        output: String,
+
+        /// Size of the wallpaper
+        #[arg(long, value_name = "WIDTHxHEIGHT", default_value = "1920x1080",
+              value_parser = layout::parse_size)]
+        size: (u32, u32),
+
+        /// Fade into the closest other palette color towards the bottom
+        #[arg(long)]
+        gradient: bool,
+
+        #[command(flatten)]
+        source: PaletteArgs,
+
+        /// Treat warnings as errors
+        #[arg(long)]
+        strict: bool,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum PaletteCommands {
+    /// Print the pairwise delta E and contrast of the palette colors and list the ones
+    /// that will be hard to tell apart on the wallpaper. Exits with 1 if there are any
+    Audit {
+        #[command(flatten)]
+        source: PaletteArgs,
+
+        /// Delta E (Oklab, 2 is just noticeable) below which two colors are flagged
+        #[arg(long, default_value_t = audit::DEFAULT_MIN_DELTA_E)]
+        min_delta_e: f32,
+
+        /// Treat warnings about the palette as errors
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Print the palette the palette options come down to, after --exclude, --max-colors
+    /// and the other modifiers, as a render would use it
+    Export {
+        #[command(flatten)]
+        source: PaletteArgs,
+
+        /// How to print the palette
+        #[arg(long, value_enum, default_value_t)]
+        format: palette_export::Format,
+
+        /// Treat warnings about the palette as errors
+        #[arg(long)]
+        strict: bool,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CacheCommands {
+    /// Remove old entries, then the least recently used ones until the cache fits. The
+    /// --watch history is left alone, it keeps its last renders itself
+    Gc {
+        /// Largest size the cache may keep, e.g. 500M or 2G
+        #[arg(long, default_value = cache::DEFAULT_MAX_SIZE, value_parser = cache::parse_size)]
+        max_size: u64,
+
+        /// Remove entries that were not used for this long, e.g. 30d, 12h or 2w
+        #[arg(long, default_value = cache::DEFAULT_MAX_AGE, value_parser = cache::parse_age)]
+        max_age: std::time::Duration,
+    },
+
+    /// Print the number of entries and the size of each cache
+    Stats,
+}
+
+fn decode_xresources(contents: String) -> Vec<Rgb<u8>> {
+    // colorN resources are kept in their numbered slots so @colorN roles line up with
+    // the scheme, everything else (background, foreground, ...) goes after them
+    let mut numbered: Vec<Option<Rgb<u8>>> = Vec::new();
+    let mut others = Vec::new();
+    for line in contents.lines() {
+        let (name, value) = line.split_once(':').unwrap_or(("", line));
+        let colors = value
+            .split(" ") // split the value into words
+            .filter(|split| split.contains("#")) // only retain the color column
+            .flat_map(|substr| substr.split_inclusive("#")) // split out the hash and any text before
+            .filter(|split| !split.contains("#")) // only retain the hex codes
+            .filter_map(|hex_str| palette::parse_hex(hex_str).ok());
+        let index = name
+            .rsplit_once("color")
+            .and_then(|(_, index)| index.trim().parse::<usize>().ok());
+        for color in colors {
+            match index {
+                Some(index) => {
+                    if numbered.len() <= index {
+                        numbered.resize(index + 1, None);
+                    }
+                    numbered[index].get_or_insert(color);
+                }
+                None => others.push(color),
             }
-            return acc;
-        });
+        }
+    }
+    palette::dedup(numbered.into_iter().flatten().chain(others).collect())
+}
 
-    let red = (avg[0] / pixels.len()).clamp(0, 255) as u8; // now we calculate the average
-    let green = (avg[1] / pixels.len()).clamp(0, 255) as u8;
-    let blue = (avg[2] / pixels.len()).clamp(0, 255) as u8;
-    return Rgb([red, green, blue]);
+/// Do what xrdb's preprocessor would for an Xresources file: drop `!` comments and
+/// other directives, follow `#include "file"` relative to `dir` and substitute `#define`d
+/// names, as schemes often name their colors
+fn preprocess_xresources(
+    contents: &str,
+    dir: &Path,
+    defines: &mut Vec<(String, String)>,
+    depth: usize,
+) -> Result<String, Error> {
+    // deepest chain of includes followed, against include loops
+    const MAX_INCLUDE_DEPTH: usize = 5;
+    let substitute = |line: &str, defines: &[(String, String)]| {
+        let mut output = String::with_capacity(line.len());
+        let mut rest = line;
+        while !rest.is_empty() {
+            let length = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if length == 0 {
+                let next = rest.chars().next().unwrap();
+                output.push(next);
+                rest = &rest[next.len_utf8()..];
+                continue;
+            }
+            let (word, after) = rest.split_at(length);
+            match defines.iter().rev().find(|(name, _)| name == word) {
+                Some((_, value)) => output.push_str(value),
+                None => output.push_str(word),
+            }
+            rest = after;
+        }
+        output
+    };
+    let mut output = String::new();
+    for line in contents.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('!') {
+            continue;
+        }
+        let Some(directive) = trimmed.strip_prefix('#') else {
+            output.push_str(&substitute(line, defines));
+            output.push('\n');
+            continue;
+        };
+        let directive = directive.trim_start();
+        if let Some(definition) = directive.strip_prefix("define") {
+            let mut words = definition.trim().splitn(2, char::is_whitespace);
+            if let Some(name) = words.next().filter(|name| !name.is_empty()) {
+                let value = substitute(words.next().unwrap_or_default().trim(), defines);
+                defines.push((name.to_string(), value));
+            }
+        } else if let Some(include) = directive.strip_prefix("include") {
+            let name = include.trim().trim_matches(['"', '<', '>']);
+            let path = dir.join(name);
+            if depth < MAX_INCLUDE_DEPTH {
+                let included = fs::read_to_string(&path).map_err(|err| {
+                    Error::Palette(format!("could not read {}: {}", path.display(), err))
+                })?;
+                let included_dir = path.parent().unwrap_or(dir).to_path_buf();
+                output.push_str(&preprocess_xresources(
+                    &included,
+                    &included_dir,
+                    defines,
+                    depth + 1,
+                )?);
+            }
+        }
+    }
+    Ok(output)
 }
 
-fn decode_xresources(contents: String) -> Vec<Rgb<u8>>{
-    let palette : HashSet<Rgb<u8>> = contents
-        .lines()
-        .map(|line| line.split(" ")) // split each line into the two colums (id and color)
-        .flatten()
-        .filter(|split| split.contains("#")) // only retain the color column
-        .map(|substr| substr.split_inclusive("#")) // split out the hash and any text before
-        .flatten()
-        .filter(|split| !split.contains("#")) // only retain the hex codes
-        .map(|hex_str| {
-            let hex_num = u32::from_str_radix(hex_str, 16).unwrap();
-            let r = (hex_num >> 16) as u8;
-            let g = ((hex_num >> 8) & 0x00FF) as u8;
-            let b = (hex_num & 0x0000_00FF) as u8;
-            Rgb([r, g, b])
-        })
-        .collect();
-    return palette.into_iter().collect();
+/// The colors of an Xresources file, read directly instead of through xrdb
+fn xresources_load_file(path: &Path) -> Result<Vec<Rgb<u8>>, Error> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| Error::Palette(format!("could not read {}: {}", path.display(), err)))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let contents = preprocess_xresources(&contents, dir, &mut Vec::new(), 0)?;
+    Ok(decode_xresources(contents))
 }
 
-fn xresources_load() -> Vec<Rgb<u8>>{
-    use std::str;
-    let xrdb_output = Command::new("xrdb")
+fn xresources_load() -> Result<Vec<Rgb<u8>>, Error> {
+    let xrdb_output = exec::command("xrdb", "--xresources")?
         .arg("-query")
         .output()
-        .expect("failed to execute xrdb")
-        .stdout;
-    let mut contents = String::new();
-    contents.push_str(match str::from_utf8(&xrdb_output) {
-        Ok(val) => val,
-        Err(_) => panic!("got non UTF-8 data from xrdb"),
-    });
-    return decode_xresources(contents)
+        .map_err(|err| Error::ExternalTool(format!("failed to execute xrdb: {}", err)))?;
+    if !xrdb_output.status.success() {
+        return Err(Error::ExternalTool(format!(
+            "xrdb -query failed: {}",
+            String::from_utf8_lossy(&xrdb_output.stderr).trim()
+        )));
+    }
+    let contents = String::from_utf8(xrdb_output.stdout)
+        .map_err(|_| Error::ExternalTool("got non UTF-8 data from xrdb".to_string()))?;
+    Ok(decode_xresources(contents))
 }
 
-fn pywal_load() -> Vec<Rgb<u8>> {
-    let mut xres_loc = my_home().unwrap().unwrap();
-    xres_loc.push(".cache/wal/colors.Xresources");
-    let mut pywal_xres = File::open(xres_loc).unwrap();
-    let mut contents = String::new();
-    pywal_xres.read_to_string(&mut contents).unwrap();
+/// The colors of pywal's colors.json: color0 to color15 in their slots, followed by the
+/// special background, foreground and cursor colors
+fn decode_pywal_json(contents: &str) -> Result<Vec<Rgb<u8>>, String> {
+    let json: serde_json::Value = serde_json::from_str(contents).map_err(|err| err.to_string())?;
+    let colors = json["colors"]
+        .as_object()
+        .ok_or_else(|| "there is no colors section".to_string())?;
+    let mut numbered: Vec<(usize, Rgb<u8>)> = colors
+        .iter()
+        .filter_map(|(name, value)| {
+            let index = name.strip_prefix("color")?.parse::<usize>().ok()?;
+            Some((index, palette::parse_hex(value.as_str()?).ok()?))
+        })
+        .collect();
+    numbered.sort_by_key(|(index, _)| *index);
+    let special = ["background", "foreground", "cursor"]
+        .into_iter()
+        .filter_map(|name| palette::parse_hex(json["special"][name].as_str()?).ok());
+    Ok(palette::dedup(
+        numbered
+            .into_iter()
+            .map(|(_, color)| color)
+            .chain(special)
+            .collect(),
+    ))
+}
 
-    return decode_xresources(contents);
- }
+/// Load a pywal cache file, colors.json or colors.Xresources by its extension
+fn pywal_load_file(path: &Path) -> Result<Vec<Rgb<u8>>, Error> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| Error::Palette(format!("could not read {}: {}", path.display(), err)))?;
+        decode_pywal_json(&contents)
+            .map_err(|err| Error::Palette(format!("{}: {}", path.display(), err)))
+    } else {
+        xresources_load_file(path)
+    }
+}
 
-fn main() {
-    let args = Args::parse();
-    let mut input_img = ImageReader::open(args.input)
-        .unwrap()
+/// pywal's colors.json, or the colors.Xresources it writes next to it
+fn pywal_load() -> Result<Vec<Rgb<u8>>, Error> {
+    let mut cache = my_home()
+        .ok()
+        .flatten()
+        .ok_or_else(|| Error::Palette("could not find the home directory".to_string()))?;
+    cache.push(".cache/wal");
+    let json = cache.join("colors.json");
+    if json.is_file() {
+        return pywal_load_file(&json);
+    }
+    pywal_load_file(&cache.join("colors.Xresources"))
+}
+
+fn open_image(path: &str) -> Result<RgbImage, Error> {
+    Ok(ImageReader::open(path)
+        .map_err(|err| Error::Input(format!("could not open {}: {}", path, err)))?
         .decode()
-        .unwrap()
-        .into_rgb8(); //enforce rgb8
-    let mut output_img = RgbImage::new(input_img.dimensions().0, input_img.dimensions().1);
-    // default palette
-    let mut palette = vec![
-        Rgb([0, 0, 0]),
-        Rgb([29, 43, 83]),
-        Rgb([126, 37, 83]),
-        Rgb([0, 135, 81]),
-        Rgb([171, 82, 54]),
-        Rgb([95, 87, 79]),
-        Rgb([194, 195, 199]),
-        Rgb([255, 241, 232]),
-        Rgb([255, 0, 77]),
-        Rgb([255, 163, 0]),
-        Rgb([255, 236, 39]),
-        Rgb([0, 228, 54]),
-        Rgb([41, 173, 255]),
-        Rgb([131, 118, 156]),
-        Rgb([255, 119, 168]),
-        Rgb([255, 204, 170]),
-    ];
+        .map_err(|err| Error::Input(format!("could not decode {}: {}", path, err)))?
+        .into_rgb8()) //enforce rgb8
+}
+
+fn run_command(command: Commands) -> Result<ExitCode, Error> {
+    match command {
+        Commands::Stats {
+            image,
+            colors,
+            json,
+        } => {
+            let stats = stats::compute(&open_image(&image)?, colors)
+                .map_err(|err| Error::Input(format!("{}: {}", image, err)))?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+            } else {
+                stats::print_text(&stats);
+            }
+        }
+        Commands::RegionStats {
+            image,
+            mut rect,
+            rects_from,
+        } => {
+            if let Some(path) = &rects_from {
+                rect.extend(stats::read_rects(path).map_err(Error::Input)?);
+            }
+            let img = open_image(&image)?;
+            let regions = rect
+                .iter()
+                .map(|rect| {
+                    stats::region(&img, rect).ok_or_else(|| {
+                        Error::Input(format!(
+                            "the rect {},{},{},{} is outside of the {}x{} image",
+                            rect.x,
+                            rect.y,
+                            rect.width,
+                            rect.height,
+                            img.width(),
+                            img.height()
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            println!("{}", serde_json::to_string_pretty(&regions).unwrap());
+        }
+        Commands::SuggestTheme { image, colors } => {
+            let img = open_image(&image)?;
+            let matches = stats::suggest_themes(&img, colors)
+                .map_err(|err| Error::Input(format!("{}: {}", image, err)))?;
+            println!(
+                "{:<4} {:<20} {:>10} {:>10}",
+                "rank", "theme", "alteration", "hausdorff"
+            );
+            for (rank, theme) in matches.iter().enumerate() {
+                println!(
+                    "{:<4} {:<20} {:>10.2} {:>10.2}",
+                    rank + 1,
+                    theme.name,
+                    theme.alteration,
+                    theme.hausdorff
+                );
+            }
+        }
+        Commands::Plugins => {
+            if let Some(dir) = plugins::plugin_dir() {
+                println!("plugin directory: {}", dir.display());
+            }
+            for plugin in plugins::discover() {
+                let mut provides = Vec::new();
+                if plugin.has_palette() {
+                    provides.push("palette");
+                }
+                if plugin.has_process() {
+                    provides.push("process");
+                }
+                println!(
+                    "{:<20} {:<16} {}",
+                    plugin.name,
+                    provides.join(","),
+                    plugin.path.display()
+                );
+            }
+        }
+        #[cfg(feature = "net")]
+        Commands::Fetch {
+            provider,
+            query,
+            resolution,
+            set,
+            output,
+            options,
+        } => {
+            let download = fetch::fetch(provider, &query, resolution)?;
+            let mut argv = vec!["imagecolorizer".to_string()];
+            let sized = options
+                .iter()
+                .any(|option| option.starts_with("--resize") || option.starts_with("--eink"));
+            if let (Some((width, height)), false) = (resolution, sized) {
+                argv.extend(["--resize".to_string(), format!("{}x{}", width, height)]);
+            }
+            argv.extend(options);
+            argv.extend([
+                "--".to_string(),
+                download.to_string_lossy().to_string(),
+                output.clone(),
+            ]);
+            let mut args = Args::try_parse_from(&argv).unwrap_or_else(|err| err.exit());
+            args.argv = argv;
+            if args.command.is_some() {
+                return Err(Error::Input(
+                    "the options after -- can't contain a subcommand".to_string(),
+                ));
+            }
+            run(args)?;
+            if set.as_deref() == Some("portal") {
+                portal::set_wallpaper(Path::new(&output))?;
+            } else if let Some(set) = set {
+                let mut words = set.split_whitespace();
+                let program = words
+                    .next()
+                    .ok_or_else(|| Error::Input("--set needs a command".to_string()))?;
+                let status = exec::command(program, "--set")?
+                    .args(words)
+                    .arg(&output)
+                    .status()
+                    .map_err(|err| {
+                        Error::ExternalTool(format!("failed to execute {}: {}", program, err))
+                    })?;
+                if !status.success() {
+                    return Err(Error::ExternalTool(format!("{} failed: {}", set, status)));
+                }
+            }
+        }
+        Commands::Pack {
+            input,
+            output,
+            theme,
+            resolution,
+            windows_theme,
+            deskthemepack,
+            install_kde,
+            options,
+        } => pack::run(
+            &input,
+            &output,
+            &theme,
+            &resolution,
+            &options,
+            &pack::Packaging {
+                windows_theme,
+                deskthemepack,
+                install_kde,
+            },
+        )?,
+        Commands::Brand {
+            output,
+            wallpaper,
+            template,
+            source,
+            strict,
+        } => {
+            let palette = load_palette(&source, strict)?;
+            brand::run(&output, wallpaper.as_deref(), template.as_deref(), &palette)?;
+        }
+        Commands::Tint {
+            input,
+            output,
+            color,
+            shadow,
+            source,
+            strict,
+        } => {
+            let palette = load_palette(&source, strict)?;
+            let resolve =
+                |spec: &str| palette::resolve_color(spec, &palette).map_err(Error::Palette);
+            let shadow = shadow.as_deref().map(resolve).transpose()?;
+            tint::run(&input, &output, resolve(&color)?, shadow, strict)?;
+        }
+        Commands::Generate {
+            command:
+                GenerateCommands::AverageOf {
+                    image,
+                    output,
+                    size: (width, height),
+                    gradient,
+                    source,
+                    strict,
+                },
+        } => {
+            let palette = load_palette(&source, strict)?;
+            let dominant = stats::dominant_colors(&open_image(&image)?, 4)
+                .map_err(|err| Error::Input(format!("{}: {}", image, err)))?;
+            let (dominant, _) = *dominant
+                .first()
+                .ok_or_else(|| Error::Input(format!("{} has no pixels", image)))?;
+            let top = render::nearest_color(dominant, &palette);
+            let bottom = if gradient {
+                // the closest other color keeps the gradient subtle
+                palette
+                    .iter()
+                    .filter(|color| **color != top)
+                    .min_by(|a, b| color::delta_e(top, **a).total_cmp(&color::delta_e(top, **b)))
+                    .copied()
+                    .unwrap_or(top)
+            } else {
+                top
+            };
+            effects::gradient(width, height, top, bottom)
+                .save(&output)
+                .map_err(|err| Error::Encode(format!("could not write {}: {}", output, err)))?;
+            println!("{}", palette::to_hex(top));
+        }
+        Commands::Monitors => {
+            for monitor in edid::connected_monitors() {
+                println!(
+                    "{:<16} {}x{} {}x{}mm {:.0} dpi",
+                    monitor.name,
+                    monitor.width,
+                    monitor.height,
+                    monitor.width_mm,
+                    monitor.height_mm,
+                    monitor.pixels_per_mm() * 25.4
+                );
+            }
+        }
+        Commands::Doctor { set } => {
+            if doctor::print(set.as_deref()) {
+                return Ok(ExitCode::from(1));
+            }
+        }
+        Commands::History => history::print(),
+        Commands::Rollback { steps } => history::rollback(steps)?,
+        Commands::Cache {
+            command: CacheCommands::Gc { max_size, max_age },
+        } => {
+            let (files, bytes) = cache::gc(max_size, max_age)?;
+            println!("removed {} files, {}", files, cache::format_size(bytes));
+        }
+        Commands::Cache {
+            command: CacheCommands::Stats,
+        } => cache::print_stats(),
+        Commands::Palette {
+            command:
+                PaletteCommands::Audit {
+                    source,
+                    min_delta_e,
+                    strict,
+                },
+        } => {
+            if audit::print(&load_palette(&source, strict)?, min_delta_e) {
+                return Ok(ExitCode::from(1));
+            }
+        }
+        Commands::Palette {
+            command:
+                PaletteCommands::Export {
+                    source,
+                    format,
+                    strict,
+                },
+        } => print!(
+            "{}",
+            palette_export::format(&load_palette(&source, strict)?, format)?
+        ),
+        Commands::Inspect { image, json } => {
+            let provenance = provenance::read(Path::new(&image))?
+                .ok_or_else(|| Error::Input(format!("{} has no embedded settings", image)))?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&provenance).unwrap());
+            } else {
+                provenance::print_text(&provenance);
+            }
+        }
+        Commands::Rerun {
+            previous,
+            output,
+            search,
+            same_palette,
+            overrides,
+        } => run(rerun::args(
+            &previous,
+            output.as_deref(),
+            &search,
+            same_palette,
+            &overrides,
+        )?)?,
+        Commands::Verify { image, expected } => {
+            let actual = hash::pixel_hash(&open_image(&image)?);
+            let expected = match blake3::Hash::from_hex(&expected) {
+                Ok(hash) if !Path::new(&expected).exists() => hash,
+                _ => hash::pixel_hash(&open_image(&expected)?),
+            };
+            if actual != expected {
+                println!("differ {} != {}", actual, expected);
+                return Ok(ExitCode::from(1));
+            }
+            println!("match {}", actual);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn load_palette(args: &PaletteArgs, strict: bool) -> Result<Vec<Rgb<u8>>, Error> {
+    Ok(palette::colors(&load_weighted_palette(args, strict)?))
+}
+
+/// The palette with the weights of --palette, 1 for the colors of every other source
+fn load_weighted_palette(
+    args: &PaletteArgs,
+    strict: bool,
+) -> Result<Vec<palette::PaletteEntry>, Error> {
+    let mut palette = args
+        .theme
+        .as_ref()
+        .map(|name| themes::find(name).map(|theme| theme.palette()))
+        .transpose()?;
 
     if args.wal {
-        palette = pywal_load();
+        palette = Some(pywal_load()?);
+    }
+
+    if let Some(path) = &args.wal_file {
+        palette = Some(pywal_load_file(Path::new(path))?);
+    }
+
+    if let Some(path) = &args.wallust {
+        palette = Some(wallust::load(path.as_deref())?);
     }
 
     if args.xresources {
-        palette = xresources_load();
-    }
-
-    if let Some(palette_input) = args.palette {
-        if palette_input.len() == 0 {
-            panic!("Palette input malformed")
-        } else {
-            let palette_hash : HashSet<Rgb<u8>> = palette_input.iter().filter(|split| split.contains("#")) // only retain the color column
-                .map(|substr| substr.split_inclusive("#")) // split out the hash and any text before
-                .flatten()
-                .filter(|split| !split.contains("#")) // only retain the hex codes
-                .map(|hex_str| {
-                    let hex_num = u32::from_str_radix(hex_str, 16).unwrap();
-                    let r = (hex_num >> 16) as u8;
-                    let g = ((hex_num >> 8) & 0x00FF) as u8;
-                    let b = (hex_num & 0x0000_00FF) as u8;
-                    Rgb([r, g, b])
-                })
-                .collect();
-            palette = palette_hash.into_iter().collect();
-        }
-    }
-
-    if !args.no_quantize {
-        input_img = ImagePipeline::try_from(&input_img)
-            .unwrap()
-            .palette_size(palette.len() as u8) // limit the no. of colors to the length of the pallet
-            .dither(!args.no_dither)
-            .colorspace(ColorSpace::Oklab) // use a more accurate color space
-            .quantize_method(QuantizeMethod::kmeans()) // use a more accurate quantization algorithm
-            .quantized_rgbimage_par(); // run the pipeline in parallel to get a [`RgbImage`]
-    }
-    let output: Vec<Rgb<u8>> = input_img
-        .par_enumerate_pixels()
-        .map(|(x, y, pixel)| {
-            // lazy way of checking for averaging
-            if args.average > 0 {
-                // To get the average for a group of pixels, instead of using a 2d vector
-                // we flatten all of
-                let mut pixel_vec = Vec::<Rgb<u8>>::new();
-                // get pixels within a range about the central pixel
-                for row in -args.average..args.average {
-                    for column in -args.average..args.average {
-                        // this block is limited in image sizes and the
-                        // conversions ultimately as long as nobody attempts to
-                        // use a massive image we should be fine
-                        if let Some(pixel) = input_img.get_pixel_checked(
-                            ((x as i32) + column).clamp(0, input_img.width() as i32) as u32,
-                            ((y as i32) + row).clamp(0, input_img.height() as i32) as u32,
-                        ) {
-                            pixel_vec.push(*pixel);
-                        }
-                    }
-                }
-                return average_color(pixel_vec);
-            } else {
-                return *pixel;
+        palette = Some(xresources_load()?);
+    }
+
+    if let Some(path) = &args.xresources_file {
+        palette = Some(xresources_load_file(Path::new(path))?);
+    }
+
+    if let Some(path) = &args.base16 {
+        palette = Some(base16::load(path)?);
+    }
+
+    if let Some(path) = &args.palette_file {
+        palette = Some(palette_file::load(path)?);
+    }
+
+    if args.palette_stdin {
+        palette = Some(palette_file::load_stdin()?);
+    }
+
+    if let Some(path) = &args.palette_from {
+        palette = Some(
+            extract::palette(&open_image(path)?, args.palette_colors, args.min_share)
+                .map_err(|err| Error::Palette(format!("{}: {}", path, err)))?,
+        );
+    }
+
+    if let Some(path) = &args.css {
+        palette = Some(css::load(path)?);
+    }
+
+    #[cfg(feature = "net")]
+    if let Some(slug) = &args.lospec {
+        palette = Some(lospec::load(slug)?);
+    }
+
+    if let Some(source) = &args.matugen {
+        palette = Some(matugen::load(source, args.matugen_mode)?);
+    }
+
+    if let Some(theme) = &args.gogh {
+        palette = Some(gogh::load(theme)?);
+    }
+
+    if let Some(path) = &args.alacritty {
+        palette = Some(alacritty::load(path.as_deref())?);
+    }
+
+    if let Some(path) = &args.kitty {
+        palette = Some(kitty::load(path.as_deref())?);
+    }
+
+    if let Some(path) = &args.tmux {
+        palette = Some(tmux::load(path.as_deref())?);
+    }
+
+    if let Some(scheme) = &args.wezterm {
+        palette = Some(wezterm::load(scheme)?);
+    }
+
+    if args.term_colors {
+        palette = Some(term_colors::load()?);
+    }
+
+    if let Some(name) = &args.plugin_palette {
+        palette = Some(plugins::find(&plugins::discover(), name)?.palette()?);
+    }
+
+    let mut palette = palette.map(palette::unweighted);
+    if let Some(palette_input) = &args.palette {
+        let mut entries = Vec::new();
+        for list in palette_input {
+            entries.extend(palette::parse_entries(list).map_err(Error::Palette)?);
+        }
+        if entries.is_empty() {
+            return Err(Error::Palette(
+                "--palette needs at least one color".to_string(),
+            ));
+        }
+        for (index, entry) in entries.iter().enumerate() {
+            if let Some(other) = entries[..index]
+                .iter()
+                .find(|other| other.color == entry.color && other.weight != entry.weight)
+            {
+                return Err(Error::Palette(format!(
+                    "--palette gives {} the weights {} and {}, give it once",
+                    palette::to_hex(entry.color),
+                    other.weight,
+                    entry.weight
+                )));
             }
-        })
-        // this map finds the closest color within the pallet and selects it
-        .map(|averaged_pixel| {
-            palette
+        }
+        palette = Some(palette::dedup_entries(entries));
+    }
+    let palette = match palette {
+        Some(palette) => palette,
+        None => {
+            let (name, reason) = appearance::default_theme();
+            eprintln!("no palette given and {}, using the {} theme", reason, name);
+            palette::unweighted(themes::find(name)?.palette())
+        }
+    };
+    refine_palette(palette, args, strict)
+}
+
+/// Apply the palette options that change a loaded palette: exclude, merge, reduce, add
+/// shades and sort. The colors that are kept keep their weight
+fn refine_palette(
+    mut palette: Vec<palette::PaletteEntry>,
+    args: &PaletteArgs,
+    strict: bool,
+) -> Result<Vec<palette::PaletteEntry>, Error> {
+    if palette.is_empty() {
+        return Err(Error::Palette("the palette has no colors".to_string()));
+    }
+    if !args.exclude.is_empty() {
+        for excluded in &args.exclude {
+            let matched = palette
                 .iter()
-                // this map finds the differences for all colors in the palette
-                // compared to the pixel
-                .map(|color| (color.clone(), color_difference(averaged_pixel, *color)))
-                // this fold actually finds the closest palette color
-                .fold((Rgb([0, 0, 0]), u32::MAX), |lowest_current, x| {
-                    if x.1 < lowest_current.1 {
-                        x
-                    } else {
-                        lowest_current
-                    }
-                })
-                .0
-        })
+                .any(|entry| color::delta_e(entry.color, *excluded) <= args.exclude_distance);
+            if !matched {
+                warn(
+                    strict,
+                    Error::Palette(format!(
+                        "--exclude {} matches no palette color",
+                        palette::to_hex(*excluded)
+                    )),
+                )?;
+            }
+        }
+        let kept = palette::exclude(
+            &palette::colors(&palette),
+            &args.exclude,
+            args.exclude_distance,
+        );
+        palette = palette::keep_weights(&palette, kept);
+        if palette.is_empty() {
+            return Err(Error::Palette(
+                "--exclude removed every color of the palette".to_string(),
+            ));
+        }
+    }
+    if let Some(distance) = args.merge_distance {
+        let kept = palette::merge_close(&palette::colors(&palette), distance);
+        palette = palette::keep_weights(&palette, kept);
+    }
+    if let Some(colors) = args.max_colors {
+        let kept = palette::reduce(&palette::colors(&palette), colors);
+        palette = palette::keep_weights(&palette, kept);
+    }
+    if let Some(shades) = args.extend_shades {
+        palette = palette::extend_shades(&palette, shades).map_err(Error::Palette)?;
+    }
+    if let Some(steps) = args.expand_palette {
+        palette = palette::expand(&palette, steps).map_err(Error::Palette)?;
+    }
+    if let Some(order) = args.sort_palette {
+        let sorted = palette::sort(&palette::colors(&palette), order);
+        palette = palette::keep_weights(&palette, sorted);
+    }
+    let distinct = palette::distinct_colors(&palette::colors(&palette));
+    if distinct.len() < 2 {
+        warn(
+            strict,
+            Error::Palette(format!(
+                "the palette only has one usable color ({}), the output will be a flat image \
+                 of it. Add colors, or use --extend-shades to derive shades from it",
+                palette::to_hex(distinct[0])
+            )),
+        )?;
+    }
+    Ok(palette)
+}
+
+/// Warn when the palette has more colors than quantization can reduce an image to, so
+/// some of them can't get a cluster of their own
+fn warn_truncated_quantization(palette: &[Rgb<u8>], args: &Args) -> Result<(), Error> {
+    if palette.len() <= render::MAX_COLORS || args.no_quantize {
+        return Ok(());
+    }
+    warn(
+        args.strict,
+        Error::Palette(format!(
+            "quantization is limited to {} colors, the palette has {}: the image is \
+             quantized to {} colors before they are mapped to the palette. Use --max-colors \
+             or --no-quantize",
+            render::MAX_COLORS,
+            palette.len(),
+            render::MAX_COLORS
+        )),
+    )
+}
+
+/// Warn about palette colors that do not appear anywhere in a mapped image
+fn warn_unused_colors(img: &RgbImage, palette: &[Rgb<u8>], strict: bool) -> Result<(), Error> {
+    // one pass over the image, however large the palette
+    let used: HashSet<Rgb<u8>> = img.pixels().copied().collect();
+    let unused: Vec<String> = palette
+        .iter()
+        .filter(|color| !used.contains(*color))
+        .map(|color| palette::to_hex(*color))
         .collect();
+    if !unused.is_empty() {
+        warn(
+            strict,
+            Error::Palette(format!(
+                "{} of {} palette colors are unused: {}",
+                unused.len(),
+                palette.len(),
+                unused.join(" ")
+            )),
+        )?;
+    }
+    Ok(())
+}
+
+/// `--terminal-bg`: dim the image until the palette's text colors are readable on it
+fn terminal_background(
+    img: &RgbImage,
+    palette: &[Rgb<u8>],
+    min_contrast: f32,
+    strict: bool,
+) -> Result<RgbImage, Error> {
+    let background = palette::resolve_color("@background", palette).map_err(Error::Palette)?;
+    // colors that aren't readable on the plain background can never pass, leave them
+    // out rather than dimming the image away entirely
+    let (text_colors, unreadable): (Vec<Rgb<u8>>, Vec<Rgb<u8>>) = palette
+        .iter()
+        .filter(|color| **color != background)
+        .partition(|color| color::contrast_ratio(**color, background) >= min_contrast);
+    let (dimmed, dim) = effects::terminal_background(img, background, &text_colors, min_contrast);
+    println!(
+        "dimmed {:.0}% towards the background, {} text colors reach a contrast of {}",
+        dim * 100.0,
+        text_colors.len(),
+        min_contrast
+    );
+    for color in unreadable {
+        warn(
+            strict,
+            Error::Palette(format!(
+                "{} only has a contrast of {:.2} on the plain background",
+                palette::to_hex(color),
+                color::contrast_ratio(color, background)
+            )),
+        )?;
+    }
+    Ok(dimmed)
+}
+
+fn render_options(palette: &[Rgb<u8>], args: &Args) -> Result<render::RenderOptions, Error> {
+    Ok(render::RenderOptions {
+        quantize: !args.no_quantize,
+        weighting: args.quantize_weighting,
+        dither: !args.no_dither,
+        average: args.average,
+        flat: args
+            .no_dither_colors
+            .iter()
+            .map(|spec| palette::resolve_color(spec, palette).map_err(Error::Palette))
+            .collect::<Result<_, _>>()?,
+        cache: args.cache,
+        intermediates: args.keep_intermediates.clone(),
+        assign: args.assign,
+        deterministic: args.deterministic,
+        sequence: args.sequence.then_some(args.sequence_threshold),
+        fallback: args
+            .fallback
+            .as_deref()
+            .map(|spec| {
+                Ok(render::Fallback {
+                    color: palette::resolve_color(spec, palette).map_err(Error::Palette)?,
+                    max_delta_e: args.fallback_threshold,
+                })
+            })
+            .transpose()?,
+    })
+}
+
+/// Run the whole pipeline for a single image
+fn process(
+    input: &str,
+    output: &str,
+    palette: &[palette::PaletteEntry],
+    args: &Args,
+) -> Result<(), Error> {
+    let colors = palette::colors(palette);
+    if jpeg::passthrough(input, output, &colors, args)? {
+        return Ok(());
+    }
+    if let Some(target) = args.target_size {
+        return target_size::process(input, output, palette, args, target);
+    }
+    let output_img = render_image(input, palette, args, !args.no_dither)?;
+    match args.split {
+        Some((columns, rows)) => {
+            let gap = match args.bezel {
+                Some(bezel) => bezel_gap(bezel, args.dpi)?,
+                None => (args.split_gap as f32 * args.scale.factor()?).round() as u32,
+            };
+            let tiles = layout::split(&output_img, columns, rows, gap);
+            for (i, tile) in tiles.into_iter().enumerate() {
+                save(
+                    input,
+                    &layout::numbered_path(output, i + 1),
+                    tile,
+                    &colors,
+                    args,
+                    None,
+                )?;
+            }
+            Ok(())
+        }
+        None => save(input, output, output_img, &colors, args, None),
+    }
+}
+
+/// Render the input with every option up to saving it, with `dither` instead of
+/// --no-dither
+fn render_image(
+    input: &str,
+    palette: &[palette::PaletteEntry],
+    args: &Args,
+    dither: bool,
+) -> Result<RgbImage, Error> {
+    let colors = palette::colors(palette);
+    let mut input_img = open_image(input)?;
+    let factor = args.scale.factor()?;
+    let scaled = |pixels: u32| (pixels as f32 * factor).round() as u32;
+    if let Some((width, height)) = args.resize {
+        input_img = layout::fit(
+            &input_img,
+            scaled(width),
+            scaled(height),
+            args.fit,
+            args.resize_filter,
+        );
+    }
+    if let Some(method) = args.auto_wb {
+        adjust::auto_white_balance(&mut input_img, method);
+    }
+    if let Some(clip) = args.clahe {
+        adjust::clahe(&mut input_img, clip);
+    }
+    if args.shadows != 0.0 || args.highlights != 0.0 {
+        adjust::tone_curve(&mut input_img, args.shadows, args.highlights);
+    }
+    let resolve = |spec: &str| palette::resolve_color(spec, &colors).map_err(Error::Palette);
+    let entropy = stats::entropy(&input_img);
+    if entropy < 1.0 {
+        warn(
+            args.strict,
+            Error::Input(format!(
+                "{} is essentially a single color (entropy {:.2} bits), the output will be \
+                 nearly flat. Try --clahe, --shadows or --highlights to bring out detail",
+                input, entropy
+            )),
+        )?;
+    }
+
+    let mut options = render_options(&colors, args)?;
+    options.dither = dither;
+    options.keep("1-adjusted", &input_img);
+    // plain palette mapping, as opposed to one of the alternative output modes
+    let mapped = args.split_tone.is_none()
+        && args.posterize.is_none()
+        && args.threshold.is_none()
+        && args.eink.is_none()
+        && !args.ladder;
+    // the foreground and background palettes, when the regions are mapped separately
+    let window = match &args.match_window {
+        Some(class) => Some(window::find(class)?),
+        None => None,
+    };
+    // the colors of the palette keep their weight, colors from outside it count like
+    // an unweighted palette color
+    let weighted = |colors: Vec<Rgb<u8>>| -> Vec<palette::PaletteEntry> {
+        colors
+            .into_iter()
+            .map(|color| {
+                palette
+                    .iter()
+                    .find(|entry| entry.color == color)
+                    .copied()
+                    .unwrap_or(palette::PaletteEntry { color, weight: 1.0 })
+            })
+            .collect()
+    };
+    let regions = if let Some(window) = &window {
+        Some((
+            weighted(window::harmonizing(&colors, window.color)),
+            palette.to_vec(),
+        ))
+    } else if args.fg_palette.is_empty() && args.bg_palette.is_empty() {
+        None
+    } else {
+        let resolve_all = |specs: &[String]| -> Result<Vec<palette::PaletteEntry>, Error> {
+            if specs.is_empty() {
+                return Ok(palette.to_vec());
+            }
+            Ok(weighted(
+                specs
+                    .iter()
+                    .map(|spec| resolve(spec))
+                    .collect::<Result<_, _>>()?,
+            ))
+        };
+        Some((
+            resolve_all(&args.fg_palette)?,
+            resolve_all(&args.bg_palette)?,
+        ))
+    };
+    let mut output_img = if let Some(settings) = &args.split_tone {
+        let shadows = resolve(&settings.shadows)?;
+        let highlights = resolve(&settings.highlights)?;
+        adjust::split_tone(
+            &mut input_img,
+            shadows,
+            highlights,
+            settings.balance,
+            settings.amount,
+        );
+        input_img
+    } else if let Some(levels) = args.posterize {
+        adjust::posterize(&mut input_img, levels);
+        input_img
+    } else if let Some(threshold) = args.threshold {
+        render::threshold(
+            &input_img,
+            threshold,
+            dither,
+            resolve("@background")?,
+            resolve("@foreground")?,
+        )
+    } else if let Some(levels) = args.eink {
+        let (width, height) = args.eink_size;
+        let fitted = layout::fit(
+            &layout::match_orientation(input_img, width, height),
+            width,
+            height,
+            args.fit,
+            args.resize_filter,
+        );
+        // an explicit palette is a color e-paper panel, otherwise use a gray ramp
+        let panel = match args.source.palette {
+            Some(_) => colors.clone(),
+            None => (0..levels)
+                .map(|level| {
+                    let value = (level as f32 / (levels - 1) as f32 * 255.0).round() as u8;
+                    Rgb([value, value, value])
+                })
+                .collect(),
+        };
+        render::ordered_dither(&fitted, &panel, 255.0 / (panel.len() - 1).max(1) as f32)
+    } else if args.ladder {
+        render::ladder(&input_img, palette, &options)
+    } else if let Some((foreground, background)) = &regions {
+        let mask = match (&window, &args.mask) {
+            (Some(window), _) => window.mask(input_img.width(), input_img.height()),
+            (None, Some(path)) => regions::load_mask(path, input_img.width(), input_img.height())?,
+            (None, None) => regions::auto_mask(&input_img),
+        };
+        check_exposure(input, &input_img, args, |img| {
+            regions::render(img, foreground, background, &mask, &options)
+        })?
+    } else {
+        let mut mapped_img = check_exposure(input, &input_img, args, |img| {
+            render::render(img, palette, &options)
+        })?;
+        if let Some(path) = &args.script {
+            script::Script::load(path, &colors)?.apply(
+                &input_img,
+                &mut mapped_img,
+                args.average,
+            )?;
+        }
+        mapped_img
+    };
 
-    // this is seperated from the main iterator because doing it within the
-    // iterator would require a mutex (expensive)
-    for i in 0..output.len() as u32 {
-        let x = i % input_img.width();
-        let y = i / input_img.width();
-        output_img.put_pixel(x, y, output[i as usize])
+    watch::check()?;
+    options.keep("4-mapped", &output_img);
+    if let Some((foreground, background)) = &regions {
+        let used = palette::dedup(palette::colors(
+            &[foreground.as_slice(), background].concat(),
+        ));
+        warn_unused_colors(&output_img, &used, args.strict)?;
+    } else if mapped {
+        warn_unused_colors(&output_img, &colors, args.strict)?;
+    }
+    if let Some(upscale) = args.upscale {
+        output_img = layout::upscale(&output_img, upscale);
     }
+
     if args.blur {
         output_img = image::imageops::blur(&output_img, 1.0);
     }
-    output_img.save(args.output).unwrap();
+    if args.terminal_bg {
+        output_img = terminal_background(&output_img, &colors, args.min_contrast, args.strict)?;
+    }
+    for fade in &args.fade {
+        effects::fade(
+            &mut output_img,
+            fade.edge,
+            scaled(fade.size),
+            resolve(&fade.color)?,
+        );
+    }
+    for area in &args.safe_area {
+        effects::safe_area(
+            &mut output_img,
+            area,
+            scaled(area.size),
+            resolve(&area.color)?,
+            resolve("@foreground")?,
+            args.min_contrast,
+        );
+    }
+    if let Some(frame) = &args.frame {
+        effects::frame(
+            &mut output_img,
+            scaled(frame.width),
+            resolve(&frame.color)?,
+            scaled(args.frame_shadow),
+        );
+    }
+    if !args.plugin.is_empty() {
+        let installed = plugins::discover();
+        for name in &args.plugin {
+            plugins::find(&installed, name)?.process(&mut output_img, &colors)?;
+        }
+    }
+    Ok(output_img)
+}
+
+/// Shift of the mean luminance from the input to the mapped image above which the output
+/// counts as too dark or too bright
+const MAX_EXPOSURE_SHIFT: f32 = 0.15;
+/// Extra mappings --auto-exposure-fix tries to get the shift below the threshold
+const EXPOSURE_PASSES: usize = 3;
+
+/// Map the input with `render`, and check that the mean luminance didn't move too far.
+/// With --auto-exposure-fix the input is mapped again through a gamma curve that moves
+/// the other way, and the closest of the results is kept
+fn check_exposure(
+    input: &str,
+    input_img: &RgbImage,
+    args: &Args,
+    render: impl Fn(&RgbImage) -> RgbImage,
+) -> Result<RgbImage, Error> {
+    let target = adjust::mean_luminance(input_img);
+    let mut best = render(input_img);
+    // a render --watch stopped is black, there is nothing to check
+    watch::check()?;
+    let mut shift = adjust::mean_luminance(&best) - target;
+    if shift.abs() <= MAX_EXPOSURE_SHIFT {
+        return Ok(best);
+    }
+    let describe = |shift: f32| {
+        format!(
+            "{} mean luminance {:.2} to {:.2}",
+            if shift < 0.0 { "darker," } else { "brighter," },
+            target,
+            target + shift
+        )
+    };
+    if !args.auto_exposure_fix {
+        warn(
+            args.strict,
+            Error::Palette(format!(
+                "the output of {} is much {}, the palette may lack mid-tones. Use \
+                 --auto-exposure-fix to compensate, or --extend-shades",
+                input,
+                describe(shift)
+            )),
+        )?;
+        return Ok(best);
+    }
+    // gamma = 2^exponent, lowered to brighten a dark result and raised to darken a
+    // bright one, by the ratio of the mean luminances
+    let mut exponent = 0.0f32;
+    let mut attempt_shift = shift;
+    for _ in 0..EXPOSURE_PASSES {
+        let ratio = (target + attempt_shift).max(0.01) / target.max(0.01);
+        exponent += ratio.log2();
+        let mut adjusted = input_img.clone();
+        adjust::gamma(&mut adjusted, 2f32.powf(exponent));
+        let attempt = render(&adjusted);
+        watch::check()?;
+        attempt_shift = adjust::mean_luminance(&attempt) - target;
+        if attempt_shift.abs() < shift.abs() {
+            (best, shift) = (attempt, attempt_shift);
+        }
+        if shift.abs() <= MAX_EXPOSURE_SHIFT {
+            return Ok(best);
+        }
+    }
+    warn(
+        args.strict,
+        Error::Palette(format!(
+            "the output of {} stays much {} even with --auto-exposure-fix",
+            input,
+            describe(shift)
+        )),
+    )?;
+    Ok(best)
+}
+
+/// Pixels hidden behind `bezel` millimeters of bezel
+fn bezel_gap(bezel: f32, dpi: Option<f32>) -> Result<u32, Error> {
+    let pixels_per_mm = match dpi {
+        Some(dpi) => dpi / 25.4,
+        None => {
+            let monitors = edid::connected_monitors();
+            let monitor = monitors.first().ok_or_else(|| {
+                Error::Input(
+                    "no monitor reports its physical size, give the density with --dpi".to_string(),
+                )
+            })?;
+            if monitors.iter().any(|other| {
+                (other.pixels_per_mm() - monitor.pixels_per_mm()).abs()
+                    > 0.05 * monitor.pixels_per_mm()
+            }) {
+                eprintln!(
+                    "warning: the monitors have different pixel densities, using the one of {}",
+                    monitor.name
+                );
+            }
+            monitor.pixels_per_mm()
+        }
+    };
+    Ok((bezel * pixels_per_mm).round() as u32)
+}
+
+/// The image as it is written: 8 bit grayscale for e-readers, RGB otherwise
+fn output_image(output_img: RgbImage, args: &Args) -> image::DynamicImage {
+    if args.eink.is_some() && args.source.palette.is_none() {
+        // e-readers want 8 bit grayscale files
+        image::DynamicImage::ImageRgb8(output_img)
+            .into_luma8()
+            .into()
+    } else {
+        image::DynamicImage::ImageRgb8(output_img)
+    }
+}
+
+/// Write the output, as `encoded` when --target-size already encoded it
+fn save(
+    input: &str,
+    output: &str,
+    output_img: RgbImage,
+    palette: &[Rgb<u8>],
+    args: &Args,
+    encoded: Option<Vec<u8>>,
+) -> Result<(), Error> {
+    // a render the watched files changed under writes nothing
+    watch::check()?;
+    if let Some(target) = &args.widget_colors {
+        widgets::export(target, &output_img, palette, &args.widget_region)?;
+    }
+    if let Some(name) = shm::target(output) {
+        let path = shm::write(&output_img, name)?;
+        if args.emit_hash {
+            println!("{}  {}", hash::pixel_hash(&output_img), path.display());
+        }
+        return Ok(());
+    }
+    let output_img = output_image(output_img, args);
+    let saved = if args.embed_settings && provenance::is_png(output) {
+        let provenance = provenance::Provenance::new(input, palette, args)?;
+        provenance::save_png(&output_img, output, &provenance)
+    } else if args.embed_settings && jpeg::is_jpeg(output) {
+        let provenance = provenance::Provenance::new(input, palette, args)?;
+        provenance::save_jpeg(&output_img, output, &provenance)
+    } else {
+        if args.embed_settings {
+            warn(
+                args.strict,
+                Error::Input(format!(
+                    "settings can only be embedded in PNG and JPEG files, not {}",
+                    output
+                )),
+            )?;
+        }
+        let failed = |err: &dyn std::fmt::Display| {
+            Error::Encode(format!("could not write {}: {}", output, err))
+        };
+        match encoded {
+            Some(encoded) => fs::write(output, encoded).map_err(|err| failed(&err)),
+            None => output_img.save(output).map_err(|err| failed(&err)),
+        }
+    };
+    saved?;
+    if args.emit_hash {
+        // hash what was actually written so lossy formats can be verified too
+        println!("{}  {}", hash::pixel_hash(&open_image(output)?), output);
+    }
+    Ok(())
+}
+
+/// SVG inputs keep being vectors: only their colors are mapped to the palette
+fn recolor_svg(input: &str, output: &str, palette: &[Rgb<u8>]) -> Result<(), Error> {
+    if !svg::is_svg(output) {
+        return Err(Error::Input(
+            "SVG inputs are recolored as vectors and need an .svg output".to_string(),
+        ));
+    }
+    let source = std::fs::read_to_string(input)
+        .map_err(|err| Error::Input(format!("could not read {}: {}", input, err)))?;
+    let (recolored, replaced) = svg::recolor(&source, palette);
+    std::fs::write(output, recolored)
+        .map_err(|err| Error::Encode(format!("could not write {}: {}", output, err)))?;
+    println!("replaced {} colors", replaced);
+    Ok(())
+}
+
+#[cfg(feature = "layered")]
+fn recolor_layered(
+    input: &str,
+    output: &str,
+    palette: &[palette::PaletteEntry],
+    args: &Args,
+) -> Result<(), Error> {
+    let keep_layers = layered::keeps_layers(output)?;
+    let mut document = layered::read(input)?;
+    for blend in layered::unsupported_blends(&document) {
+        warn(
+            args.strict,
+            Error::Input(format!("blend mode {} is composited as normal", blend)),
+        )?;
+    }
+    let colors = palette::colors(palette);
+    let options = render::RenderOptions {
+        intermediates: None,
+        ..render_options(&colors, args)?
+    };
+    layered::recolor(&mut document, palette, &options);
+    if keep_layers {
+        if args.embed_settings {
+            warn(
+                args.strict,
+                Error::Input("settings can't be embedded in OpenRaster files".to_string()),
+            )?;
+        }
+        return layered::write_ora(&document, output);
+    }
+    // transparent areas show the palette background
+    let background = palette::resolve_color("@background", &colors).map_err(Error::Palette)?;
+    let flattened = layered::flatten(&document);
+    let output_img = RgbImage::from_fn(document.width, document.height, |x, y| {
+        let [r, g, b, a] = flattened.get_pixel(x, y).0;
+        color::blend(background, Rgb([r, g, b]), a as f32 / 255.0)
+    });
+    save(input, output, output_img, &colors, args, None)
+}
+
+fn run(args: Args) -> Result<(), Error> {
+    if args.list_themes {
+        for theme in themes::THEMES {
+            let colors: Vec<String> = theme.palette().into_iter().map(palette::to_hex).collect();
+            println!("{:<18} {}", theme.name, colors.join(" "));
+        }
+        return Ok(());
+    }
+    // clap guarantees the input is set when no subcommand is given unless only a
+    // swatch is asked for, and the output unless a pipeline is or only a swatch is
+    let Some(input) = args.input.clone() else {
+        let palette = load_palette(&args.source, args.strict)?;
+        return swatch::write(args.swatch.as_deref().unwrap(), &palette);
+    };
+    if args.output.is_none() && args.pipeline.is_none() {
+        return Err(Error::Input(format!("no output given for {}", input)));
+    }
+    if !args.themes.is_empty() {
+        return render_themes(&input, args);
+    }
+    let weighted = load_weighted_palette(&args.source, args.strict)?;
+    let palette = palette::colors(&weighted);
+    if let Some(path) = &args.swatch {
+        swatch::write(path, &palette)?;
+    }
+    warn_truncated_quantization(&palette, &args)?;
+    if let Some(desktop) = args.export_accent {
+        accent::export(desktop, &palette)?;
+    }
+    if args.cache {
+        // the defaults are valid, see the cache gc arguments
+        cache::gc(
+            cache::parse_size(cache::DEFAULT_MAX_SIZE).unwrap(),
+            cache::parse_age(cache::DEFAULT_MAX_AGE).unwrap(),
+        )?;
+    }
+    if args.dark_theme.is_some() || args.install_kde {
+        let output = args.output.as_deref().unwrap();
+        if Path::new(&input).is_dir() || shm::target(output).is_some() {
+            return Err(Error::Input(
+                "--dark-theme and --install-kde need a single input image and output file"
+                    .to_string(),
+            ));
+        }
+    }
+    if args.sequence && !Path::new(&input).is_dir() {
+        return Err(Error::Input(
+            "--sequence needs a directory of frames as input".to_string(),
+        ));
+    }
+    if args.widget_colors.is_some() && Path::new(&input).is_dir() {
+        return Err(Error::Input(
+            "--widget-colors needs a single input image".to_string(),
+        ));
+    }
+    // the dark variant gets the theme in place of the light palette, with the same
+    // palette options
+    let dark_palette = match &args.dark_theme {
+        Some(theme) => {
+            let palette = palette::unweighted(themes::find(theme)?.palette());
+            let palette = refine_palette(palette, &args.source, args.strict)?;
+            warn_truncated_quantization(&palette::colors(&palette), &args)?;
+            Some(palette)
+        }
+        None => None,
+    };
+    let mut files = render_outputs(&input, args.output.as_deref(), &weighted, &args)?;
+    let mut dark_files = Vec::new();
+    if let Some(dark_palette) = dark_palette {
+        let light = args.output.as_deref().unwrap();
+        let dark = gnome::dark_path(light);
+        watch::check()?;
+        dark_files = render_outputs(&input, Some(&dark), &dark_palette, &args)?;
+        gnome::write(light, &dark, palette[0], args.install_gnome)?;
+    }
+    if args.install_kde {
+        let light = PathBuf::from(args.output.as_deref().unwrap());
+        let name = light.file_stem().unwrap_or_default().to_string_lossy();
+        kde::install(&name, &files, &dark_files)?;
+    }
+    files.extend(dark_files);
+    watch::check()?;
+    if args.watch && !files.is_empty() {
+        if let Err(err) = history::record(&files, &palette) {
+            warn(args.strict, err)?;
+        }
+    }
+    if args.thumbnail.is_some() || args.thumbnail_cache {
+        thumbnail::write(&files, args.thumbnail, args.thumbnail_cache, args.strict)?;
+    }
+    if let Some(path) = &args.emit_manifest {
+        manifest::write(path, &files, &palette)?;
+    }
+    Ok(())
+}
+
+/// `--themes`: a batch into a directory per theme, sharing the quantization of every
+/// image between the themes through the cache
+fn render_themes(input: &str, mut args: Args) -> Result<(), Error> {
+    let output = args.output.clone().unwrap();
+    if !Path::new(input).is_dir() || shm::target(&output).is_some() {
+        return Err(Error::Input(
+            "--themes needs an input directory and an output directory".to_string(),
+        ));
+    }
+    #[cfg(feature = "net")]
+    if args.source.lospec.is_some() {
+        return Err(Error::Input(
+            "--themes can't be used with --lospec".to_string(),
+        ));
+    }
+    args.cache = true;
+    // the defaults are valid, see the cache gc arguments
+    cache::gc(
+        cache::parse_size(cache::DEFAULT_MAX_SIZE).unwrap(),
+        cache::parse_age(cache::DEFAULT_MAX_AGE).unwrap(),
+    )?;
+    // check every name before anything is rendered
+    let names = args
+        .themes
+        .iter()
+        .map(|name| Ok(themes::find(name)?.name))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let mut files = Vec::new();
+    let mut failures = Vec::new();
+    for name in names {
+        watch::check()?;
+        args.source.theme = Some(name.to_string());
+        let weighted = load_weighted_palette(&args.source, args.strict)?;
+        warn_truncated_quantization(&palette::colors(&weighted), &args)?;
+        let dir = Path::new(&output).join(name);
+        match batch::run(input, &dir.to_string_lossy(), &weighted, &args) {
+            Ok(outputs) => files.extend(outputs),
+            Err(Error::PartialBatch(err)) if !args.fail_fast => {
+                failures.push(format!("{}: {}", name, err))
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    if args.thumbnail.is_some() || args.thumbnail_cache {
+        thumbnail::write(&files, args.thumbnail, args.thumbnail_cache, args.strict)?;
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::PartialBatch(failures.join("; ")))
+    }
+}
+
+/// Render the input to `output` as the arguments say, returning the files that were
+/// written
+fn render_outputs(
+    input: &str,
+    output: Option<&str>,
+    palette: &[palette::PaletteEntry],
+    args: &Args,
+) -> Result<Vec<PathBuf>, Error> {
+    if let Some(path) = &args.pipeline {
+        return pipeline::run(path, input, output, palette, args.strict);
+    }
+    let output = output.unwrap().to_string();
+    if svg::is_svg(input) {
+        if args.embed_settings {
+            warn(
+                args.strict,
+                Error::Input("settings can't be embedded in SVG files".to_string()),
+            )?;
+        }
+        recolor_svg(input, &output, &palette::colors(palette))?;
+        return Ok(vec![PathBuf::from(output)]);
+    }
+    #[cfg(feature = "layered")]
+    if layered::is_layered(input) {
+        recolor_layered(input, &output, palette, args)?;
+        return Ok(vec![PathBuf::from(output)]);
+    }
+    if Path::new(input).is_dir() {
+        if shm::target(&output).is_some() {
+            return Err(Error::Input(
+                "batch mode needs an output directory, not shared memory".to_string(),
+            ));
+        }
+        if args.split.is_some() {
+            return Err(Error::Input(
+                "--split can't be used in batch mode".to_string(),
+            ));
+        }
+        return batch::run(input, &output, palette, args);
+    }
+    process(input, &output, palette, args)?;
+    Ok(match args.split {
+        _ if shm::target(&output).is_some() => Vec::new(),
+        Some((columns, rows)) => (1..=(columns * rows) as usize)
+            .map(|n| PathBuf::from(layout::numbered_path(&output, n)))
+            .collect(),
+        None => vec![PathBuf::from(output)],
+    })
+}
+
+fn main() -> ExitCode {
+    let mut args = Args::parse();
+    args.argv = std::env::args().collect();
+    if args.no_exec {
+        exec::forbid();
+    }
+    if let Some(threads) = args.threads {
+        // only fails when a pool was built already, which nothing does before this
+        let _ = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global();
+    }
+    let result = match args.command {
+        Some(command) => run_command(command),
+        None if args.watch => watch::run(args).map(|_| ExitCode::SUCCESS),
+        None => run(args).map(|_| ExitCode::SUCCESS),
+    };
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        ExitCode::from(err.exit_code())
+    })
 }
@@ -0,0 +1,98 @@
+//! `--emit-manifest`: a JSON list of the files a run wrote, for static site generators
+//! and wallpaper pack publishing. For example:
+//!
+//! ```json
+//! {
+//!   "arguments": ["photo.jpg", "out.png", "--wal"],
+//!   "palette": ["#1d1f21", "#cc6666"],
+//!   "outputs": [
+//!     {
+//!       "path": "out.png",
+//!       "hashed_name": "out.3f2a9c1e0b7d.png",
+//!       "width": 1920,
+//!       "height": 1080,
+//!       "bytes": 1048576,
+//!       "hash": "3f2a9c1e0b7d..."
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! `hash` is the BLAKE3 hash of the file and `hashed_name` its name with the start of the
+//! hash added, to copy it to for cache busting. Paths are relative to the manifest where
+//! they are inside its directory. Dimensions are null for vector and layered outputs.
+
+use crate::error::Error;
+use crate::palette::to_hex;
+use image::Rgb;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Hex digits of the hash that go into `hashed_name`
+const HASHED_NAME_DIGITS: usize = 12;
+
+#[derive(Serialize)]
+struct Manifest {
+    /// the command line the outputs were made with
+    arguments: Vec<String>,
+    palette: Vec<String>,
+    outputs: Vec<Output>,
+}
+
+#[derive(Serialize)]
+pub struct Output {
+    path: String,
+    hashed_name: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    bytes: u64,
+    hash: String,
+}
+
+/// Describe an output file, with its path relative to `base` (canonical) if it is inside
+pub fn describe(file: &Path, base: &Path) -> Result<Output, Error> {
+    let contents = fs::read(file)
+        .map_err(|err| Error::Encode(format!("could not read {}: {}", file.display(), err)))?;
+    let hash = blake3::hash(&contents).to_hex().to_string();
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let short = &hash[..HASHED_NAME_DIGITS];
+    let hashed_name = match file.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, short, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, short),
+    };
+    let dimensions = image::image_dimensions(file).ok();
+    let path = fs::canonicalize(file)
+        .ok()
+        .and_then(|file| file.strip_prefix(base).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| file.to_path_buf());
+    Ok(Output {
+        path: path.to_string_lossy().to_string(),
+        hashed_name,
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+        bytes: contents.len() as u64,
+        hash,
+    })
+}
+
+/// Describe `files` in a manifest at `path`
+pub fn write(path: &str, files: &[PathBuf], palette: &[Rgb<u8>]) -> Result<(), Error> {
+    let failed =
+        |err: &dyn std::fmt::Display| Error::Encode(format!("could not write {}: {}", path, err));
+    let base = Path::new(path)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let base = fs::canonicalize(base).map_err(|err| failed(&err))?;
+    let manifest = Manifest {
+        arguments: std::env::args().skip(1).collect(),
+        palette: palette.iter().map(|color| to_hex(*color)).collect(),
+        outputs: files
+            .iter()
+            .map(|file| describe(file, &base))
+            .collect::<Result<_, _>>()?,
+    };
+    let json = serde_json::to_string_pretty(&manifest).map_err(|err| failed(&err))?;
+    fs::write(path, json + "\n").map_err(|err| failed(&err))
+}
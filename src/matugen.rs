@@ -0,0 +1,134 @@
+//! `--matugen`: a Material You palette from matugen, read from the JSON it prints with
+//! `matugen image wallpaper.png --json hex`, or made by running that on an image.
+//!
+//! The palette is picked from the tonal palettes of the export, tones of the primary,
+//! secondary, tertiary and neutral palettes, with the tones Material You uses for the
+//! roles of a dark or light scheme (`--matugen-mode`):
+//!
+//! - 0: the surface, neutral 6 in dark schemes and 98 in light ones (@background)
+//! - 1 to 3: the primary, secondary and tertiary containers, 30 or 90
+//! - 4 to 6: primary, secondary and tertiary, 80 or 40
+//! - 7: text on the surface, neutral 90 or 10 (@foreground)
+//! - 8: a raised surface, neutral 17 or 92
+//! - 9 to 11: the inverse primary, 40 or 80, and secondary and tertiary 50
+//! - 12 to 14: the fixed primary, secondary and tertiary, 90 or 10
+//! - 15: secondary text, neutral variant 80 or 30
+//!
+//! Exports that lack a tone use the closest one they have.
+
+use crate::error::Error;
+use crate::exec;
+use crate::palette::parse_hex;
+use image::Rgb;
+use std::fs;
+use std::path::Path;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    #[default]
+    Dark,
+    Light,
+}
+
+const DARK_TONES: [(&str, u8); 16] = [
+    ("neutral", 6),
+    ("primary", 30),
+    ("secondary", 30),
+    ("tertiary", 30),
+    ("primary", 80),
+    ("secondary", 80),
+    ("tertiary", 80),
+    ("neutral", 90),
+    ("neutral", 17),
+    ("primary", 40),
+    ("secondary", 50),
+    ("tertiary", 50),
+    ("primary", 90),
+    ("secondary", 90),
+    ("tertiary", 90),
+    ("neutral_variant", 80),
+];
+
+const LIGHT_TONES: [(&str, u8); 16] = [
+    ("neutral", 98),
+    ("primary", 90),
+    ("secondary", 90),
+    ("tertiary", 90),
+    ("primary", 40),
+    ("secondary", 40),
+    ("tertiary", 40),
+    ("neutral", 10),
+    ("neutral", 92),
+    ("primary", 80),
+    ("secondary", 50),
+    ("tertiary", 50),
+    ("primary", 10),
+    ("secondary", 10),
+    ("tertiary", 10),
+    ("neutral_variant", 30),
+];
+
+/// The color of `tone` in a tonal palette of the export, or of the closest tone in it
+fn tone(palettes: &serde_json::Value, name: &str, tone: u8) -> Result<Rgb<u8>, String> {
+    let tones = palettes[name]
+        .as_object()
+        .ok_or_else(|| format!("there is no {} palette", name))?;
+    let (_, color) = tones
+        .iter()
+        .filter_map(|(key, color)| Some((key.parse::<u8>().ok()?, color.as_str()?)))
+        .min_by_key(|(key, _)| key.abs_diff(tone))
+        .ok_or_else(|| format!("the {} palette has no tones", name))?;
+    parse_hex(color).map_err(|err| format!("{} {}: {}", name, tone, err))
+}
+
+fn decode(contents: &str, mode: Mode) -> Result<Vec<Rgb<u8>>, String> {
+    // matugen can log before the JSON
+    let start = contents
+        .find('{')
+        .ok_or_else(|| "there is no JSON".to_string())?;
+    let json: serde_json::Value =
+        serde_json::from_str(&contents[start..]).map_err(|err| err.to_string())?;
+    let palettes = &json["palettes"];
+    if !palettes.is_object() {
+        return Err("there are no tonal palettes, export them with matugen --json hex".to_string());
+    }
+    let tones = match mode {
+        Mode::Dark => DARK_TONES,
+        Mode::Light => LIGHT_TONES,
+    };
+    tones
+        .iter()
+        .map(|(name, value)| tone(palettes, name, *value))
+        .collect()
+}
+
+/// Run matugen on an image for its export
+fn run(image: &str) -> Result<String, Error> {
+    let output = exec::command("matugen", "--matugen")?
+        .args(["image", image, "--json", "hex", "--dry-run"])
+        .output()
+        .map_err(|err| Error::ExternalTool(format!("failed to execute matugen: {}", err)))?;
+    if !output.status.success() {
+        return Err(Error::ExternalTool(format!(
+            "matugen failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| Error::ExternalTool("got non UTF-8 data from matugen".to_string()))
+}
+
+/// The palette of a matugen JSON export, or of the one matugen makes for an image
+pub fn load(source: &str, mode: Mode) -> Result<Vec<Rgb<u8>>, Error> {
+    let path = Path::new(source);
+    let contents = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        fs::read_to_string(path)
+            .map_err(|err| Error::Palette(format!("could not read {}: {}", source, err)))?
+    } else {
+        run(source)?
+    };
+    decode(&contents, mode).map_err(|err| Error::Palette(format!("{}: {}", source, err)))
+}
@@ -0,0 +1,254 @@
+//! The `pack` subcommand: render a directory of wallpapers for several themes and
+//! resolutions at once into `<theme>/<resolution>/<name>.png`, with an `index.json`
+//! describing every file, for publishing pre-themed wallpaper collections.
+//!
+//! Images render in parallel and always use the quantization cache: all built-in themes
+//! have 16 colors, so every image is only quantized once per resolution.
+//!
+//! With `--windows-theme` every theme and resolution also becomes a Windows theme, see
+//! [`crate::windows_theme`], and with `--install-kde` every image and theme a Plasma
+//! wallpaper with a variant per resolution, see [`crate::kde`].
+
+use crate::batch::list_images;
+use crate::error::{warn, Error};
+use crate::kde;
+use crate::manifest::{self, Output};
+use crate::palette::{self, to_hex};
+use crate::themes;
+use crate::windows_theme;
+use crate::Args;
+use clap::Parser;
+use image::Rgb;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const INDEX: &str = "index.json";
+
+#[derive(Serialize)]
+struct Index {
+    /// the colorizing options after --
+    options: Vec<String>,
+    themes: BTreeMap<String, Vec<String>>,
+    files: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    theme: String,
+    resolution: String,
+    name: String,
+    #[serde(flatten)]
+    output: Output,
+}
+
+/// What to make of the rendered files besides the pack itself
+pub struct Packaging {
+    pub windows_theme: bool,
+    pub deskthemepack: bool,
+    pub install_kde: bool,
+}
+
+struct Job<'a> {
+    input: &'a Path,
+    theme: &'a str,
+    palette: &'a [Rgb<u8>],
+    resolution: String,
+    output: PathBuf,
+}
+
+/// Colorizing arguments for one render: the options after -- with the size of the
+/// resolution and the cache added
+fn job_args(options: &[String], resolution: &str, input: &str, output: &str) -> Args {
+    let mut argv = vec!["imagecolorizer".to_string()];
+    argv.extend(options.iter().cloned());
+    argv.extend([
+        "--cache".to_string(),
+        "--resize".to_string(),
+        resolution.to_string(),
+        "--".to_string(),
+        input.to_string(),
+        output.to_string(),
+    ]);
+    let mut args = Args::try_parse_from(&argv).unwrap_or_else(|err| err.exit());
+    args.argv = argv;
+    args
+}
+
+pub fn run(
+    input_dir: &str,
+    output_dir: &str,
+    theme_names: &[String],
+    resolutions: &[(u32, u32)],
+    options: &[String],
+    packaging: &Packaging,
+) -> Result<(), Error> {
+    let themes: Vec<(&str, Vec<Rgb<u8>>)> = theme_names
+        .iter()
+        .map(|name| {
+            let theme = themes::find(name)?;
+            Ok((theme.name, theme.palette()))
+        })
+        .collect::<Result<_, Error>>()?;
+
+    // check the options once, before anything is rendered
+    let check = job_args(options, "1x1", input_dir, output_dir);
+    if check.command.is_some() {
+        return Err(Error::Input(
+            "the options after -- can't contain a subcommand".to_string(),
+        ));
+    }
+    if check.pipeline.is_some()
+        || check.split.is_some()
+        || check.eink.is_some()
+        || check.dark_theme.is_some()
+        || check.install_kde
+    {
+        return Err(Error::Input(
+            "--pipeline, --split, --eink, --dark-theme and --install-kde can't be used in a \
+             pack"
+                .to_string(),
+        ));
+    }
+    let strict = check.strict;
+
+    let (images, skipped) = list_images(Path::new(input_dir))?;
+    for path in &skipped {
+        warn(
+            strict,
+            Error::PartialBatch(format!("skipping {}, not an image", path.display())),
+        )?;
+    }
+    let output_dir = Path::new(output_dir);
+    // grouped by image and resolution, the first theme of a group fills the cache
+    // and the following ones reuse its quantization
+    let mut jobs = Vec::new();
+    for (width, height) in resolutions {
+        let resolution = format!("{}x{}", width, height);
+        for (theme, _) in &themes {
+            let dir = output_dir.join(theme).join(&resolution);
+            fs::create_dir_all(&dir).map_err(|err| {
+                Error::Encode(format!("could not create {}: {}", dir.display(), err))
+            })?;
+        }
+        for input in &images {
+            let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+            for (theme, palette) in &themes {
+                jobs.push(Job {
+                    input,
+                    theme,
+                    palette,
+                    resolution: resolution.clone(),
+                    output: output_dir
+                        .join(theme)
+                        .join(&resolution)
+                        .join(format!("{}.png", stem)),
+                });
+            }
+        }
+    }
+
+    let results: Vec<Result<(), String>> = jobs
+        .par_chunks(themes.len())
+        .flat_map_iter(|group| {
+            group.iter().map(|job| {
+                let input = job.input.to_string_lossy();
+                let output = job.output.to_string_lossy();
+                let args = job_args(options, &job.resolution, &input, &output);
+                crate::process(
+                    &input,
+                    &output,
+                    &palette::unweighted(job.palette.to_vec()),
+                    &args,
+                )
+                .map(|_| println!("{}: done", output))
+                .map_err(|err| {
+                    eprintln!("error: {}: {}", output, err);
+                    output.to_string()
+                })
+            })
+        })
+        .collect();
+
+    let base = fs::canonicalize(output_dir).map_err(|err| {
+        Error::Encode(format!("could not read {}: {}", output_dir.display(), err))
+    })?;
+    let mut files = Vec::new();
+    let mut failures = Vec::new();
+    let mut variants: BTreeMap<(&str, &str), Vec<&Job>> = BTreeMap::new();
+    for (job, result) in jobs.iter().zip(results) {
+        match result {
+            Ok(()) => files.push(Entry {
+                theme: job.theme.to_string(),
+                resolution: job.resolution.clone(),
+                name: job.input.file_name().unwrap().to_string_lossy().to_string(),
+                output: manifest::describe(&job.output, &base)?,
+            }),
+            Err(output) => {
+                failures.push(output);
+                continue;
+            }
+        }
+        variants
+            .entry((job.theme, &job.resolution))
+            .or_default()
+            .push(job);
+    }
+    if packaging.windows_theme {
+        for ((theme, resolution), variant) in &variants {
+            let images: Vec<PathBuf> = variant.iter().map(|job| job.output.clone()).collect();
+            let palette = variant[0].palette;
+            windows_theme::write(
+                output_dir,
+                theme,
+                resolution,
+                palette,
+                &images,
+                packaging.deskthemepack,
+            )?;
+        }
+    }
+    if packaging.install_kde {
+        let mut wallpapers: BTreeMap<(String, &str), Vec<PathBuf>> = BTreeMap::new();
+        for job in variants.values().flatten() {
+            let stem = job.input.file_stem().unwrap_or_default().to_string_lossy();
+            wallpapers
+                .entry((stem.to_string(), job.theme))
+                .or_default()
+                .push(job.output.clone());
+        }
+        for ((stem, theme), images) in &wallpapers {
+            kde::install(&format!("{}-{}", stem, theme), images, &[])?;
+        }
+    }
+    let index = Index {
+        options: options.to_vec(),
+        themes: themes
+            .iter()
+            .map(|(name, palette)| {
+                let colors = palette.iter().map(|color| to_hex(*color)).collect();
+                (name.to_string(), colors)
+            })
+            .collect(),
+        files,
+    };
+    let index_path = output_dir.join(INDEX);
+    fs::write(
+        &index_path,
+        serde_json::to_string_pretty(&index).unwrap() + "\n",
+    )
+    .map_err(|err| Error::Encode(format!("could not write {}: {}", index_path.display(), err)))?;
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::PartialBatch(format!(
+            "{} of {} files failed: {}",
+            failures.len(),
+            jobs.len(),
+            failures.join(", ")
+        )))
+    }
+}
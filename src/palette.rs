@@ -0,0 +1,388 @@
+use crate::color::{blend, chroma, delta_e, from_oklab, luminance, to_oklab, to_oklch};
+use crate::named_colors;
+use crate::render::MAX_COLORS;
+use image::{Rgb, RgbImage};
+use quantette::{ColorSpace, ImagePipeline, QuantizeMethod};
+
+/// Parse a `#RRGGBB` hex color
+pub fn parse_hex(hex_str: &str) -> Result<Rgb<u8>, String> {
+    let digits = hex_str.trim().trim_start_matches('#');
+    if digits.len() != 6 {
+        return Err(format!("'{}' is not a #RRGGBB color", hex_str));
+    }
+    let hex_num = u32::from_str_radix(digits, 16)
+        .map_err(|_| format!("'{}' is not a #RRGGBB color", hex_str))?;
+    let r = (hex_num >> 16) as u8;
+    let g = ((hex_num >> 8) & 0x00FF) as u8;
+    let b = (hex_num & 0x0000_00FF) as u8;
+    Ok(Rgb([r, g, b]))
+}
+
+/// Parse a color literal: `#RRGGBB`, `#RGB`, `0xRRGGBB`, bare `RRGGBB` or `RGB`,
+/// `rgb(R, G, B)` with channels from 0 to 255, or a CSS color name
+pub fn parse_color(literal: &str) -> Result<Rgb<u8>, String> {
+    let invalid = || {
+        format!(
+            "'{}' is not a color, use #RRGGBB, #RGB, 0xRRGGBB, RRGGBB, rgb(R, G, B) or a CSS \
+             color name",
+            literal
+        )
+    };
+    let trimmed = literal.trim();
+    if let Some(channels) = trimmed
+        .strip_prefix("rgb(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let channels: Vec<u8> = channels
+            .split(',')
+            .map(|channel| channel.trim().parse::<u8>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        return match channels[..] {
+            [r, g, b] => Ok(Rgb([r, g, b])),
+            _ => Err(invalid()),
+        };
+    }
+    if let Some(color) = named_colors::lookup(trimmed) {
+        return Ok(color);
+    }
+    let digits = trimmed
+        .strip_prefix('#')
+        .or_else(|| trimmed.strip_prefix("0x"))
+        .or_else(|| trimmed.strip_prefix("0X"))
+        .unwrap_or(trimmed);
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    match digits.len() {
+        3 => parse_hex(&digits.chars().flat_map(|c| [c, c]).collect::<String>()),
+        6 => parse_hex(digits),
+        _ => Err(invalid()),
+    }
+}
+
+/// A palette color and how strongly the mapping prefers it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaletteEntry {
+    pub color: Rgb<u8>,
+    /// Distances to the color are divided by this, so colors weighted above 1 take more
+    /// pixels and colors below 1 fewer
+    pub weight: f32,
+}
+
+/// Parse a color literal with an optional weight after a colon, like `#1d2021:3`
+pub fn parse_entry(literal: &str) -> Result<PaletteEntry, String> {
+    let Some((color, weight)) = literal.rsplit_once(':') else {
+        return Ok(PaletteEntry {
+            color: parse_color(literal)?,
+            weight: 1.0,
+        });
+    };
+    let weight = weight
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|weight| weight.is_finite() && *weight > 0.0)
+        .ok_or_else(|| format!("'{}' in '{}' is not a positive weight", weight, literal))?;
+    Ok(PaletteEntry {
+        color: parse_color(color)?,
+        weight,
+    })
+}
+
+/// Parse a list of color literals with optional weights, separated by spaces or commas
+pub fn parse_entries(list: &str) -> Result<Vec<PaletteEntry>, String> {
+    let mut entries = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in list.char_indices().chain([(list.len(), ' ')]) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth <= 0 && (c == ',' || c.is_whitespace()) => {
+                let literal = &list[start..index];
+                if !literal.is_empty() {
+                    entries.push(parse_entry(literal)?);
+                }
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// The colors of a weighted palette
+pub fn colors(entries: &[PaletteEntry]) -> Vec<Rgb<u8>> {
+    entries.iter().map(|entry| entry.color).collect()
+}
+
+/// `colors` with a weight of 1 each
+pub fn unweighted(colors: Vec<Rgb<u8>>) -> Vec<PaletteEntry> {
+    colors
+        .into_iter()
+        .map(|color| PaletteEntry { color, weight: 1.0 })
+        .collect()
+}
+
+/// `colors`, picked from the weighted palette `entries`, with the weight they have there.
+/// Every copy of a color has the same weight, so which one is picked doesn't matter
+pub fn keep_weights(entries: &[PaletteEntry], colors: Vec<Rgb<u8>>) -> Vec<PaletteEntry> {
+    colors
+        .into_iter()
+        .map(|color| {
+            *entries
+                .iter()
+                .find(|entry| entry.color == color)
+                .expect("the colors are picked from the entries")
+        })
+        .collect()
+}
+
+/// The entries without the colors that an earlier entry already has, so a shade that
+/// lands on a color of the palette doesn't take over its weight
+pub fn dedup_entries(entries: Vec<PaletteEntry>) -> Vec<PaletteEntry> {
+    let mut palette: Vec<PaletteEntry> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if palette.iter().all(|kept| kept.color != entry.color) {
+            palette.push(entry);
+        }
+    }
+    palette
+}
+
+/// Format a color as `#rrggbb`
+pub fn to_hex(color: Rgb<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.0[0], color.0[1], color.0[2])
+}
+
+/// Default ΔE within which `--exclude` drops a palette color
+pub const DEFAULT_EXCLUDE_DELTA_E: f32 = 5.0;
+
+/// The palette without the colors within `max_delta_e` of one of `excluded`
+pub fn exclude(palette: &[Rgb<u8>], excluded: &[Rgb<u8>], max_delta_e: f32) -> Vec<Rgb<u8>> {
+    palette
+        .iter()
+        .filter(|color| {
+            !excluded
+                .iter()
+                .any(|excluded| delta_e(**color, *excluded) <= max_delta_e)
+        })
+        .copied()
+        .collect()
+}
+
+/// Remove duplicate colors while keeping the order they were given in, so that
+/// `@colorN` roles keep pointing at the same color between runs
+pub fn dedup(colors: Vec<Rgb<u8>>) -> Vec<Rgb<u8>> {
+    let mut palette = Vec::with_capacity(colors.len());
+    for color in colors {
+        if !palette.contains(&color) {
+            palette.push(color);
+        }
+    }
+    palette
+}
+
+/// The palette without the colors within `max_delta_e` of a color before them, so a
+/// near duplicate never takes over from the color it duplicates
+pub fn merge_close(palette: &[Rgb<u8>], max_delta_e: f32) -> Vec<Rgb<u8>> {
+    let mut merged: Vec<Rgb<u8>> = Vec::with_capacity(palette.len());
+    for color in palette {
+        if merged
+            .iter()
+            .all(|kept| delta_e(*color, *kept) > max_delta_e)
+        {
+            merged.push(*color);
+        }
+    }
+    merged
+}
+
+/// A canonical order for `--sort-palette`
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Order {
+    /// Darkest to lightest
+    Luminance,
+    /// Grays from dark to light, then the other colors around the OkLCh hue wheel
+    /// starting at red, darkest first for the same hue
+    Hue,
+}
+
+/// Chroma below which a color counts as a gray for `Order::Hue`, its hue is just noise
+const NEUTRAL_CHROMA: f32 = 0.02;
+
+/// Sort the palette into `order`. Colors are compared by value, so the result only
+/// depends on which colors are in the palette, not on the order they came in
+pub fn sort(palette: &[Rgb<u8>], order: Order) -> Vec<Rgb<u8>> {
+    let mut sorted = palette.to_vec();
+    sorted.sort_by(|a, b| {
+        let (a_lch, b_lch) = (to_oklch(*a), to_oklch(*b));
+        let primary = match order {
+            Order::Luminance => a_lch[0].total_cmp(&b_lch[0]),
+            Order::Hue => {
+                let hue = |[_, chroma, hue]: [f32; 3]| {
+                    // grays first
+                    if chroma < NEUTRAL_CHROMA {
+                        -1.0
+                    } else {
+                        hue
+                    }
+                };
+                hue(a_lch)
+                    .total_cmp(&hue(b_lch))
+                    .then(a_lch[0].total_cmp(&b_lch[0]))
+            }
+        };
+        // colors of the same lightness and hue are told apart by their value
+        primary.then(a.0.cmp(&b.0))
+    });
+    sorted
+}
+
+/// Resolve a color given either as a literal (`#RRGGBB`) or as a palette role:
+///
+/// - `@colorN`: the Nth color of the palette
+/// - `@background`: color 0, following the terminal convention
+/// - `@foreground`: color 7, or the color furthest in luminance from the background for
+///   palettes with less than 8 colors
+/// - `@accent`: the most saturated color in the palette
+pub fn resolve_color(spec: &str, palette: &[Rgb<u8>]) -> Result<Rgb<u8>, String> {
+    let Some(role) = spec.trim().strip_prefix('@') else {
+        return parse_color(spec);
+    };
+    if palette.is_empty() {
+        return Err(format!("cannot resolve {} against an empty palette", spec));
+    }
+    match role {
+        "background" => Ok(palette[0]),
+        "foreground" => Ok(match palette.get(7) {
+            Some(color) => *color,
+            None => *palette
+                .iter()
+                .max_by(|a, b| {
+                    let a_diff = (luminance(**a) - luminance(palette[0])).abs();
+                    let b_diff = (luminance(**b) - luminance(palette[0])).abs();
+                    a_diff.total_cmp(&b_diff)
+                })
+                .unwrap(),
+        }),
+        "accent" => Ok(*palette.iter().max_by_key(|color| chroma(**color)).unwrap()),
+        _ => {
+            let index = role
+                .strip_prefix("color")
+                .and_then(|index| index.parse::<usize>().ok())
+                .ok_or_else(|| format!("unknown palette role {}", spec))?;
+            palette.get(index).copied().ok_or_else(|| {
+                format!(
+                    "{} is out of range for a {} color palette",
+                    spec,
+                    palette.len()
+                )
+            })
+        }
+    }
+}
+
+/// Most shades --extend-shades and --expand-palette add on each side of a color, so that
+/// a single color stays within `MAX_COLORS`
+pub const MAX_SHADES: i64 = ((MAX_COLORS - 1) / 2) as i64;
+
+/// `palette` without duplicate colors, unless that grew past the colors an image can be
+/// quantized to
+fn within_limit(palette: Vec<PaletteEntry>, option: &str) -> Result<Vec<PaletteEntry>, String> {
+    let palette = dedup_entries(palette);
+    if palette.len() > MAX_COLORS {
+        return Err(format!(
+            "{} makes {} colors, more than the {} an image can be quantized to. Use fewer \
+             shades, or --max-colors to cut the palette down first",
+            option,
+            palette.len(),
+            MAX_COLORS
+        ));
+    }
+    Ok(palette)
+}
+
+/// Add `shades` darker and `shades` lighter versions of every color after the palette,
+/// so the original colors keep their @colorN slots. The shades get the weight of their
+/// color
+pub fn extend_shades(palette: &[PaletteEntry], shades: u8) -> Result<Vec<PaletteEntry>, String> {
+    let mut extended = palette.to_vec();
+    for entry in palette {
+        for shade in 1..=shades {
+            let amount = shade as f32 / (shades as f32 + 1.0);
+            for target in [Rgb([0, 0, 0]), Rgb([255, 255, 255])] {
+                extended.push(PaletteEntry {
+                    color: blend(entry.color, target, amount),
+                    ..*entry
+                });
+            }
+        }
+    }
+    within_limit(extended, "--extend-shades")
+}
+
+/// Add `steps` shades and `steps` tints of every color after the palette, evenly spaced
+/// in Oklab between the color and black or white, so gradients get perceptually even
+/// steps that keep the hue of the theme. They get the weight of their color
+pub fn expand(palette: &[PaletteEntry], steps: u8) -> Result<Vec<PaletteEntry>, String> {
+    let mut expanded = palette.to_vec();
+    for entry in palette {
+        let lab = to_oklab(entry.color);
+        for step in 1..=steps {
+            let amount = step as f32 / (steps as f32 + 1.0);
+            for target in [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]] {
+                expanded.push(PaletteEntry {
+                    color: from_oklab([0, 1, 2].map(|i| lab[i] + (target[i] - lab[i]) * amount)),
+                    ..*entry
+                });
+            }
+        }
+    }
+    within_limit(expanded, "--expand-palette")
+}
+
+/// The `colors` most distinct colors of a large palette: the palette is clustered with
+/// k-means in Oklab and the palette color closest to each cluster is kept, in the order
+/// of the palette
+pub fn reduce(palette: &[Rgb<u8>], colors: u8) -> Vec<Rgb<u8>> {
+    let palette = dedup(palette.to_vec());
+    if palette.len() <= colors as usize {
+        return palette;
+    }
+    let pixels = RgbImage::from_fn(palette.len() as u32, 1, |x, _| palette[x as usize]);
+    let (clusters, _) = ImagePipeline::try_from(&pixels)
+        .unwrap()
+        .palette_size(colors)
+        .colorspace(ColorSpace::Oklab)
+        .quantize_method(QuantizeMethod::kmeans())
+        .dither(false)
+        .indexed_palette();
+    let kept: Vec<Rgb<u8>> = clusters
+        .into_iter()
+        .map(|cluster| {
+            let cluster = Rgb([cluster.red, cluster.green, cluster.blue]);
+            *palette
+                .iter()
+                .min_by(|a, b| delta_e(**a, cluster).total_cmp(&delta_e(**b, cluster)))
+                .unwrap()
+        })
+        .collect();
+    palette
+        .into_iter()
+        .filter(|color| kept.contains(color))
+        .collect()
+}
+
+/// Colors of the palette that can be told apart: colors closer than a just noticeable
+/// difference to an earlier one don't count
+pub fn distinct_colors(palette: &[Rgb<u8>]) -> Vec<Rgb<u8>> {
+    let mut distinct: Vec<Rgb<u8>> = Vec::new();
+    for color in palette {
+        if distinct.iter().all(|other| delta_e(*color, *other) >= 2.0) {
+            distinct.push(*color);
+        }
+    }
+    distinct
+}
@@ -0,0 +1,57 @@
+//! `palette export`: print the palette a render would use, after every palette option
+//! and modifier, without rendering anything. For checking what a set of options comes
+//! down to, and for handing the colors to other theming tools:
+//!
+//! ```text
+//! imagecolorizer palette export --wal --exclude black --format gpl > wal.gpl
+//! ```
+
+use crate::error::Error;
+use crate::palette::{resolve_color, to_hex};
+use crate::palette_file::encode_gpl;
+use image::Rgb;
+use serde::Serialize;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Format {
+    /// One #rrggbb color per line
+    #[default]
+    Hex,
+    /// The colors and the background, foreground and accent roles
+    Json,
+    /// A GIMP palette, which Inkscape, Krita and Lospec read too
+    Gpl,
+}
+
+#[derive(Serialize)]
+struct Exported {
+    colors: Vec<String>,
+    background: String,
+    foreground: String,
+    accent: String,
+}
+
+/// The palette in `format`
+pub fn format(palette: &[Rgb<u8>], format: Format) -> Result<String, Error> {
+    match format {
+        Format::Hex => Ok(palette
+            .iter()
+            .map(|color| format!("{}\n", to_hex(*color)))
+            .collect()),
+        Format::Json => {
+            let role = |role: &str| {
+                resolve_color(role, palette)
+                    .map(to_hex)
+                    .map_err(Error::Palette)
+            };
+            let exported = Exported {
+                colors: palette.iter().map(|color| to_hex(*color)).collect(),
+                background: role("@background")?,
+                foreground: role("@foreground")?,
+                accent: role("@accent")?,
+            };
+            Ok(serde_json::to_string_pretty(&exported).unwrap() + "\n")
+        }
+        Format::Gpl => Ok(encode_gpl(palette, "imagecolorizer")),
+    }
+}
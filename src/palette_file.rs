@@ -0,0 +1,249 @@
+//! `--palette-file`: palettes saved by image editors and palette sites, such as the
+//! GIMP palettes Lospec offers for download:
+//!
+//! ```text
+//! GIMP Palette
+//! Name: Sweetie 16
+//! Columns: 8
+//! # comment
+//!  26  28  44  black
+//!  93  39  93  purple
+//! ```
+//!
+//! and Adobe Swatch Exchange (.ase) files, in which designers tend to keep brand and
+//! theme palettes. Their RGB, CMYK, LAB and gray swatches are all converted to sRGB, and
+//! groups are flattened. The format is told by the start of the file, not its name.
+//!
+//! The colors keep the order of the file, the names are ignored.
+//!
+//! `--palette-stdin` reads the same formats from stdin, and besides them pywal's
+//! colors.json and plain lists of one color per line, as `wal --preview` or other tools
+//! print them.
+
+use crate::color::from_linear;
+use crate::error::Error;
+use crate::palette;
+use image::Rgb;
+use std::fs;
+use std::io::{self, Read};
+
+const GPL_HEADER: &str = "GIMP Palette";
+const ASE_SIGNATURE: &[u8] = b"ASEF";
+const ASE_COLOR_ENTRY: u16 = 0x0001;
+/// CIE D50 white point, the reference of ASE LAB colors
+const D50: [f32; 3] = [0.964_22, 1.0, 0.825_21];
+
+/// Write a GIMP palette, each color named by its hex code
+pub fn encode_gpl(colors: &[Rgb<u8>], name: &str) -> String {
+    let mut contents = format!("{}\nName: {}\nColumns: 8\n#\n", GPL_HEADER, name);
+    for color in colors {
+        let [r, g, b] = color.0;
+        contents.push_str(&format!(
+            "{:>3} {:>3} {:>3}\t{}\n",
+            r,
+            g,
+            b,
+            palette::to_hex(*color)
+        ));
+    }
+    contents
+}
+
+/// Parse a GIMP palette
+pub fn decode_gpl(contents: &str) -> Result<Vec<Rgb<u8>>, String> {
+    let mut lines = contents.lines().enumerate();
+    let header = lines
+        .next()
+        .map(|(_, line)| line.trim_start_matches('\u{feff}').trim());
+    if header != Some(GPL_HEADER) {
+        return Err("neither a GIMP palette nor an Adobe Swatch Exchange file".to_string());
+    }
+    let mut colors = Vec::new();
+    for (number, line) in lines {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+        let channels: Vec<u8> = line
+            .split_whitespace()
+            .take(3)
+            .map(|channel| channel.parse::<u8>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("line {} is not an RGB triple: {}", number + 1, line))?;
+        let [red, green, blue] = channels[..] else {
+            return Err(format!(
+                "line {} is not an RGB triple: {}",
+                number + 1,
+                line
+            ));
+        };
+        colors.push(Rgb([red, green, blue]));
+    }
+    Ok(colors)
+}
+
+/// Big endian reader over the bytes of an ASE file
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < count {
+            return Err("the file is truncated".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+fn to_u8(channel: f32) -> u8 {
+    (channel.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// CIE L*a*b* relative to D50, as ASE stores it, to sRGB
+fn lab_to_rgb(lightness: f32, a: f32, b: f32) -> Rgb<u8> {
+    let f_y = (lightness + 16.0) / 116.0;
+    let f_x = f_y + a / 500.0;
+    let f_z = f_y - b / 200.0;
+    let inverse = |f: f32| {
+        if f.powi(3) > 216.0 / 24389.0 {
+            f.powi(3)
+        } else {
+            (116.0 * f - 16.0) * 27.0 / 24389.0
+        }
+    };
+    let [x, y, z] = [
+        inverse(f_x) * D50[0],
+        inverse(f_y) * D50[1],
+        inverse(f_z) * D50[2],
+    ];
+    // XYZ (D50) to linear sRGB, with Bradford adaptation to D65
+    let r = 3.133_856 * x - 1.616_867 * y - 0.490_615 * z;
+    let g = -0.978_768 * x + 1.916_142 * y + 0.033_454 * z;
+    let b = 0.071_945 * x - 0.228_991 * y + 1.405_243 * z;
+    Rgb([from_linear(r), from_linear(g), from_linear(b)])
+}
+
+/// Parse an Adobe Swatch Exchange file
+pub fn decode_ase(bytes: &[u8]) -> Result<Vec<Rgb<u8>>, String> {
+    let mut reader = Reader { bytes };
+    if reader.take(4)? != ASE_SIGNATURE {
+        return Err("not an Adobe Swatch Exchange file".to_string());
+    }
+    let _version = (reader.u16()?, reader.u16()?);
+    let blocks = reader.u32()?;
+    let mut colors = Vec::new();
+    for _ in 0..blocks {
+        let kind = reader.u16()?;
+        let length = reader.u32()? as usize;
+        let mut block = Reader {
+            bytes: reader.take(length)?,
+        };
+        // group starts and ends only structure the swatches
+        if kind != ASE_COLOR_ENTRY {
+            continue;
+        }
+        let name_length = block.u16()? as usize;
+        block.take(name_length * 2)?;
+        let model = block.take(4)?;
+        let color = match model {
+            b"RGB " => Rgb([
+                to_u8(block.f32()?),
+                to_u8(block.f32()?),
+                to_u8(block.f32()?),
+            ]),
+            b"CMYK" => {
+                let [c, m, y, k] = [block.f32()?, block.f32()?, block.f32()?, block.f32()?];
+                Rgb([
+                    to_u8((1.0 - c) * (1.0 - k)),
+                    to_u8((1.0 - m) * (1.0 - k)),
+                    to_u8((1.0 - y) * (1.0 - k)),
+                ])
+            }
+            // the lightness is stored from 0 to 1, a and b as they are
+            b"LAB " => lab_to_rgb(block.f32()? * 100.0, block.f32()?, block.f32()?),
+            b"Gray" => {
+                let gray = to_u8(block.f32()?);
+                Rgb([gray, gray, gray])
+            }
+            _ => {
+                return Err(format!(
+                    "unknown color model '{}'",
+                    String::from_utf8_lossy(model)
+                ))
+            }
+        };
+        colors.push(color);
+    }
+    Ok(colors)
+}
+
+/// Parse a palette file of either format
+fn decode(contents: &[u8]) -> Result<Vec<Rgb<u8>>, String> {
+    if contents.starts_with(ASE_SIGNATURE) {
+        decode_ase(contents)
+    } else {
+        decode_gpl(&String::from_utf8_lossy(contents))
+    }
+}
+
+fn check_colors(colors: Vec<Rgb<u8>>, source: &str) -> Result<Vec<Rgb<u8>>, Error> {
+    if colors.is_empty() {
+        return Err(Error::Palette(format!(
+            "{}: the palette has no colors",
+            source
+        )));
+    }
+    Ok(colors)
+}
+
+pub fn load(path: &str) -> Result<Vec<Rgb<u8>>, Error> {
+    let contents = fs::read(path)
+        .map_err(|err| Error::Palette(format!("could not read {}: {}", path, err)))?;
+    let colors = decode(&contents).map_err(|err| Error::Palette(format!("{}: {}", path, err)))?;
+    check_colors(colors, path)
+}
+
+/// `--palette-stdin`: a GIMP or ASE palette, pywal's colors.json, or one color literal
+/// per line, as [`palette::parse_color`] reads them
+pub fn load_stdin() -> Result<Vec<Rgb<u8>>, Error> {
+    let mut contents = Vec::new();
+    io::stdin()
+        .read_to_end(&mut contents)
+        .map_err(|err| Error::Palette(format!("could not read stdin: {}", err)))?;
+    let text = String::from_utf8_lossy(&contents);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    let colors = if contents.starts_with(ASE_SIGNATURE) || text.starts_with(GPL_HEADER) {
+        decode(&contents)
+    } else if text.starts_with('{') {
+        crate::decode_pywal_json(text)
+    } else {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                palette::parse_color(line).map_err(|err| format!("line {}: {}", number + 1, err))
+            })
+            .collect()
+    }
+    .map_err(|err| Error::Palette(format!("stdin: {}", err)))?;
+    check_colors(colors, "stdin")
+}
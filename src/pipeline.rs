@@ -0,0 +1,441 @@
+//! Declarative pipelines given with `--pipeline`: an ordered list of stages that is run
+//! on the input, followed by any number of outputs that each run their own stages on
+//! the result and save it. For example:
+//!
+//! ```toml
+//! stages = [
+//!     { op = "auto-wb" },
+//!     { op = "tone", shadows = 20.0 },
+//! ]
+//!
+//! [[outputs]]
+//! path = "~/.cache/wallpaper/desktop.png"
+//! stages = [
+//!     { op = "resize", size = "2560x1440" },
+//!     { op = "map", average = 2 },
+//! ]
+//!
+//! [[outputs]]
+//! path = "lockscreen.png"
+//! stages = [
+//!     { op = "resize", size = "2560x1440" },
+//!     { op = "map" },
+//!     { op = "terminal-bg", min-contrast = 7.0 },
+//! ]
+//! ```
+//!
+//! The stages are (with their options and defaults):
+//!
+//! - `resize`: `size = "WIDTHxHEIGHT"`, scale and crop to cover the size, `fit` and
+//!   `filter` as for `--fit` and `--resize-filter`
+//! - `auto-wb`: `method = "gray-world"` or `"white-patch"`
+//! - `clahe`: `clip = 2.0`
+//! - `tone`: `shadows = 0.0`, `highlights = 0.0`
+//! - `posterize`: `levels`
+//! - `split-tone`: `spec`, as for `--split-tone`
+//! - `threshold`: `level = "otsu"` or 0 to 255 as a string, `dither = true`
+//! - `map`: `quantize = true`, `quantize-weighting = "uniform"` or `"saliency"`,
+//!   `dither = true`, `average = 0` or `"auto"`, `no-dither-colors = []`, `script`, `cache = false`,
+//!   `deterministic = false`, `assign = "nearest"` or `"hue-buckets"`, `fallback`,
+//!   `fallback-threshold = 25.0`
+//! - `blur`: `sigma = 1.0`
+//! - `terminal-bg`: `min-contrast = 4.5`
+//! - `frame`: `width`, `color = "@accent"`, `shadow = 0`
+//! - `fade`: `spec`, as for `--fade`
+//! - `plugin`: `name`
+//!
+//! Relative output paths are resolved against the output directory given on the command
+//! line, or the current directory. Paths of the form `shm:NAME` write a shared memory
+//! frame as described in [`crate::shm`]. Every output is rendered before any file is written,
+//! and the files are only moved into place once all of them were saved, so a failing
+//! pipeline leaves the previous outputs untouched.
+
+use crate::error::Error;
+use crate::palette::PaletteEntry;
+use crate::{adjust, effects, layout, palette, plugins, render, script, shm, watch};
+use homedir::my_home;
+use image::RgbImage;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The long help of `--pipeline`, the format in short
+pub const HELP: &str = "Run the stages and outputs described in a pipeline file instead of the \
+processing options given on the command line. The file is TOML: the top level stages run on \
+the input, then every output runs its own stages on the result and saves it:
+
+    stages = [{ op = \"auto-wb\" }, { op = \"tone\", shadows = 20.0 }]
+
+    [[outputs]]
+    path = \"~/.cache/wallpaper/desktop.png\"
+    stages = [{ op = \"resize\", size = \"2560x1440\" }, { op = \"map\", average = 2 }]
+
+    [[outputs]]
+    path = \"lockscreen.png\"
+    stages = [{ op = \"map\" }, { op = \"terminal-bg\", min-contrast = 7.0 }]
+
+The stages are resize, auto-wb, clahe, tone, posterize, split-tone, threshold, map, blur, \
+terminal-bg, frame, fade and plugin. Their options are named after the command line options, \
+like min-contrast for --min-contrast. Relative output paths are resolved against the output directory, and no file is \
+replaced unless every output could be written";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Pipeline {
+    #[serde(default)]
+    stages: Vec<Stage>,
+    outputs: Vec<Output>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Output {
+    path: String,
+    #[serde(default)]
+    stages: Vec<Stage>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn default_white_balance() -> adjust::WhiteBalance {
+    adjust::WhiteBalance::GrayWorld
+}
+
+fn default_clip() -> f32 {
+    2.0
+}
+
+fn default_sigma() -> f32 {
+    1.0
+}
+
+fn default_fallback_threshold() -> f32 {
+    render::DEFAULT_FALLBACK_DELTA_E
+}
+
+fn default_min_contrast() -> f32 {
+    4.5
+}
+
+fn default_frame_color() -> String {
+    "@accent".to_string()
+}
+
+fn default_level() -> String {
+    "otsu".to_string()
+}
+
+/// A box size or `"auto"`, as for `--average`
+fn average<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<render::Average, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Average {
+        Radius(i32),
+        Mode(String),
+    }
+    match Average::deserialize(deserializer)? {
+        Average::Radius(radius) => Ok(render::Average::Radius(radius)),
+        Average::Mode(mode) => render::parse_average(&mode).map_err(serde::de::Error::custom),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(
+    tag = "op",
+    rename_all = "kebab-case",
+    rename_all_fields = "kebab-case"
+)]
+enum Stage {
+    Resize {
+        size: String,
+        #[serde(default)]
+        fit: layout::Fit,
+        filter: Option<layout::ResizeFilter>,
+    },
+    AutoWb {
+        #[serde(default = "default_white_balance")]
+        method: adjust::WhiteBalance,
+    },
+    Clahe {
+        #[serde(default = "default_clip")]
+        clip: f32,
+    },
+    Tone {
+        #[serde(default)]
+        shadows: f32,
+        #[serde(default)]
+        highlights: f32,
+    },
+    Posterize {
+        levels: u8,
+    },
+    SplitTone {
+        spec: String,
+    },
+    Threshold {
+        #[serde(default = "default_level")]
+        level: String,
+        #[serde(default = "enabled")]
+        dither: bool,
+    },
+    Map {
+        #[serde(default = "enabled")]
+        quantize: bool,
+        #[serde(default)]
+        quantize_weighting: render::Weighting,
+        #[serde(default = "enabled")]
+        dither: bool,
+        #[serde(default, deserialize_with = "average")]
+        average: render::Average,
+        #[serde(default)]
+        no_dither_colors: Vec<String>,
+        script: Option<String>,
+        #[serde(default)]
+        cache: bool,
+        #[serde(default)]
+        deterministic: bool,
+        #[serde(default)]
+        assign: render::Assign,
+        fallback: Option<String>,
+        #[serde(default = "default_fallback_threshold")]
+        fallback_threshold: f32,
+    },
+    Blur {
+        #[serde(default = "default_sigma")]
+        sigma: f32,
+    },
+    TerminalBg {
+        #[serde(default = "default_min_contrast")]
+        min_contrast: f32,
+    },
+    Fade {
+        spec: String,
+    },
+    Frame {
+        width: u32,
+        #[serde(default = "default_frame_color")]
+        color: String,
+        #[serde(default)]
+        shadow: u32,
+    },
+    Plugin {
+        name: String,
+    },
+}
+
+impl Stage {
+    fn apply(
+        &self,
+        mut img: RgbImage,
+        palette: &[PaletteEntry],
+        strict: bool,
+    ) -> Result<RgbImage, Error> {
+        let colors = palette::colors(palette);
+        let resolve = |spec: &str| palette::resolve_color(spec, &colors).map_err(Error::Palette);
+        match self {
+            Stage::Resize { size, fit, filter } => {
+                let (width, height) = layout::parse_size(size).map_err(Error::Input)?;
+                img = layout::fit(&img, width, height, *fit, *filter);
+            }
+            Stage::AutoWb { method } => adjust::auto_white_balance(&mut img, *method),
+            Stage::Clahe { clip } => adjust::clahe(&mut img, *clip),
+            Stage::Tone {
+                shadows,
+                highlights,
+            } => adjust::tone_curve(&mut img, *shadows, *highlights),
+            Stage::Posterize { levels } => {
+                if *levels < 2 {
+                    return Err(Error::Input(
+                        "posterize needs at least 2 levels".to_string(),
+                    ));
+                }
+                adjust::posterize(&mut img, *levels);
+            }
+            Stage::SplitTone { spec } => {
+                let settings = adjust::parse_split_tone(spec).map_err(Error::Input)?;
+                adjust::split_tone(
+                    &mut img,
+                    resolve(&settings.shadows)?,
+                    resolve(&settings.highlights)?,
+                    settings.balance,
+                    settings.amount,
+                );
+            }
+            Stage::Threshold { level, dither } => {
+                let threshold = render::parse_threshold(level).map_err(Error::Input)?;
+                img = render::threshold(
+                    &img,
+                    threshold,
+                    *dither,
+                    resolve("@background")?,
+                    resolve("@foreground")?,
+                );
+            }
+            Stage::Map {
+                quantize,
+                quantize_weighting,
+                dither,
+                average,
+                no_dither_colors,
+                script,
+                cache,
+                deterministic,
+                assign,
+                fallback,
+                fallback_threshold,
+            } => {
+                let options = render::RenderOptions {
+                    quantize: *quantize,
+                    weighting: *quantize_weighting,
+                    dither: *dither,
+                    average: *average,
+                    flat: no_dither_colors
+                        .iter()
+                        .map(|spec| resolve(spec))
+                        .collect::<Result<_, _>>()?,
+                    cache: *cache,
+                    intermediates: None,
+                    assign: *assign,
+                    deterministic: *deterministic,
+                    sequence: None,
+                    fallback: fallback
+                        .as_deref()
+                        .map(|spec| {
+                            Ok(render::Fallback {
+                                color: resolve(spec)?,
+                                max_delta_e: *fallback_threshold,
+                            })
+                        })
+                        .transpose()?,
+                };
+                let mut mapped = render::render(&img, palette, &options);
+                if let Some(path) = script {
+                    script::Script::load(path, &colors)?.apply(&img, &mut mapped, *average)?;
+                }
+                crate::warn_unused_colors(&mapped, &colors, strict)?;
+                img = mapped;
+            }
+            Stage::Blur { sigma } => img = image::imageops::blur(&img, *sigma),
+            Stage::TerminalBg { min_contrast } => {
+                img = crate::terminal_background(&img, &colors, *min_contrast, strict)?;
+            }
+            Stage::Fade { spec } => {
+                let fade = effects::parse_fade(spec).map_err(Error::Input)?;
+                effects::fade(&mut img, fade.edge, fade.size, resolve(&fade.color)?);
+            }
+            Stage::Frame {
+                width,
+                color,
+                shadow,
+            } => effects::frame(&mut img, *width, resolve(color)?, *shadow),
+            Stage::Plugin { name } => {
+                plugins::find(&plugins::discover(), name)?.process(&mut img, &colors)?;
+            }
+        }
+        Ok(img)
+    }
+}
+
+fn apply_all(
+    stages: &[Stage],
+    mut img: RgbImage,
+    palette: &[PaletteEntry],
+    strict: bool,
+) -> Result<RgbImage, Error> {
+    for stage in stages {
+        watch::check()?;
+        img = stage.apply(img, palette, strict)?;
+    }
+    Ok(img)
+}
+
+/// Where an output of the pipeline goes, expanding `~` and resolving relative paths
+/// against `output_dir`
+fn output_path(path: &str, output_dir: Option<&str>) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Ok(Some(home)) = my_home() {
+            return home.join(rest);
+        }
+    }
+    match output_dir {
+        Some(dir) => Path::new(dir).join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Run the pipeline file at `path` on `input`
+pub fn run(
+    path: &str,
+    input: &str,
+    output_dir: Option<&str>,
+    palette: &[PaletteEntry],
+    strict: bool,
+) -> Result<Vec<PathBuf>, Error> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| Error::Input(format!("could not read {}: {}", path, err)))?;
+    let pipeline: Pipeline =
+        toml::from_str(&contents).map_err(|err| Error::Input(format!("{}: {}", path, err)))?;
+    if pipeline.outputs.is_empty() {
+        return Err(Error::Input(format!("{} has no outputs", path)));
+    }
+
+    let img = apply_all(&pipeline.stages, crate::open_image(input)?, palette, strict)?;
+    let mut rendered = Vec::new();
+    let mut frames = Vec::new();
+    for output in &pipeline.outputs {
+        let result = apply_all(&output.stages, img.clone(), palette, strict)?;
+        match shm::target(&output.path) {
+            Some(name) => frames.push((name, result)),
+            None => rendered.push((output_path(&output.path, output_dir), result)),
+        }
+    }
+
+    watch::check()?;
+    // save everything next to its destination first, and only replace the old outputs
+    // once every file could be written
+    let mut partials = Vec::new();
+    for (destination, result) in &rendered {
+        let name = destination
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let partial = destination.with_file_name(format!(".partial-{}", name));
+        let saved = destination
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .map_or(Ok(()), fs::create_dir_all)
+            .map_err(image::ImageError::IoError)
+            .and_then(|_| result.save(&partial));
+        if let Err(err) = saved {
+            for partial in &partials {
+                let _ = fs::remove_file(partial);
+            }
+            let _ = fs::remove_file(&partial);
+            return Err(Error::Encode(format!(
+                "could not write {}: {}",
+                destination.display(),
+                err
+            )));
+        }
+        partials.push(partial);
+    }
+    for ((destination, _), partial) in rendered.iter().zip(&partials) {
+        fs::rename(partial, destination).map_err(|err| {
+            Error::Encode(format!(
+                "could not write {}: {}",
+                destination.display(),
+                err
+            ))
+        })?;
+        println!("wrote {}", destination.display());
+    }
+    for (name, result) in &frames {
+        println!("wrote {}", shm::write(result, name)?.display());
+    }
+    Ok(rendered
+        .into_iter()
+        .map(|(destination, _)| destination)
+        .collect())
+}
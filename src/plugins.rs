@@ -0,0 +1,170 @@
+//! Third party palette sources and post-processing stages, loaded from shared libraries
+//! (`cdylib`s) in `~/.config/imagecolorizer/plugins/`.
+//!
+//! A plugin exports these C functions, everything except the first two is optional:
+//!
+//! ```c
+//! // must return IMAGECOLORIZER_PLUGIN_ABI (1)
+//! uint32_t imagecolorizer_plugin_abi(void);
+//! // name used to select the plugin on the command line, a static string
+//! const char *imagecolorizer_plugin_name(void);
+//! // palette source: write up to `capacity` colors as packed RGB bytes into `colors` and
+//! // return how many colors the plugin has
+//! size_t imagecolorizer_plugin_palette(uint8_t *colors, size_t capacity);
+//! // post-processing stage: modify the packed RGB8 pixels in place, `palette` holds
+//! // `palette_len` packed RGB colors. Return 0 on success
+//! int32_t imagecolorizer_plugin_process(uint8_t *pixels, uint32_t width, uint32_t height,
+//!                                       const uint8_t *palette, size_t palette_len);
+//! ```
+
+use crate::error::Error;
+use homedir::my_home;
+use image::{Rgb, RgbImage};
+use libloading::{Library, Symbol};
+use std::ffi::{c_char, CStr};
+use std::path::PathBuf;
+
+pub const ABI_VERSION: u32 = 1;
+
+type AbiFn = unsafe extern "C" fn() -> u32;
+type NameFn = unsafe extern "C" fn() -> *const c_char;
+type PaletteFn = unsafe extern "C" fn(*mut u8, usize) -> usize;
+type ProcessFn = unsafe extern "C" fn(*mut u8, u32, u32, *const u8, usize) -> i32;
+
+pub struct Plugin {
+    pub name: String,
+    pub path: PathBuf,
+    library: Library,
+}
+
+impl Plugin {
+    pub fn has_palette(&self) -> bool {
+        // SAFETY: only checks that the symbol exists, the type is not used
+        unsafe {
+            self.library
+                .get::<PaletteFn>(b"imagecolorizer_plugin_palette")
+                .is_ok()
+        }
+    }
+
+    pub fn has_process(&self) -> bool {
+        // SAFETY: only checks that the symbol exists, the type is not used
+        unsafe {
+            self.library
+                .get::<ProcessFn>(b"imagecolorizer_plugin_process")
+                .is_ok()
+        }
+    }
+
+    pub fn palette(&self) -> Result<Vec<Rgb<u8>>, Error> {
+        // SAFETY: the signature is fixed by the plugin ABI, checked when loading
+        let palette_fn: Symbol<PaletteFn> = unsafe {
+            self.library.get(b"imagecolorizer_plugin_palette")
+        }
+        .map_err(|_| Error::Palette(format!("plugin {} does not provide a palette", self.name)))?;
+        // ask for the size first, then for the colors
+        // SAFETY: a zero capacity means the plugin may not write to the pointer
+        let count = unsafe { palette_fn(std::ptr::null_mut(), 0) };
+        let mut colors = vec![0u8; count * 3];
+        // SAFETY: the buffer holds exactly `count` colors
+        let written = unsafe { palette_fn(colors.as_mut_ptr(), count) }.min(count);
+        Ok(colors[..written * 3]
+            .chunks_exact(3)
+            .map(|rgb| Rgb([rgb[0], rgb[1], rgb[2]]))
+            .collect())
+    }
+
+    pub fn process(&self, img: &mut RgbImage, palette: &[Rgb<u8>]) -> Result<(), Error> {
+        // SAFETY: the signature is fixed by the plugin ABI, checked when loading
+        let process_fn: Symbol<ProcessFn> =
+            unsafe { self.library.get(b"imagecolorizer_plugin_process") }.map_err(|_| {
+                Error::ExternalTool(format!("plugin {} is not a processing stage", self.name))
+            })?;
+        let packed: Vec<u8> = palette.iter().flat_map(|color| color.0).collect();
+        let (width, height) = img.dimensions();
+        // SAFETY: the pixel buffer is exactly width * height * 3 bytes and the palette
+        // palette.len() * 3 bytes, both outlive the call
+        let status = unsafe {
+            process_fn(
+                img.as_mut_ptr(),
+                width,
+                height,
+                packed.as_ptr(),
+                palette.len(),
+            )
+        };
+        if status != 0 {
+            return Err(Error::ExternalTool(format!(
+                "plugin {} failed with status {}",
+                self.name, status
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn plugin_dir() -> Option<PathBuf> {
+    let mut dir = my_home().ok().flatten()?;
+    dir.push(".config/imagecolorizer/plugins");
+    Some(dir)
+}
+
+fn load(path: PathBuf) -> Result<Plugin, String> {
+    // SAFETY: loading a library runs its initializers, plugins are trusted code the user
+    // installed themselves
+    let library = unsafe { Library::new(&path) }.map_err(|err| err.to_string())?;
+    // SAFETY: the signatures are fixed by the plugin ABI
+    let name = unsafe {
+        let abi: Symbol<AbiFn> = library
+            .get(b"imagecolorizer_plugin_abi")
+            .map_err(|_| "missing imagecolorizer_plugin_abi".to_string())?;
+        if abi() != ABI_VERSION {
+            return Err(format!(
+                "built for plugin ABI {}, expected {}",
+                abi(),
+                ABI_VERSION
+            ));
+        }
+        let name: Symbol<NameFn> = library
+            .get(b"imagecolorizer_plugin_name")
+            .map_err(|_| "missing imagecolorizer_plugin_name".to_string())?;
+        CStr::from_ptr(name()).to_string_lossy().to_string()
+    };
+    Ok(Plugin {
+        name,
+        path,
+        library,
+    })
+}
+
+/// Load every plugin in the plugin directory, reporting the ones that fail to load
+pub fn discover() -> Vec<Plugin> {
+    let Some(entries) = plugin_dir().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == std::env::consts::DLL_EXTENSION)
+        })
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .filter_map(|path| match load(path.clone()) {
+            Ok(plugin) => Some(plugin),
+            Err(err) => {
+                eprintln!("warning: could not load plugin {}: {}", path.display(), err);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn find<'a>(plugins: &'a [Plugin], name: &str) -> Result<&'a Plugin, Error> {
+    plugins
+        .iter()
+        .find(|plugin| plugin.name == name)
+        .ok_or_else(|| Error::ExternalTool(format!("no plugin named {} is installed", name)))
+}
@@ -0,0 +1,117 @@
+//! `--set portal`: set the wallpaper through the wallpaper portal of the freedesktop
+//! desktop portals (`org.freedesktop.portal.Wallpaper`), which GNOME, KDE and other
+//! desktops implement, and which is the way to set it from inside a Flatpak sandbox.
+//!
+//! The portal is called over the session bus with `gdbus`, which comes with GLib on the
+//! host and in the Flatpak runtimes. The call only returns the object path of a request:
+//! the desktop handles it afterwards, it may ask the user for permission first, and then
+//! sends the outcome as the `Response` signal of the request. So the signals of the
+//! portal are watched with `gdbus monitor` from before the call until that one arrives.
+
+use crate::error::Error;
+use crate::exec;
+use crate::thumbnail::file_uri;
+use std::fs;
+use std::io::{BufRead, BufReader, Lines};
+use std::path::Path;
+use std::process::{ChildStdout, Stdio};
+
+const PORTAL: &str = "org.freedesktop.portal.Desktop";
+
+/// Lines printed by `gdbus monitor`
+type Monitor = Lines<BufReader<ChildStdout>>;
+
+/// The response code of the `Response` signal of `handle` among the signals like
+/// `/org/.../request/1_42/t: org.freedesktop.portal.Request.Response (uint32 0, ...)`
+fn response_code(monitor: &mut Monitor, handle: &str) -> Result<u32, Error> {
+    let prefix = format!(
+        "{}: org.freedesktop.portal.Request.Response (uint32 ",
+        handle
+    );
+    for line in monitor.map_while(Result::ok) {
+        if let Some(code) = line.strip_prefix(&prefix) {
+            let code = code.split(|c: char| !c.is_ascii_digit()).next().unwrap();
+            return code.parse().map_err(|_| {
+                Error::ExternalTool(format!(
+                    "unexpected answer of the wallpaper portal: {}",
+                    line
+                ))
+            });
+        }
+    }
+    Err(Error::ExternalTool(
+        "gdbus monitor stopped before the wallpaper portal answered".to_string(),
+    ))
+}
+
+/// Call SetWallpaperURI with `monitor` watching, returning the response code
+fn call(uri: &str, monitor: &mut Monitor) -> Result<u32, Error> {
+    // the first line is printed once the monitor listens, a signal sent earlier would
+    // be missed
+    monitor.next().transpose().map_err(|err| {
+        Error::ExternalTool(format!("failed to read from gdbus monitor: {}", err))
+    })?;
+    let output = exec::command("gdbus", "--set portal")?
+        .args([
+            "call",
+            "--session",
+            "--dest",
+            PORTAL,
+            "--object-path",
+            "/org/freedesktop/portal/desktop",
+            "--method",
+            "org.freedesktop.portal.Wallpaper.SetWallpaperURI",
+            // no parent window
+            "''",
+            uri,
+            "{'show-preview': <false>, 'set-on': <'background'>}",
+        ])
+        .output()
+        .map_err(|err| Error::ExternalTool(format!("failed to execute gdbus: {}", err)))?;
+    if !output.status.success() {
+        return Err(Error::ExternalTool(format!(
+            "the wallpaper portal failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // the reply is the handle of the request, like (objectpath '/org/.../request/1_42/t',)
+    let reply = String::from_utf8_lossy(&output.stdout);
+    let handle = reply.split('\'').nth(1).ok_or_else(|| {
+        Error::ExternalTool(format!(
+            "unexpected reply of the wallpaper portal: {}",
+            reply.trim()
+        ))
+    })?;
+    response_code(monitor, handle)
+}
+
+/// Ask the portal to set the image at `path` as the desktop background, and wait for
+/// the desktop to have done it
+pub fn set_wallpaper(path: &Path) -> Result<(), Error> {
+    let path = fs::canonicalize(path)
+        .map_err(|err| Error::Input(format!("could not read {}: {}", path.display(), err)))?;
+    // GVariant text: URIs are percent-encoded, so they have no quotes to escape
+    let uri = format!("'{}'", file_uri(&path));
+    let mut child = exec::command("gdbus", "--set portal")?
+        .args(["monitor", "--session", "--dest", PORTAL])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| Error::ExternalTool(format!("failed to execute gdbus: {}", err)))?;
+    // the output was piped above
+    let mut monitor = BufReader::new(child.stdout.take().unwrap()).lines();
+    let result = call(&uri, &mut monitor);
+    // it would watch forever otherwise
+    let _ = child.kill();
+    let _ = child.wait();
+    match result? {
+        0 => Ok(()),
+        1 => Err(Error::ExternalTool(
+            "the wallpaper portal did not set the wallpaper, it was cancelled".to_string(),
+        )),
+        code => Err(Error::ExternalTool(format!(
+            "the wallpaper portal did not set the wallpaper (response {})",
+            code
+        ))),
+    }
+}
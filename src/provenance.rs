@@ -0,0 +1,184 @@
+//! Settings embedded in PNG and JPEG outputs with `--embed-settings`, so a wallpaper can
+//! be regenerated or tweaked later. They are kept as JSON in an iTXt chunk with the
+//! keyword `imagecolorizer`, next to a plain tEXt `Software` chunk, or in a JPEG comment
+//! segment that starts with the keyword, and read back by the `inspect` subcommand.
+
+use crate::error::Error;
+use crate::jpeg;
+use crate::palette::to_hex;
+use crate::Args;
+use image::{DynamicImage, ImageFormat, Rgb};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek};
+use std::path::Path;
+
+const KEYWORD: &str = "imagecolorizer";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Provenance {
+    /// version of imagecolorizer that made the image
+    pub version: String,
+    /// the command line options, without the input and output
+    pub options: Vec<String>,
+    pub palette: Vec<String>,
+    /// absolute path of the source image
+    pub input: String,
+    /// BLAKE3 hash of the source image file
+    pub input_hash: String,
+}
+
+impl Provenance {
+    pub fn new(input: &str, palette: &[Rgb<u8>], args: &Args) -> Result<Provenance, Error> {
+        let contents = fs::read(input)
+            .map_err(|err| Error::Input(format!("could not read {}: {}", input, err)))?;
+        Ok(Provenance {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            options: options(args),
+            palette: palette.iter().map(|color| to_hex(*color)).collect(),
+            input: fs::canonicalize(input)
+                .map(|path| path.to_string_lossy().to_string())
+                .unwrap_or_else(|_| input.to_string()),
+            input_hash: blake3::hash(&contents).to_hex().to_string(),
+        })
+    }
+}
+
+/// The command line options of `args`, without the input and output
+pub fn options(args: &Args) -> Vec<String> {
+    let mut options: Vec<String> = args
+        .argv
+        .iter()
+        .skip(1)
+        .filter(|option| *option != "--")
+        .cloned()
+        .collect();
+    // the positional arguments come last, and in batch mode they are directories
+    for positional in [&args.output, &args.input].into_iter().flatten() {
+        if let Some(index) = options.iter().rposition(|option| option == positional) {
+            options.remove(index);
+        }
+    }
+    options
+}
+
+pub fn is_png(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".png")
+}
+
+/// Save an 8 bit RGB or grayscale image as PNG with the settings embedded
+pub fn save_png(img: &DynamicImage, path: &str, provenance: &Provenance) -> Result<(), Error> {
+    let failed =
+        |err: &dyn std::fmt::Display| Error::Encode(format!("could not write {}: {}", path, err));
+    let (color, data) = match img {
+        DynamicImage::ImageLuma8(img) => (png::ColorType::Grayscale, img.as_raw().as_slice()),
+        DynamicImage::ImageRgb8(img) => (png::ColorType::Rgb, img.as_raw().as_slice()),
+        _ => return Err(failed(&"only 8 bit RGB and grayscale images are supported")),
+    };
+    let file = fs::File::create(path).map_err(|err| failed(&err))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), img.width(), img.height());
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let json = serde_json::to_string(provenance).map_err(|err| failed(&err))?;
+    encoder
+        .add_text_chunk(
+            "Software".to_string(),
+            format!("imagecolorizer {}", provenance.version),
+        )
+        .and_then(|_| encoder.add_itxt_chunk(KEYWORD.to_string(), json))
+        .map_err(|err| failed(&err))?;
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(data))
+        .map_err(|err| failed(&err))
+}
+
+/// Save an image as JPEG with the settings embedded
+pub fn save_jpeg(img: &DynamicImage, path: &str, provenance: &Provenance) -> Result<(), Error> {
+    let failed =
+        |err: &dyn std::fmt::Display| Error::Encode(format!("could not write {}: {}", path, err));
+    let mut encoded = Vec::new();
+    img.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Jpeg)
+        .map_err(|err| failed(&err))?;
+    let json = serde_json::to_string(provenance).map_err(|err| failed(&err))?;
+    let encoded = jpeg::with_comment(&encoded, KEYWORD, &json).map_err(|err| failed(&err))?;
+    fs::write(path, encoded).map_err(|err| failed(&err))
+}
+
+/// Replace the settings embedded in the JPEG at `path` by `provenance`, or remove them
+/// with None, for JPEGs copied from other outputs
+pub fn replace_jpeg(path: &str, provenance: Option<&Provenance>) -> Result<(), Error> {
+    let failed =
+        |err: &dyn std::fmt::Display| Error::Encode(format!("could not write {}: {}", path, err));
+    let data = fs::read(path).map_err(|err| failed(&err))?;
+    let data = match provenance {
+        Some(provenance) => {
+            let json = serde_json::to_string(provenance).map_err(|err| failed(&err))?;
+            jpeg::with_comment(&data, KEYWORD, &json).map_err(|err| failed(&err))?
+        }
+        None => jpeg::without_comment(&data, KEYWORD),
+    };
+    fs::write(path, data).map_err(|err| failed(&err))
+}
+
+/// The settings embedded in the contents of a JPEG, if it has any
+pub fn read_jpeg(data: &[u8]) -> Option<Provenance> {
+    serde_json::from_str(&jpeg::comment(data, KEYWORD)?).ok()
+}
+
+/// The settings embedded in a PNG or JPEG, if it has any
+pub fn read(path: &Path) -> Result<Option<Provenance>, Error> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        Error::Input(format!("could not read {}: {}", path.display(), err))
+    };
+    let mut file = fs::File::open(path).map_err(|err| invalid(&err))?;
+    let mut signature = [0; 2];
+    if file.read_exact(&mut signature).is_ok() && signature == [0xff, 0xd8] {
+        let data = fs::read(path).map_err(|err| invalid(&err))?;
+        return match jpeg::comment(&data, KEYWORD) {
+            Some(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|err| invalid(&err)),
+            None => Ok(None),
+        };
+    }
+    file.rewind().map_err(|err| invalid(&err))?;
+    let reader = png::Decoder::new(BufReader::new(file))
+        .read_info()
+        .map_err(|err| invalid(&err))?;
+    let Some(chunk) = reader
+        .info()
+        .utf8_text
+        .iter()
+        .find(|chunk| chunk.keyword == KEYWORD)
+    else {
+        return Ok(None);
+    };
+    let json = chunk.get_text().map_err(|err| invalid(&err))?;
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|err| invalid(&err))
+}
+
+/// Quote an argument for a POSIX shell where it needs it
+pub fn shell_quote(argument: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c);
+    if !argument.is_empty() && argument.chars().all(safe) {
+        argument.to_string()
+    } else {
+        format!("'{}'", argument.replace('\'', "'\\''"))
+    }
+}
+
+pub fn print_text(provenance: &Provenance) {
+    println!("made by: imagecolorizer {}", provenance.version);
+    println!("source: {}", provenance.input);
+    println!("source hash: {}", provenance.input_hash);
+    println!("palette: {}", provenance.palette.join(" "));
+    let options: Vec<String> = provenance
+        .options
+        .iter()
+        .map(|option| shell_quote(option))
+        .collect();
+    println!("options: {}", options.join(" "));
+}
@@ -0,0 +1,62 @@
+//! `--fg-palette` and `--bg-palette`: map the subject and the backdrop of the image to
+//! different palettes, e.g. accents for a character and muted colors behind it. The
+//! subject is given as a mask image or found automatically from the saliency map of
+//! [`crate::saliency`].
+
+use crate::error::Error;
+use crate::palette::PaletteEntry;
+use crate::render::{self, otsu_level, RenderOptions};
+use crate::saliency::saliency;
+use image::RgbImage;
+
+/// Which pixels are the foreground, row by row
+pub type Mask = Vec<bool>;
+
+/// Read a mask image, white (or opaque) for the foreground, scaled to the image size
+pub fn load_mask(path: &str, width: u32, height: u32) -> Result<Mask, Error> {
+    let mask = image::open(path)
+        .map_err(|err| Error::Input(format!("could not read mask {}: {}", path, err)))?;
+    // masks cut out with transparency rather than painted in black and white
+    let has_alpha = mask.color().has_alpha();
+    let mask = image::imageops::resize(
+        &mask.to_luma_alpha8(),
+        width,
+        height,
+        image::imageops::FilterType::Triangle,
+    );
+    Ok(mask
+        .pixels()
+        .map(|pixel| {
+            let value = if has_alpha { pixel[1] } else { pixel[0] };
+            value >= 128
+        })
+        .collect())
+}
+
+/// The salient part of the image, split from the rest with otsu's method
+pub fn auto_mask(img: &RgbImage) -> Mask {
+    let values: Vec<u8> = saliency(img)
+        .into_iter()
+        .map(|value| (value * 255.0).round() as u8)
+        .collect();
+    let level = otsu_level(&values);
+    values.into_iter().map(|value| value > level).collect()
+}
+
+/// Map the foreground and the background of the image to their own palette
+pub fn render(
+    input_img: &RgbImage,
+    foreground: &[PaletteEntry],
+    background: &[PaletteEntry],
+    mask: &Mask,
+    options: &RenderOptions,
+) -> RgbImage {
+    let subject = render::render(input_img, foreground, options);
+    let mut output_img = render::render(input_img, background, options);
+    for ((pixel, subject), inside) in output_img.pixels_mut().zip(subject.pixels()).zip(mask) {
+        if *inside {
+            *pixel = *subject;
+        }
+    }
+    output_img
+}
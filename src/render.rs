@@ -0,0 +1,632 @@
+use crate::color::{color_difference, delta_e, luminance, to_oklch};
+use crate::integral::Integral;
+use crate::palette::PaletteEntry;
+use image::{Rgb, RgbImage};
+use quantette::{ColorSpace, ImagePipeline, QuantizeMethod};
+use rayon::prelude::*;
+use std::path::PathBuf;
+
+/// Average color of the box of pixels within `radius` of the pixel at `x`, `y`
+pub fn box_average(input_img: &RgbImage, x: u32, y: u32, radius: i32) -> Rgb<u8> {
+    // the sums are kept as the box is walked, so no pixel is stored on the way
+    let (mut sum, mut count) = ([0usize; 3], 0);
+    // get pixels within a range about the central pixel
+    for row in -radius..radius {
+        for column in -radius..radius {
+            // this block is limited in image sizes and the
+            // conversions ultimately as long as nobody attempts to
+            // use a massive image we should be fine
+            if let Some(pixel) = input_img.get_pixel_checked(
+                ((x as i32) + column).clamp(0, input_img.width() as i32) as u32,
+                ((y as i32) + row).clamp(0, input_img.height() as i32) as u32,
+            ) {
+                for (sum, value) in sum.iter_mut().zip(pixel.0) {
+                    *sum += value as usize;
+                }
+                count += 1;
+            }
+        }
+    }
+    Rgb(sum.map(|sum| (sum / count).min(255) as u8))
+}
+
+/// How the mapping averages the pixels, see `--average`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Average {
+    /// the box within this radius, 0 or less for no averaging
+    Radius(i32),
+    /// a large box in flat regions and smaller ones near edges and fine detail
+    Auto,
+}
+
+impl Default for Average {
+    fn default() -> Average {
+        Average::Radius(0)
+    }
+}
+
+impl Average {
+    pub fn enabled(self) -> bool {
+        !matches!(self, Average::Radius(radius) if radius <= 0)
+    }
+}
+
+pub fn parse_average(input: &str) -> Result<Average, String> {
+    match input {
+        "auto" => Ok(Average::Auto),
+        radius => radius
+            .parse::<i32>()
+            .map(Average::Radius)
+            .map_err(|_| format!("'{}' is neither a box size nor auto", radius)),
+    }
+}
+
+/// Box radii `--average auto` tries, largest first
+const AUTO_RADII: [u32; 6] = [10, 7, 5, 3, 2, 1];
+/// Summed channel variance up to which a box counts as flat for `--average auto`, a
+/// standard deviation of 20 per channel. Dithering and grain stay below it, edges between
+/// regions of different colors don't
+const AUTO_FLAT_VARIANCE: f32 = 3.0 * 20.0 * 20.0;
+
+/// The colors the closest color search works on, the pixels or their averages
+pub struct Averager<'a> {
+    img: &'a RgbImage,
+    average: Average,
+    integral: Option<Integral>,
+}
+
+impl Averager<'_> {
+    pub fn new(img: &RgbImage, average: Average) -> Averager<'_> {
+        Averager {
+            img,
+            average,
+            integral: (average == Average::Auto).then(|| Integral::new(img)),
+        }
+    }
+
+    pub fn at(&self, x: u32, y: u32) -> Rgb<u8> {
+        match (self.average, &self.integral) {
+            (Average::Radius(radius), _) if radius > 0 => box_average(self.img, x, y, radius),
+            // the largest box around the pixel that is still flat, or the pixel itself on
+            // an edge
+            (Average::Auto, Some(integral)) => AUTO_RADII
+                .iter()
+                .map(|radius| integral.stats(x, y, *radius))
+                .find(|stats| stats.variance <= AUTO_FLAT_VARIANCE)
+                .map_or(*self.img.get_pixel(x, y), |stats| stats.average),
+            _ => *self.img.get_pixel(x, y),
+        }
+    }
+}
+
+/// The palette color closest to `color`
+pub fn nearest_color(color: Rgb<u8>, palette: &[Rgb<u8>]) -> Rgb<u8> {
+    *palette
+        .iter()
+        .min_by_key(|candidate| color_difference(color, **candidate))
+        .unwrap()
+}
+
+/// Default ΔE from the closest palette color above which a pixel uses the fallback
+pub const DEFAULT_FALLBACK_DELTA_E: f32 = 25.0;
+
+/// Where the closest palette color is a poor match, use this color instead
+pub struct Fallback {
+    pub color: Rgb<u8>,
+    /// ΔE above which the closest color counts as a poor match
+    pub max_delta_e: f32,
+}
+
+/// How pixels pick their palette color
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Assign {
+    /// The closest palette color
+    #[default]
+    Nearest,
+    /// Split the hue wheel among the chromatic palette colors and pick by hue, then by
+    /// weighted lightness among the colors of that hue, so rainbows stay rainbows
+    HueBuckets,
+}
+
+/// OkLCh chroma from which a palette color counts as chromatic
+const CHROMATIC: f32 = 0.04;
+/// Palette colors closer in hue than this (in degrees) share a bucket, like shades
+const SAME_HUE: f32 = 20.0;
+
+/// Palette colors with their lightness and weight
+type Shades = Vec<(Rgb<u8>, f32, f32)>;
+
+/// The palette split into hue buckets for [`Assign::HueBuckets`]
+struct HueBuckets {
+    /// the center hue and the colors with their lightness
+    buckets: Vec<(f32, Shades)>,
+    neutrals: Shades,
+}
+
+fn hue_distance(hue1: f32, hue2: f32) -> f32 {
+    let distance = (hue1 - hue2).rem_euclid(360.0);
+    distance.min(360.0 - distance)
+}
+
+/// The color closest in lightness, with the differences divided by the weights
+fn closest_lightness(colors: &[(Rgb<u8>, f32, f32)], lightness: f32) -> Rgb<u8> {
+    let distance = |(_, shade, weight): &(Rgb<u8>, f32, f32)| (shade - lightness).abs() / weight;
+    colors
+        .iter()
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .unwrap()
+        .0
+}
+
+impl HueBuckets {
+    fn new(palette: &[PaletteEntry]) -> HueBuckets {
+        let mut chromatic = Vec::new();
+        let mut neutrals = Vec::new();
+        for PaletteEntry { color, weight } in palette {
+            let [lightness, chroma, hue] = to_oklch(*color);
+            if chroma >= CHROMATIC {
+                chromatic.push((hue, (*color, lightness, *weight)));
+            } else {
+                neutrals.push((*color, lightness, *weight));
+            }
+        }
+        chromatic.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut buckets: Vec<(Vec<f32>, Shades)> = Vec::new();
+        for (hue, shade) in chromatic {
+            match buckets.last_mut() {
+                Some((hues, colors)) if hue - hues[hues.len() - 1] < SAME_HUE => {
+                    hues.push(hue);
+                    colors.push(shade);
+                }
+                _ => buckets.push((vec![hue], vec![shade])),
+            }
+        }
+        // the wheel wraps around, so the last bucket can continue the first
+        if buckets.len() > 1 {
+            let (first, last) = (
+                buckets[0].0[0],
+                *buckets[buckets.len() - 1].0.last().unwrap(),
+            );
+            if first + 360.0 - last < SAME_HUE {
+                let (hues, colors) = buckets.pop().unwrap();
+                buckets[0].0.extend(hues.into_iter().map(|hue| hue - 360.0));
+                buckets[0].1.extend(colors);
+            }
+        }
+        HueBuckets {
+            buckets: buckets
+                .into_iter()
+                .map(|(hues, colors)| (hues.iter().sum::<f32>() / hues.len() as f32, colors))
+                .collect(),
+            neutrals,
+        }
+    }
+
+    fn assign(&self, color: Rgb<u8>, palette: &[PaletteEntry]) -> Rgb<u8> {
+        let [lightness, chroma, hue] = to_oklch(color);
+        // grays go to the neutral colors, halfway to chromatic so washed out colors
+        // keep their hue
+        if chroma < CHROMATIC / 2.0 && !self.neutrals.is_empty() {
+            return closest_lightness(&self.neutrals, lightness);
+        }
+        match self
+            .buckets
+            .iter()
+            .min_by(|a, b| hue_distance(a.0, hue).total_cmp(&hue_distance(b.0, hue)))
+        {
+            Some((_, colors)) => closest_lightness(colors, lightness),
+            None => nearest_weighted(color, palette),
+        }
+    }
+}
+
+/// The palette color closest to `color`, with the distances divided by the weights
+fn nearest_weighted(color: Rgb<u8>, palette: &[PaletteEntry]) -> Rgb<u8> {
+    palette
+        .iter()
+        // this map finds the differences for all colors in the palette
+        // compared to the pixel, scaled down for preferred colors
+        .map(|entry| {
+            let difference = color_difference(color, entry.color) as f32;
+            (entry.color, difference / entry.weight)
+        })
+        // this fold actually finds the closest palette color
+        .fold((Rgb([0, 0, 0]), f32::MAX), |lowest_current, x| {
+            if x.1 < lowest_current.1 {
+                x
+            } else {
+                lowest_current
+            }
+        })
+        .0
+}
+
+/// Map every pixel (or the average of the box around it) to a palette color picked with
+/// `assign`, or to the fallback where that color is too far off. Both ways of assigning
+/// divide distances by the weights of the colors
+pub fn map_to_palette(
+    input_img: &RgbImage,
+    palette: &[PaletteEntry],
+    average: Average,
+    assign: Assign,
+    fallback: Option<&Fallback>,
+) -> RgbImage {
+    let buckets = (assign == Assign::HueBuckets).then(|| HueBuckets::new(palette));
+    let averager = Averager::new(input_img, average);
+    let mut output_img = RgbImage::new(input_img.width(), input_img.height());
+    // averaging, the search and the fallback run in one pass that writes the output
+    // pixels in place, without a buffer of averages or of results in between. Dithering
+    // is not part of it, quantette dithers while it quantizes, before the mapping
+    let width = input_img.width() as usize;
+    output_img
+        .par_chunks_mut(width * 3)
+        .enumerate()
+        .for_each(|(y, row)| {
+            // the rest of a render --watch starts over is of no use, its rows stay black
+            if crate::watch::superseded() {
+                return;
+            }
+            for (x, output_pixel) in row.chunks_exact_mut(3).enumerate() {
+                let averaged_pixel = averager.at(x as u32, y as u32);
+                // this finds the closest color within the pallet and selects it
+                let closest = if let Some(buckets) = &buckets {
+                    buckets.assign(averaged_pixel, palette)
+                } else {
+                    nearest_weighted(averaged_pixel, palette)
+                };
+                let color = match fallback {
+                    Some(fallback) if delta_e(averaged_pixel, closest) > fallback.max_delta_e => {
+                        fallback.color
+                    }
+                    _ => closest,
+                };
+                output_pixel.copy_from_slice(&color.0);
+            }
+        });
+    output_img
+}
+
+/// How much each pixel counts when quantizing
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Weighting {
+    /// Every pixel counts the same
+    #[default]
+    Uniform,
+    /// Pixels whose color stands out from the mean color of the image count up to 16
+    /// times as much, less the further they are from the center, so a small subject
+    /// keeps its own colors instead of losing them to a large, plain sky
+    Saliency,
+}
+
+/// Most colors an image is quantized to, larger palettes are quantized to this many
+pub const MAX_COLORS: usize = 255;
+
+/// Reduce the image to `colors` colors with k-means so that it maps cleanly onto a
+/// palette of the same size. The parallel k-means and dithering can come out slightly
+/// different with the number of threads, `deterministic` runs them on one thread
+pub fn quantize(
+    input_img: &RgbImage,
+    colors: usize,
+    dither: bool,
+    weighting: Weighting,
+    deterministic: bool,
+) -> RgbImage {
+    let colors = colors.min(MAX_COLORS);
+    if weighting == Weighting::Saliency {
+        return crate::saliency::quantize(input_img, colors, dither, deterministic);
+    }
+    let mut pipeline = ImagePipeline::try_from(input_img).unwrap();
+    pipeline
+        .palette_size(colors as u8) // limit the no. of colors to the length of the pallet
+        .dither(dither)
+        .colorspace(ColorSpace::Oklab) // use a more accurate color space
+        .quantize_method(QuantizeMethod::kmeans()); // use a more accurate quantization algorithm
+    if deterministic {
+        pipeline.quantized_rgbimage()
+    } else {
+        pipeline.quantized_rgbimage_par() // run the pipeline in parallel to get a [`RgbImage`]
+    }
+}
+
+pub struct RenderOptions {
+    pub quantize: bool,
+    pub weighting: Weighting,
+    pub dither: bool,
+    pub average: Average,
+    /// palette colors that should never be dithered
+    pub flat: Vec<Rgb<u8>>,
+    /// keep quantization results in the on disk cache
+    pub cache: bool,
+    /// directory to save the result of every stage to, for debugging
+    pub intermediates: Option<PathBuf>,
+    pub assign: Assign,
+    pub fallback: Option<Fallback>,
+    /// the same result whatever the number of threads
+    pub deterministic: bool,
+    /// reuse the quantized colors of the last quantized frame while the share of pixels
+    /// that would have to change color bins to get from its histogram to the one of the
+    /// frame stays below this
+    pub sequence: Option<f32>,
+}
+
+impl RenderOptions {
+    fn quantize(&self, input_img: &RgbImage, colors: usize, dither: bool) -> RgbImage {
+        let fresh = || {
+            if self.cache {
+                crate::cache::quantize(
+                    input_img,
+                    colors,
+                    dither,
+                    self.weighting,
+                    self.deterministic,
+                )
+            } else {
+                quantize(
+                    input_img,
+                    colors,
+                    dither,
+                    self.weighting,
+                    self.deterministic,
+                )
+            }
+        };
+        match self.sequence {
+            Some(threshold) => {
+                crate::sequence::quantize(input_img, colors, dither, threshold, fresh)
+            }
+            None => fresh(),
+        }
+    }
+
+    /// Save the result of a stage when intermediates are kept. A failure only warns, the
+    /// intermediates are no reason to lose the render
+    pub fn keep(&self, name: &str, img: &RgbImage) {
+        let Some(dir) = &self.intermediates else {
+            return;
+        };
+        let path = dir.join(format!("{}.png", name));
+        if let Err(err) = std::fs::create_dir_all(dir).map_err(image::ImageError::IoError) {
+            eprintln!("warning: could not create {}: {}", dir.display(), err);
+        } else if let Err(err) = img.save(&path) {
+            eprintln!("warning: could not write {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Every pixel replaced by the average of the box around it, as the mapping sees it
+fn averaged(input_img: &RgbImage, average: Average) -> RgbImage {
+    let averager = Averager::new(input_img, average);
+    let pixels: Vec<Rgb<u8>> = input_img
+        .par_enumerate_pixels()
+        .map(|(x, y, _)| averager.at(x, y))
+        .collect();
+    RgbImage::from_fn(input_img.width(), input_img.height(), |x, y| {
+        pixels[(y * input_img.width() + x) as usize]
+    })
+}
+
+/// The input the closest color search works on, saved when intermediates are kept
+fn keep_mapping_input(img: &RgbImage, options: &RenderOptions) {
+    if options.intermediates.is_some() && options.average.enabled() {
+        options.keep("3-averaged", &averaged(img, options.average));
+    }
+}
+
+/// The full palette mapping: optional quantization followed by the closest color search
+pub fn render(input_img: &RgbImage, palette: &[PaletteEntry], options: &RenderOptions) -> RgbImage {
+    if !options.quantize {
+        keep_mapping_input(input_img, options);
+        return map_to_palette(
+            input_img,
+            palette,
+            options.average,
+            options.assign,
+            options.fallback.as_ref(),
+        );
+    }
+    let quantized = options.quantize(input_img, palette.len(), options.dither);
+    options.keep("2-quantized", &quantized);
+    keep_mapping_input(&quantized, options);
+    let dithered = map_to_palette(
+        &quantized,
+        palette,
+        options.average,
+        options.assign,
+        options.fallback.as_ref(),
+    );
+    if !options.dither || options.flat.is_empty() {
+        return dithered;
+    }
+    // regions that land on a flat color without dithering keep the clean result,
+    // everything else uses the dithered one
+    let mut output_img = map_to_palette(
+        &options.quantize(input_img, palette.len(), false),
+        palette,
+        options.average,
+        options.assign,
+        options.fallback.as_ref(),
+    );
+    output_img
+        .par_pixels_mut()
+        .zip(dithered.par_pixels())
+        .for_each(|(smooth, dithered)| {
+            if !options.flat.contains(smooth) {
+                *smooth = *dithered;
+            }
+        });
+    output_img
+}
+
+/// Render the image with the first 2, 4, 8 and 16 palette colors and tile the results
+/// into a 2 column contact sheet the size of the input
+pub fn ladder(input_img: &RgbImage, palette: &[PaletteEntry], options: &RenderOptions) -> RgbImage {
+    let mut steps: Vec<usize> = [2, 4, 8, 16]
+        .into_iter()
+        .map(|step| step.min(palette.len()))
+        .collect();
+    steps.dedup();
+
+    let (tile_width, tile_height) = (input_img.width() / 2, input_img.height() / 2);
+    let rows = steps.len().div_ceil(2) as u32;
+    let mut sheet = RgbImage::new(tile_width * 2, tile_height * rows);
+    for (i, step) in steps.into_iter().enumerate() {
+        let rendered = render(input_img, &palette[..step], options);
+        let tile = image::imageops::thumbnail(&rendered, tile_width, tile_height);
+        image::imageops::replace(
+            &mut sheet,
+            &tile,
+            (i as u32 % 2 * tile_width) as i64,
+            (i as u32 / 2 * tile_height) as i64,
+        );
+    }
+    sheet
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Threshold {
+    Level(u8),
+    /// pick the level that best separates the two halves of the luminance histogram
+    Otsu,
+}
+
+pub fn parse_threshold(input: &str) -> Result<Threshold, String> {
+    match input {
+        "otsu" => Ok(Threshold::Otsu),
+        level => level
+            .parse::<u8>()
+            .map(Threshold::Level)
+            .map_err(|_| format!("'{}' is neither a level from 0 to 255 nor otsu", level)),
+    }
+}
+
+/// The level that best splits the values in two classes, with otsu's method
+pub fn otsu_level(luma: &[u8]) -> u8 {
+    let mut histogram = [0u64; 256];
+    for value in luma {
+        histogram[*value as usize] += 1;
+    }
+    let total = luma.len() as f64;
+    let sum: f64 = (0..256)
+        .map(|value| value as f64 * histogram[value] as f64)
+        .sum();
+    let mut background_sum = 0.0;
+    let mut background_count = 0.0;
+    let mut best = (0, 0.0);
+    for (value, count) in histogram.iter().enumerate() {
+        background_count += *count as f64;
+        if background_count == 0.0 || background_count == total {
+            continue;
+        }
+        background_sum += value as f64 * *count as f64;
+        let background_mean = background_sum / background_count;
+        let foreground_mean = (sum - background_sum) / (total - background_count);
+        // maximise the variance between the two classes
+        let variance = background_count
+            * (total - background_count)
+            * (background_mean - foreground_mean).powi(2);
+        if variance > best.1 {
+            best = (value, variance);
+        }
+    }
+    best.0 as u8
+}
+
+/// Strict two color output: pixels darker than the threshold become `background`,
+/// the rest `foreground`. With dithering the level picks the midpoint of the error
+/// diffusion instead of a hard cut
+pub fn threshold(
+    input_img: &RgbImage,
+    threshold: Threshold,
+    dither: bool,
+    background: Rgb<u8>,
+    foreground: Rgb<u8>,
+) -> RgbImage {
+    let (width, height) = input_img.dimensions();
+    let luma: Vec<u8> = input_img
+        .pixels()
+        .map(|pixel| (luminance(*pixel) * 255.0).round() as u8)
+        .collect();
+    let level = match threshold {
+        Threshold::Level(level) => level,
+        Threshold::Otsu => otsu_level(&luma),
+    };
+
+    let mut output_img = RgbImage::new(width, height);
+    if !dither {
+        for (pixel, value) in output_img.pixels_mut().zip(luma) {
+            *pixel = if value < level {
+                background
+            } else {
+                foreground
+            };
+        }
+        return output_img;
+    }
+
+    // stretch the luminance so the chosen level sits in the middle of the range, then
+    // Floyd-Steinberg it down to black and white
+    let level = (level as f32).clamp(1.0, 254.0);
+    let mut values: Vec<f32> = luma
+        .into_iter()
+        .map(|value| {
+            let value = value as f32;
+            if value < level {
+                value / level * 127.5
+            } else {
+                127.5 + (value - level) / (255.0 - level) * 127.5
+            }
+        })
+        .collect();
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as usize;
+            let white = values[i] >= 127.5;
+            let error = values[i] - if white { 255.0 } else { 0.0 };
+            output_img.put_pixel(x, y, if white { foreground } else { background });
+            let mut spread = |dx: i32, dy: u32, weight: f32| {
+                let (nx, ny) = (x as i32 + dx, y + dy);
+                if nx >= 0 && (nx as u32) < width && ny < height {
+                    values[(ny * width + nx as u32) as usize] += error * weight;
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+    output_img
+}
+
+/// Ordered (Bayer 8x8) dithering straight onto the palette. `spread` is how far, in
+/// channel values, the pattern is allowed to push a pixel, usually the gap between two
+/// neighbouring palette colors
+pub fn ordered_dither(input_img: &RgbImage, palette: &[Rgb<u8>], spread: f32) -> RgbImage {
+    // the classic recursive 8x8 bayer matrix
+    let bayer: [[u8; 8]; 8] = std::array::from_fn(|y| {
+        std::array::from_fn(|x| {
+            let mut value = 0;
+            for bit in 0..3 {
+                let (bx, by) = ((x >> bit) & 1, (y >> bit) & 1);
+                value |= ((bx ^ by) << 1 | by) << (4 - 2 * bit);
+            }
+            value as u8
+        })
+    });
+    let mut output_img = input_img.clone();
+    output_img
+        .par_enumerate_pixels_mut()
+        .for_each(|(x, y, pixel)| {
+            let offset = (bayer[y as usize % 8][x as usize % 8] as f32 + 0.5) / 64.0 - 0.5;
+            let shifted = Rgb(pixel
+                .0
+                .map(|channel| (channel as f32 + offset * spread).round().clamp(0.0, 255.0) as u8));
+            *pixel = *palette
+                .iter()
+                .min_by_key(|color| color_difference(shifted, **color))
+                .unwrap();
+        });
+    output_img
+}
@@ -0,0 +1,196 @@
+//! The `rerun` subcommand: render an image again from the settings embedded in an
+//! earlier output (see [`crate::provenance`]), with some options replaced. The typical
+//! use is "same look, new palette": options like `--wal` pick up the current colors, or
+//! a new palette can be given as an override.
+
+use crate::error::{warn, Error};
+use crate::provenance::{self, Provenance};
+use crate::Args;
+use clap::{CommandFactory, Parser};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Options that together choose the palette, so overriding one replaces all of them
+pub const PALETTE_OPTIONS: [&str; 31] = [
+    "theme",
+    "palette",
+    "extend_shades",
+    "expand_palette",
+    "max_colors",
+    "exclude",
+    "exclude_distance",
+    "merge_distance",
+    "sort_palette",
+    "wal",
+    "wal_file",
+    "wallust",
+    "xresources",
+    "xresources_file",
+    "base16",
+    "palette_file",
+    "palette_stdin",
+    "palette_from",
+    "palette_colors",
+    "min_share",
+    "css",
+    "lospec",
+    "matugen",
+    "matugen_mode",
+    "gogh",
+    "alacritty",
+    "kitty",
+    "tmux",
+    "wezterm",
+    "term_colors",
+    "plugin_palette",
+];
+
+/// The id of the option a command line token names, if it is one
+pub fn option_id(token: &str) -> Option<String> {
+    let command = Args::command();
+    if let Some(long) = token.strip_prefix("--") {
+        let long = long.split('=').next().unwrap_or(long);
+        return command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long))
+            .map(|arg| arg.get_id().to_string());
+    }
+    let short = token.strip_prefix('-')?.chars().next()?;
+    if short.is_ascii_digit() {
+        // a negative number
+        return None;
+    }
+    let id = command
+        .get_arguments()
+        .find(|arg| arg.get_short() == Some(short))
+        .map(|arg| arg.get_id().to_string());
+    id
+}
+
+/// Split options into groups of an option and the values that follow it
+pub fn group(options: &[String]) -> Vec<(Option<String>, Vec<String>)> {
+    let mut groups: Vec<(Option<String>, Vec<String>)> = Vec::new();
+    for token in options {
+        match option_id(token) {
+            Some(id) => groups.push((Some(id), vec![token.clone()])),
+            None => match groups.last_mut() {
+                Some((_, tokens)) => tokens.push(token.clone()),
+                None => groups.push((None, vec![token.clone()])),
+            },
+        }
+    }
+    groups
+}
+
+/// The stored options with everything the overrides set again taken out, followed by
+/// the overrides
+fn merge(stored: &[String], overrides: &[String]) -> Vec<String> {
+    let overridden: Vec<String> = group(overrides)
+        .into_iter()
+        .filter_map(|(id, _)| id)
+        .collect();
+    let palette_overridden = overridden
+        .iter()
+        .any(|id| PALETTE_OPTIONS.contains(&id.as_str()));
+    let mut options: Vec<String> = group(stored)
+        .into_iter()
+        .filter(|(id, _)| {
+            let replaced = |id: &String| {
+                overridden.contains(id)
+                    || (palette_overridden && PALETTE_OPTIONS.contains(&id.as_str()))
+            };
+            !id.as_ref().is_some_and(replaced)
+        })
+        .flat_map(|(_, tokens)| tokens)
+        .collect();
+    options.extend(overrides.iter().cloned());
+    options
+}
+
+/// Find the source image: where it was, or a file with the same contents in one of the
+/// search directories or next to where it was
+fn locate(provenance: &Provenance, search: &[String], strict: bool) -> Result<PathBuf, Error> {
+    let recorded = Path::new(&provenance.input);
+    let matches = |path: &Path| {
+        fs::read(path).is_ok_and(|contents| {
+            blake3::hash(&contents).to_hex().as_str() == provenance.input_hash
+        })
+    };
+    if matches(recorded) {
+        return Ok(recorded.to_path_buf());
+    }
+    let directories = search
+        .iter()
+        .map(PathBuf::from)
+        .chain(recorded.parent().map(Path::to_path_buf));
+    for directory in directories {
+        let Ok(entries) = fs::read_dir(&directory) else {
+            continue;
+        };
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        files.sort();
+        if let Some(found) = files.into_iter().find(|path| matches(path)) {
+            println!("found the source at {}", found.display());
+            return Ok(found);
+        }
+    }
+    if recorded.is_file() {
+        warn(
+            strict,
+            Error::Input(format!(
+                "{} changed since the image was made, the result will differ",
+                recorded.display()
+            )),
+        )?;
+        return Ok(recorded.to_path_buf());
+    }
+    Err(Error::Input(format!(
+        "the source {} is gone, use --search with the directory it moved to",
+        recorded.display()
+    )))
+}
+
+/// The arguments to render `previous` again with
+pub fn args(
+    previous: &str,
+    output: Option<&str>,
+    search: &[String],
+    same_palette: bool,
+    overrides: &[String],
+) -> Result<Args, Error> {
+    let provenance = provenance::read(Path::new(previous))?.ok_or_else(|| {
+        Error::Input(format!(
+            "{} has no embedded settings, it was made without --embed-settings",
+            previous
+        ))
+    })?;
+    if provenance.version != env!("CARGO_PKG_VERSION") {
+        eprintln!(
+            "warning: {} was made by version {}, this is {}",
+            previous,
+            provenance.version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    let mut overrides = overrides.to_vec();
+    if same_palette {
+        overrides.push("--palette".to_string());
+        overrides.extend(provenance.palette.iter().cloned());
+    }
+    let options = merge(&provenance.options, &overrides);
+    let strict = options.iter().any(|option| option == "--strict");
+    let source = locate(&provenance, search, strict)?;
+
+    let mut argv = vec!["imagecolorizer".to_string()];
+    argv.extend(options);
+    // so options with any number of values don't take the paths
+    argv.push("--".to_string());
+    argv.push(source.to_string_lossy().to_string());
+    argv.push(output.unwrap_or(previous).to_string());
+    let mut args = Args::try_parse_from(&argv).unwrap_or_else(|err| err.exit());
+    args.argv = argv;
+    Ok(args)
+}
@@ -0,0 +1,182 @@
+//! `--quantize-weighting saliency`: k-means quantization that counts the pixels of the
+//! visually important parts of the image more, so a small subject gets its own colors
+//! instead of losing them to a large, boring sky.
+//!
+//! Saliency is estimated with the frequency-tuned method of Achanta et al.: how far the
+//! slightly blurred color of a pixel is from the mean color of the image, in Oklab. It is
+//! combined with a center bias, as subjects tend to be framed near the middle.
+
+use crate::color::to_oklab;
+use image::{Rgb, RgbImage};
+use quantette::kmeans::{self, Centroids};
+use quantette::palette::{IntoColor, LinSrgb, Oklab, Srgb};
+use quantette::{
+    wu, ColorCounts, ColorCountsParallelRemap, ColorCountsRemap, ColorSpace, FloydSteinberg,
+    PaletteSize,
+};
+use rayon::prelude::*;
+
+/// Saliency is computed on a thumbnail with this longest side
+const THUMBNAIL_SIZE: u32 = 256;
+/// Blur of the thumbnail, to ignore fine texture and noise
+const BLUR_SIGMA: f32 = 2.0;
+/// Spread of the center bias, as a fraction of the image size
+const CENTER_SIGMA: f32 = 0.3;
+/// Extra weight of the most salient pixel, on top of the weight 1 every pixel has
+const MAX_EXTRA_WEIGHT: f32 = 15.0;
+/// Fraction of the (weighted) pixels k-means samples, as quantette does by default
+const SAMPLING_FACTOR: f32 = 0.5;
+const BATCH_SIZE: u32 = 4096;
+
+/// Saliency of every pixel from 0.0 to 1.0, row by row
+pub fn saliency(img: &RgbImage) -> Vec<f32> {
+    let (width, height) = img.dimensions();
+    let scale = THUMBNAIL_SIZE as f32 / width.max(height) as f32;
+    let thumbnail = if scale < 1.0 {
+        image::imageops::thumbnail(
+            img,
+            ((width as f32 * scale) as u32).max(1),
+            ((height as f32 * scale) as u32).max(1),
+        )
+    } else {
+        img.clone()
+    };
+    let blurred = image::imageops::blur(&thumbnail, BLUR_SIGMA);
+    let labs: Vec<[f32; 3]> = blurred.pixels().map(|pixel| to_oklab(*pixel)).collect();
+    let mean = labs.iter().fold([0.0; 3], |mut sum, lab| {
+        (0..3).for_each(|i| sum[i] += lab[i] / labs.len() as f32);
+        sum
+    });
+    let (thumb_width, thumb_height) = blurred.dimensions();
+    let mut values: Vec<f32> = labs
+        .iter()
+        .enumerate()
+        .map(|(i, lab)| {
+            let distance = (0..3)
+                .map(|c| (lab[c] - mean[c]).powi(2))
+                .sum::<f32>()
+                .sqrt();
+            let x = (i as u32 % thumb_width) as f32 / thumb_width as f32 - 0.5;
+            let y = (i as u32 / thumb_width) as f32 / thumb_height as f32 - 0.5;
+            let center = (-(x * x + y * y) / (2.0 * CENTER_SIGMA * CENTER_SIGMA)).exp();
+            distance * center
+        })
+        .collect();
+    let max = values.iter().copied().fold(0.0, f32::max);
+    if max > 0.0 {
+        values.iter_mut().for_each(|value| *value /= max);
+    }
+    // back to the size of the image
+    (0..width * height)
+        .map(|i| {
+            let x = (i % width) * thumb_width / width;
+            let y = (i / width) * thumb_height / height;
+            values[(y * thumb_width + x) as usize]
+        })
+        .collect()
+}
+
+/// The pixels in Oklab, with a count per pixel that k-means treats as that many pixels
+struct Weighted {
+    colors: Vec<Oklab>,
+    counts: Vec<u32>,
+    total: u32,
+}
+
+impl ColorCounts<Oklab, f32, 3> for Weighted {
+    fn colors(&self) -> &[Oklab] {
+        &self.colors
+    }
+
+    fn total_count(&self) -> u32 {
+        self.total
+    }
+
+    fn counts(&self) -> Option<&[u32]> {
+        Some(&self.counts)
+    }
+
+    fn indices(&self) -> Option<&[u32]> {
+        None
+    }
+}
+
+impl ColorCountsRemap<Oklab, f32, 3> for Weighted {
+    fn map_indices(&self, indices: Vec<u8>) -> Vec<u8> {
+        indices
+    }
+}
+
+impl ColorCountsParallelRemap<Oklab, f32, 3> for Weighted {
+    fn map_indices_par(&self, indices: Vec<u8>) -> Vec<u8> {
+        indices
+    }
+}
+
+/// [`crate::render::quantize`] with the pixels weighted by their saliency
+pub fn quantize(
+    input_img: &RgbImage,
+    colors: usize,
+    dither: bool,
+    deterministic: bool,
+) -> RgbImage {
+    let counts: Vec<u32> = saliency(input_img)
+        .into_iter()
+        .map(|value| 1 + (value * MAX_EXTRA_WEIGHT).round() as u32)
+        .collect();
+    let weighted = Weighted {
+        colors: input_img
+            .par_pixels()
+            .map(|pixel| {
+                let linear: LinSrgb = Srgb::new(pixel[0], pixel[1], pixel[2]).into_linear();
+                linear.into_color()
+            })
+            .collect(),
+        total: counts.iter().sum(),
+        counts,
+    };
+    let size = PaletteSize::from(colors.min(crate::render::MAX_COLORS) as u8);
+    let binner = ColorSpace::default_binner_oklab_f32();
+    let samples = (weighted.total as f32 * SAMPLING_FACTOR) as u32;
+    let (width, height) = input_img.dimensions();
+    let result = if deterministic {
+        let initial = Centroids::from_truncated(wu::palette(&weighted, size, &binner).palette);
+        kmeans::indexed_palette(&weighted, samples, initial, 0)
+    } else {
+        let initial = Centroids::from_truncated(wu::palette_par(&weighted, size, &binner).palette);
+        kmeans::indexed_palette_par(&weighted, samples, BATCH_SIZE, initial, 0)
+    };
+    let mut indices = result.indices;
+    if dither {
+        let ditherer = FloydSteinberg::new();
+        if deterministic {
+            ditherer.dither(
+                &result.palette,
+                &mut indices,
+                &weighted.colors,
+                width,
+                height,
+            );
+        } else {
+            ditherer.dither_par(
+                &result.palette,
+                &mut indices,
+                &weighted.colors,
+                width,
+                height,
+            );
+        }
+    }
+    let palette: Vec<Rgb<u8>> = result
+        .palette
+        .into_iter()
+        .map(|color| {
+            let linear: LinSrgb = color.into_color();
+            let srgb: Srgb<u8> = linear.into_encoding();
+            Rgb([srgb.red, srgb.green, srgb.blue])
+        })
+        .collect();
+    RgbImage::from_fn(width, height, |x, y| {
+        palette[indices[(y * width + x) as usize] as usize]
+    })
+}
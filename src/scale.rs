@@ -0,0 +1,94 @@
+//! Display scale factors, so sizes given in logical pixels can be rendered at the real
+//! resolution of HiDPI screens.
+
+use crate::error::Error;
+use crate::exec;
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Scale {
+    Factor(f32),
+    Auto,
+}
+
+/// Parse `--scale`: a factor such as 1.5, or `auto` to ask the desktop
+pub fn parse_scale(input: &str) -> Result<Scale, String> {
+    match input {
+        "auto" => Ok(Scale::Auto),
+        factor => match factor.parse::<f32>() {
+            Ok(factor) if factor > 0.0 => Ok(Scale::Factor(factor)),
+            _ => Err(format!(
+                "'{}' is neither a positive factor nor auto",
+                factor
+            )),
+        },
+    }
+}
+
+impl Scale {
+    pub fn factor(self) -> Result<f32, Error> {
+        match self {
+            Scale::Factor(factor) => Ok(factor),
+            // the environment variables alone would silently give a different scale
+            Scale::Auto if exec::forbidden() => Err(Error::ExternalTool(
+                "--scale auto needs to ask swaymsg, hyprctl or xrdb, which --no-exec forbids, \
+                 give the factor instead"
+                    .to_string(),
+            )),
+            Scale::Auto => Ok(detect().unwrap_or(1.0)),
+        }
+    }
+}
+
+fn command_json(program: &str, args: &[&str]) -> Option<Value> {
+    let output = exec::command(program, "--scale auto")
+        .ok()?
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+/// The scale of the focused output (or the first one) of a sway or hyprland session
+fn wayland_scale() -> Option<f32> {
+    let outputs = command_json("swaymsg", &["-t", "get_outputs"])
+        .or_else(|| command_json("hyprctl", &["monitors", "-j"]))?;
+    let outputs = outputs.as_array()?;
+    let output = outputs
+        .iter()
+        .find(|output| output["focused"].as_bool().unwrap_or(false))
+        .or(outputs.first())?;
+    output["scale"].as_f64().map(|scale| scale as f32)
+}
+
+/// Xft.dpi relative to the default 96 dpi
+fn xft_scale() -> Option<f32> {
+    let output = exec::command("xrdb", "--scale auto")
+        .ok()?
+        .arg("-query")
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("Xft.dpi:"))
+        .and_then(|dpi| dpi.trim().parse::<f32>().ok())
+        .map(|dpi| dpi / 96.0)
+}
+
+/// The scale factor of the desktop: the Wayland compositor's, then Xft.dpi, then the
+/// GDK_SCALE and QT_SCALE_FACTOR environment variables
+pub fn detect() -> Option<f32> {
+    let environment = |name: &str| std::env::var(name).ok()?.parse::<f32>().ok();
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        if let Some(scale) = wayland_scale() {
+            return Some(scale);
+        }
+    }
+    xft_scale()
+        .or_else(|| environment("GDK_SCALE"))
+        .or_else(|| environment("QT_SCALE_FACTOR"))
+        .filter(|scale| *scale > 0.0)
+}
@@ -0,0 +1,166 @@
+//! Custom mapping rules written in [Rhai](https://rhai.rs), given with `--script`.
+//!
+//! The script is compiled once and then evaluated for every pixel after the normal
+//! palette mapping. It sees these variables:
+//!
+//! - `pixel`: the input color of the pixel
+//! - `average`: the average color of the box around the pixel (see `--average`), or
+//!   `pixel` when averaging is off
+//! - `mapped`: the palette color the normal mapping picked
+//! - `x`, `y`: the position of the pixel
+//! - `palette`: an array of the palette colors
+//! - `background`, `foreground`, `accent`: the palette roles
+//!
+//! Colors have the properties `r`, `g`, `b` (0 to 255), `luminance` (0.0 to 1.0),
+//! `chroma` (0 to 255), `hue` (degrees) and `hex`, and these functions are available:
+//!
+//! - `rgb(r, g, b)`: make a color
+//! - `nearest(color)`: the closest palette color
+//! - `contrast(color1, color2)`: the WCAG contrast ratio, from 1.0 to 21.0
+//! - `distance(color1, color2)`: the perceptual distance, around 2 is just noticeable
+//!
+//! The value of the script is the color of the output pixel: a color, a palette index,
+//! a hex code or role string such as `"@background"`, or nothing (`()`) to keep `mapped`.
+//! For example:
+//!
+//! ```rhai
+//! if pixel.luminance < 0.1 { background } else if pixel.chroma > 128 { accent }
+//! ```
+
+use crate::color::{chroma, contrast_ratio, delta_e, luminance, to_oklch};
+use crate::error::Error;
+use crate::palette::{resolve_color, to_hex};
+use crate::render::{nearest_color, Average, Averager};
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use rhai::{Array, Dynamic, Engine, Scope, AST, FLOAT, INT};
+
+#[derive(Clone, Copy)]
+struct Color(Rgb<u8>);
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    palette: Vec<Rgb<u8>>,
+}
+
+fn channel(value: INT) -> u8 {
+    value.clamp(0, 255) as u8
+}
+
+impl Script {
+    pub fn load(path: &str, palette: &[Rgb<u8>]) -> Result<Script, Error> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| Error::Input(format!("could not read {}: {}", path, err)))?;
+
+        let mut engine = Engine::new();
+        engine
+            .register_type_with_name::<Color>("Color")
+            .register_fn("rgb", |r: INT, g: INT, b: INT| {
+                Color(Rgb([channel(r), channel(g), channel(b)]))
+            })
+            .register_get("r", |color: &mut Color| color.0 .0[0] as INT)
+            .register_get("g", |color: &mut Color| color.0 .0[1] as INT)
+            .register_get("b", |color: &mut Color| color.0 .0[2] as INT)
+            .register_get("luminance", |color: &mut Color| luminance(color.0) as FLOAT)
+            .register_get("chroma", |color: &mut Color| chroma(color.0) as INT)
+            .register_get("hue", |color: &mut Color| to_oklch(color.0)[2] as FLOAT)
+            .register_get("hex", |color: &mut Color| to_hex(color.0))
+            .register_fn("to_string", |color: &mut Color| to_hex(color.0))
+            .register_fn("contrast", |color1: Color, color2: Color| {
+                contrast_ratio(color1.0, color2.0) as FLOAT
+            })
+            .register_fn("distance", |color1: Color, color2: Color| {
+                delta_e(color1.0, color2.0) as FLOAT
+            });
+        let owned = palette.to_vec();
+        engine.register_fn("nearest", move |color: Color| {
+            Color(nearest_color(color.0, &owned))
+        });
+
+        let ast = engine
+            .compile(&source)
+            .map_err(|err| Error::Input(format!("{}: {}", path, err)))?;
+        Ok(Script {
+            engine,
+            ast,
+            palette: palette.to_vec(),
+        })
+    }
+
+    fn output_color(&self, value: Dynamic, mapped: Rgb<u8>) -> Result<Rgb<u8>, String> {
+        if value.is_unit() {
+            Ok(mapped)
+        } else if let Some(color) = value.clone().try_cast::<Color>() {
+            Ok(color.0)
+        } else if let Ok(index) = value.as_int() {
+            usize::try_from(index)
+                .ok()
+                .and_then(|index| self.palette.get(index).copied())
+                .ok_or_else(|| format!("palette index {} out of range", index))
+        } else if value.is_string() {
+            resolve_color(&value.into_string().unwrap(), &self.palette)
+        } else {
+            Err(format!(
+                "expected a color, palette index or string, got {}",
+                value.type_name()
+            ))
+        }
+    }
+
+    /// Run the script over every pixel of `mapped`, the palette mapping of `source`
+    pub fn apply(
+        &self,
+        source: &RgbImage,
+        mapped: &mut RgbImage,
+        average: Average,
+    ) -> Result<(), Error> {
+        let resolve = |role: &str| resolve_color(role, &self.palette).map_err(Error::Palette);
+        let palette: Array = self
+            .palette
+            .iter()
+            .map(|color| Dynamic::from(Color(*color)))
+            .collect();
+        let mut constants = Scope::new();
+        constants
+            .push_constant("palette", palette)
+            .push_constant("background", Color(resolve("@background")?))
+            .push_constant("foreground", Color(resolve("@foreground")?))
+            .push_constant("accent", Color(resolve("@accent")?));
+
+        let width = mapped.width();
+        let averager = Averager::new(source, average);
+        mapped
+            .par_chunks_mut(width as usize * 3)
+            .enumerate()
+            .try_for_each(|(y, row)| {
+                let y = y as u32;
+                let mut scope = constants.clone();
+                let base = scope.len();
+                for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+                    let x = x as u32;
+                    let pixel = *source.get_pixel(x, y);
+                    let averaged = averager.at(x, y);
+                    let current = Rgb([rgb[0], rgb[1], rgb[2]]);
+                    // variables the script declared for the previous pixel are dropped
+                    scope.rewind(base);
+                    scope
+                        .push("pixel", Color(pixel))
+                        .push("average", Color(averaged))
+                        .push("mapped", Color(current))
+                        .push("x", x as INT)
+                        .push("y", y as INT);
+                    let color = self
+                        .engine
+                        .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)
+                        .map_err(|err| err.to_string())
+                        .and_then(|value| self.output_color(value, current))
+                        .map_err(|err| {
+                            Error::Input(format!("script failed at pixel {},{}: {}", x, y, err))
+                        })?;
+                    rgb.copy_from_slice(&color.0);
+                }
+                Ok(())
+            })
+    }
+}
@@ -0,0 +1,170 @@
+//! `--sequence`: render a directory of frames, like the frames of a video or a
+//! timelapse, in name order, quantizing only the frames where the picture changes.
+//!
+//! The colors k-means finds for a frame (the keyframe) are reused for the frames after
+//! it, which are just mapped to them through a lookup table, until a frame's color
+//! histogram drifts more than the threshold from the keyframe's. That frame is quantized
+//! again and becomes the next keyframe. Besides skipping the slowest stage, holding the
+//! colors steady keeps the output from flickering between frames of the same shot.
+//!
+//! Drift is the share of pixels that would have to change color bins to turn one
+//! histogram into the other, from 0 for frames with the same colors to 1 for frames
+//! without any in common.
+
+use crate::color::to_oklab;
+use crate::palette;
+use image::{Rgb, RgbImage};
+use quantette::FloydSteinberg;
+use rayon::prelude::*;
+use std::sync::Mutex;
+
+/// Default drift past which a frame is quantized again
+pub const DEFAULT_THRESHOLD: f32 = 0.2;
+
+/// Levels per channel of the histogram, coarse so noise and small motion don't count
+const LEVELS: usize = 8;
+
+/// Share of the pixels in each color bin
+struct Histogram(Vec<f32>);
+
+impl Histogram {
+    fn new(img: &RgbImage) -> Histogram {
+        let mut bins = vec![0f32; LEVELS * LEVELS * LEVELS];
+        for Rgb([r, g, b]) in img.pixels() {
+            let bin = |channel: u8| channel as usize * LEVELS / 256;
+            bins[(bin(*r) * LEVELS + bin(*g)) * LEVELS + bin(*b)] += 1.0;
+        }
+        let count = (img.width() * img.height()).max(1) as f32;
+        Histogram(bins.into_iter().map(|bin| bin / count).collect())
+    }
+
+    fn drift(&self, other: &Histogram) -> f32 {
+        self.0
+            .iter()
+            .zip(&other.0)
+            .map(|(a, b)| (a - b).abs())
+            .sum::<f32>()
+            / 2.0
+    }
+}
+
+/// Bits per channel of the lookup table, colors closer than this are mapped alike
+const LUT_BITS: usize = 6;
+
+/// The nearest of a set of colors in Oklab for every color, on a grid of `LUT_BITS`
+/// per channel
+struct Lut {
+    colors: Vec<Rgb<u8>>,
+    nearest: Vec<u8>,
+}
+
+impl Lut {
+    fn new(colors: Vec<Rgb<u8>>) -> Lut {
+        let targets: Vec<[f32; 3]> = colors.iter().map(|color| to_oklab(*color)).collect();
+        let levels = 1 << LUT_BITS;
+        let step = 256 / levels;
+        let nearest = (0..levels * levels * levels)
+            .into_par_iter()
+            .map(|cell| {
+                // the middle of the cell
+                let channel = |shift: usize| ((cell >> shift) % levels * step + step / 2) as u8;
+                let lab = to_oklab(Rgb([channel(2 * LUT_BITS), channel(LUT_BITS), channel(0)]));
+                let distance = |target: &[f32; 3]| {
+                    (lab[0] - target[0]).powi(2)
+                        + (lab[1] - target[1]).powi(2)
+                        + (lab[2] - target[2]).powi(2)
+                };
+                (0..targets.len())
+                    .min_by(|a, b| distance(&targets[*a]).total_cmp(&distance(&targets[*b])))
+                    .unwrap_or_default() as u8
+            })
+            .collect();
+        Lut { colors, nearest }
+    }
+
+    fn get(&self, [r, g, b]: [u8; 3]) -> Rgb<u8> {
+        let cell = |channel: u8| channel as usize >> (8 - LUT_BITS);
+        let index = (cell(r) << (2 * LUT_BITS)) | (cell(g) << LUT_BITS) | cell(b);
+        self.colors[self.nearest[index] as usize]
+    }
+}
+
+struct Keyframe {
+    histogram: Histogram,
+    /// The quantized colors, as many as were asked for or fewer
+    lut: Lut,
+    /// The number of colors asked for
+    requested: usize,
+}
+
+static KEYFRAME: Mutex<Option<Keyframe>> = Mutex::new(None);
+
+/// `quantize` for a frame of a sequence: the frame mapped onto the colors of the
+/// keyframe, or `fresh` run on the frame when it drifted past `threshold`
+pub fn quantize(
+    input_img: &RgbImage,
+    colors: usize,
+    dither: bool,
+    threshold: f32,
+    fresh: impl FnOnce() -> RgbImage,
+) -> RgbImage {
+    let histogram = Histogram::new(input_img);
+    let mut keyframe = KEYFRAME.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(keyframe) = keyframe
+        .as_ref()
+        .filter(|keyframe| keyframe.requested == colors && !keyframe.lut.colors.is_empty())
+        .filter(|keyframe| histogram.drift(&keyframe.histogram) <= threshold)
+    {
+        return remap(input_img, &keyframe.lut, dither);
+    }
+    let quantized = fresh();
+    *keyframe = Some(Keyframe {
+        histogram,
+        lut: Lut::new(palette::dedup(quantized.pixels().copied().collect())),
+        requested: colors,
+    });
+    quantized
+}
+
+/// Every pixel replaced by its color in `lut`, with Floyd-Steinberg error diffusion
+/// when `dither` is set
+fn remap(input_img: &RgbImage, lut: &Lut, dither: bool) -> RgbImage {
+    let (width, height) = input_img.dimensions();
+    let mut output_img = RgbImage::new(width, height);
+    if !dither {
+        output_img
+            .par_pixels_mut()
+            .zip(input_img.par_pixels())
+            .for_each(|(output, input)| *output = lut.get(input.0));
+        return output_img;
+    }
+    let mut values: Vec<[f32; 3]> = input_img
+        .pixels()
+        .map(|pixel| pixel.0.map(|channel| channel as f32))
+        .collect();
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as usize;
+            let color = lut.get(values[i].map(|value| value.round().clamp(0.0, 255.0) as u8));
+            output_img.put_pixel(x, y, color);
+            let error: [f32; 3] = std::array::from_fn(|channel| {
+                (values[i][channel] - color.0[channel] as f32)
+                    * FloydSteinberg::DEFAULT_ERROR_DIFFUSION
+            });
+            let mut spread = |dx: i32, dy: u32, weight: f32| {
+                let (nx, ny) = (x as i32 + dx, y + dy);
+                if nx >= 0 && (nx as u32) < width && ny < height {
+                    let value = &mut values[(ny * width + nx as u32) as usize];
+                    for channel in 0..3 {
+                        value[channel] += error[channel] * weight;
+                    }
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+    output_img
+}
@@ -0,0 +1,72 @@
+//! Raw frames in shared memory, for wallpaper setters that would otherwise decode the
+//! PNG we just encoded. Giving `shm:NAME` as the output writes the frame to
+//! `/dev/shm/imagecolorizer-NAME` (the temporary directory where there is no
+//! `/dev/shm`), which a setter can `mmap` and hand to the compositor as is.
+//!
+//! The file is a 32 byte header followed by the pixels:
+//!
+//! | offset | size | contents                                          |
+//! |--------|------|---------------------------------------------------|
+//! | 0      | 8    | magic, `ICFRAME1`                                 |
+//! | 8      | 4    | width in pixels                                   |
+//! | 12     | 4    | height in pixels                                  |
+//! | 16     | 4    | stride in bytes, always width * 4                 |
+//! | 20     | 4    | pixel format, the `wl_shm` code: 1 for XRGB8888   |
+//! | 24     | 8    | BLAKE3 pixel hash prefix, changes with every frame |
+//!
+//! All numbers are little endian. The pixels are XRGB8888 like `wl_shm` expects, which
+//! on little endian machines is the byte order B, G, R, X. A new frame is written to a
+//! temporary file and renamed over the old one, so readers never see half a frame.
+
+use crate::error::Error;
+use crate::hash::pixel_hash;
+use image::RgbImage;
+use std::fs;
+use std::path::PathBuf;
+
+const MAGIC: &[u8; 8] = b"ICFRAME1";
+/// `WL_SHM_FORMAT_XRGB8888`
+const FORMAT_XRGB8888: u32 = 1;
+
+/// The shared memory name given with an output of `shm:NAME`, if it is one
+pub fn target(output: &str) -> Option<&str> {
+    output.strip_prefix("shm:")
+}
+
+pub fn frame_path(name: &str) -> PathBuf {
+    let dir = PathBuf::from("/dev/shm");
+    let dir = if dir.is_dir() {
+        dir
+    } else {
+        std::env::temp_dir()
+    };
+    dir.join(format!("imagecolorizer-{}", name))
+}
+
+pub fn write(img: &RgbImage, name: &str) -> Result<PathBuf, Error> {
+    if name.is_empty() || name.contains('/') {
+        return Err(Error::Encode(format!(
+            "'{}' is not a valid shared memory name",
+            name
+        )));
+    }
+    let (width, height) = img.dimensions();
+    let mut frame = Vec::with_capacity(32 + img.as_raw().len() / 3 * 4);
+    frame.extend_from_slice(MAGIC);
+    frame.extend_from_slice(&width.to_le_bytes());
+    frame.extend_from_slice(&height.to_le_bytes());
+    frame.extend_from_slice(&(width * 4).to_le_bytes());
+    frame.extend_from_slice(&FORMAT_XRGB8888.to_le_bytes());
+    frame.extend_from_slice(&pixel_hash(img).as_bytes()[..8]);
+    for pixel in img.pixels() {
+        let [r, g, b] = pixel.0;
+        frame.extend_from_slice(&[b, g, r, 0xff]);
+    }
+
+    let path = frame_path(name);
+    let partial = path.with_extension("partial");
+    fs::write(&partial, &frame)
+        .and_then(|_| fs::rename(&partial, &path))
+        .map_err(|err| Error::Encode(format!("could not write {}: {}", path.display(), err)))?;
+    Ok(path)
+}
@@ -0,0 +1,350 @@
+use crate::color::{average_color, delta_e, luminance, to_oklch};
+use crate::palette::to_hex;
+use crate::themes::THEMES;
+use image::{Rgb, RgbImage};
+use quantette::ImagePipeline;
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct DominantColor {
+    pub color: String,
+    /// fraction of the image covered by this color, from 0.0 to 1.0
+    pub share: f32,
+}
+
+#[derive(Serialize)]
+pub struct Histograms {
+    /// 32 bins per channel
+    pub red: Vec<u32>,
+    pub green: Vec<u32>,
+    pub blue: Vec<u32>,
+    /// OkLCh lightness in 10 bins from 0.0 to 1.0
+    pub lightness: Vec<u32>,
+    /// OkLCh chroma in 10 bins from 0.0 to 0.4
+    pub chroma: Vec<u32>,
+    /// OkLCh hue in 12 bins of 30 degrees, gray pixels are left out
+    pub hue: Vec<u32>,
+}
+
+#[derive(Serialize)]
+pub struct Stats {
+    pub width: u32,
+    pub height: u32,
+    /// mean luminance from 0.0 (black) to 1.0 (white)
+    pub average_luminance: f32,
+    /// Hasler and Süsstrunk's colorfulness metric, roughly 0 for grayscale and above 100
+    /// for extremely colorful images
+    pub colorfulness: f32,
+    /// Shannon entropy of the colors in bits, 0 for a single color and up to 12
+    pub entropy: f32,
+    pub dominant_colors: Vec<DominantColor>,
+    pub histograms: Histograms,
+}
+
+/// The `colors` most common colors of the image (by k-means clustering) and the share
+/// of the image each of them covers, most common first
+pub fn dominant_colors(img: &RgbImage, colors: u8) -> Result<Vec<(Rgb<u8>, f32)>, String> {
+    let (palette, indices) = ImagePipeline::try_from(img)
+        .map_err(|err| format!("too large to quantize: {}", err))?
+        .palette_size(colors)
+        .indexed_palette_par();
+    let mut counts = vec![0usize; palette.len()];
+    for index in &indices {
+        counts[*index as usize] += 1;
+    }
+    let mut dominant: Vec<(Rgb<u8>, f32)> = palette
+        .into_iter()
+        .zip(counts)
+        .map(|(color, count)| {
+            (
+                Rgb([color.red, color.green, color.blue]),
+                count as f32 / indices.len().max(1) as f32,
+            )
+        })
+        .collect();
+    dominant.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Ok(dominant)
+}
+
+fn colorfulness(img: &RgbImage) -> f32 {
+    // opponent color channels, see "Measuring colourfulness in natural images"
+    let (rg, yb): (Vec<f32>, Vec<f32>) = img
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b] = pixel.0.map(|channel| channel as f32);
+            (r - g, 0.5 * (r + g) - b)
+        })
+        .unzip();
+    let mean_and_deviation = |values: &[f32]| {
+        let mean = values.iter().sum::<f32>() / values.len().max(1) as f32;
+        let variance = values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f32>()
+            / values.len().max(1) as f32;
+        (mean, variance.sqrt())
+    };
+    let (rg_mean, rg_deviation) = mean_and_deviation(&rg);
+    let (yb_mean, yb_deviation) = mean_and_deviation(&yb);
+    rg_deviation.hypot(yb_deviation) + 0.3 * rg_mean.hypot(yb_mean)
+}
+
+/// Shannon entropy of the image's colors, counted at 4 bits per channel so noise and
+/// gradients don't inflate it. Below 1 bit the image is essentially a single color
+pub fn entropy(img: &RgbImage) -> f32 {
+    let mut histogram = vec![0u32; 4096];
+    for pixel in img.pixels() {
+        let [r, g, b] = pixel.0.map(|channel| (channel >> 4) as usize);
+        histogram[r << 8 | g << 4 | b] += 1;
+    }
+    let total = (img.width() * img.height()).max(1) as f32;
+    histogram
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f32 / total;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+pub fn compute(img: &RgbImage, colors: u8) -> Result<Stats, String> {
+    let mut histograms = Histograms {
+        red: vec![0; 32],
+        green: vec![0; 32],
+        blue: vec![0; 32],
+        lightness: vec![0; 10],
+        chroma: vec![0; 10],
+        hue: vec![0; 12],
+    };
+    let mut luminance_sum = 0.0;
+    for pixel in img.pixels() {
+        histograms.red[pixel.0[0] as usize / 8] += 1;
+        histograms.green[pixel.0[1] as usize / 8] += 1;
+        histograms.blue[pixel.0[2] as usize / 8] += 1;
+        let [l, c, h] = to_oklch(*pixel);
+        histograms.lightness[((l * 10.0) as usize).min(9)] += 1;
+        histograms.chroma[((c / 0.04) as usize).min(9)] += 1;
+        if c > 0.02 {
+            histograms.hue[((h / 30.0) as usize).min(11)] += 1;
+        }
+        luminance_sum += luminance(*pixel) as f64;
+    }
+
+    Ok(Stats {
+        width: img.width(),
+        height: img.height(),
+        average_luminance: (luminance_sum / (img.width() * img.height()).max(1) as f64) as f32,
+        colorfulness: colorfulness(img),
+        entropy: entropy(img),
+        dominant_colors: dominant_colors(img, colors)?
+            .into_iter()
+            .map(|(color, share)| DominantColor {
+                color: to_hex(color),
+                share,
+            })
+            .collect(),
+        histograms,
+    })
+}
+
+fn print_histogram(name: &str, labels: &[String], bins: &[u32]) {
+    const WIDTH: u32 = 40;
+    let max = bins.iter().max().copied().unwrap_or(0).max(1);
+    println!("{}:", name);
+    for (label, count) in labels.iter().zip(bins) {
+        let bar = "#".repeat((count * WIDTH / max) as usize);
+        println!("  {:>9} {:<40} {}", label, bar, count);
+    }
+}
+
+pub fn print_text(stats: &Stats) {
+    println!("size: {}x{}", stats.width, stats.height);
+    println!("average luminance: {:.3}", stats.average_luminance);
+    println!("colorfulness: {:.1}", stats.colorfulness);
+    println!("entropy: {:.2} bits", stats.entropy);
+    println!("dominant colors:");
+    for dominant in &stats.dominant_colors {
+        println!("  {} {:5.1}%", dominant.color, dominant.share * 100.0);
+    }
+    // the channel histograms are printed at half resolution to keep them readable
+    let halve =
+        |bins: &[u32]| -> Vec<u32> { bins.chunks(2).map(|pair| pair.iter().sum()).collect() };
+    let channel_labels: Vec<String> = (0..16)
+        .map(|bin| format!("{}-{}", bin * 16, bin * 16 + 15))
+        .collect();
+    print_histogram("red", &channel_labels, &halve(&stats.histograms.red));
+    print_histogram("green", &channel_labels, &halve(&stats.histograms.green));
+    print_histogram("blue", &channel_labels, &halve(&stats.histograms.blue));
+    let lightness_labels: Vec<String> = (0..10)
+        .map(|bin| format!("{:.1}-{:.1}", bin as f32 / 10.0, (bin + 1) as f32 / 10.0))
+        .collect();
+    print_histogram("lightness", &lightness_labels, &stats.histograms.lightness);
+    let chroma_labels: Vec<String> = (0..10)
+        .map(|bin| format!("{:.2}-{:.2}", bin as f32 * 0.04, (bin + 1) as f32 * 0.04))
+        .collect();
+    print_histogram("chroma", &chroma_labels, &stats.histograms.chroma);
+    let hue_labels: Vec<String> = (0..12)
+        .map(|bin| format!("{}-{}", bin * 30, (bin + 1) * 30))
+        .collect();
+    print_histogram("hue", &hue_labels, &stats.histograms.hue);
+}
+
+pub struct ThemeMatch {
+    pub name: &'static str,
+    /// share weighted average distance from each image color to its closest theme color,
+    /// i.e. how much the image would have to change to fit the theme
+    pub alteration: f32,
+    /// the worst distance between the two palettes in either direction (Hausdorff)
+    pub hausdorff: f32,
+}
+
+/// Rank the built-in themes by how closely they already match the image's colors, best
+/// match first
+pub fn suggest_themes(img: &RgbImage, colors: u8) -> Result<Vec<ThemeMatch>, String> {
+    let dominant = dominant_colors(img, colors)?;
+    if dominant.is_empty() {
+        return Err("no pixels to match against".to_string());
+    }
+    let nearest = |color: Rgb<u8>, palette: &[Rgb<u8>]| {
+        palette
+            .iter()
+            .map(|other| delta_e(color, *other))
+            .fold(f32::MAX, f32::min)
+    };
+    let mut matches: Vec<ThemeMatch> = THEMES
+        .iter()
+        .map(|theme| {
+            let palette = theme.palette();
+            let alteration = dominant
+                .iter()
+                .map(|(color, share)| nearest(*color, &palette) * share)
+                .sum();
+            let image_colors: Vec<Rgb<u8>> = dominant.iter().map(|(color, _)| *color).collect();
+            let hausdorff = image_colors
+                .iter()
+                .map(|color| nearest(*color, &palette))
+                .chain(palette.iter().map(|color| nearest(*color, &image_colors)))
+                .fold(0.0, f32::max);
+            ThemeMatch {
+                name: theme.name,
+                alteration,
+                hausdorff,
+            }
+        })
+        .collect();
+    matches.sort_by(|a, b| a.alteration.total_cmp(&b.alteration));
+    Ok(matches)
+}
+
+/// A part of an image, in pixels
+#[derive(Clone, Debug)]
+pub struct Rect {
+    pub name: Option<String>,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Parse `--rect`: `x,y,width,height` in pixels
+pub fn parse_rect(input: &str) -> Result<Rect, String> {
+    let values: Vec<u32> = input
+        .split(',')
+        .map(|value| value.trim().parse::<u32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("'{}' is not a rect like 40,40,300,120", input))?;
+    match values[..] {
+        [x, y, width, height] if width > 0 && height > 0 => Ok(Rect {
+            name: None,
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => Err(format!(
+            "'{}' is not a rect like 40,40,300,120, with a width and height above 0",
+            input
+        )),
+    }
+}
+
+/// Read the rects of a file, one per line as `x,y,width,height` after an optional name:
+///
+/// ```text
+/// # the bar and the clock widget
+/// bar 0,0,1920,32
+/// clock 1600,60,280,120
+/// ```
+pub fn read_rects(path: &str) -> Result<Vec<Rect>, String> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|err| format!("could not read {}: {}", path, err))?;
+    let mut rects = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, rect) = match line.rsplit_once(char::is_whitespace) {
+            Some((name, rect)) => (Some(name.trim().to_string()), rect),
+            None => (None, line),
+        };
+        let rect = parse_rect(rect).map_err(|err| format!("{}:{}: {}", path, number + 1, err))?;
+        rects.push(Rect { name, ..rect });
+    }
+    Ok(rects)
+}
+
+#[derive(Serialize)]
+pub struct RegionStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub average_color: String,
+    /// mean luminance from 0.0 (black) to 1.0 (white)
+    pub average_luminance: f32,
+    /// mean luminance difference between neighboring pixels: 0 for flat and smoothly
+    /// shaded areas, above 0.05 for busy ones where thin text is hard to read
+    pub busyness: f32,
+}
+
+/// The statistics of `rect` of the image, clipped to it, or None when it is outside
+pub fn region(img: &RgbImage, rect: &Rect) -> Option<RegionStats> {
+    let right = rect.x.saturating_add(rect.width).min(img.width());
+    let bottom = rect.y.saturating_add(rect.height).min(img.height());
+    if rect.x >= right || rect.y >= bottom {
+        return None;
+    }
+    let lum = |x: u32, y: u32| luminance(*img.get_pixel(x, y));
+    let mut pixels = Vec::new();
+    let (mut luminance_sum, mut difference_sum, mut differences) = (0.0, 0.0, 0u32);
+    for y in rect.y..bottom {
+        for x in rect.x..right {
+            pixels.push(*img.get_pixel(x, y));
+            let here = lum(x, y);
+            luminance_sum += here;
+            if x + 1 < right {
+                difference_sum += (lum(x + 1, y) - here).abs();
+                differences += 1;
+            }
+            if y + 1 < bottom {
+                difference_sum += (lum(x, y + 1) - here).abs();
+                differences += 1;
+            }
+        }
+    }
+    let count = pixels.len() as f32;
+    Some(RegionStats {
+        name: rect.name.clone(),
+        x: rect.x,
+        y: rect.y,
+        width: right - rect.x,
+        height: bottom - rect.y,
+        average_color: to_hex(average_color(pixels)),
+        average_luminance: luminance_sum / count,
+        busyness: difference_sum / differences.max(1) as f32,
+    })
+}
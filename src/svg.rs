@@ -0,0 +1,136 @@
+//! Vector-native recoloring of SVG files: every color in a fill, stroke or other color
+//! property is replaced by its closest palette color and everything else is left as it
+//! is, so the result stays scalable.
+
+use crate::palette::{parse_hex, to_hex};
+use crate::render::nearest_color;
+use image::Rgb;
+
+/// Properties (attributes or CSS) that hold a color
+const PROPERTIES: [&str; 6] = [
+    "fill",
+    "stroke",
+    "stop-color",
+    "flood-color",
+    "lighting-color",
+    "color",
+];
+
+/// The CSS basic color keywords
+const NAMED_COLORS: [(&str, [u8; 3]); 16] = [
+    ("black", [0, 0, 0]),
+    ("silver", [192, 192, 192]),
+    ("gray", [128, 128, 128]),
+    ("white", [255, 255, 255]),
+    ("maroon", [128, 0, 0]),
+    ("red", [255, 0, 0]),
+    ("purple", [128, 0, 128]),
+    ("fuchsia", [255, 0, 255]),
+    ("green", [0, 128, 0]),
+    ("lime", [0, 255, 0]),
+    ("olive", [128, 128, 0]),
+    ("yellow", [255, 255, 0]),
+    ("navy", [0, 0, 128]),
+    ("blue", [0, 0, 255]),
+    ("teal", [0, 128, 128]),
+    ("aqua", [0, 255, 255]),
+];
+
+/// The CSS basic color called `name`
+pub fn named_color(name: &str) -> Option<Rgb<u8>> {
+    NAMED_COLORS
+        .iter()
+        .find(|(known, _)| name.eq_ignore_ascii_case(known))
+        .map(|(_, rgb)| Rgb(*rgb))
+}
+
+pub fn is_svg(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".svg")
+}
+
+/// Parse a color value at the start of `value`, returning it and its length
+fn parse_color(value: &str) -> Option<(Rgb<u8>, usize)> {
+    if let Some(hex) = value.strip_prefix('#') {
+        let digits = hex.chars().take_while(|c| c.is_ascii_hexdigit()).count();
+        return match digits {
+            6 => Some((parse_hex(&hex[..6]).ok()?, 7)),
+            3 => {
+                let long: String = hex[..3].chars().flat_map(|c| [c, c]).collect();
+                Some((parse_hex(&long).ok()?, 4))
+            }
+            _ => None,
+        };
+    }
+    if value.starts_with("rgb(") {
+        let end = value.find(')')?;
+        let channels: Vec<u8> = value[4..end]
+            .split(',')
+            .map(|channel| channel.trim().parse::<u8>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        if let [r, g, b] = channels[..] {
+            return Some((Rgb([r, g, b]), end + 1));
+        }
+        return None;
+    }
+    let word: String = value
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect();
+    named_color(&word).map(|color| (color, word.len()))
+}
+
+/// Replace every color of the SVG with its closest palette color, returning the new
+/// document and the number of colors that were replaced
+pub fn recolor(source: &str, palette: &[Rgb<u8>]) -> (String, usize) {
+    let mut output = String::with_capacity(source.len());
+    let mut replaced = 0;
+    let mut rest = source;
+    // colors are only replaced in tags and style sheets, not in text
+    let (mut in_tag, mut in_style) = (false, false);
+    'scan: while !rest.is_empty() {
+        if rest.starts_with("<style") {
+            in_style = true;
+        } else if rest.starts_with("</style") {
+            in_style = false;
+        }
+        match rest.as_bytes()[0] {
+            b'<' => in_tag = true,
+            b'>' => in_tag = false,
+            _ => {}
+        }
+        let boundary = output
+            .chars()
+            .last()
+            .is_none_or(|c| !(c.is_ascii_alphanumeric() || c == '-'));
+        if boundary && (in_tag || in_style) {
+            for property in PROPERTIES {
+                let Some(after) = rest.strip_prefix(property) else {
+                    continue;
+                };
+                // `fill="..."`, `fill='...'` or `fill: ...`
+                let trimmed = after.trim_start();
+                let value = trimmed
+                    .strip_prefix("=\"")
+                    .or_else(|| trimmed.strip_prefix("='"))
+                    .or_else(|| trimmed.strip_prefix(':'))
+                    .map(str::trim_start);
+                let Some(value) = value else {
+                    continue;
+                };
+                if let Some((color, length)) = parse_color(value) {
+                    let prefix = rest.len() - value.len();
+                    output.push_str(&rest[..prefix]);
+                    output.push_str(&to_hex(nearest_color(color, palette)));
+                    rest = &rest[prefix + length..];
+                    replaced += 1;
+                    continue 'scan;
+                }
+            }
+        }
+        let next = rest.chars().next().unwrap();
+        output.push(next);
+        rest = &rest[next.len_utf8()..];
+    }
+    (output, replaced)
+}
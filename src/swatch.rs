@@ -0,0 +1,107 @@
+//! `--swatch`: an image of the palette a render uses, one labeled swatch per color in
+//! rows of eight like a terminal palette, to check what was read from pywal, Xresources
+//! or a theme file before rendering with it. Each swatch shows its index in the palette
+//! (`@colorN`) and its hex code, in black or white, whichever is easier to read on it.
+
+use crate::color::contrast_ratio;
+use crate::error::Error;
+use crate::palette::to_hex;
+use image::{Rgb, RgbImage};
+
+const COLUMNS: usize = 8;
+const SWATCH_WIDTH: u32 = 120;
+const SWATCH_HEIGHT: u32 = 80;
+/// Size of a font pixel in image pixels
+const SCALE: u32 = 3;
+const MARGIN: u32 = 8;
+
+/// A 3x5 pixel font of the characters in the labels, rows top to bottom
+fn glyph(c: char) -> [&'static str; 5] {
+    match c {
+        '0' => ["###", "#.#", "#.#", "#.#", "###"],
+        '1' => [".#.", "##.", ".#.", ".#.", "###"],
+        '2' => ["###", "..#", "###", "#..", "###"],
+        '3' => ["###", "..#", ".##", "..#", "###"],
+        '4' => ["#.#", "#.#", "###", "..#", "..#"],
+        '5' => ["###", "#..", "###", "..#", "###"],
+        '6' => ["###", "#..", "###", "#.#", "###"],
+        '7' => ["###", "..#", "..#", ".#.", ".#."],
+        '8' => ["###", "#.#", "###", "#.#", "###"],
+        '9' => ["###", "#.#", "###", "..#", "###"],
+        'a' => [".#.", "#.#", "###", "#.#", "#.#"],
+        'b' => ["##.", "#.#", "##.", "#.#", "##."],
+        'c' => [".##", "#..", "#..", "#..", ".##"],
+        'd' => ["##.", "#.#", "#.#", "#.#", "##."],
+        'e' => ["###", "#..", "##.", "#..", "###"],
+        'f' => ["###", "#..", "##.", "#..", "#.."],
+        '#' => ["#.#", "###", "#.#", "###", "#.#"],
+        _ => ["...", "...", "...", "...", "..."],
+    }
+}
+
+/// Draw `text` with its top left corner at `x`, `y`
+fn draw_text(img: &mut RgbImage, text: &str, x: u32, y: u32, color: Rgb<u8>) {
+    for (index, c) in text.chars().enumerate() {
+        let left = x + index as u32 * 4 * SCALE;
+        for (row, pixels) in glyph(c).iter().enumerate() {
+            for (column, pixel) in pixels.chars().enumerate() {
+                if pixel != '#' {
+                    continue;
+                }
+                for dy in 0..SCALE {
+                    for dx in 0..SCALE {
+                        img.put_pixel(
+                            left + column as u32 * SCALE + dx,
+                            y + row as u32 * SCALE + dy,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The swatches of `palette`
+pub fn render(palette: &[Rgb<u8>]) -> RgbImage {
+    let columns = palette.len().clamp(1, COLUMNS);
+    let rows = palette.len().div_ceil(COLUMNS).max(1);
+    let mut img = RgbImage::new(columns as u32 * SWATCH_WIDTH, rows as u32 * SWATCH_HEIGHT);
+    let (black, white) = (Rgb([0, 0, 0]), Rgb([255, 255, 255]));
+    for (index, color) in palette.iter().enumerate() {
+        let left = (index % COLUMNS) as u32 * SWATCH_WIDTH;
+        let top = (index / COLUMNS) as u32 * SWATCH_HEIGHT;
+        for y in top..top + SWATCH_HEIGHT {
+            for x in left..left + SWATCH_WIDTH {
+                img.put_pixel(x, y, *color);
+            }
+        }
+        let text = if contrast_ratio(*color, black) >= contrast_ratio(*color, white) {
+            black
+        } else {
+            white
+        };
+        draw_text(
+            &mut img,
+            &index.to_string(),
+            left + MARGIN,
+            top + MARGIN,
+            text,
+        );
+        draw_text(
+            &mut img,
+            &to_hex(*color),
+            left + MARGIN,
+            top + SWATCH_HEIGHT - MARGIN - 5 * SCALE,
+            text,
+        );
+    }
+    img
+}
+
+/// Save the swatches of `palette` to `path`
+pub fn write(path: &str, palette: &[Rgb<u8>]) -> Result<(), Error> {
+    render(palette)
+        .save(path)
+        .map_err(|err| Error::Encode(format!("could not write {}: {}", path, err)))
+}
@@ -0,0 +1,206 @@
+//! `--target-size`: keep the output under a number of bytes, for wallpapers kept in
+//! dotfile repositories or boot splashes with a size limit. The output is encoded as
+//! small as its format allows, and when that isn't enough it is rendered again with
+//! less detail until it fits:
+//!
+//! 1. as asked, PNGs with a palette of 1 to 8 bit indices and JPEGs at the best quality
+//!    that fits, down to `MIN_JPEG_QUALITY`
+//! 2. without dithering, whose noise compresses worst
+//! 3. with one shade fewer per color of `--extend-shades` or `--expand-palette` at a
+//!    time: the palette is cut down to as many colors, the most distinct ones
+//!
+//! When nothing fits, the smallest try is kept with a warning. WebP is only written
+//! lossless, so for it only the rendering changes.
+
+use crate::cache::format_size;
+use crate::error::{warn, Error};
+use crate::palette::{self, PaletteEntry};
+use crate::{shm, Args};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use std::collections::HashMap;
+use std::io::Cursor;
+
+const MIN_JPEG_QUALITY: u8 = 50;
+const MAX_JPEG_QUALITY: u8 = 95;
+
+/// A rendering to try
+struct Step {
+    dither: bool,
+    /// Size to cut the palette down to
+    colors: usize,
+}
+
+/// The renderings to try, in order, for a palette of `colors` colors
+fn steps(colors: usize, args: &Args) -> Vec<Step> {
+    let mut steps = vec![Step {
+        dither: !args.no_dither,
+        colors,
+    }];
+    if !args.no_dither {
+        steps.push(Step {
+            dither: false,
+            colors,
+        });
+    }
+    let shades = args.source.extend_shades.or(args.source.expand_palette);
+    if let Some(shades) = shades.map(usize::from).filter(|shades| *shades > 0) {
+        // every color comes with a shade and a tint per step
+        let base = colors.div_ceil(2 * shades + 1);
+        steps.extend((0..shades).rev().map(|shades| Step {
+            dither: false,
+            colors: (base * (2 * shades + 1)).clamp(2, 255),
+        }));
+    }
+    steps
+}
+
+/// A PNG of `img` with a palette instead of RGB colors, with as few bits per pixel as
+/// the number of colors allows. None when it has more than 256 colors
+fn indexed_png(img: &RgbImage) -> Option<Result<Vec<u8>, png::EncodingError>> {
+    let mut indices: HashMap<Rgb<u8>, u8> = HashMap::new();
+    let mut colors = Vec::new();
+    for pixel in img.pixels() {
+        if !indices.contains_key(pixel) {
+            indices.insert(*pixel, u8::try_from(colors.len()).ok()?);
+            colors.push(*pixel);
+        }
+    }
+    let (depth, bits) = match colors.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+    // rows start on a byte, with the highest bits first
+    let row_bytes = (img.width() as usize * bits).div_ceil(8);
+    let mut data = vec![0u8; row_bytes * img.height() as usize];
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let bit = x as usize * bits;
+        let byte = y as usize * row_bytes + bit / 8;
+        data[byte] |= indices[pixel] << (8 - bits - bit % 8);
+    }
+    let mut encoded = Vec::new();
+    let mut encoder = png::Encoder::new(&mut encoded, img.width(), img.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(colors.iter().flat_map(|color| color.0).collect::<Vec<u8>>());
+    encoder.set_compression(png::Compression::High);
+    // filters predict gradients, which indices of unordered colors don't have
+    encoder.set_filter(png::Filter::NoFilter);
+    let result = encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data));
+    Some(result.map(|_| encoded))
+}
+
+/// `img` in `format` as small as it goes, for JPEG at the best quality that is no
+/// larger than `target`
+fn encode(img: &DynamicImage, format: ImageFormat, target: u64) -> Result<Vec<u8>, String> {
+    let mut encoded = Vec::new();
+    match format {
+        ImageFormat::Png => {
+            if let DynamicImage::ImageRgb8(img) = img {
+                if let Some(result) = indexed_png(img) {
+                    return result.map_err(|err| err.to_string());
+                }
+            }
+            let encoder = PngEncoder::new_with_quality(
+                &mut encoded,
+                CompressionType::Best,
+                FilterType::Adaptive,
+            );
+            img.write_with_encoder(encoder)
+                .map_err(|err| err.to_string())?;
+        }
+        ImageFormat::Jpeg => {
+            let jpeg = |quality: u8| {
+                let mut encoded = Vec::new();
+                img.write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, quality))
+                    .map(|_| encoded)
+                    .map_err(|err| err.to_string())
+            };
+            // the highest quality that fits, by bisection
+            let (mut low, mut high) = (MIN_JPEG_QUALITY, MAX_JPEG_QUALITY);
+            encoded = jpeg(low)?;
+            if encoded.len() as u64 > target {
+                return Ok(encoded);
+            }
+            while low < high {
+                let quality = (low + high).div_ceil(2);
+                let candidate = jpeg(quality)?;
+                if candidate.len() as u64 <= target {
+                    (low, encoded) = (quality, candidate);
+                } else {
+                    high = quality - 1;
+                }
+            }
+        }
+        _ => img
+            .write_to(&mut Cursor::new(&mut encoded), format)
+            .map_err(|err| err.to_string())?,
+    }
+    Ok(encoded)
+}
+
+/// Render `input` to `output` no larger than `target` bytes
+pub fn process(
+    input: &str,
+    output: &str,
+    palette: &[PaletteEntry],
+    args: &Args,
+    target: u64,
+) -> Result<(), Error> {
+    if shm::target(output).is_some() {
+        return Err(Error::Input(
+            "--target-size needs an output file, not shared memory".to_string(),
+        ));
+    }
+    let format = ImageFormat::from_path(output)
+        .map_err(|err| Error::Encode(format!("could not write {}: {}", output, err)))?;
+    let mut smallest: Option<(Vec<u8>, RgbImage, Vec<PaletteEntry>)> = None;
+    for step in steps(palette.len(), args) {
+        let palette = if step.colors < palette.len() {
+            let kept = palette::reduce(&palette::colors(palette), step.colors as u8);
+            palette::keep_weights(palette, kept)
+        } else {
+            palette.to_vec()
+        };
+        let img = crate::render_image(input, &palette, args, step.dither)?;
+        let encoded = encode(&crate::output_image(img.clone(), args), format, target)
+            .map_err(|err| Error::Encode(format!("could not write {}: {}", output, err)))?;
+        let fits = encoded.len() as u64 <= target;
+        if fits
+            || smallest
+                .as_ref()
+                .is_none_or(|(smallest, _, _)| encoded.len() < smallest.len())
+        {
+            smallest = Some((encoded, img, palette));
+        }
+        if fits {
+            break;
+        }
+    }
+    // there is always at least one step
+    let (encoded, img, palette) = smallest.unwrap();
+    if encoded.len() as u64 > target {
+        warn(
+            args.strict,
+            Error::Encode(format!(
+                "{} is {}, it could not be made smaller than the target of {}",
+                output,
+                format_size(encoded.len() as u64),
+                format_size(target)
+            )),
+        )?;
+    }
+    crate::save(
+        input,
+        output,
+        img,
+        &palette::colors(&palette),
+        args,
+        Some(encoded),
+    )
+}
@@ -0,0 +1,188 @@
+//! `--term-colors`: ask the terminal the program runs in for its colors, so the palette
+//! is whatever the terminal shows without reading any config file.
+//!
+//! Colors 0 to 15 are queried with OSC 4, the background with OSC 11 and the foreground
+//! with OSC 10, and they make up the palette in that order, as for pywal. The queries are
+//! followed by a device attributes request, which every terminal answers, so a terminal
+//! that ignores the color queries is noticed right away instead of after the timeout.
+//! Multiplexers like tmux and screen only pass the queries on when configured to.
+
+use crate::error::Error;
+use image::Rgb;
+
+/// Number of colors and their slots: 16 indexed colors, the background and foreground
+const COLORS: usize = 18;
+/// Longest time to wait for all answers
+#[cfg(unix)]
+const TIMEOUT_MS: i32 = 1000;
+
+/// A channel of an `rgb:` answer, 1 to 4 hex digits
+fn channel(hex: &str) -> Option<u8> {
+    if hex.is_empty() || hex.len() > 4 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let max = (1u32 << (4 * hex.len())) - 1;
+    Some((value * 255 / max) as u8)
+}
+
+/// The color of an `rgb:RRRR/GGGG/BBBB` answer
+fn parse_rgb(spec: &str) -> Option<Rgb<u8>> {
+    let channels: Vec<u8> = spec
+        .strip_prefix("rgb:")?
+        .split('/')
+        .map(channel)
+        .collect::<Option<_>>()?;
+    match channels[..] {
+        [red, green, blue] => Some(Rgb([red, green, blue])),
+        _ => None,
+    }
+}
+
+/// The colors in the answers of the terminal, by slot
+fn parse_answers(answers: &str) -> [Option<Rgb<u8>>; COLORS] {
+    let mut colors = [None; COLORS];
+    // every answer starts with ESC ], and ends with BEL or ESC \
+    for answer in answers.split("\x1b]").skip(1) {
+        let answer = answer.split(['\x07', '\x1b']).next().unwrap_or_default();
+        let mut fields = answer.split(';');
+        let slot = match fields.next() {
+            Some("4") => fields.next().and_then(|index| index.parse::<usize>().ok()),
+            Some("11") => Some(16),
+            Some("10") => Some(17),
+            _ => None,
+        };
+        if let (Some(slot), Some(color)) = (slot, fields.next().and_then(parse_rgb)) {
+            if slot < COLORS {
+                colors[slot] = Some(color);
+            }
+        }
+    }
+    colors
+}
+
+/// Put the terminal in raw mode until dropped, so the answers can be read as they come
+/// and don't show up on screen
+#[cfg(unix)]
+struct RawMode {
+    fd: i32,
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    fn enable(fd: i32) -> std::io::Result<RawMode> {
+        // SAFETY: fd is an open terminal and the termios structs are fully written by
+        // tcgetattr before they are read
+        unsafe {
+            let mut original = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut original) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(RawMode { fd, original })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in enable on the same open terminal
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// Send the queries and collect the answers, up to the device attributes or the timeout
+#[cfg(unix)]
+fn ask() -> std::io::Result<String> {
+    use std::io::{Read, Write};
+    use std::os::fd::AsRawFd;
+    use std::time::Instant;
+
+    let mut tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")?;
+    let fd = tty.as_raw_fd();
+    let _raw = RawMode::enable(fd)?;
+    let mut queries: String = (0..16)
+        .map(|index| format!("\x1b]4;{};?\x07", index))
+        .collect();
+    queries.push_str("\x1b]11;?\x07\x1b]10;?\x07\x1b[c");
+    tty.write_all(queries.as_bytes())?;
+    tty.flush()?;
+
+    let start = Instant::now();
+    let mut answers = Vec::new();
+    let mut buffer = [0u8; 1024];
+    loop {
+        let elapsed = start.elapsed().as_millis() as i32;
+        if elapsed >= TIMEOUT_MS {
+            break;
+        }
+        let mut poll = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: poll gets one valid pollfd
+        let ready = unsafe { libc::poll(&mut poll, 1, TIMEOUT_MS - elapsed) };
+        if ready <= 0 {
+            break;
+        }
+        let read = tty.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        answers.extend_from_slice(&buffer[..read]);
+        // the device attributes answer, ESC [ ? ... c, comes last
+        let text = String::from_utf8_lossy(&answers);
+        if let Some(index) = text.rfind("\x1b[?") {
+            if text[index..].contains('c') {
+                break;
+            }
+        }
+    }
+    Ok(String::from_utf8_lossy(&answers).to_string())
+}
+
+#[cfg(not(unix))]
+fn ask() -> std::io::Result<String> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "only supported on Unix",
+    ))
+}
+
+/// The palette of the terminal
+pub fn load() -> Result<Vec<Rgb<u8>>, Error> {
+    let answers = ask()
+        .map_err(|err| Error::Palette(format!("could not query the terminal colors: {}", err)))?;
+    let colors = parse_answers(&answers);
+    if colors.iter().all(Option::is_none) {
+        return Err(Error::Palette(
+            "the terminal did not answer the color queries".to_string(),
+        ));
+    }
+    colors
+        .iter()
+        .enumerate()
+        .map(|(slot, color)| {
+            color.ok_or_else(|| {
+                let name = match slot {
+                    16 => "the background".to_string(),
+                    17 => "the foreground".to_string(),
+                    _ => format!("color {}", slot),
+                };
+                Error::Palette(format!("the terminal did not report {}", name))
+            })
+        })
+        .collect()
+}
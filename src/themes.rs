@@ -0,0 +1,121 @@
+use crate::error::Error;
+use image::Rgb;
+
+/// A built-in color scheme. Colors follow the terminal convention: color 0 is the
+/// background and color 7 the foreground, so palette roles work the same as for pywal
+pub struct Theme {
+    pub name: &'static str,
+    pub colors: [u32; 16],
+}
+
+pub const THEMES: &[Theme] = &[
+    Theme {
+        name: "pico8",
+        colors: [
+            0x000000, 0x1d2b53, 0x7e2553, 0x008751, 0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8,
+            0xff004d, 0xffa300, 0xffec27, 0x00e436, 0x29adff, 0x83769c, 0xff77a8, 0xffccaa,
+        ],
+    },
+    Theme {
+        name: "nord",
+        colors: [
+            0x2e3440, 0xbf616a, 0xa3be8c, 0xebcb8b, 0x81a1c1, 0xb48ead, 0x88c0d0, 0xe5e9f0,
+            0x4c566a, 0xd08770, 0x3b4252, 0x434c5e, 0x5e81ac, 0x8fbcbb, 0xd8dee9, 0xeceff4,
+        ],
+    },
+    Theme {
+        name: "gruvbox",
+        colors: [
+            0x282828, 0xcc241d, 0x98971a, 0xd79921, 0x458588, 0xb16286, 0x689d6a, 0xebdbb2,
+            0x928374, 0xfb4934, 0xb8bb26, 0xfabd2f, 0x83a598, 0xd3869b, 0x8ec07c, 0xa89984,
+        ],
+    },
+    Theme {
+        name: "gruvbox-light",
+        colors: [
+            0xfbf1c7, 0xcc241d, 0x98971a, 0xd79921, 0x458588, 0xb16286, 0x689d6a, 0x3c3836,
+            0x928374, 0x9d0006, 0x79740e, 0xb57614, 0x076678, 0x8f3f71, 0x427b58, 0x7c6f64,
+        ],
+    },
+    Theme {
+        name: "dracula",
+        colors: [
+            0x282a36, 0xff5555, 0x50fa7b, 0xf1fa8c, 0xbd93f9, 0xff79c6, 0x8be9fd, 0xf8f8f2,
+            0x6272a4, 0xff6e6e, 0x69ff94, 0xffffa5, 0xd6acff, 0xff92df, 0xa4ffff, 0x44475a,
+        ],
+    },
+    Theme {
+        name: "catppuccin",
+        colors: [
+            0x1e1e2e, 0xf38ba8, 0xa6e3a1, 0xf9e2af, 0x89b4fa, 0xf5c2e7, 0x94e2d5, 0xcdd6f4,
+            0x585b70, 0xfab387, 0x313244, 0x45475a, 0xb4befe, 0xcba6f7, 0x89dceb, 0xbac2de,
+        ],
+    },
+    Theme {
+        name: "catppuccin-latte",
+        colors: [
+            0xeff1f5, 0xd20f39, 0x40a02b, 0xdf8e1d, 0x1e66f5, 0xea76cb, 0x179299, 0x4c4f69,
+            0xacb0be, 0xfe640b, 0xccd0da, 0xbcc0cc, 0x7287fd, 0x8839ef, 0x04a5e5, 0x5c5f77,
+        ],
+    },
+    Theme {
+        name: "solarized",
+        colors: [
+            0x002b36, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0x839496,
+            0x073642, 0xcb4b16, 0x586e75, 0x657b83, 0x93a1a1, 0x6c71c4, 0xeee8d5, 0xfdf6e3,
+        ],
+    },
+    Theme {
+        name: "solarized-light",
+        colors: [
+            0xfdf6e3, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0x657b83,
+            0xeee8d5, 0xcb4b16, 0x93a1a1, 0x839496, 0x586e75, 0x6c71c4, 0x073642, 0x002b36,
+        ],
+    },
+    Theme {
+        name: "tokyo-night",
+        colors: [
+            0x1a1b26, 0xf7768e, 0x9ece6a, 0xe0af68, 0x7aa2f7, 0xbb9af7, 0x7dcfff, 0xc0caf5,
+            0x414868, 0xff9e64, 0x24283b, 0x565f89, 0x2ac3de, 0x9d7cd8, 0x73daca, 0xa9b1d6,
+        ],
+    },
+    Theme {
+        name: "one-dark",
+        colors: [
+            0x282c34, 0xe06c75, 0x98c379, 0xe5c07b, 0x61afef, 0xc678dd, 0x56b6c2, 0xabb2bf,
+            0x5c6370, 0xbe5046, 0x3e4451, 0xd19a66, 0x528bff, 0x828997, 0x4b5263, 0xffffff,
+        ],
+    },
+];
+
+impl Theme {
+    pub fn palette(&self) -> Vec<Rgb<u8>> {
+        self.colors
+            .iter()
+            .map(|hex_num| {
+                let r = (hex_num >> 16) as u8;
+                let g = ((hex_num >> 8) & 0x00FF) as u8;
+                let b = (hex_num & 0x0000_00FF) as u8;
+                Rgb([r, g, b])
+            })
+            .collect()
+    }
+}
+
+pub fn lookup(name: &str) -> Option<&'static Theme> {
+    THEMES
+        .iter()
+        .find(|theme| theme.name.eq_ignore_ascii_case(name))
+}
+
+/// [`lookup`], with an error listing the themes when there is no such theme
+pub fn find(name: &str) -> Result<&'static Theme, Error> {
+    lookup(name).ok_or_else(|| {
+        let known: Vec<&str> = THEMES.iter().map(|theme| theme.name).collect();
+        Error::Palette(format!(
+            "unknown theme '{}', the themes are {}",
+            name,
+            known.join(", ")
+        ))
+    })
+}
@@ -0,0 +1,118 @@
+//! `--thumbnail` and `--thumbnail-cache`: small previews of the output, so file pickers
+//! show freshly rendered wallpapers right away. `--thumbnail SIZE` saves
+//! `<name>.thumb.png` next to the output, `--thumbnail-cache` fills the freedesktop
+//! thumbnail cache (`~/.cache/thumbnails/normal` and `large`) for it, named by the MD5
+//! of the file URI and tagged with the URI and modification time it was made for.
+
+use crate::error::{warn, Error};
+use image::DynamicImage;
+use md5::{Digest, Md5};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Characters of a path that stay as they are in a file URI
+const PATH: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// The cache directories and the size of their thumbnails
+const CACHE_SIZES: [(&str, u32); 2] = [("normal", 128), ("large", 256)];
+
+fn failed(path: &Path, err: impl std::fmt::Display) -> Error {
+    Error::Encode(format!("could not write {}: {}", path.display(), err))
+}
+
+/// Save a preview of `img` no larger than `size` next to `output`
+fn write_sidecar(output: &Path, img: &DynamicImage, size: u32) -> Result<(), Error> {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let path = output.with_file_name(format!("{}.thumb.png", stem));
+    img.thumbnail(size, size)
+        .save(&path)
+        .map_err(|err| failed(&path, err))
+}
+
+fn cache_root() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir).join("thumbnails")),
+        None => Some(homedir::my_home().ok().flatten()?.join(".cache/thumbnails")),
+    }
+}
+
+/// The file URI of an absolute path
+pub fn file_uri(path: &Path) -> String {
+    format!(
+        "file://{}",
+        utf8_percent_encode(&path.to_string_lossy(), PATH)
+    )
+}
+
+/// Put thumbnails of the file at `output`, showing `img`, into the thumbnail cache
+fn write_cache(output: &Path, img: &DynamicImage) -> Result<(), Error> {
+    let path = fs::canonicalize(output).map_err(|err| failed(output, err))?;
+    let uri = file_uri(&path);
+    let mtime = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|err| failed(&path, err))?
+        .duration_since(UNIX_EPOCH)
+        .map(|mtime| mtime.as_secs())
+        .unwrap_or_default();
+    let name = format!("{:x}.png", Md5::digest(uri.as_bytes()));
+    let root = cache_root().ok_or_else(|| {
+        Error::Encode("could not find the home directory for the thumbnail cache".to_string())
+    })?;
+    for (directory, size) in CACHE_SIZES {
+        let dir = root.join(directory);
+        fs::create_dir_all(&dir).map_err(|err| failed(&dir, err))?;
+        let thumbnail = img.thumbnail(size, size).into_rgba8();
+        // file managers read the cache while it is written, so rename it into place
+        let partial = dir.join(format!(".{}", name));
+        let file = fs::File::create(&partial).map_err(|err| failed(&partial, err))?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), thumbnail.width(), thumbnail.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .add_text_chunk("Thumb::URI".to_string(), uri.clone())
+            .and_then(|_| encoder.add_text_chunk("Thumb::MTime".to_string(), mtime.to_string()))
+            .and_then(|_| {
+                encoder.add_text_chunk("Software".to_string(), "imagecolorizer".to_string())
+            })
+            .map_err(|err| failed(&partial, err))?;
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(thumbnail.as_raw()))
+            .map_err(|err| failed(&partial, err))?;
+        let path = dir.join(&name);
+        fs::rename(&partial, &path).map_err(|err| failed(&path, err))?;
+    }
+    Ok(())
+}
+
+/// Write the thumbnails the options ask for, of every output of the run
+pub fn write(files: &[PathBuf], size: Option<u32>, cache: bool, strict: bool) -> Result<(), Error> {
+    for file in files {
+        let Ok(img) = image::open(file) else {
+            warn(
+                strict,
+                Error::Input(format!(
+                    "no thumbnail for {}, it is not a raster image",
+                    file.display()
+                )),
+            )?;
+            continue;
+        };
+        if let Some(size) = size {
+            write_sidecar(file, &img, size)?;
+        }
+        if cache {
+            write_cache(file, &img)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,135 @@
+//! The `tint` subcommand: recolor a directory of monochrome icons, such as an icon theme
+//! or the PNG frames of an X cursor theme before `xcursorgen`, to palette colors, so they
+//! match a wallpaper made with the same palette.
+//!
+//! Icons get the `--color`. With `--shadow`, two-tone icons like cursors with a dark
+//! outline keep their two tones: light parts get the color and dark parts the shadow. In
+//! PNGs the tones are blended by lightness, keeping antialiased edges and transparency
+//! as they were. SVGs are recolored as vectors like any other SVG input, against the one
+//! or two tint colors. Other files, like `index.theme` and cursor configs, are copied
+//! unchanged, so the output directory is a complete theme.
+
+use crate::color::{blend, luminance};
+use crate::error::{warn, Error};
+use crate::svg;
+use image::{ImageFormat, Rgb, Rgba};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn failed(path: &Path, err: impl std::fmt::Display) -> Error {
+    Error::Encode(format!("could not write {}: {}", path.display(), err))
+}
+
+/// Every entry below `dir` that is not a directory, relative to it
+fn walk(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let entries = fs::read_dir(dir.join(relative)).map_err(|err| {
+        Error::Input(format!(
+            "could not read {}: {}",
+            dir.join(relative).display(),
+            err
+        ))
+    })?;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = relative.join(entry.file_name());
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => walk(dir, &path, files)?,
+            Ok(_) => files.push(path),
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn tint_png(
+    input: &Path,
+    output: &Path,
+    color: Rgb<u8>,
+    shadow: Option<Rgb<u8>>,
+) -> Result<(), Error> {
+    let mut img = image::open(input)
+        .map_err(|err| Error::Input(format!("could not decode {}: {}", input.display(), err)))?
+        .into_rgba8();
+    for pixel in img.pixels_mut() {
+        let Rgba([red, green, blue, alpha]) = *pixel;
+        let Rgb(tinted) = match shadow {
+            Some(shadow) => blend(shadow, color, luminance(Rgb([red, green, blue]))),
+            None => color,
+        };
+        *pixel = Rgba([tinted[0], tinted[1], tinted[2], alpha]);
+    }
+    img.save(output).map_err(|err| failed(output, err))
+}
+
+fn tint_svg(input: &Path, output: &Path, colors: &[Rgb<u8>]) -> Result<(), Error> {
+    let source = fs::read_to_string(input)
+        .map_err(|err| Error::Input(format!("could not read {}: {}", input.display(), err)))?;
+    let (recolored, _) = svg::recolor(&source, colors);
+    fs::write(output, recolored).map_err(|err| failed(output, err))
+}
+
+/// Mirror `relative`, a symlink in the input, in the output, so icon themes keep their
+/// aliases
+#[cfg(unix)]
+fn link(input_dir: &Path, output: &Path, relative: &Path) -> Result<bool, Error> {
+    let input = input_dir.join(relative);
+    let Ok(target) = fs::read_link(&input) else {
+        return Ok(false);
+    };
+    if output.symlink_metadata().is_ok() {
+        fs::remove_file(output).map_err(|err| failed(output, err))?;
+    }
+    std::os::unix::fs::symlink(target, output).map_err(|err| failed(output, err))?;
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn link(_input_dir: &Path, _output: &Path, _relative: &Path) -> Result<bool, Error> {
+    Ok(false)
+}
+
+/// Tint every icon of `input_dir` into `output_dir`
+pub fn run(
+    input_dir: &str,
+    output_dir: &str,
+    color: Rgb<u8>,
+    shadow: Option<Rgb<u8>>,
+    strict: bool,
+) -> Result<(), Error> {
+    let (input_dir, output_dir) = (Path::new(input_dir), Path::new(output_dir));
+    let mut files = Vec::new();
+    walk(input_dir, Path::new(""), &mut files)?;
+    files.sort();
+    // dark colors are closer to the shadow, which goes first for ties
+    let colors: Vec<Rgb<u8>> = shadow.into_iter().chain([color]).collect();
+    let mut tinted = 0;
+    for relative in &files {
+        let input = input_dir.join(relative);
+        let output = output_dir.join(relative);
+        let dir = output.parent().unwrap();
+        fs::create_dir_all(dir).map_err(|err| failed(dir, err))?;
+        if link(input_dir, &output, relative)? {
+            continue;
+        }
+        let name = relative.to_string_lossy();
+        if svg::is_svg(&name) {
+            tint_svg(&input, &output, &colors)?;
+            tinted += 1;
+        } else if matches!(ImageFormat::from_path(&input), Ok(ImageFormat::Png)) {
+            tint_png(&input, &output, color, shadow)?;
+            tinted += 1;
+        } else {
+            if ImageFormat::from_path(&input).is_ok() {
+                warn(
+                    strict,
+                    Error::Input(format!(
+                        "copying {} unchanged, only SVG and PNG icons are tinted",
+                        input.display()
+                    )),
+                )?;
+            }
+            fs::copy(&input, &output).map_err(|err| failed(&output, err))?;
+        }
+    }
+    println!("tinted {} of {} files", tinted, files.len());
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! `--tmux`: a palette from the colors of a tmux config or theme, the hex colors of its
+//! `*-style` options in the order they are set, each color once:
+//!
+//! ```text
+//! set -g status-style "bg=#1e1e2e,fg=#cdd6f4"
+//! setw -g window-status-current-style fg=#89b4fa,bold
+//! ```
+//!
+//! Files pulled in with `source-file` are read in place, so themes kept in their own
+//! file are found. Named colors and `colour123` are skipped, as tmux maps them to the
+//! terminal palette. Without a path, the config is looked for where tmux looks for it.
+
+use crate::error::Error;
+use crate::palette::{self, parse_hex};
+use image::Rgb;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Deepest chain of source-file commands followed, against loops
+const MAX_SOURCE_DEPTH: usize = 5;
+/// Fewest distinct colors that make a palette
+const MIN_COLORS: usize = 2;
+
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => homedir::my_home()
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+/// The hex colors in an option value like `fg=#cdd6f4,bg=#1e1e2e,bold`
+fn style_colors(value: &str, colors: &mut Vec<Rgb<u8>>) {
+    for attribute in value.split([',', ' ']) {
+        let color = attribute
+            .split_once('=')
+            .map_or(attribute, |(_, color)| color)
+            .trim_matches(['"', '\'']);
+        if let Ok(color) = parse_hex(color) {
+            colors.push(color);
+        }
+    }
+}
+
+/// Read the config at `path` into `colors`, `source-file` paths are relative to `dir`
+fn read(path: &Path, dir: &Path, colors: &mut Vec<Rgb<u8>>, depth: usize) -> Result<(), String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    for line in contents.lines() {
+        let mut words = line
+            .split_whitespace()
+            .take_while(|word| !word.starts_with('#'));
+        let Some(command) = words.next() else {
+            continue;
+        };
+        // flags like -g, -q and -w come before the option or path
+        let mut words = words.skip_while(|word| word.starts_with('-'));
+        match command {
+            "source-file" | "source" => {
+                for source in words {
+                    let source = expand_home(source.trim_matches(['"', '\'']));
+                    let source = dir.join(source);
+                    // missing files are skipped, as tmux does with -q
+                    if depth < MAX_SOURCE_DEPTH && source.is_file() {
+                        read(&source, dir, colors, depth + 1)?;
+                    }
+                }
+            }
+            "set" | "set-option" | "setw" | "set-window-option" => {
+                let Some(option) = words.next() else {
+                    continue;
+                };
+                if option.ends_with("-style") {
+                    // the value is the rest of the line, up to a comment
+                    let value = line
+                        .split_once(option)
+                        .map_or("", |(_, value)| value)
+                        .split(" #")
+                        .next()
+                        .unwrap_or_default();
+                    style_colors(value.trim(), colors);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Where tmux looks for its config
+pub fn default_path() -> Option<PathBuf> {
+    let home = homedir::my_home().ok().flatten();
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+    [
+        home.as_ref().map(|home| home.join(".tmux.conf")),
+        config_home.map(|dir| dir.join("tmux/tmux.conf")),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|path| path.is_file())
+}
+
+/// The palette of the config at `path`, or of the default config
+pub fn load(path: Option<&str>) -> Result<Vec<Rgb<u8>>, Error> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => default_path()
+            .ok_or_else(|| Error::Palette("could not find a tmux config".to_string()))?,
+    };
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let mut colors = Vec::new();
+    read(&path, &dir, &mut colors, 0).map_err(Error::Palette)?;
+    let colors = palette::dedup(colors);
+    if colors.len() < MIN_COLORS {
+        return Err(Error::Palette(format!(
+            "{} sets {} distinct hex colors in its *-style options, at least {} are needed for a \
+             palette",
+            path.display(),
+            colors.len(),
+            MIN_COLORS
+        )));
+    }
+    Ok(colors)
+}
@@ -0,0 +1,96 @@
+//! `--wallust`: the palette of wallust, the successor of pywal. wallust keeps the colors
+//! it generated in its cache directory, `~/.cache/wallust`, one JSON file per image and
+//! set of options:
+//!
+//! ```json
+//! {"background": "#1a1b26", "foreground": "#c0caf5", "cursor": "#c0caf5",
+//!  "color0": "#15161e", ..., "color15": "#c0caf5"}
+//! ```
+//!
+//! Without a path the newest of them is used, which is the palette of the last run. A
+//! path can also name a file a wallust template wrote, in this format or in pywal's
+//! colors.json format. Like for pywal, color0 to color15 are followed by the background,
+//! foreground and cursor colors.
+
+use crate::error::Error;
+use crate::palette::{self, parse_hex};
+use image::Rgb;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            homedir::my_home()
+                .ok()
+                .flatten()
+                .map(|home| home.join(".cache"))
+        })
+        .map(|dir| dir.join("wallust"))
+}
+
+/// The colors of a file in the format of the wallust cache
+fn decode_flat(json: &serde_json::Value) -> Result<Vec<Rgb<u8>>, String> {
+    let color = |name: &str| {
+        json[name]
+            .as_str()
+            .ok_or_else(|| format!("there is no {}", name))
+            .and_then(|value| parse_hex(value).map_err(|err| format!("{}: {}", name, err)))
+    };
+    let colors = (0..16)
+        .map(|index| color(&format!("color{}", index)))
+        .chain(["background", "foreground"].map(color))
+        .chain(color("cursor").ok().map(Ok))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(palette::dedup(colors))
+}
+
+fn decode(contents: &str) -> Result<Vec<Rgb<u8>>, String> {
+    let json: serde_json::Value = serde_json::from_str(contents).map_err(|err| err.to_string())?;
+    if json["colors"].is_object() {
+        crate::decode_pywal_json(contents)
+    } else {
+        decode_flat(&json)
+    }
+}
+
+fn load_file(path: &Path) -> Result<Vec<Rgb<u8>>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    decode(&contents).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// The palette of the newest file in the cache. The cache also has files that aren't
+/// palettes, such as the colors wallust read from the image, those are skipped
+fn load_newest() -> Result<Vec<Rgb<u8>>, String> {
+    let dir = cache_dir().ok_or_else(|| "could not find the cache directory".to_string())?;
+    let entries = fs::read_dir(&dir).map_err(|err| {
+        format!(
+            "could not read {}: {}, has wallust been run?",
+            dir.display(),
+            err
+        )
+    })?;
+    let mut files: Vec<(std::time::SystemTime, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect();
+    files.sort();
+    files
+        .iter()
+        .rev()
+        .find_map(|(_, path)| load_file(path).ok())
+        .ok_or_else(|| format!("there is no wallust palette in {}", dir.display()))
+}
+
+/// The palette of the file at `path`, or of the last wallust run
+pub fn load(path: Option<&str>) -> Result<Vec<Rgb<u8>>, Error> {
+    match path {
+        Some(path) => load_file(Path::new(path)),
+        None => load_newest(),
+    }
+    .map_err(Error::Palette)
+}
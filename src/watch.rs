@@ -0,0 +1,153 @@
+//! `--watch`: keep running and render again whenever the files the palette comes from
+//! change, to follow pywal, wallust or a theme switcher. The files are polled, which
+//! works the same everywhere and is cheap for the few files of a palette.
+//!
+//! Tools like pywal write their files several times within a moment, so a change only
+//! starts a render once the files have stayed the same for `--watch-debounce`
+//! milliseconds, and the changes in between make a single render. A render that is still
+//! running when they change again stops where it is, between the rows of the mapping,
+//! the stages of a pipeline, the themes or the images of a batch, without writing its
+//! output, and starts over with the newer palette. The images of a batch already done
+//! are not redone unless the palette changed.
+//!
+//! The renders are kept in the history for `rollback`, see [`crate::history`].
+
+use crate::error::Error;
+use crate::{alacritty, kitty, tmux, wallust, Args, PaletteArgs};
+use clap::Parser;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_DEBOUNCE: u64 = 500;
+
+/// How often the files are looked at
+const POLL: Duration = Duration::from_millis(100);
+
+static SUPERSEDED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the files changed since the render that is running started
+pub fn superseded() -> bool {
+    SUPERSEDED.load(Ordering::Relaxed)
+}
+
+/// Stop a render the files changed under, before it writes anything
+pub fn check() -> Result<(), Error> {
+    if superseded() {
+        return Err(Error::Superseded);
+    }
+    Ok(())
+}
+
+/// The files the palette options read
+fn sources(args: &PaletteArgs) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = [
+        &args.wal_file,
+        &args.xresources_file,
+        &args.base16,
+        &args.palette_file,
+        &args.palette_from,
+        &args.css,
+        &args.wezterm,
+    ]
+    .into_iter()
+    .flatten()
+    .map(PathBuf::from)
+    .collect();
+    if args.wal {
+        if let Ok(Some(home)) = homedir::my_home() {
+            let cache = home.join(".cache/wal");
+            paths.extend([cache.join("colors.json"), cache.join("colors.Xresources")]);
+        }
+    }
+    // the option without a path reads the default file, or the newest wallust palette,
+    // which is a new file in the cache directory
+    let defaults = [
+        (&args.wallust, wallust::cache_dir as fn() -> Option<PathBuf>),
+        (&args.alacritty, alacritty::default_path),
+        (&args.kitty, kitty::default_path),
+        (&args.tmux, tmux::default_path),
+    ];
+    for (option, default) in defaults {
+        match option {
+            Some(Some(path)) => paths.push(PathBuf::from(path)),
+            Some(None) => paths.extend(default()),
+            None => {}
+        }
+    }
+    // a WezTerm scheme can also be the name of one
+    paths.retain(|path| path.exists());
+    paths
+}
+
+fn modified(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+/// Render as `args` say, then again after every change of the palette files. Only
+/// returns when there is nothing to watch
+pub fn run(args: Args) -> Result<(), Error> {
+    let paths = sources(&args.source);
+    if paths.is_empty() {
+        return Err(Error::Input(
+            "--watch needs a palette that is read from files, such as --wal, --base16 or \
+             --palette-file"
+                .to_string(),
+        ));
+    }
+    let debounce = Duration::from_millis(args.watch_debounce);
+    let argv = args.argv.clone();
+    let (changed, changes) = mpsc::channel();
+    let watched = paths.clone();
+    thread::spawn(move || {
+        let mut last = modified(&watched);
+        loop {
+            thread::sleep(POLL);
+            let now = modified(&watched);
+            if now != last {
+                last = now;
+                SUPERSEDED.store(true, Ordering::Relaxed);
+                if changed.send(()).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    let mut args = Some(args);
+    loop {
+        SUPERSEDED.store(false, Ordering::Relaxed);
+        // the command line was parsed before, so it parses again
+        let args = args.take().unwrap_or_else(|| {
+            let mut args = Args::parse_from(&argv);
+            args.argv = argv.clone();
+            args
+        });
+        // a palette caught halfway through being written fails to load, the next
+        // change brings the rest
+        match crate::run(args) {
+            Ok(()) => {}
+            Err(Error::Superseded) => eprintln!("the files changed, rendering again"),
+            Err(err) => eprintln!("error: {}", err),
+        }
+        let names: Vec<String> = paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        eprintln!("watching {}", names.join(", "));
+        // wait for a change, then for the files to settle
+        if changes.recv().is_err() {
+            return Ok(());
+        }
+        while changes.recv_timeout(debounce).is_ok() {}
+    }
+}
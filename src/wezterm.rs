@@ -0,0 +1,352 @@
+//! `--wezterm`: palettes from WezTerm, the ANSI colors followed by the bright ones, the
+//! background and the foreground. The argument is either
+//!
+//! - a color scheme file, in the TOML format of the schemes WezTerm and
+//!   iTerm2-Color-Schemes ship:
+//!
+//!   ```toml
+//!   [colors]
+//!   ansi = ["#000000", "#cc0000", ...]
+//!   brights = ["#555555", "#ff0000", ...]
+//!   background = "#1d1f21"
+//!   foreground = "#c5c8c6"
+//!   ```
+//!
+//! - a Lua config (`.lua`), from its `colors` table, or the scheme its `color_scheme`
+//!   names when it has none
+//! - the name of a scheme, looked up in `~/.config/wezterm/colors/` by file name or by
+//!   the name in its metadata. WezTerm compiles its own schemes into the program, so
+//!   those have to be downloaded there first
+//!
+//! Colors are hex codes or CSS color names. Lua configs are not run, only their table
+//! constructors are read, so colors computed in Lua can't be found.
+
+use crate::error::Error;
+use crate::palette::parse_hex;
+use crate::svg::named_color;
+use image::Rgb;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct Scheme {
+    colors: Colors,
+    metadata: Option<Metadata>,
+}
+
+#[derive(Deserialize, Default)]
+struct Colors {
+    #[serde(default)]
+    ansi: Vec<String>,
+    #[serde(default)]
+    brights: Vec<String>,
+    background: Option<String>,
+    foreground: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    name: Option<String>,
+}
+
+impl Colors {
+    fn palette(&self) -> Result<Vec<Rgb<u8>>, String> {
+        if self.ansi.len() != 8 || self.brights.len() != 8 {
+            return Err(format!(
+                "expected 8 ansi and 8 brights colors, found {} and {}",
+                self.ansi.len(),
+                self.brights.len()
+            ));
+        }
+        self.ansi
+            .iter()
+            .chain(&self.brights)
+            .chain(&self.background)
+            .chain(&self.foreground)
+            .map(|color| match named_color(color) {
+                Some(color) => Ok(color),
+                None => parse_hex(color),
+            })
+            .collect()
+    }
+}
+
+/// Part of a Lua table constructor
+enum Value {
+    String(String),
+    Table(Vec<(Option<String>, Value)>),
+    /// numbers, function calls and anything else that isn't needed
+    Other,
+}
+
+/// Just enough of a Lua reader for table constructors in configs
+struct Lua<'a> {
+    source: &'a str,
+}
+
+impl Lua<'_> {
+    fn skip_space(&mut self) {
+        loop {
+            self.source = self.source.trim_start();
+            if let Some(rest) = self.source.strip_prefix("--") {
+                self.source = rest.split_once('\n').map_or("", |(_, rest)| rest);
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.skip_space();
+        let quote = self
+            .source
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')?;
+        let (value, rest) = self.source[1..].split_once(quote)?;
+        self.source = rest;
+        Some(value.to_string())
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        self.skip_space();
+        let length = self
+            .source
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.source.len());
+        if length == 0 {
+            return None;
+        }
+        let (identifier, rest) = self.source.split_at(length);
+        self.source = rest;
+        Some(identifier.to_string())
+    }
+
+    fn eat(&mut self, token: char) -> bool {
+        self.skip_space();
+        match self.source.strip_prefix(token) {
+            Some(rest) => {
+                self.source = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Skip a value that isn't a string or a table, up to the end of the field
+    fn skip_other(&mut self) {
+        let mut depth = 0;
+        while let Some(c) = self.source.chars().next() {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' if depth > 0 => depth -= 1,
+                ',' | ';' | '}' if depth == 0 => return,
+                '"' | '\'' => {
+                    self.string();
+                    continue;
+                }
+                _ => {}
+            }
+            self.source = &self.source[c.len_utf8()..];
+        }
+    }
+
+    fn value(&mut self) -> Value {
+        self.skip_space();
+        if let Some(string) = self.string() {
+            return Value::String(string);
+        }
+        if !self.eat('{') {
+            self.skip_other();
+            return Value::Other;
+        }
+        let mut fields = Vec::new();
+        loop {
+            if self.eat('}') || self.source.is_empty() {
+                return Value::Table(fields);
+            }
+            // `name = value` or a plain value
+            let before = self.source;
+            let key = self.identifier().filter(|_| self.eat('='));
+            if key.is_none() {
+                self.source = before;
+            }
+            fields.push((key, self.value()));
+            if !self.eat(',') {
+                self.eat(';');
+            }
+        }
+    }
+}
+
+fn lua_colors(table: &[(Option<String>, Value)]) -> Colors {
+    let strings = |value: &Value| match value {
+        Value::Table(fields) => fields
+            .iter()
+            .filter_map(|(_, value)| match value {
+                Value::String(string) => Some(string.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let mut colors = Colors::default();
+    for (key, value) in table {
+        match (key.as_deref(), value) {
+            (Some("ansi"), _) => colors.ansi = strings(value),
+            (Some("brights"), _) => colors.brights = strings(value),
+            (Some("background"), Value::String(color)) => colors.background = Some(color.clone()),
+            (Some("foreground"), Value::String(color)) => colors.foreground = Some(color.clone()),
+            _ => {}
+        }
+    }
+    colors
+}
+
+/// The config without its line comments
+fn strip_comments(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    for line in source.lines() {
+        let mut quote = None;
+        let mut end = line.len();
+        for (index, c) in line.char_indices() {
+            match quote {
+                Some(open) if c == open => quote = None,
+                Some(_) => {}
+                None if c == '"' || c == '\'' => quote = Some(c),
+                None if line[index..].starts_with("--") => {
+                    end = index;
+                    break;
+                }
+                None => {}
+            }
+        }
+        stripped.push_str(&line[..end]);
+        stripped.push('\n');
+    }
+    stripped
+}
+
+/// The value assigned to `name` anywhere in the config, as `name = value` in a table or
+/// `config.name = value`
+fn lua_assignment(source: &str, name: &str) -> Option<Value> {
+    let mut rest = source;
+    while let Some(index) = rest.find(name) {
+        let before = rest[..index].chars().next_back();
+        let after = &rest[index + name.len()..];
+        rest = after;
+        if before.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+            continue;
+        }
+        let mut lua = Lua { source: after };
+        if lua.eat('=') && !lua.source.starts_with('=') {
+            return Some(lua.value());
+        }
+    }
+    None
+}
+
+/// Directories schemes are looked up in by name
+fn scheme_dirs() -> Vec<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            homedir::my_home()
+                .ok()
+                .flatten()
+                .map(|home| home.join(".config"))
+        });
+    config_home
+        .map(|dir| vec![dir.join("wezterm/colors")])
+        .unwrap_or_default()
+}
+
+fn read_scheme(path: &Path) -> Result<Scheme, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    toml::from_str(&contents).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn find_scheme(name: &str) -> Result<Vec<Rgb<u8>>, String> {
+    for dir in scheme_dirs() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "toml")
+            })
+            .collect();
+        files.sort();
+        for path in files {
+            let by_file = path.file_stem().is_some_and(|stem| stem == name);
+            let Ok(scheme) = read_scheme(&path) else {
+                continue;
+            };
+            let by_metadata = scheme
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.name.as_deref())
+                == Some(name);
+            if by_file || by_metadata {
+                return scheme
+                    .colors
+                    .palette()
+                    .map_err(|err| format!("{}: {}", path.display(), err));
+            }
+        }
+    }
+    Err(format!(
+        "there is no color scheme '{}' in {}. WezTerm's built-in schemes are not files, \
+         download them from iTerm2-Color-Schemes to use them",
+        name,
+        scheme_dirs()
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))
+}
+
+fn read_lua(path: &Path) -> Result<Vec<Rgb<u8>>, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let source = strip_comments(&source);
+    if let Some(Value::Table(table)) = lua_assignment(&source, "colors") {
+        let colors = lua_colors(&table);
+        if !colors.ansi.is_empty() {
+            return colors
+                .palette()
+                .map_err(|err| format!("{}: colors: {}", path.display(), err));
+        }
+    }
+    match lua_assignment(&source, "color_scheme") {
+        Some(Value::String(name)) => find_scheme(&name),
+        _ => Err(format!(
+            "{} has neither a colors table with ansi colors nor a color_scheme",
+            path.display()
+        )),
+    }
+}
+
+/// The palette of a scheme file, Lua config or scheme name
+pub fn load(scheme: &str) -> Result<Vec<Rgb<u8>>, Error> {
+    let path = Path::new(scheme);
+    let palette = if !path.is_file() {
+        find_scheme(scheme)
+    } else if path.extension().is_some_and(|extension| extension == "lua") {
+        read_lua(path)
+    } else {
+        read_scheme(path).and_then(|scheme| {
+            scheme
+                .colors
+                .palette()
+                .map_err(|err| format!("{}: {}", path.display(), err))
+        })
+    };
+    palette.map_err(Error::Palette)
+}
@@ -0,0 +1,166 @@
+//! `--widget-colors`: a variables file of the palette roles for desktop widgets, and of
+//! the wallpaper behind them, so they can pick text that stays readable on it. The format
+//! follows the extension of the file:
+//!
+//! - `.lua` for conky, a table of hex colors without the `#`, as conky settings take them:
+//!   `local colors = dofile("colors.lua")` then `color1 = colors.accent`
+//! - `.yuck` for eww, one `(defvar ...)` per color, for `(include "colors.yuck")`
+//! - `.scss` for eww styles, one `$variable` per color
+//!
+//! The colors are `background`, `foreground`, `accent` and `color0` up to the size of the
+//! palette. Each `--widget-region NAME=WIDTHxHEIGHT+X+Y`, in pixels of the output image,
+//! adds `NAME_average`, the average color of the wallpaper there, and `NAME_text`, the
+//! background or foreground color, whichever contrasts more with it.
+
+use crate::color::{average_color, contrast_ratio};
+use crate::error::Error;
+use crate::palette::{resolve_color, to_hex};
+use image::{Rgb, RgbImage};
+use std::fs;
+use std::path::Path;
+
+/// A named part of the wallpaper that a widget sits on
+#[derive(Clone, Debug)]
+pub struct Region {
+    name: String,
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+}
+
+/// Parse `--widget-region`: a name, then an X geometry like `300x120+40+40`
+pub fn parse_region(input: &str) -> Result<Region, String> {
+    let invalid = || format!("'{}' is not a region like clock=300x120+40+40", input);
+    let (name, geometry) = input.split_once('=').ok_or_else(invalid)?;
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+        return Err(format!(
+            "'{}' is not a region name, use letters, digits and underscores",
+            name
+        ));
+    }
+    let (size, offset) = geometry.split_once('+').ok_or_else(invalid)?;
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let (x, y) = offset.split_once('+').ok_or_else(invalid)?;
+    let number = |value: &str| value.parse::<u32>().map_err(|_| invalid());
+    let (width, height) = (number(width)?, number(height)?);
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    Ok(Region {
+        name: name.to_string(),
+        width,
+        height,
+        x: number(x)?,
+        y: number(y)?,
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Lua,
+    Yuck,
+    Scss,
+}
+
+/// The file to write, and its format
+#[derive(Clone, Debug)]
+pub struct Target {
+    path: String,
+    format: Format,
+}
+
+/// Parse `--widget-colors`: a path with one of the known extensions
+pub fn parse_target(input: &str) -> Result<Target, String> {
+    let extension = Path::new(input)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let format = match extension.as_deref() {
+        Some("lua") => Format::Lua,
+        Some("yuck") => Format::Yuck,
+        Some("scss") => Format::Scss,
+        _ => {
+            return Err(format!(
+                "can't tell the widget format of {}, use a .lua, .yuck or .scss file",
+                input
+            ))
+        }
+    };
+    Ok(Target {
+        path: input.to_string(),
+        format,
+    })
+}
+
+/// The average color of `region`, clipped to the image
+fn region_average(img: &RgbImage, region: &Region) -> Result<Rgb<u8>, Error> {
+    let right = region.x.saturating_add(region.width).min(img.width());
+    let bottom = region.y.saturating_add(region.height).min(img.height());
+    if region.x >= right || region.y >= bottom {
+        return Err(Error::Input(format!(
+            "the widget region {} is outside of the {}x{} output",
+            region.name,
+            img.width(),
+            img.height()
+        )));
+    }
+    let pixels = (region.y..bottom)
+        .flat_map(|y| (region.x..right).map(move |x| *img.get_pixel(x, y)))
+        .collect();
+    Ok(average_color(pixels))
+}
+
+/// Write the widget colors for `palette` and the output image `img` to `target`
+pub fn export(
+    target: &Target,
+    img: &RgbImage,
+    palette: &[Rgb<u8>],
+    regions: &[Region],
+) -> Result<(), Error> {
+    let resolve = |role: &str| resolve_color(role, palette).map_err(Error::Palette);
+    let background = resolve("@background")?;
+    let foreground = resolve("@foreground")?;
+    let mut colors = vec![
+        ("background".to_string(), background),
+        ("foreground".to_string(), foreground),
+        ("accent".to_string(), resolve("@accent")?),
+    ];
+    colors.extend(
+        palette
+            .iter()
+            .enumerate()
+            .map(|(index, color)| (format!("color{}", index), *color)),
+    );
+    for region in regions {
+        let average = region_average(img, region)?;
+        let text = if contrast_ratio(average, foreground) >= contrast_ratio(average, background) {
+            foreground
+        } else {
+            background
+        };
+        colors.push((format!("{}_average", region.name), average));
+        colors.push((format!("{}_text", region.name), text));
+    }
+
+    let mut contents = match target.format {
+        Format::Lua => "-- Generated by imagecolorizer\nreturn {\n".to_string(),
+        Format::Yuck => ";; Generated by imagecolorizer\n".to_string(),
+        Format::Scss => "// Generated by imagecolorizer\n".to_string(),
+    };
+    for (name, color) in &colors {
+        let hex = to_hex(*color);
+        contents.push_str(&match target.format {
+            Format::Lua => format!("  {} = \"{}\",\n", name, &hex[1..]),
+            Format::Yuck => format!("(defvar {} \"{}\")\n", name, hex),
+            Format::Scss => format!("${}: {};\n", name, hex),
+        });
+    }
+    if let Format::Lua = target.format {
+        contents.push_str("}\n");
+    }
+    fs::write(&target.path, contents)
+        .map_err(|err| Error::Encode(format!("could not write {}: {}", target.path, err)))
+}
@@ -0,0 +1,227 @@
+//! `--match-window`: harmonize the part of the wallpaper behind a window with it, for
+//! windows that stay in place like dashboards, conky or a terminal kept open. The
+//! window is the focused one, or the first with the given class (app id on Wayland).
+//!
+//! Its place is asked from sway (`swaymsg`), Hyprland (`hyprctl`) or, on X11,
+//! `xdotool`, and its average color is read from a screenshot taken with `grim` or
+//! ImageMagick's `import`. The area of the image behind the window is then mapped to the
+//! palette colors closest to that color. The wallpaper is assumed to cover the screen
+//! the screenshot was taken of, stretched to it.
+
+use crate::color::{average_color, delta_e};
+use crate::error::Error;
+use crate::exec;
+use crate::regions::Mask;
+use image::{Rgb, RgbImage};
+use serde_json::Value;
+
+const FEATURE: &str = "--match-window";
+
+/// A window on the screen, in screenshot pixels
+pub struct Window {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    screen_width: u32,
+    screen_height: u32,
+    /// Average color of the window's contents
+    pub color: Rgb<u8>,
+}
+
+/// Position and size, in the coordinates of the window system
+type Rect = (i64, i64, i64, i64);
+
+fn output(program: &str, args: &[&str]) -> Result<Vec<u8>, Error> {
+    let output = exec::command(program, FEATURE)?
+        .args(args)
+        .output()
+        .map_err(|err| Error::ExternalTool(format!("failed to execute {}: {}", program, err)))?;
+    if !output.status.success() {
+        return Err(Error::ExternalTool(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+fn output_json(program: &str, args: &[&str]) -> Result<Value, Error> {
+    serde_json::from_slice(&output(program, args)?)
+        .map_err(|err| Error::ExternalTool(format!("{} printed invalid JSON: {}", program, err)))
+}
+
+fn not_found(class: &str) -> Error {
+    match class {
+        "focused" => Error::ExternalTool("there is no focused window".to_string()),
+        class => Error::ExternalTool(format!("there is no window with the class {}", class)),
+    }
+}
+
+/// The first node of a sway tree, depth first, that is the window
+fn sway_node<'a>(node: &'a Value, class: &str) -> Option<&'a Value> {
+    let is_window = node["pid"].is_number()
+        && match class {
+            "focused" => node["focused"].as_bool().unwrap_or(false),
+            class => {
+                node["app_id"].as_str() == Some(class)
+                    || node["window_properties"]["class"].as_str() == Some(class)
+            }
+        };
+    if is_window {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[key].as_array())
+        .flatten()
+        .find_map(|child| sway_node(child, class))
+}
+
+fn sway_rect(class: &str) -> Result<Rect, Error> {
+    let tree = output_json("swaymsg", &["-t", "get_tree"])?;
+    let rect = &sway_node(&tree, class).ok_or_else(|| not_found(class))?["rect"];
+    let field = |name: &str| rect[name].as_i64().unwrap_or_default();
+    Ok((field("x"), field("y"), field("width"), field("height")))
+}
+
+fn hyprland_rect(class: &str) -> Result<Rect, Error> {
+    let client = match class {
+        "focused" => Some(output_json("hyprctl", &["activewindow", "-j"])?),
+        class => output_json("hyprctl", &["clients", "-j"])?
+            .as_array()
+            .and_then(|clients| {
+                clients
+                    .iter()
+                    .find(|client| client["class"].as_str() == Some(class))
+                    .cloned()
+            }),
+    };
+    let client = client
+        .filter(|client| client["at"].is_array())
+        .ok_or_else(|| not_found(class))?;
+    let field = |name: &str, index: usize| client[name][index].as_i64().unwrap_or_default();
+    Ok((
+        field("at", 0),
+        field("at", 1),
+        field("size", 0),
+        field("size", 1),
+    ))
+}
+
+fn x11_rect(class: &str) -> Result<Rect, Error> {
+    let args = match class {
+        "focused" => vec!["getactivewindow"],
+        class => vec!["search", "--onlyvisible", "--class", class],
+    };
+    // both fail without printing anything when there is no such window
+    let ids = exec::command("xdotool", FEATURE)?
+        .args(args)
+        .output()
+        .map_err(|err| Error::ExternalTool(format!("failed to execute xdotool: {}", err)))?;
+    let ids = String::from_utf8_lossy(&ids.stdout);
+    let id = ids
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| not_found(class))?;
+    // prints X=, Y=, WIDTH= and HEIGHT= lines
+    let geometry = output("xdotool", &["getwindowgeometry", "--shell", id])?;
+    let geometry = String::from_utf8_lossy(&geometry);
+    let field = |name: &str| {
+        geometry
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+            .and_then(|value| value.trim().parse::<i64>().ok())
+            .unwrap_or_default()
+    };
+    Ok((field("X"), field("Y"), field("WIDTH"), field("HEIGHT")))
+}
+
+/// The window with the class `class`, or the focused window for `focused`
+pub fn find(class: &str) -> Result<Window, Error> {
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    let (rect, screenshot) = if wayland {
+        let rect = if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+            hyprland_rect(class)?
+        } else {
+            sway_rect(class)?
+        };
+        // compositors give logical pixels, grim captures at the scale of the output
+        let scale = crate::scale::detect().unwrap_or(1.0) as f64;
+        let scaled = |value: i64| (value as f64 * scale).round() as i64;
+        let rect = (
+            scaled(rect.0),
+            scaled(rect.1),
+            scaled(rect.2),
+            scaled(rect.3),
+        );
+        (rect, output("grim", &["-"])?)
+    } else {
+        (
+            x11_rect(class)?,
+            output("import", &["-window", "root", "png:-"])?,
+        )
+    };
+    let screenshot = image::load_from_memory(&screenshot)
+        .map_err(|err| Error::ExternalTool(format!("could not read the screenshot: {}", err)))?
+        .into_rgb8();
+    window(&screenshot, rect).ok_or_else(|| {
+        Error::ExternalTool(format!(
+            "the window {} is not on the screen",
+            match class {
+                "focused" => "in focus",
+                class => class,
+            }
+        ))
+    })
+}
+
+/// The window at `rect` of the screenshot, clipped to it
+fn window(screenshot: &RgbImage, (x, y, width, height): Rect) -> Option<Window> {
+    let (screen_width, screen_height) = screenshot.dimensions();
+    let left = x.clamp(0, screen_width as i64) as u32;
+    let top = y.clamp(0, screen_height as i64) as u32;
+    let right = (x + width).clamp(0, screen_width as i64) as u32;
+    let bottom = (y + height).clamp(0, screen_height as i64) as u32;
+    if right <= left || bottom <= top {
+        return None;
+    }
+    let pixels = (top..bottom)
+        .flat_map(|row| (left..right).map(move |column| *screenshot.get_pixel(column, row)))
+        .collect();
+    Some(Window {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+        screen_width,
+        screen_height,
+        color: average_color(pixels),
+    })
+}
+
+impl Window {
+    /// The pixels of a `width` by `height` image that are behind the window
+    pub fn mask(&self, width: u32, height: u32) -> Mask {
+        let scale_x = width as f64 / self.screen_width as f64;
+        let scale_y = height as f64 / self.screen_height as f64;
+        let left = (self.x as f64 * scale_x).floor() as u32;
+        let right = ((self.x + self.width) as f64 * scale_x).ceil() as u32;
+        let top = (self.y as f64 * scale_y).floor() as u32;
+        let bottom = ((self.y + self.height) as f64 * scale_y).ceil() as u32;
+        (0..height)
+            .flat_map(|y| {
+                (0..width).map(move |x| (left..right).contains(&x) && (top..bottom).contains(&y))
+            })
+            .collect()
+    }
+}
+
+/// The half of the palette closest to `color`, at least two colors, nearest first
+pub fn harmonizing(palette: &[Rgb<u8>], color: Rgb<u8>) -> Vec<Rgb<u8>> {
+    let mut colors = palette.to_vec();
+    colors.sort_by(|a, b| delta_e(*a, color).total_cmp(&delta_e(*b, color)));
+    colors.truncate(palette.len().div_ceil(2).max(2));
+    colors
+}
@@ -0,0 +1,123 @@
+//! `pack --windows-theme`: Windows themes of the rendered variants, one per theme and
+//! resolution, so Windows users get a desktop slideshow without any extra tool.
+//!
+//! Every theme is written to `windows/<theme>-<resolution>/` with the layout Windows uses
+//! for installed themes, a `.theme` file next to a `DesktopBackground` folder of images.
+//! The `.theme` file refers to them through `%ThemeDir%`, which only points there once
+//! the folder is in `%LOCALAPPDATA%\Microsoft\Windows\Themes`. With `--deskthemepack`
+//! the folder is also packed into a `.deskthemepack` cabinet that installs the theme when
+//! opened, without touching the registry by hand.
+
+use crate::color::chroma;
+use crate::error::Error;
+use image::Rgb;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const DIR: &str = "windows";
+const BACKGROUNDS: &str = "DesktopBackground";
+/// Time each wallpaper of the slideshow is shown, in milliseconds
+const SLIDESHOW_INTERVAL: u32 = 30 * 60 * 1000;
+/// Opacity of the accent color of windows and the taskbar
+const ACCENT_ALPHA: u8 = 0xc4;
+
+fn failed(path: &Path, err: impl std::fmt::Display) -> Error {
+    Error::Encode(format!("could not write {}: {}", path.display(), err))
+}
+
+/// The most colorful color of the palette, for the borders and the taskbar
+fn accent(palette: &[Rgb<u8>]) -> Rgb<u8> {
+    palette
+        .iter()
+        .copied()
+        .max_by_key(|color| chroma(*color))
+        .unwrap_or(Rgb([0, 0, 0]))
+}
+
+/// The contents of the .theme file, with Windows line endings
+fn theme_file(name: &str, palette: &[Rgb<u8>], images: &[String]) -> String {
+    let Rgb([red, green, blue]) = accent(palette);
+    let lines = [
+        "; made by imagecolorizer".to_string(),
+        "[Theme]".to_string(),
+        format!("DisplayName={}", name),
+        String::new(),
+        "[Control Panel\\Desktop]".to_string(),
+        format!("Wallpaper=%ThemeDir%{}\\{}", BACKGROUNDS, images[0]),
+        "TileWallpaper=0".to_string(),
+        // fill the screen, cropping if needed
+        "WallpaperStyle=10".to_string(),
+        String::new(),
+        "[VisualStyles]".to_string(),
+        "Path=%SystemRoot%\\resources\\themes\\Aero\\Aero.msstyles".to_string(),
+        "ColorStyle=NormalColor".to_string(),
+        "Size=NormalSize".to_string(),
+        "AutoColorization=0".to_string(),
+        format!(
+            "ColorizationColor=0X{:02X}{:02X}{:02X}{:02X}",
+            ACCENT_ALPHA, red, green, blue
+        ),
+        String::new(),
+        "[Slideshow]".to_string(),
+        format!("Interval={}", SLIDESHOW_INTERVAL),
+        "Shuffle=1".to_string(),
+        format!("ImagesRootPath=%ThemeDir%{}", BACKGROUNDS),
+        String::new(),
+        "[MasterThemeSelector]".to_string(),
+        "MTSM=RJSPBS".to_string(),
+    ];
+    lines.join("\r\n") + "\r\n"
+}
+
+/// Pack a theme folder into a cabinet, the format of .deskthemepack files
+fn write_themepack(dir: &Path, theme: &str, images: &[String], path: &Path) -> io::Result<()> {
+    let mut builder = cab::CabinetBuilder::new();
+    let folder = builder.add_folder(cab::CompressionType::MsZip);
+    folder.add_file(theme);
+    for image in images {
+        folder.add_file(format!("{}\\{}", BACKGROUNDS, image));
+    }
+    let mut writer = builder.build(fs::File::create(path)?)?;
+    while let Some(mut file) = writer.next_file()? {
+        let source = dir.join(file.file_name().replace('\\', "/"));
+        io::copy(&mut fs::File::open(source)?, &mut file)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Write the Windows theme named `<theme>-<resolution>` of the rendered `images`, and its
+/// .deskthemepack if `themepack` is set
+pub fn write(
+    output_dir: &Path,
+    theme: &str,
+    resolution: &str,
+    palette: &[Rgb<u8>],
+    images: &[PathBuf],
+    themepack: bool,
+) -> Result<(), Error> {
+    if images.is_empty() {
+        return Ok(());
+    }
+    let name = format!("{}-{}", theme, resolution);
+    let dir = output_dir.join(DIR).join(&name);
+    let backgrounds = dir.join(BACKGROUNDS);
+    fs::create_dir_all(&backgrounds).map_err(|err| failed(&backgrounds, err))?;
+    let mut names = Vec::new();
+    for image in images {
+        let file_name = image.file_name().unwrap_or_default().to_string_lossy();
+        let copy = backgrounds.join(&*file_name);
+        fs::copy(image, &copy).map_err(|err| failed(&copy, err))?;
+        names.push(file_name.to_string());
+    }
+    let theme_name = format!("{}.theme", name);
+    let theme_path = dir.join(&theme_name);
+    fs::write(&theme_path, theme_file(&name, palette, &names))
+        .map_err(|err| failed(&theme_path, err))?;
+    if themepack {
+        let path = output_dir.join(DIR).join(format!("{}.deskthemepack", name));
+        write_themepack(&dir, &theme_name, &names, &path).map_err(|err| failed(&path, err))?;
+    }
+    Ok(())
+}